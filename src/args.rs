@@ -1,11 +1,39 @@
+use std::collections::HashMap;
 use std::env;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::time::Duration;
 
+use anyhow::{Context, Result};
 use clap::error::ErrorKind;
-use clap::{ArgGroup, Command, CommandFactory, Parser};
+use clap::{ArgGroup, Command, CommandFactory, FromArgMatches, Parser, Subcommand};
+use clap_complete::Shell;
 use dotenv::dotenv;
+use jsonwebtoken::Algorithm;
+use url::Url;
 
-use crate::config_file::ConfigFile;
+use crate::InteractionRequired;
+use crate::claims::RequiredClaim;
+use crate::client_auth_method::ClientAuthMethod;
+use crate::clock_skew::ClockSkewMode;
+use crate::commands::import::ImportSource;
+use crate::config_file::{self, ConfigFile};
+use crate::environment::Environment;
 use crate::grant::Grant;
+use crate::hooks::{HookEnv, HookFailure};
+use crate::http_client::parse_extra_header;
+#[cfg(feature = "kubernetes")]
+use crate::integrations::kubernetes;
+use crate::interop::CacheFormat;
+use crate::jwt::parse_algorithm;
+use crate::openidc_discovery::parse_discovery_mapping;
+use crate::output;
+use crate::output::OutputFormat;
+use crate::prompt::{InputRequired, PromptBackend, PromptOptions, prompt_secret};
+use crate::providers::Provider;
+use crate::sdk_hints::SdkTarget;
+use crate::secret_source::SecretSource;
+use crate::systemd;
 
 #[derive(Parser, Debug, Clone)]
 #[clap(author, version, about)]
@@ -25,6 +53,14 @@ pub struct Arguments {
     #[clap(long, value_enum, default_value_t = Grant::AuthorizationCodeWithPkce, env = "DOKEN_GRANT")]
     pub grant: Grant,
 
+    /// Grant used instead of a browser based `--grant` when no browser can be shown, ex. in an SSH session or without DISPLAY
+    #[clap(long, value_enum, env = "DOKEN_FALLBACK_GRANT")]
+    pub fallback_grant: Option<Grant>,
+
+    /// Identity provider specific behaviour
+    #[clap(long, value_enum, default_value_t = Provider::Generic, env = "DOKEN_PROVIDER")]
+    pub provider: Provider,
+
     /// OAuth 2.0 token exchange url
     #[clap(long, env = "DOKEN_TOKEN_URL")]
     pub token_url: Option<String>,
@@ -33,14 +69,30 @@ pub struct Arguments {
     #[clap(long, env = "DOKEN_AUTHORIZATION_URL")]
     pub authorization_url: Option<String>,
 
+    /// OAuth 2.0 device authorization url of `device-code` grant, taken from the discovery document with `--discovery-url`
+    #[clap(long, env = "DOKEN_DEVICE_AUTHORIZATION_URL")]
+    pub device_authorization_url: Option<String>,
+
     /// OpenID Connect discovery url
     #[clap(long, env = "DOKEN_DISCOVERY_URL")]
     pub discovery_url: Option<String>,
 
+    /// JSON path of a discovery document field for providers with non-standard documents, ex. `token_endpoint=$.endpoints.token`
+    #[clap(long, value_parser = parse_discovery_mapping, value_delimiter = ',', env = "DOKEN_DISCOVERY_MAP")]
+    pub discovery_map: Vec<(String, String)>,
+
+    /// HTTP header sent with token requests and by the controlled browser during the authorization, ex. `X-Device-Id: 1234` for conditional access
+    #[clap(long, value_parser = parse_extra_header, value_delimiter = ',', env = "DOKEN_EXTRA_HEADERS")]
+    pub extra_header: Vec<(String, String)>,
+
     /// Callback URL that's been set for your application
     #[clap(long, env = "DOKEN_CALLBACK_URL")]
     pub callback_url: Option<String>,
 
+    /// Opens the authorization url with this command instead of the controlled browser, ex. `firefox --private-window {url}`. The code is received on the `http://` loopback `--callback-url`
+    #[clap(long, env = "DOKEN_BROWSER_CMD")]
+    pub browser_cmd: Option<String>,
+
     /// OAuth 2.0 Client Identifier <https://www.rfc-editor.org/rfc/rfc6749#section-2.2>
     #[clap(long, env = "DOKEN_CLIENT_ID")]
     pub client_id: String,
@@ -49,10 +101,72 @@ pub struct Arguments {
     #[clap(long, env = "DOKEN_CLIENT_SECRET")]
     pub client_secret: Option<String>,
 
+    /// How the client authenticates at the token endpoint. Negotiated from `token_endpoint_auth_methods_supported` of the discovery document by default
+    #[clap(long, value_enum, env = "DOKEN_CLIENT_AUTH_METHOD")]
+    pub client_auth_method: Option<ClientAuthMethod>,
+
+    /// Private key signing client assertions of `private_key_jwt`: a PEM file, `yubikey:<slot>`, ex. `yubikey:9a`, for a key in a YubiKey PIV slot, or a `pkcs11:` URI of a key in an HSM. Turns `private-key-jwt` client authentication on
+    #[clap(long, env = "DOKEN_CLIENT_ASSERTION_KEY")]
+    pub client_assertion_key: Option<String>,
+
+    /// Signing algorithm of client assertions. YubiKeys sign with RS256, ES256 or ES384
+    #[clap(long, value_parser = parse_algorithm, default_value = "RS256", env = "DOKEN_CLIENT_ASSERTION_ALG")]
+    pub client_assertion_alg: Algorithm,
+
+    /// `kid` of client assertions, if the provider knows more than one key of the client
+    #[clap(long, env = "DOKEN_CLIENT_ASSERTION_KID")]
+    pub client_assertion_kid: Option<String>,
+
+    /// Private key signing assertions of `jwt-bearer` grant: a PEM file, a private JWK or a Google service account key file
+    #[clap(long, env = "DOKEN_ASSERTION_KEY_FILE")]
+    pub assertion_key_file: Option<String>,
+
+    /// `sub` of `jwt-bearer` assertions, ex. the user a Salesforce connected app acts for. The issuer itself by default
+    #[clap(long, env = "DOKEN_ASSERTION_SUBJECT")]
+    pub assertion_subject: Option<String>,
+
+    /// `aud` of `jwt-bearer` assertions, ex. `https://login.salesforce.com`. The token endpoint by default
+    #[clap(long, env = "DOKEN_ASSERTION_AUDIENCE")]
+    pub assertion_audience: Option<String>,
+
+    /// SAML assertion of `saml2-bearer` grant, as XML or base64 <https://www.rfc-editor.org/rfc/rfc7522#section-2.1>
+    #[clap(long, env = "DOKEN_SAML_ASSERTION_FILE")]
+    pub saml_assertion_file: Option<String>,
+
+    /// SAML assertion of `saml2-bearer` grant from standard input
+    #[clap(
+        long,
+        action,
+        default_value_t = false,
+        conflicts_with = "saml_assertion_file"
+    )]
+    pub saml_assertion_stdin: bool,
+
+    /// Refresh token to redeem instead of the cached token, ex. exported from another machine. The state file is neither read nor written. Please use `--refresh-token-stdin`, because it doesn't get stored in a shell history
+    #[clap(long, env = "DOKEN_REFRESH_TOKEN")]
+    pub refresh_token: Option<String>,
+
+    /// Refresh token from standard input
+    #[clap(
+        long,
+        action,
+        default_value_t = false,
+        conflicts_with = "refresh_token"
+    )]
+    pub refresh_token_stdin: bool,
+
     /// OAuth 2.0 Client Secret from standard input <https://www.rfc-editor.org/rfc/rfc6749#section-2.3.1>
     #[clap(long, action, default_value_t = false)]
     pub client_secret_stdin: bool,
 
+    /// OAuth 2.0 Client Secret read from a password manager, ex. `op://<vault>/<item>/<field>` (1Password CLI) or `bw://<item>/<field>` (Bitwarden CLI)
+    #[clap(
+        long,
+        conflicts_with = "client_secret_stdin",
+        env = "DOKEN_CLIENT_SECRET_FROM"
+    )]
+    pub client_secret_from: Option<String>,
+
     /// OAuth 2.0 Resource Owner Password Client Credentials Grant's username <https://www.rfc-editor.org/rfc/rfc6749#section-4.3.2>
     #[clap(short, long, env = "DOKEN_USERNAME")]
     pub username: Option<String>,
@@ -65,6 +179,18 @@ pub struct Arguments {
     #[clap(long, action, default_value_t = false)]
     pub password_stdin: bool,
 
+    /// Base32 TOTP secret. A one-time password is generated and appended to the password (or sent as `--otp-param`) for providers enforcing MFA this way
+    #[clap(long, env = "DOKEN_OTP_SECRET")]
+    pub otp_secret: Option<String>,
+
+    /// Base32 TOTP secret from standard input
+    #[clap(long, action, default_value_t = false)]
+    pub otp_stdin: bool,
+
+    /// Form parameter carrying the one-time password instead of appending it to the password, ex. `otp`
+    #[clap(long, env = "DOKEN_OTP_PARAM")]
+    pub otp_param: Option<String>,
+
     /// OAuth 2.0 Scope <https://www.rfc-editor.org/rfc/rfc6749#section-3.3>
     #[clap(long, default_value = "offline_access", env = "DOKEN_SCOPE")]
     pub scope: String,
@@ -73,50 +199,771 @@ pub struct Arguments {
     #[clap(long, env = "DOKEN_AUDIENCE")]
     pub audience: Option<String>,
 
+    /// Resource indicator of the API the token is meant for, sent with authorization and token requests <https://www.rfc-editor.org/rfc/rfc8707>. Repeatable
+    #[clap(long, value_delimiter = ',', env = "DOKEN_RESOURCE")]
+    pub resource: Vec<String>,
+
+    /// Exchanges the token of the signed in (admin) user for a token of this Keycloak user, ex. for QA. The client needs the `impersonation` permission for token exchange
+    #[clap(long, env = "DOKEN_IMPERSONATE")]
+    pub impersonate: Option<String>,
+
+    /// Tenant of a multi-tenant provider the token has to be issued for, ex. Entra ID's `tid`. Tokens are cached per tenant
+    #[clap(long, env = "DOKEN_TENANT")]
+    pub tenant: Option<String>,
+
+    /// Signs in again asking for the account or tenant (`prompt=select_account`) and replaces only the cached token of this client
+    #[clap(long, action, default_value_t = false)]
+    pub tenant_switch: bool,
+
+    /// Profile whose access_token is exchanged with `--grant token-exchange`, ex. the corporate IdP federated into this issuer
+    #[clap(long, env = "DOKEN_UPSTREAM_PROFILE")]
+    pub upstream_profile: Option<String>,
+
+    /// Shows the requested scopes and asks for confirmation before a new flow is started
+    #[clap(long, action, default_value_t = false, env = "DOKEN_CONFIRM_SCOPES")]
+    pub confirm_scopes: bool,
+
+    /// Signing algorithms accepted for the id_token, ex. `RS256,ES256`. Enables signature verification with the provider's JWKS
+    #[clap(long, value_parser = parse_algorithm, value_delimiter = ',', env = "DOKEN_ALLOWED_ALGS")]
+    pub allowed_algs: Vec<Algorithm>,
+
+    /// Key ids the id_token has to be signed with. Enables signature verification with the provider's JWKS
+    #[clap(long, value_delimiter = ',', env = "DOKEN_PIN_KID")]
+    pub pin_kid: Vec<String>,
+
+    /// Claims a cached token has to carry as `name=value`, ex. `tid=<tenant_id>,roles=admin`. Otherwise a new token is requested
+    #[clap(long, value_delimiter = ',', env = "DOKEN_REQUIRE_CLAIM")]
+    pub require_claim: Vec<RequiredClaim>,
+
+    /// Token cache of another CLI tool to import the token from when doken has none cached, and to keep up to date afterwards
+    #[clap(long, value_enum, env = "DOKEN_INTEROP_CACHE")]
+    pub interop_cache: Option<CacheFormat>,
+
+    /// Signs in within a native window (WKWebView on macOS, WebView2 on Windows) instead of Chromium
+    #[cfg(all(feature = "webview", any(target_os = "macos", windows)))]
+    #[clap(long, action, default_value_t = false, env = "DOKEN_WEBVIEW")]
+    pub webview: bool,
+
+    /// Reads client_id, client_secret and issuer from a Kubernetes Secret, ex. `ci/oauth-client`, or ConfigMap, ex. `ci/configmap/oauth-client`. Mounted ones are used before `kubectl`
+    #[cfg(feature = "kubernetes")]
+    #[clap(long, env = "DOKEN_FROM_K8S_SECRET")]
+    pub from_k8s_secret: Option<String>,
+
+    /// Url `doken daemon` and `doken watch` POST a JSON event to whenever a token is refreshed or a refresh fails
+    #[clap(long, env = "DOKEN_WEBHOOK_URL")]
+    pub webhook_url: Option<String>,
+
+    /// Signs webhook events with HMAC-SHA256 in the `X-Doken-Signature-256` header
+    #[clap(long, env = "DOKEN_WEBHOOK_SECRET", requires = "webhook_url")]
+    pub webhook_secret: Option<String>,
+
+    /// Command run before a token is refreshed or a new flow starts, ex. to connect a VPN. Cached tokens skip it
+    #[clap(long, env = "DOKEN_PRE_HOOK")]
+    pub pre_hook: Option<String>,
+
+    /// Command run after a token is refreshed or a new flow ends. Reads the token as JSON on stdin
+    #[clap(long, env = "DOKEN_POST_HOOK")]
+    pub post_hook: Option<String>,
+
+    /// Milliseconds a hook may run before it's killed and considered failed
+    #[clap(long, default_value_t = 30_000, env = "DOKEN_HOOK_TIMEOUT")]
+    pub hook_timeout: u64,
+
+    /// What a failing hook does to the run
+    #[clap(long, value_enum, default_value_t = HookFailure::Abort, env = "DOKEN_HOOK_FAILURE")]
+    pub hook_failure: HookFailure,
+
+    /// Environment hooks run with. `DOKEN_*` variables are never passed on
+    #[clap(long, value_enum, default_value_t = HookEnv::Inherit, env = "DOKEN_HOOK_ENV")]
+    pub hook_env: HookEnv,
+
+    /// Proxy used for all requests to the provider. System proxy settings are used by default
+    #[clap(long, env = "DOKEN_PROXY")]
+    pub proxy: Option<String>,
+
+    /// Ignores system proxy settings
+    #[clap(long, action, default_value_t = false)]
+    pub no_system_proxy: bool,
+
+    /// Additional trusted CA certificates (PEM bundle)
+    #[clap(long, env = "DOKEN_CA_CERT")]
+    pub ca_cert: Option<String>,
+
+    /// Ignores certificates from the system trust store
+    #[clap(long, action, default_value_t = false)]
+    pub no_system_trust: bool,
+
     /// Authorization Code, Authorization Code with PKCE and Implicit Grants' timeout,
     #[clap(short, long, default_value_t = 30_000, env = "DOKEN_TIMEOUT")]
     pub timeout: u64,
 
+    /// What to do when the local clock differs from the `Date` of the token endpoint by more than `--max-clock-skew`
+    #[clap(long, value_enum, default_value_t = ClockSkewMode::Warn, env = "DOKEN_CLOCK_SKEW")]
+    pub clock_skew: ClockSkewMode,
+
+    /// Seconds the local clock may differ from the provider's before `--clock-skew` applies
+    #[clap(long, default_value_t = 300, env = "DOKEN_MAX_CLOCK_SKEW")]
+    pub max_clock_skew: u64,
+
     /// When turned on ignores the state file and continues with a fresh flow
     #[clap(short, long, action, default_value_t = false)]
     pub force: bool,
 
+    /// Only returns a valid cached token. Otherwise fails right away with exit code 3 without refreshing, prompting or opening a browser. Meant for editor integrations
+    #[clap(
+        long,
+        action,
+        default_value_t = false,
+        env = "DOKEN_SILENT_FAIL_CACHE_ONLY"
+    )]
+    pub silent_fail_cache_only: bool,
+
+    /// Prints every request the IdP sends to the callback origin (method, query, form, fragment) without validating it
+    #[clap(long, action, default_value_t = false)]
+    pub echo_callback: bool,
+
+    /// Prints why the cached token was used or rejected, whether it was refreshed and which flow ran
+    #[clap(long, action, default_value_t = false)]
+    pub explain: bool,
+
+    /// Saves a screenshot and the url of the page to ~/.doken/debug when a flow in the controlled browser fails or times out
+    #[clap(long, action, default_value_t = false)]
+    pub debug_screenshot: bool,
+
+    /// Records the network activity of the controlled browser during the flow to a HAR file, ex. to share with the IdP support
+    #[clap(long)]
+    pub capture_har: Option<PathBuf>,
+
+    /// Keeps cookies, credentials, codes and tokens in the `--capture-har` file. Don't share such a file
+    #[clap(long, action, default_value_t = false, requires = "capture_har")]
+    pub har_include_secrets: bool,
+
+    /// Prints every step of getting the token (discovery, browser launch, waiting for the user, ...) as JSON lines to stderr
+    #[clap(long, action, default_value_t = false)]
+    pub progress_events: bool,
+
+    /// Fails instead of warning when the state or config file is readable by other users
+    #[clap(
+        long,
+        action,
+        default_value_t = false,
+        env = "DOKEN_STRICT_PERMISSIONS"
+    )]
+    pub strict_permissions: bool,
+
+    /// Fails the flow on the first callback with a wrong or missing `state` instead of ignoring it and waiting for another one
+    #[clap(long, action, default_value_t = false, env = "DOKEN_STRICT_CSRF")]
+    pub strict_csrf: bool,
+
+    /// Keeps the pending authorization request on disk for 10 minutes, so a rerun after a crash or restart accepts its callback instead of starting over
+    #[clap(long, action, default_value_t = false, env = "DOKEN_RESUME_FLOWS")]
+    pub resume_flows: bool,
+
+    /// Lets concurrent runs with `--browser-cmd` share the callback port. Each flow gets its own `/cb/<flow-id>` callback path, which the IdP has to accept
+    #[clap(
+        long,
+        action,
+        default_value_t = false,
+        env = "DOKEN_MULTIPLEX_CALLBACK"
+    )]
+    pub multiplex_callback: bool,
+
+    /// Receives the callback of `--browser-cmd` on an ephemeral unix domain socket instead of a port. The command gets its path in `DOKEN_CALLBACK_SOCKET_PATH` and hands the callback url over with `$DOKEN_CALLBACK_RELAY <url>`
+    #[clap(
+        long,
+        action,
+        default_value_t = false,
+        requires = "browser_cmd",
+        conflicts_with = "multiplex_callback",
+        env = "DOKEN_CALLBACK_SOCKET"
+    )]
+    pub callback_socket: bool,
+
+    /// Addresses the callback server of `--browser-cmd` listens on, ex. `127.0.0.1,::1`. Loopback callback urls listen on both 127.0.0.1 and ::1 by default
+    #[clap(long, value_delimiter = ',', env = "DOKEN_BIND_ADDRESS")]
+    pub bind_address: Vec<IpAddr>,
+
     /// Add diagnostics info
     #[clap(short, long, action, default_value_t = false)]
     pub debug: bool,
 
-    /// Profile defined in ~/.doken/config.toml file
+    /// Prints tokens and other secrets in diagnostics as they are. They're masked by default
+    #[clap(long, action, default_value_t = false, env = "DOKEN_UNSAFE_LOGS")]
+    pub unsafe_logs: bool,
+
+    /// Fails instead of printing more than this many bytes to stdout, ex. a whole token response into CI logs
+    #[clap(long, env = "DOKEN_MAX_STDOUT_BYTES")]
+    pub max_stdout_bytes: Option<usize>,
+
+    /// Profile defined in ~/.doken/config.toml file. Repeated, ex. `--profile a --profile b`, prints tokens of all of them as a JSON map keyed by profile
     #[clap(long)]
     pub profile: Option<String>,
+
+    /// How to ask for secrets passed via `--*-stdin` arguments and MFA codes
+    #[clap(long, value_enum, default_value_t = PromptBackend::Terminal, env = "DOKEN_PROMPT_BACKEND")]
+    pub prompt_backend: PromptBackend,
+
+    /// Runs the controlled browser without a window. Defaults to true when no browser can be shown (CI, SSH, no DISPLAY)
+    #[clap(long, num_args = 0..=1, default_missing_value = "true", env = "DOKEN_HEADLESS")]
+    pub headless: Option<bool>,
+
+    /// Overrides the detection of whether a browser can be shown, which decides about `--fallback-grant` and `--headless`
+    #[clap(long, env = "DOKEN_BROWSER_AVAILABLE")]
+    pub browser_available: Option<bool>,
+
+    /// Launches the controlled browser with a new profile removed after the run, so parallel runs don't share one. IdP sessions aren't kept then
+    #[clap(
+        long,
+        action,
+        default_value_t = false,
+        env = "DOKEN_EPHEMERAL_BROWSER_PROFILE"
+    )]
+    pub ephemeral_browser_profile: bool,
+
+    /// Answers yes to every confirmation, ex. of `--confirm-scopes`
+    #[clap(long, action, default_value_t = false, env = "DOKEN_ASSUME_YES")]
+    pub assume_yes: bool,
+
+    /// Never prompts nor opens a browser. Fails with exit code 3 when input would be required. Meant for provisioning tools
+    #[clap(long, action, default_value_t = false, env = "DOKEN_NON_INTERACTIVE")]
+    pub non_interactive: bool,
+
+    /// Shows times of `--explain` in UTC instead of local time
+    #[clap(long, action, default_value_t = false, env = "DOKEN_UTC")]
+    pub utc: bool,
+
+    /// Format of the command output
+    #[clap(long, value_enum, default_value_t = OutputFormat::AccessToken, env = "DOKEN_OUTPUT")]
+    pub output: OutputFormat,
+
+    /// Template of the command output instead of `--output`, ex. `{{.access_token}} exp={{.expires_at}}`. Fields of `--output json` are available along with `expires_in`, `claims.<name>` and `id_token_claims.<name>`
+    #[clap(long, conflicts_with = "output", env = "DOKEN_FORMAT")]
+    pub format: Option<String>,
+
+    /// Stack whose environment variables are printed with `--output sdk-hints`
+    #[clap(
+        long,
+        value_enum,
+        required_if_eq("output", "sdk-hints"),
+        env = "DOKEN_TARGET"
+    )]
+    pub target: Option<SdkTarget>,
+
+    /// Prints the versioned JSON Schema of `--output json`, `doken watch` and `--progress-events` and exits
+    #[clap(long, action, default_value_t = false)]
+    pub output_schema: bool,
+}
+
+impl Arguments {
+    /// Why no browser can be shown, based on `--browser-available` or the detected environment
+    pub fn browser_unavailable(&self) -> Option<&'static str> {
+        match self.browser_available {
+            Some(true) => None,
+            Some(false) => Some("`--browser-available false` is set"),
+            None => Environment::detect().browser_unavailable(),
+        }
+    }
+
+    pub fn headless(&self) -> bool {
+        self.headless
+            .unwrap_or_else(|| self.browser_unavailable().is_some())
+    }
+
+    pub fn prompt_options(&self) -> PromptOptions {
+        PromptOptions {
+            backend: self.prompt_backend.clone(),
+            assume_yes: self.assume_yes,
+            non_interactive: self.non_interactive,
+        }
+    }
+
+    /// Fills secrets the arguments don't have yet with the given lookup of `client_secret`, `password`
+    /// and `otp_secret`. Used for profile arguments built without the environment, ex. by the daemon
+    pub fn with_secrets(mut self, lookup: impl Fn(&str) -> Option<String>) -> Arguments {
+        self.client_secret = self.client_secret.or_else(|| lookup("client_secret"));
+        self.password = self.password.or_else(|| lookup("password"));
+        self.otp_secret = self.otp_secret.or_else(|| lookup("otp_secret"));
+        self.webhook_secret = self.webhook_secret.or_else(|| lookup("webhook_secret"));
+
+        self
+    }
+
+    /// Reads the client secret of `--client-secret-from` with the password manager's CLI, unless one is given already
+    pub async fn with_client_secret_from(mut self) -> Result<Arguments> {
+        if let (None, Some(reference)) = (&self.client_secret, &self.client_secret_from) {
+            let source =
+                SecretSource::parse(reference).context("Cannot read `--client-secret-from`")?;
+            let secret = source
+                .read()
+                .await
+                .context("Cannot read `--client-secret-from`")?;
+            self.client_secret = Some(secret);
+        }
+
+        Ok(self)
+    }
+
+    /// Expands `@<name>` scope aliases of the `[scopes]` table in the config
+    pub fn with_scope_aliases(
+        mut self,
+        aliases: &HashMap<String, Vec<String>>,
+    ) -> Result<Arguments> {
+        if self.scope.contains('@') {
+            self.scope = config_file::expand_scope_aliases(&self.scope, aliases)
+                .context("Cannot expand `--scope`")?;
+        }
+
+        Ok(self)
+    }
+
+    /// Key of the token in the state file. Exchanged tokens are kept per requested audience,
+    /// because one client may exchange the same upstream token for many APIs. So are tokens of
+    /// providers with multi-resource refresh tokens, kept per audience or scope. Tokens of
+    /// `--resource` indicators are valid only for the named resources, so kept per resources too.
+    /// Tokens of a `--tenant` are kept apart from the ones of the tenant signed in last
+    pub fn state_key(&self) -> String {
+        let mut state_key = self.audience_state_key();
+
+        if !self.resource.is_empty() {
+            let mut resources = self.resource.to_owned();
+            resources.sort();
+            resources.dedup();
+            state_key = format!("{}#{}", state_key, resources.join(" "));
+        }
+
+        match &self.tenant {
+            Some(tenant) => format!("{}@{}", state_key, tenant),
+            None => state_key,
+        }
+    }
+
+    fn audience_state_key(&self) -> String {
+        match (&self.grant, &self.audience) {
+            (Grant::TokenExchange, Some(audience)) => format!("{}#{}", self.client_id, audience),
+            (_, audience) if self.provider.has_multi_resource_refresh_tokens() => format!(
+                "{}#{}",
+                self.client_id,
+                audience.as_deref().unwrap_or(&self.scope)
+            ),
+            _ => self.client_id.to_owned(),
+        }
+    }
 }
 
 impl Default for Arguments {
     fn default() -> Self {
         Self {
             grant: Grant::AuthorizationCodeWithPkce,
+            fallback_grant: Default::default(),
+            provider: Default::default(),
             token_url: Default::default(),
             authorization_url: Default::default(),
+            device_authorization_url: Default::default(),
             discovery_url: Default::default(),
+            discovery_map: Default::default(),
+            extra_header: Default::default(),
             callback_url: Default::default(),
+            browser_cmd: Default::default(),
             client_id: Default::default(),
             client_secret: Default::default(),
+            client_auth_method: Default::default(),
+            client_assertion_key: Default::default(),
+            client_assertion_alg: Algorithm::RS256,
+            client_assertion_kid: Default::default(),
+            assertion_key_file: Default::default(),
+            assertion_subject: Default::default(),
+            assertion_audience: Default::default(),
+            saml_assertion_file: Default::default(),
+            saml_assertion_stdin: Default::default(),
+            refresh_token: Default::default(),
+            refresh_token_stdin: Default::default(),
             client_secret_stdin: Default::default(),
+            client_secret_from: Default::default(),
             username: Default::default(),
             password: Default::default(),
             password_stdin: Default::default(),
+            otp_secret: Default::default(),
+            otp_stdin: Default::default(),
+            otp_param: Default::default(),
             scope: Default::default(),
             audience: Default::default(),
+            resource: Default::default(),
+            impersonate: Default::default(),
+            tenant: Default::default(),
+            tenant_switch: Default::default(),
+            upstream_profile: Default::default(),
+            confirm_scopes: Default::default(),
+            allowed_algs: Default::default(),
+            pin_kid: Default::default(),
+            require_claim: Default::default(),
+            interop_cache: Default::default(),
+            #[cfg(all(feature = "webview", any(target_os = "macos", windows)))]
+            webview: Default::default(),
+            #[cfg(feature = "kubernetes")]
+            from_k8s_secret: Default::default(),
+            webhook_url: Default::default(),
+            webhook_secret: Default::default(),
+            pre_hook: Default::default(),
+            post_hook: Default::default(),
+            hook_timeout: 30_000,
+            hook_failure: Default::default(),
+            hook_env: Default::default(),
+            proxy: Default::default(),
+            no_system_proxy: Default::default(),
+            ca_cert: Default::default(),
+            no_system_trust: Default::default(),
             timeout: 30_000,
+            clock_skew: Default::default(),
+            max_clock_skew: 300,
             force: Default::default(),
+            silent_fail_cache_only: Default::default(),
+            echo_callback: Default::default(),
+            explain: Default::default(),
+            debug_screenshot: Default::default(),
+            capture_har: Default::default(),
+            har_include_secrets: Default::default(),
+            progress_events: Default::default(),
+            strict_permissions: Default::default(),
+            strict_csrf: Default::default(),
+            resume_flows: Default::default(),
+            multiplex_callback: Default::default(),
+            callback_socket: Default::default(),
+            bind_address: Default::default(),
             debug: Default::default(),
+            unsafe_logs: Default::default(),
+            max_stdout_bytes: Default::default(),
             profile: Default::default(),
+            prompt_backend: Default::default(),
+            headless: Default::default(),
+            ephemeral_browser_profile: Default::default(),
+            browser_available: Default::default(),
+            assume_yes: Default::default(),
+            non_interactive: Default::default(),
+            utc: Default::default(),
+            output: Default::default(),
+            format: Default::default(),
+            target: Default::default(),
+            output_schema: Default::default(),
         }
     }
 }
 
+// Parsed once per run, so commands flattening the whole `Arguments` aren't boxed
+#[allow(clippy::large_enum_variant)]
+#[derive(Subcommand, Debug, Clone)]
+pub enum DokenCommand {
+    /// Updates doken to the latest GitHub release. Its download isn't signed, only checked for corruption
+    SelfUpdate {
+        /// Only checks if a newer version is available
+        #[clap(long, action, default_value_t = false)]
+        check: bool,
+    },
+    /// Shows the state of a cached token
+    Status {
+        /// OAuth 2.0 Client Identifier <https://www.rfc-editor.org/rfc/rfc6749#section-2.2>
+        #[clap(long, env = "DOKEN_CLIENT_ID")]
+        client_id: String,
+
+        /// Profile defined in ~/.doken/config.toml file
+        #[clap(long)]
+        profile: Option<String>,
+
+        /// Shows the expiry in UTC instead of local time
+        #[clap(long, action, default_value_t = false, env = "DOKEN_UTC")]
+        utc: bool,
+    },
+    /// Lists which grants the provider supports according to its discovery document and why, ex. for onboarding screens of wrapper UIs
+    Grants {
+        /// Discovery url of the provider, ex. `https://my-idp.com/.well-known/openid-configuration`
+        #[clap(long, env = "DOKEN_DISCOVERY_URL")]
+        discovery_url: String,
+
+        /// Prints a JSON array instead of a table
+        #[clap(long, action, default_value_t = false)]
+        json: bool,
+    },
+    /// Measures latency of token exchanges against a provider. Uses client credentials with `--grant client-credentials`, otherwise refreshes the cached token
+    Bench {
+        /// How many exchanges are made
+        #[clap(long, default_value_t = 20)]
+        requests: usize,
+
+        /// How many client credentials exchanges run at the same time. Refreshes run one after another
+        #[clap(long, default_value_t = 4)]
+        concurrency: usize,
+
+        #[clap(flatten)]
+        args: Arguments,
+    },
+    /// Exchanges an authorization code obtained outside of doken, ex. through a mobile deep link, for a token
+    ExchangeCode {
+        /// Authorization code or the whole callback url carrying it
+        #[clap(long)]
+        code: String,
+
+        /// PKCE code verifier the authorization was started with
+        #[clap(long)]
+        code_verifier: Option<String>,
+
+        #[clap(flatten)]
+        args: Arguments,
+    },
+    /// Redeems a refresh token stored outside of doken with the client settings of the profile and prints the new token as JSON
+    Refresh {
+        /// Saves the new token in the cache, so later runs of doken use it
+        #[clap(long, action, default_value_t = false)]
+        store: bool,
+
+        #[clap(flatten)]
+        args: Arguments,
+    },
+    /// Runs a local forward-auth endpoint (traefik `forwardAuth`, nginx `auth_request`) answering with an `Authorization` header of a cached or refreshed token
+    ServeOidcProxy {
+        /// Address the endpoint listens on
+        #[clap(long, default_value = "127.0.0.1:4181")]
+        listen: SocketAddr,
+
+        /// Answers only requests carrying this value in the `X-Doken-Proxy-Secret` header
+        #[clap(long, env = "DOKEN_PROXY_SHARED_SECRET")]
+        shared_secret: Option<String>,
+
+        #[clap(flatten)]
+        args: Arguments,
+    },
+    /// Prints the token as a JSON line and again each time it's refreshed, for tools rereading credentials from stdin
+    Watch {
+        /// How long to keep printing refreshed tokens, ex. `2h`
+        #[clap(long = "for", value_parser = humantime::parse_duration)]
+        duration: Duration,
+
+        /// Serves `/healthz` and `/readyz` on this address, ex. `127.0.0.1:4182`. `/readyz` fails while no valid token is cached
+        #[clap(long)]
+        health_listen: Option<SocketAddr>,
+
+        #[clap(flatten)]
+        args: Arguments,
+    },
+    /// Runs in the foreground keeping sessions of profiles with `keep_alive = true` alive
+    Daemon {
+        /// How often sessions are kept alive, ex. `4h`. Each call is jittered by ±10%
+        #[clap(long, value_parser = humantime::parse_duration, default_value = "4h")]
+        keep_alive_interval: Duration,
+
+        /// Serves `/healthz` and `/readyz` on this address, ex. `127.0.0.1:4182`. `/readyz` fails while no valid token is cached
+        #[clap(long)]
+        health_listen: Option<SocketAddr>,
+
+        /// Receives OpenID Connect front- and back-channel logout notifications on this address, ex. `127.0.0.1:4183`, and removes cached tokens of ended IdP sessions
+        #[clap(long)]
+        logout_listen: Option<SocketAddr>,
+    },
+    /// Stops orphaned Chromium processes of doken and removes the locks and temporary profiles they left behind
+    Cleanup {
+        /// Removes doken's Chromium profile too, which logs out of all IdP sessions
+        #[clap(long, action, default_value_t = false)]
+        browser_profile: bool,
+    },
+    /// Manages ~/.doken/config.toml
+    Config {
+        #[clap(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Opt-in anonymous usage statistics (grants used and error categories). Off by default
+    Telemetry {
+        #[clap(subcommand)]
+        command: TelemetryCommand,
+    },
+    /// Runs `doken daemon` at logon as a Windows scheduled task
+    Service {
+        #[clap(subcommand)]
+        command: ServiceCommand,
+    },
+    /// Runs `doken daemon` at login as a macOS launchd agent
+    Agent {
+        #[clap(subcommand)]
+        command: AgentCommand,
+    },
+    /// Shows when and how tokens were acquired, ex. why a browser was opened. Recording is opt-in
+    History {
+        #[clap(subcommand)]
+        command: Option<HistoryCommand>,
+
+        /// Shows only entries of the given profile or client_id
+        #[clap(long)]
+        profile: Option<String>,
+
+        /// How many of the latest entries are shown
+        #[clap(long, default_value_t = 20)]
+        limit: usize,
+
+        /// Shows times in UTC instead of local time
+        #[clap(long, action, default_value_t = false, env = "DOKEN_UTC")]
+        utc: bool,
+    },
+    /// Manages cached tokens
+    Token {
+        #[clap(subcommand)]
+        command: TokenCommand,
+    },
+    /// Issues a new client secret of the profile with the Keycloak admin API or Microsoft Graph, stores it in the profile and verifies a token can still be obtained
+    RotateSecret {
+        /// Profile defined in ~/.doken/config.toml file
+        #[clap(long)]
+        profile: String,
+
+        /// Keeps the old secret of an Entra ID app registration valid instead of removing it
+        #[clap(long, action, default_value_t = false)]
+        keep_old: bool,
+    },
+    /// Creates profiles from the configuration of oauth2c, oauth2l, the Azure CLI or gcloud, with their refresh tokens where they cache them
+    Import {
+        /// Tool to migrate from
+        #[clap(long, value_enum)]
+        from: ImportSource,
+
+        /// Prints the profiles instead of adding them to ~/.doken/config.toml
+        #[clap(long, action, default_value_t = false)]
+        dry_run: bool,
+
+        /// oauth2c command line, ex. `-- https://idp.com --client-id app --grant-type client_credentials`
+        #[clap(last = true)]
+        args: Vec<String>,
+    },
+    /// Prints a completion script of the shell, ex. `doken completions zsh --dynamic > ~/.zfunc/_doken`
+    Completions {
+        #[clap(value_enum)]
+        shell: Shell,
+
+        /// Completes profiles and cached audiences too, by calling back into doken as you type. bash, zsh and fish only
+        #[clap(long, action, default_value_t = false)]
+        dynamic: bool,
+    },
+    /// Candidates of dynamic completion scripts for the command line after `doken`
+    #[clap(name = "__complete", hide = true)]
+    Complete {
+        #[clap(last = true)]
+        words: Vec<String>,
+    },
+    /// Hands a callback url over to the run waiting on `--callback-socket`. Meant for `--browser-cmd`, as `$DOKEN_CALLBACK_RELAY <url>`
+    CallbackRelay {
+        /// Callback url the IdP redirected to, ex. `http://localhost:8081/callback?code=...&state=...`
+        url: Url,
+
+        /// Socket of the waiting run
+        #[clap(long, env = "DOKEN_CALLBACK_SOCKET_PATH")]
+        socket: PathBuf,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum ConfigCommand {
+    /// Checks profiles for conflicting or missing options without running any flow
+    Lint,
+    /// Prints profiles merged from ~/.doken/config.toml and `DOKEN_PROFILE_<NAME>_<OPTION>` variables, with secrets redacted and the source of each value
+    ShowEffective {
+        /// Shows only the given profile
+        #[clap(long)]
+        profile: Option<String>,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum TelemetryCommand {
+    /// Starts reporting usage events to the given endpoint
+    On {
+        /// `https://` collector receiving JSON events or `file://` path they're appended to
+        #[clap(long)]
+        endpoint: String,
+    },
+    /// Stops reporting usage events
+    Off,
+    /// Shows whether telemetry is enabled and what's reported
+    Status,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum ServiceCommand {
+    /// Registers and starts a scheduled task of the current user. Its output goes to the Application Event Log
+    Install {
+        /// How often sessions are kept alive, ex. `4h`
+        #[clap(long, value_parser = humantime::parse_duration, default_value = "4h")]
+        keep_alive_interval: Duration,
+
+        /// Serves `/healthz` and `/readyz` on this address, ex. `127.0.0.1:4182`
+        #[clap(long)]
+        health_listen: Option<SocketAddr>,
+    },
+    /// Stops and removes the scheduled task
+    Uninstall,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum AgentCommand {
+    /// Writes ~/Library/LaunchAgents/io.github.riddleman.doken.plist and loads it. Its output goes to ~/Library/Logs/doken.log
+    Install {
+        /// How often sessions are kept alive, ex. `4h`
+        #[clap(long, value_parser = humantime::parse_duration, default_value = "4h")]
+        keep_alive_interval: Duration,
+
+        /// Serves `/healthz` and `/readyz` on this address, ex. `127.0.0.1:4182`
+        #[clap(long)]
+        health_listen: Option<SocketAddr>,
+    },
+    /// Unloads and removes the launchd agent
+    Uninstall,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum HistoryCommand {
+    /// Starts recording token acquisitions in ~/.doken/history.jsonl
+    On,
+    /// Stops recording. Recorded entries are kept
+    Off,
+    /// Removes entries older than the given age
+    Prune {
+        /// ex. `30d`
+        #[clap(long, value_parser = humantime::parse_duration)]
+        older_than: Duration,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum TokenCommand {
+    /// Manages the token cache (~/.doken.json)
+    Cache {
+        #[clap(subcommand)]
+        command: CacheCommand,
+    },
+    /// Prints a single field of the token unquoted, ex. `access_token`, `refresh_token`, `id_token`, `expires_at` or `claims.email`
+    Print {
+        /// Field of `--output json`, `expires_at`, `claims.<name>` or `id_token_claims.<name>`
+        #[clap(long)]
+        field: String,
+
+        #[clap(flatten)]
+        args: Arguments,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum CacheCommand {
+    /// Refreshes cached tokens of all profiles ahead of time, ex. before going offline
+    Warm {
+        /// How many profiles are refreshed at the same time
+        #[clap(long, default_value_t = 4)]
+        concurrency: usize,
+    },
+}
+
+#[derive(Parser, Debug)]
+#[clap(author, version, about)]
+struct CommandArguments {
+    #[clap(subcommand)]
+    command: DokenCommand,
+}
+
 pub struct Args;
 
-// TODO: match green color as the rest of clap messages
 impl Args {
     fn assert_urls_for_authorization_grants(args: &Arguments) {
         let mut cmd: Command = Arguments::command();
@@ -141,7 +988,7 @@ impl Args {
         }
     }
 
-    fn assert_grant_specific_arguments(args: &Arguments) {
+    pub(crate) fn assert_grant_specific_arguments(args: &Arguments) {
         let mut cmd: Command = Arguments::command();
 
         match args.grant {
@@ -160,7 +1007,10 @@ impl Args {
                     .exit();
                 }
 
-                if args.client_secret.is_none() && !args.client_secret_stdin {
+                if args.client_secret.is_none()
+                    && !args.client_secret_stdin
+                    && args.client_secret_from.is_none()
+                {
                     cmd.error(
                         ErrorKind::MissingRequiredArgument,
                         "--client-secret or --client-secret-stdin is required while used with `client-credentials` grant.",
@@ -193,7 +1043,10 @@ impl Args {
                     .exit();
                 }
 
-                if args.client_secret.is_none() && !args.client_secret_stdin {
+                if args.client_secret.is_none()
+                    && !args.client_secret_stdin
+                    && args.client_secret_from.is_none()
+                {
                     cmd.error(
                         ErrorKind::MissingRequiredArgument,
                         "--client-secret or --client-secret-stdin is required while used with `client-credentials` grant.",
@@ -201,7 +1054,98 @@ impl Args {
                         .exit();
                 }
             }
+            Grant::OktaSessionToken => {
+                Self::assert_urls_for_authorization_grants(args);
+
+                if args.username.is_none() {
+                    cmd.error(
+                        ErrorKind::MissingRequiredArgument,
+                        "--username is required while used with `okta-session-token` grant.",
+                    )
+                    .exit();
+                }
+
+                if args.password.is_none() && !args.password_stdin {
+                    cmd.error(
+                        ErrorKind::MissingRequiredArgument,
+                        "--password or --password-stdin is required while used with `okta-session-token` grant.",
+                    )
+                    .exit();
+                }
+            }
+            Grant::JwtBearer => {
+                if args.token_url.is_none() && args.discovery_url.is_none() {
+                    cmd.error(
+                        ErrorKind::MissingRequiredArgument,
+                        "<--token-url|--discovery-url> arguments have to be provided",
+                    )
+                    .exit();
+                }
+
+                if args.assertion_key_file.is_none() {
+                    cmd.error(
+                        ErrorKind::MissingRequiredArgument,
+                        "--assertion-key-file is required while used with `jwt-bearer` grant.",
+                    )
+                    .exit();
+                }
+            }
+            Grant::Saml2Bearer => {
+                if args.token_url.is_none() && args.discovery_url.is_none() {
+                    cmd.error(
+                        ErrorKind::MissingRequiredArgument,
+                        "<--token-url|--discovery-url> arguments have to be provided",
+                    )
+                    .exit();
+                }
+
+                if args.saml_assertion_file.is_none() && !args.saml_assertion_stdin {
+                    cmd.error(
+                        ErrorKind::MissingRequiredArgument,
+                        "--saml-assertion-file or --saml-assertion-stdin is required while used with `saml2-bearer` grant.",
+                    )
+                    .exit();
+                }
+            }
+            Grant::DeviceCode => {
+                if args.discovery_url.is_none()
+                    && (args.token_url.is_none() || args.device_authorization_url.is_none())
+                {
+                    cmd.error(
+                        ErrorKind::MissingRequiredArgument,
+                        "<--token-url, --device-authorization-url|--discovery-url> arguments have to be provided",
+                    )
+                    .exit();
+                }
+            }
+            // Checked again once the grant is detected
+            Grant::Auto => {}
+            Grant::TokenExchange => {
+                if args.token_url.is_none() && args.discovery_url.is_none() {
+                    cmd.error(
+                        ErrorKind::MissingRequiredArgument,
+                        "<--token-url|--discovery-url> arguments have to be provided",
+                    )
+                    .exit();
+                }
+
+                if args.upstream_profile.is_none() {
+                    cmd.error(
+                        ErrorKind::MissingRequiredArgument,
+                        "--upstream-profile is required while used with `token-exchange` grant.",
+                    )
+                    .exit();
+                }
+            }
             Grant::Implicit { .. } => {
+                if args.browser_cmd.is_some() {
+                    cmd.error(
+                        ErrorKind::ArgumentConflict,
+                        "--browser-cmd cannot be used with:\n\t--grant implicit",
+                    )
+                    .exit();
+                }
+
                 if args.token_url.is_some() {
                     cmd.error(
                         ErrorKind::ArgumentConflict,
@@ -229,39 +1173,92 @@ impl Args {
         }
     }
 
-    fn parse_client_secret(mut args: Arguments) -> Arguments {
+    fn prompt(args: &Arguments, label: &str) -> String {
+        prompt_secret(&args.prompt_options(), label).unwrap_or_else(|e| {
+            if e.is::<InputRequired>() {
+                output::error(&e);
+                std::process::exit(InteractionRequired::EXIT_CODE);
+            }
+
+            Arguments::command()
+                .error(ErrorKind::Io, format!("{:#}", e))
+                .exit()
+        })
+    }
+
+    async fn parse_client_secret(mut args: Arguments) -> Arguments {
         if args.client_secret.is_some() && std::env::var("DOKEN_CLIENT_SECRET").is_err() {
-            eprintln!("Please use `--client-secret-stdin` as a more secure variant.");
+            output::warning("Please use `--client-secret-stdin` as a more secure variant.");
         }
 
         if args.client_secret_stdin {
-            args.client_secret = Some(rpassword::prompt_password("Client Secret: ").unwrap());
+            args.client_secret = Some(Self::prompt(&args, "Client Secret"));
         }
 
-        args
+        args.with_client_secret_from().await.unwrap_or_else(|e| {
+            Arguments::command()
+                .error(ErrorKind::InvalidValue, format!("{:#}", e))
+                .exit()
+        })
+    }
+
+    async fn parse_scope(args: Arguments) -> Arguments {
+        if !args.scope.contains('@') {
+            return args;
+        }
+
+        let config = ConfigFile::new().read().await;
+        args.with_scope_aliases(&config.scopes).unwrap_or_else(|e| {
+            Arguments::command()
+                .error(ErrorKind::InvalidValue, format!("{:#}", e))
+                .exit()
+        })
     }
 
     fn parse_password(mut args: Arguments) -> Arguments {
         if args.password.is_some() && std::env::var("DOKEN_PASSWORD").is_err() {
-            eprintln!("Please use `--password-stdin` as a more secure variant.");
+            output::warning("Please use `--password-stdin` as a more secure variant.");
         }
 
         if args.password_stdin {
-            args.password = Some(rpassword::prompt_password("Password: ").unwrap());
+            args.password = Some(Self::prompt(&args, "Password"));
+        }
+
+        args
+    }
+
+    fn parse_otp_secret(mut args: Arguments) -> Arguments {
+        if args.otp_secret.is_some() && std::env::var("DOKEN_OTP_SECRET").is_err() {
+            output::warning("Please use `--otp-stdin` as a more secure variant.");
+        }
+
+        if args.otp_stdin {
+            args.otp_secret = Some(Self::prompt(&args, "OTP secret"));
         }
 
         args
     }
 
-    async fn apply_profile() {
+    async fn apply_profile(profile: Option<String>) {
         let mut cmd: Command = Arguments::command();
         let args: Vec<String> = env::args().collect();
-        let profile = match args.iter().position(|arg| arg.eq("--profile")) {
-            Some(profile_pos) => args.get(profile_pos + 1).cloned(),
-            None => None,
-        };
 
         let config = ConfigFile::new().apply_profile(profile.clone()).await;
+        systemd::apply_credentials(profile.as_deref());
+
+        #[cfg(feature = "kubernetes")]
+        {
+            let reference = match args.iter().position(|arg| arg.eq("--from-k8s-secret")) {
+                Some(reference_pos) => args.get(reference_pos + 1).cloned(),
+                None => env::var("DOKEN_FROM_K8S_SECRET").ok(),
+            };
+
+            let applied = reference.map(|reference| kubernetes::apply(&reference));
+            if let Some(Err(e)) = applied {
+                cmd.error(ErrorKind::InvalidValue, format!("{:#}", e))
+                    .exit();
+            }
+        }
 
         if config.is_err() {
             cmd.error(
@@ -275,6 +1272,65 @@ impl Args {
         }
     }
 
+    /// Validates arguments of the chosen grant and reads secrets from stdin. Commands flattening [`Arguments`] have to call it on their own
+    pub async fn prepare(args: Arguments) -> Arguments {
+        let args = Self::parse_scope(args).await;
+        Self::assert_grant_specific_arguments(&args);
+
+        // Secrets aren't needed to read the cache and asking for them would block an editor
+        if args.silent_fail_cache_only {
+            return args;
+        }
+
+        let args = Self::parse_client_secret(args).await;
+        let args = Self::parse_password(args);
+
+        Self::parse_otp_secret(args)
+    }
+
+    /// Profiles of all `--profile` arguments. More than one fetches a token of each of them
+    pub fn profiles() -> Vec<String> {
+        let args: Vec<String> = env::args().collect();
+
+        Self::split_profiles(&args).0
+    }
+
+    /// Profiles of `--profile <name>` and `--profile=<name>` arguments and the other arguments
+    fn split_profiles(args: &[String]) -> (Vec<String>, Vec<String>) {
+        let mut profiles = vec![];
+        let mut rest = vec![];
+        let mut args = args.iter();
+
+        while let Some(arg) = args.next() {
+            if arg == "--profile" {
+                profiles.extend(args.next().cloned());
+            } else if let Some(profile) = arg.strip_prefix("--profile=") {
+                profiles.push(profile.to_owned());
+            } else {
+                rest.push(arg.to_owned());
+            }
+        }
+
+        (profiles, rest)
+    }
+
+    pub async fn parse_command() -> Option<DokenCommand> {
+        let name = env::args().nth(1)?;
+
+        if !DokenCommand::has_subcommand(&name) {
+            return None;
+        }
+
+        log::debug!("Parsing `{}` command arguments...", name);
+        dotenv().ok();
+        // A half typed `--profile` being completed isn't defined yet
+        if name != "__complete" {
+            Self::apply_profile(Self::profiles().into_iter().next()).await;
+        }
+
+        Some(CommandArguments::parse().command)
+    }
+
     pub async fn parse() -> Arguments {
         log::debug!("Parsing application arguments...");
         if dotenv().is_ok() {
@@ -283,12 +1339,50 @@ impl Args {
             log::debug!(".env file not found. skipping...");
         }
 
-        Self::apply_profile().await;
+        Self::apply_profile(Self::profiles().into_iter().next()).await;
 
-        let args = Arguments::parse();
-        Self::assert_grant_specific_arguments(&args);
-        let mut args = Self::parse_client_secret(args);
-        args = Self::parse_password(args);
+        Self::parse_from(env::args()).await
+    }
+
+    /// Arguments of every profile of a run with several `--profile`s. Each is parsed as if it was
+    /// the only `--profile`, so the other arguments and environment variables apply to all of them
+    pub async fn parse_profiles(profiles: &[String]) -> Vec<Arguments> {
+        dotenv().ok();
+        let (_, args) = Self::split_profiles(&env::args().collect::<Vec<String>>());
+        let mut profile_args = vec![];
+
+        for profile in profiles {
+            // Variables set by the profile mustn't leak into the next one
+            let vars: HashMap<_, _> = env::vars_os().collect();
+            Self::apply_profile(Some(profile.to_owned())).await;
+
+            let args = [
+                &args[..1],
+                &["--profile".to_owned(), profile.to_owned()],
+                &args[1..],
+            ]
+            .concat();
+            profile_args.push(Self::parse_from(args).await);
+
+            for (key, _) in env::vars_os() {
+                if !vars.contains_key(&key) {
+                    unsafe { env::remove_var(key) }
+                }
+            }
+            for (key, value) in vars {
+                unsafe { env::set_var(key, value) }
+            }
+        }
+
+        profile_args
+    }
+
+    async fn parse_from(args: impl IntoIterator<Item = String>) -> Arguments {
+        // Commands are already handled by `parse_command`. They're attached only to be listed in `--help`
+        let matches =
+            DokenCommand::augment_subcommands(Arguments::command()).get_matches_from(args);
+        let args = Arguments::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+        let args = Self::prepare(args).await;
 
         log::debug!("Argument parsing done");
         log::debug!("Running with arguments: {:#?}", args);
@@ -296,3 +1390,23 @@ impl Args {
         args
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_splits_profiles_off_the_arguments() {
+        let args: Vec<String> = ["doken", "--profile", "a", "--profile=b", "--force"]
+            .map(str::to_owned)
+            .to_vec();
+
+        assert_eq!(
+            Args::split_profiles(&args),
+            (
+                vec!["a".to_owned(), "b".to_owned()],
+                vec!["doken".to_owned(), "--force".to_owned()]
+            )
+        );
+    }
+}
@@ -1,62 +1,122 @@
-use anyhow::{Result, anyhow};
+use anyhow::{Context, Result, anyhow};
 use chromiumoxide::browser::{Browser as CBrowser, BrowserConfig};
 use chromiumoxide::cdp::browser_protocol::target::CreateTargetParamsBuilder;
 use chromiumoxide::handler::viewport::Viewport;
 use chromiumoxide::{Handler, Page as CPage};
 use futures::StreamExt;
+use rand::Rng;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
 use std::time::Duration;
-use tokio::sync::{OnceCell, oneshot};
+use std::{env, fs};
+use tokio::sync::{Mutex, MutexGuard, watch};
 
 use super::page::Page;
+use crate::commands::cleanup;
+use crate::output;
+use crate::progress;
 
-pub struct Browser {
-    browser: OnceCell<CBrowser>,
+/// Prefix of Chromium profiles of doken, which tells doken's Chromium processes apart from others
+pub const USER_DATA_DIR_PREFIX: &str = "doken-";
+/// Prefix of per-run profiles of `--ephemeral-browser-profile`
+pub const EPHEMERAL_USER_DATA_DIR_PREFIX: &str = "doken-ephemeral-";
+/// Per-run profiles left behind by killed runs are removed after that long, unless a browser still uses them
+const STALE_EPHEMERAL_PROFILE_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// File of a Chromium profile holding the pid of the doken run that launched the browser
+const OWNER_FILE: &str = "doken-owner.pid";
+
+/// Chromium profile of doken. Keeps IdP sessions between runs
+pub fn user_data_dir() -> PathBuf {
+    env::temp_dir().join(format!("{}browser", USER_DATA_DIR_PREFIX))
+}
+
+/// Unique profile of a single run, so parallel runs don't fight over Chromium's profile lock
+fn ephemeral_user_data_dir() -> PathBuf {
+    env::temp_dir().join(format!(
+        "{}{}-{:08x}",
+        EPHEMERAL_USER_DATA_DIR_PREFIX,
+        std::process::id(),
+        rand::rng().random::<u32>()
+    ))
+}
+
+/// Pid of the doken run that launched the browser of the profile, `None` for profiles of older versions
+pub fn owner(user_data_dir: &Path) -> Option<u32> {
+    fs::read_to_string(user_data_dir.join(OWNER_FILE))
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Chromium launched on the first use and launched again after it crashed
+pub(crate) struct Launcher {
     headless: bool,
+    user_data_dir: PathBuf,
+    launched: Mutex<Option<Launched>>,
 }
 
-impl Browser {
-    pub fn new(headless: bool) -> Self {
-        Browser {
-            browser: OnceCell::new(),
-            headless,
+struct Launched {
+    browser: CBrowser,
+    /// Turns true once the handler of the browser fails, ex. after a crash of Chromium
+    crashed: watch::Receiver<bool>,
+}
+
+impl Launcher {
+    async fn launched(&self) -> Result<MutexGuard<'_, Option<Launched>>> {
+        let mut launched = self.launched.lock().await;
+
+        match launched.as_ref() {
+            Some(Launched { crashed, .. }) if *crashed.borrow() => {
+                output::warning("The browser crashed. Starting it again");
+                // Stops what's left of the previous Chromium, which holds the profile lock
+                launched.take();
+            }
+            Some(_) => return Ok(launched),
+            None => {}
         }
-    }
 
-    pub async fn open_page(&self) -> Result<Page> {
-        let browser_page = self.lazy_open_page().await?;
-        let page = Page::new(browser_page);
-        Ok(page)
+        cleanup::remove_stale_ephemeral_profiles(STALE_EPHEMERAL_PROFILE_AGE);
+        progress::notify(|observer| observer.on_browser_launch(self.headless));
+        let (browser, mut handler) =
+            Browser::launch_browser(self.headless, self.user_data_dir.clone()).await?;
+        let (crashed_tx, crashed) = watch::channel(false);
+
+        tokio::spawn(async move {
+            while let Some(event) = handler.next().await {
+                if let Err(e) = event {
+                    log::error!("Browser failed: {}", e);
+                    break;
+                }
+            }
+
+            // The handler ends together with the connection to Chromium too
+            log::debug!("Browser handler stopped");
+            let _ = crashed_tx.send(true);
+        });
+
+        *launched = Some(Launched { browser, crashed });
+        Ok(launched)
     }
 
-    pub async fn browser(&self) -> &CBrowser {
-        self.browser
-            .get_or_init(|| async {
-                let (tx, _) = oneshot::channel::<()>();
-
-                let (browser, mut handler) = Self::launch_browser(self.headless).await.unwrap();
-
-                tokio::spawn(async move {
-                    while let Some(h) = handler.next().await {
-                        if h.is_err() {
-                            log::error!("Browser failed: {}", h.err().unwrap());
-                            tx.send(()).unwrap();
-                            log::error!("Handler created an error");
-                            break;
-                        }
-                    }
-                });
-                browser
-            })
-            .await
+    /// Blank page of the browser and the signal of its crash
+    pub(crate) async fn open_page(&self) -> Result<(CPage, watch::Receiver<bool>)> {
+        let launched = self.launched().await?;
+        let Launched { browser, crashed } = launched.as_ref().context("Browser isn't running")?;
+
+        Ok((Self::lazy_open_page(browser).await?, crashed.clone()))
     }
 
-    pub async fn pages(&self) -> Result<Vec<CPage>> {
-        self.browser().await.pages().await.map_err(|e| anyhow!(e))
+    async fn pages(&self) -> Result<Vec<CPage>> {
+        let launched = self.launched().await?;
+        let Launched { browser, .. } = launched.as_ref().context("Browser isn't running")?;
+
+        browser.pages().await.map_err(|e| anyhow!(e))
     }
 
-    async fn lazy_open_page(&self) -> Result<CPage> {
-        let browser = self.browser().await;
-        let page = self.wait_for_first_page(browser).await?;
+    async fn lazy_open_page(browser: &CBrowser) -> Result<CPage> {
+        let page = Self::wait_for_first_page(browser).await?;
 
         let create_new_page = || async {
             let page_config = CreateTargetParamsBuilder::default()
@@ -78,7 +138,7 @@ impl Browser {
         }
     }
 
-    async fn wait_for_first_page(&self, browser: &CBrowser) -> Result<CPage> {
+    async fn wait_for_first_page(browser: &CBrowser) -> Result<CPage> {
         let mut retries = 10;
 
         loop {
@@ -106,8 +166,52 @@ impl Browser {
             }
         }
     }
+}
 
-    async fn launch_browser(headless: bool) -> Result<(CBrowser, Handler)> {
+pub struct Browser {
+    launcher: OnceLock<Arc<Launcher>>,
+    headless: bool,
+    ephemeral_profile: Option<PathBuf>,
+}
+
+impl Browser {
+    pub fn new(headless: bool) -> Self {
+        Browser {
+            launcher: OnceLock::new(),
+            headless,
+            ephemeral_profile: None,
+        }
+    }
+
+    /// Launches Chromium with a new profile removed when the browser is dropped, instead of the persistent one
+    pub fn with_ephemeral_profile(mut self, ephemeral: bool) -> Self {
+        self.ephemeral_profile = ephemeral.then(ephemeral_user_data_dir);
+        self
+    }
+
+    fn launcher(&self) -> &Arc<Launcher> {
+        self.launcher.get_or_init(|| {
+            Arc::new(Launcher {
+                headless: self.headless,
+                user_data_dir: self.ephemeral_profile.clone().unwrap_or_else(user_data_dir),
+                launched: Mutex::new(None),
+            })
+        })
+    }
+
+    /// Page of the browser. Its flow is resumed in a new browser once, if Chromium crashes meanwhile
+    pub async fn open_page(&self) -> Result<Page> {
+        let launcher = self.launcher().clone();
+        let (browser_page, crashed) = launcher.open_page().await?;
+
+        Ok(Page::new(browser_page).with_launcher(launcher, crashed))
+    }
+
+    pub async fn pages(&self) -> Result<Vec<CPage>> {
+        self.launcher().pages().await
+    }
+
+    async fn launch_browser(headless: bool, user_data_dir: PathBuf) -> Result<(CBrowser, Handler)> {
         log::debug!("Opening chromium instance");
         const WIDTH: u32 = 800;
         const HEIGHT: u32 = 1000;
@@ -117,6 +221,13 @@ impl Browser {
             ..Viewport::default()
         };
 
+        // `doken cleanup` stops the browser only once this run is gone
+        fs::create_dir_all(&user_data_dir)?;
+        fs::write(
+            user_data_dir.join(OWNER_FILE),
+            std::process::id().to_string(),
+        )?;
+
         let mut config = BrowserConfig::builder();
 
         if !headless {
@@ -126,6 +237,7 @@ impl Browser {
         config = config
             .viewport(viewport)
             .window_size(WIDTH, HEIGHT)
+            .user_data_dir(user_data_dir)
             .enable_request_intercept()
             .respect_https_errors()
             .enable_cache();
@@ -144,3 +256,31 @@ impl Browser {
         Ok(())
     }
 }
+
+impl Drop for Browser {
+    fn drop(&mut self) {
+        let Some(profile) = &self.ephemeral_profile else {
+            return;
+        };
+
+        // Chromium is killed together with its handle, but may still hold files of the profile for a moment
+        let launched = self
+            .launcher
+            .get()
+            .and_then(|launcher| launcher.launched.try_lock().ok());
+        if let Some(mut launched) = launched {
+            launched.take();
+        }
+        for _ in 0..10 {
+            if !profile.exists() || fs::remove_dir_all(profile).is_ok() {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+
+        log::debug!(
+            "Cannot remove {} yet. It's removed by `doken cleanup` or a later run",
+            profile.display()
+        );
+    }
+}
@@ -1,2 +1,94 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use oauth2::CsrfToken;
+use thiserror::Error;
+use url::Url;
+
 pub mod browser;
 pub mod page;
+pub mod system_browser;
+#[cfg(all(feature = "webview", any(target_os = "macos", windows)))]
+pub mod webview;
+
+/// Parameters of the callback answering an authorization request
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuthorizationResponse {
+    pub code: String,
+    /// Issuer of the code <https://www.rfc-editor.org/rfc/rfc9207>
+    pub iss: Option<String>,
+    /// Session of the IdP the code was issued in <https://openid.net/specs/openid-connect-session-1_0.html>
+    pub session_state: Option<String>,
+}
+
+/// Lets the user authorize and returns the response sent to the callback url
+#[async_trait(?Send)]
+pub trait CodeSource {
+    async fn get_code(
+        &self,
+        timeout: u64,
+        authorization_url: Url,
+        callback_url: Url,
+        csrf_token: CsrfToken,
+    ) -> Result<AuthorizationResponse>;
+}
+
+/// Callback not answering the authorization request of this flow. Ignored, unless `--strict-csrf` is set
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum StateError {
+    #[error("The callback has no `state` parameter")]
+    Missing,
+
+    #[error("The `state` parameter of the callback doesn't match the authorization request")]
+    Incorrect,
+}
+
+/// Callback the flow cannot continue with
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum CallbackError {
+    #[error(transparent)]
+    State(#[from] StateError),
+
+    #[error("The callback body is bigger than {0} bytes")]
+    TooLarge(usize),
+
+    #[error("The callback body cannot be read")]
+    Unreadable,
+
+    #[error("The callback has no `{0}` parameter")]
+    MissingField(&'static str),
+
+    #[error("`{0}` of the callback is not a number: `{1}`")]
+    InvalidNumber(&'static str, String),
+}
+
+impl CallbackError {
+    /// Malformed callbacks are answered with 400, unlike ones of other flows
+    pub fn is_malformed(&self) -> bool {
+        !matches!(self, CallbackError::State(_))
+    }
+}
+
+pub fn verify_state(state: Option<&str>, csrf_token: &CsrfToken) -> Result<(), StateError> {
+    match state {
+        Some(state) if state == csrf_token.secret() => Ok(()),
+        Some(_) => Err(StateError::Incorrect),
+        None => Err(StateError::Missing),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_verifies_state_of_the_callback() {
+        let csrf_token = CsrfToken::new("xyz".to_owned());
+
+        assert_eq!(verify_state(Some("xyz"), &csrf_token), Ok(()));
+        assert_eq!(
+            verify_state(Some("abc"), &csrf_token),
+            Err(StateError::Incorrect)
+        );
+        assert_eq!(verify_state(None, &csrf_token), Err(StateError::Missing));
+    }
+}
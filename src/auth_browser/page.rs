@@ -1,19 +1,36 @@
+use super::browser::Launcher;
+use super::{AuthorizationResponse, CallbackError, CodeSource, verify_state};
+use crate::har::{Har, ReceivedResponse, SentRequest};
+use crate::output;
+use crate::permissions;
+use crate::progress;
 use crate::token_info::TokenInfo;
 use anyhow::{Result, anyhow};
+use async_trait::async_trait;
 use base64::Engine;
 use base64::prelude::BASE64_STANDARD;
 use chromiumoxide::Page as CPage;
 use chromiumoxide::cdp::browser_protocol::fetch::{
     ContinueRequestParams, EventRequestPaused, FulfillRequestParams,
 };
+use chromiumoxide::cdp::browser_protocol::network::{
+    EventRequestWillBeSent, EventResponseReceived, Headers, Request as NetworkRequest,
+    Response as NetworkResponse, SetExtraHttpHeadersParams,
+};
+use chromiumoxide::cdp::browser_protocol::page::{
+    AddScriptToEvaluateOnNewDocumentParams, EventLoadEventFired,
+};
+use chromiumoxide::page::ScreenshotParams;
 use futures::StreamExt;
 use oauth2::CsrfToken;
+use serde_json::Value;
 use std::borrow::Cow;
 use std::ops::Add;
-use std::sync::Arc;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, SystemTime};
 use thiserror::Error;
-use tokio::sync::oneshot;
+use tokio::sync::{oneshot, watch};
 use tokio::time::sleep;
 use url::Url;
 
@@ -25,78 +42,576 @@ enum RequestError {
     // TODO: Implement channels to all pages to close them
     #[error("The user closed the browser")]
     _BrowserClosed,
+
+    #[error("The browser crashed during the authorization")]
+    BrowserCrashed,
 }
 
 const CONTENT_OK: &str = "<html><head></head><body><h1>OK</h1></body></html>";
 const CONTENT_NOT_OK: &str = "<html><head></head><body><h1>NOT OK</h1></body></html>";
+/// `form_post` responses carry a few tokens. Bigger bodies aren't decoded
+const MAX_FORM_POST_SIZE: usize = 256 * 1024;
+// Fragment never reaches a server, so it's sent back as a query parameter to be echoed too
+const CONTENT_ECHO: &str = "<html><head><script>if (location.hash.length > 1) { fetch(location.origin + location.pathname + '?doken_fragment=' + encodeURIComponent(location.hash.substring(1))); }</script></head><body><h1>ECHO</h1><p>Check doken output for request details</p></body></html>";
+
+/// How a request made to the callback origin is answered
+#[derive(Debug, PartialEq)]
+enum CallbackRoute {
+    /// The configured path and method, the only request completing the flow
+    Callback,
+    /// CORS preflight, answered with 204
+    Preflight,
+    /// Any other request ex. `/favicon.ico` or `HEAD`, answered with 404
+    NotFound,
+    /// Not the callback origin, passed to the network
+    Continue,
+}
+
+fn route(
+    method: &str,
+    request_url: &Url,
+    callback_url: &Url,
+    callback_method: &str,
+) -> CallbackRoute {
+    if request_url.origin() != callback_url.origin() {
+        CallbackRoute::Continue
+    } else if request_url.path() == callback_url.path() && method == callback_method {
+        CallbackRoute::Callback
+    } else if method == "OPTIONS" {
+        CallbackRoute::Preflight
+    } else {
+        CallbackRoute::NotFound
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct PageOptions {
+    /// Prints every request made to the callback origin without validating it
+    pub echo_callback: bool,
+    /// Directory a screenshot and the url of the page are saved to when the flow fails
+    pub debug_screenshot: Option<PathBuf>,
+    /// HAR file the network activity of the flow is written to
+    pub capture_har: Option<PathBuf>,
+    /// Keeps cookies, credentials, codes and tokens in the HAR file
+    pub har_include_secrets: bool,
+    /// Fails the flow on the first callback with a wrong or missing state instead of waiting for another one
+    pub strict_csrf: bool,
+    /// Headers sent with every request of the page, ex. device identification for conditional access
+    pub extra_headers: Vec<(String, String)>,
+}
+
+enum NetworkEvent {
+    Request(Arc<EventRequestWillBeSent>),
+    Response(Arc<EventResponseReceived>),
+}
+
+fn post_data(request: &NetworkRequest) -> Option<String> {
+    let bytes: Vec<u8> = request
+        .post_data_entries
+        .as_ref()?
+        .iter()
+        .filter_map(|entry| entry.bytes.as_ref())
+        .filter_map(|bytes| BASE64_STANDARD.decode(AsRef::<str>::as_ref(bytes)).ok())
+        .flatten()
+        .collect();
+
+    Some(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+fn received<'a>(
+    id: &'a str,
+    timestamp: f64,
+    response: &'a NetworkResponse,
+) -> ReceivedResponse<'a> {
+    ReceivedResponse {
+        id,
+        timestamp,
+        status: response.status,
+        status_text: &response.status_text,
+        protocol: response.protocol.as_deref(),
+        headers: response.headers.inner(),
+        mime_type: &response.mime_type,
+    }
+}
+
+/// Keeps SAML POST binding forms in the page, as IdP chains bridged with SAML (PingFederate,
+/// Shibboleth, ...) may post them to a new window the flow never sees
+const SAML_FORM_TARGET: &str = "document.addEventListener('DOMContentLoaded', () => { for (const form of document.forms) { if (form.querySelector('input[name=SAMLResponse], input[name=SAMLRequest]')) { form.removeAttribute('target'); } } });";
+
+/// Submits a SAML POST binding form the page didn't submit on its own, ex. because it relies on
+/// a button in `<noscript>`. Returns the submitted message and where it's sent to
+const SAML_AUTO_SUBMIT: &str = "(() => { const form = Array.from(document.forms).find((form) => form.querySelector('input[name=SAMLResponse], input[name=SAMLRequest]')); if (!form || form.dataset.dokenSubmitted) { return null; } form.dataset.dokenSubmitted = 'true'; form.removeAttribute('target'); HTMLFormElement.prototype.submit.call(form); return (form.querySelector('input[name=SAMLResponse]') ? 'SAMLResponse' : 'SAMLRequest') + ' to ' + form.action; })()";
+
+/// Time a page gets to submit a SAML form on its own
+const SAML_AUTO_SUBMIT_DELAY: Duration = Duration::from_millis(1500);
+
+/// Completes SAML hops of the flow until the returned task is aborted
+async fn submit_saml_post_bindings(page: &CPage) -> Result<tokio::task::JoinHandle<()>> {
+    page.evaluate_on_new_document(AddScriptToEvaluateOnNewDocumentParams::new(
+        SAML_FORM_TARGET,
+    ))
+    .await?;
+    let mut loads = page.event_listener::<EventLoadEventFired>().await?;
+    let page = page.clone();
+
+    Ok(tokio::spawn(async move {
+        while loads.next().await.is_some() {
+            sleep(SAML_AUTO_SUBMIT_DELAY).await;
+
+            let submitted = match page.evaluate(SAML_AUTO_SUBMIT).await {
+                Ok(result) => result.into_value::<Option<String>>().ok().flatten(),
+                Err(e) => {
+                    // The page navigated meanwhile, so there's nothing stalled
+                    log::debug!("Cannot look for SAML forms: {}", e);
+                    None
+                }
+            };
+
+            if let Some(submitted) = submitted {
+                output::status("Submitting", &format!("SAML POST binding {}", submitted));
+            }
+        }
+    }))
+}
+
+async fn set_extra_headers(page: &CPage, headers: &[(String, String)]) -> Result<()> {
+    if headers.is_empty() {
+        return Ok(());
+    }
+
+    let headers = headers
+        .iter()
+        .map(|(name, value)| (name.to_owned(), Value::String(value.to_owned())))
+        .collect();
+    page.execute(SetExtraHttpHeadersParams::new(Headers::new(Value::Object(
+        headers,
+    ))))
+    .await?;
+
+    Ok(())
+}
+
+/// Records `Network` domain events of the page until the returned task is aborted
+async fn record_har(
+    page: &CPage,
+    redact: bool,
+) -> Result<(Arc<Mutex<Har>>, tokio::task::JoinHandle<()>)> {
+    let requests = page
+        .event_listener::<EventRequestWillBeSent>()
+        .await?
+        .map(NetworkEvent::Request);
+    let responses = page
+        .event_listener::<EventResponseReceived>()
+        .await?
+        .map(NetworkEvent::Response);
+    let mut events = futures::stream::select(requests, responses);
+
+    let har = Arc::new(Mutex::new(Har::new(redact)));
+    let recorded = har.clone();
+    let handle = tokio::spawn(async move {
+        while let Some(event) = events.next().await {
+            let mut har = recorded.lock().unwrap();
+
+            match event {
+                NetworkEvent::Request(event) => {
+                    let id = event.request_id.inner();
+                    let timestamp = *event.timestamp.inner();
+                    if let Some(redirect) = &event.redirect_response {
+                        har.response(received(id, timestamp, redirect));
+                    }
+
+                    har.request(SentRequest {
+                        id,
+                        time: SystemTime::UNIX_EPOCH
+                            + Duration::from_secs_f64(event.wall_time.inner().max(0.0)),
+                        timestamp,
+                        method: &event.request.method,
+                        url: &event.request.url,
+                        headers: event.request.headers.inner(),
+                        post_data: post_data(&event.request),
+                    });
+                }
+                NetworkEvent::Response(event) => {
+                    har.response(received(
+                        event.request_id.inner(),
+                        *event.timestamp.inner(),
+                        &event.response,
+                    ));
+                }
+            }
+        }
+    });
+
+    Ok((har, handle))
+}
+
+/// Saves what the IdP was showing when the flow failed, ex. an unexpected consent or error page.
+/// It may show personal data or codes, so only the current user may read it
+async fn save_failure(page: &CPage, dir: &Path) -> Result<PathBuf> {
+    permissions::create_private_dir(dir)?;
+
+    let name = format!(
+        "failure-{}",
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+    );
+    let url = page.url().await?.unwrap_or_default();
+    let screenshot = page
+        .screenshot(ScreenshotParams::builder().full_page(true).build())
+        .await?;
+
+    let screenshot_path = dir.join(format!("{}.png", name));
+    permissions::write_private(&screenshot_path, &screenshot)?;
+    permissions::write_private(
+        &dir.join(format!("{}.url", name)),
+        format!("{}\n", url).as_bytes(),
+    )?;
+
+    Ok(screenshot_path)
+}
 
 pub struct Page {
     page: CPage,
+    options: PageOptions,
+    /// Launches the browser again after a crash, so the flow can be resumed on a new page
+    launcher: Option<Arc<Launcher>>,
+    crashed: Option<watch::Receiver<bool>>,
+}
+
+fn decode_post_data(event: &EventRequestPaused) -> Option<Vec<u8>> {
+    let entries = event.request.post_data_entries.as_ref()?;
+
+    Some(
+        entries
+            .iter()
+            .filter_map(|entry| {
+                BASE64_STANDARD
+                    .decode(entry.bytes.as_ref()?.as_ref() as &[u8])
+                    .ok()
+            })
+            .collect::<Vec<Vec<u8>>>()
+            .join("&".as_bytes()),
+    )
+}
+
+/// Page of a 400 answer, telling why the callback was rejected
+fn bad_request(error: &CallbackError) -> String {
+    format!(
+        "<html><head></head><body><h1>BAD REQUEST</h1><p>{}</p></body></html>",
+        html_escape(&error.to_string())
+    )
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Body of the `form_post` callback, no bigger than `MAX_FORM_POST_SIZE`
+fn form_post_body(event: &EventRequestPaused) -> Result<Vec<u8>, CallbackError> {
+    let Some(entries) = event.request.post_data_entries.as_ref() else {
+        // Chromium leaves out bodies it didn't keep, ex. too big ones
+        return match event.request.has_post_data {
+            Some(true) => Err(CallbackError::Unreadable),
+            _ => Ok(vec![]),
+        };
+    };
+
+    let encoded_size: usize = entries
+        .iter()
+        .filter_map(|entry| entry.bytes.as_ref())
+        .map(|bytes| AsRef::<str>::as_ref(bytes).len())
+        .sum();
+    if encoded_size / 4 * 3 > MAX_FORM_POST_SIZE {
+        return Err(CallbackError::TooLarge(MAX_FORM_POST_SIZE));
+    }
+
+    entries
+        .iter()
+        .map(|entry| {
+            let bytes = entry.bytes.as_ref().ok_or(CallbackError::Unreadable)?;
+            BASE64_STANDARD
+                .decode(AsRef::<str>::as_ref(bytes))
+                .map_err(|_| CallbackError::Unreadable)
+        })
+        .collect::<Result<Vec<Vec<u8>>, CallbackError>>()
+        .map(|entries| entries.join("&".as_bytes()))
+}
+
+/// Token of the implicit grant's `form_post` callback
+fn form_post_token(body: &[u8], csrf_token: &CsrfToken) -> Result<TokenInfo, CallbackError> {
+    let form_params = form_urlencoded::parse(body).collect::<Vec<(Cow<str>, Cow<str>)>>();
+    let param = |name: &'static str| {
+        form_params
+            .iter()
+            .find(|(param, _value)| param == name)
+            .map(|(_, value)| value.as_ref())
+    };
+
+    verify_state(param("state"), csrf_token)?;
+
+    let access_token = param("access_token").ok_or(CallbackError::MissingField("access_token"))?;
+    let expires_in = param("expires_in").ok_or(CallbackError::MissingField("expires_in"))?;
+    let expires_in = expires_in
+        .parse::<u64>()
+        .map_err(|_| CallbackError::InvalidNumber("expires_in", expires_in.to_owned()))?;
+
+    let extra = form_params
+        .iter()
+        .filter(|(name, _value)| !["access_token", "expires_in", "state"].contains(&name.as_ref()))
+        .map(|(name, value)| (name.to_string(), Value::String(value.to_string())))
+        .collect();
+
+    Ok(TokenInfo {
+        access_token: access_token.to_owned(),
+        refresh_token: None,
+        expires: Some(SystemTime::now().add(Duration::from_secs(expires_in))),
+        scope: None,
+        requested_scope: None,
+        extra,
+    })
+}
+
+fn echo_request(event: &EventRequestPaused, request_url: &Url) {
+    output::status(
+        "Callback request",
+        &format!("{} {}", event.request.method, request_url),
+    );
+
+    let query = request_url
+        .query_pairs()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect::<Vec<(String, String)>>();
+    let form = decode_post_data(event)
+        .map(|body| {
+            form_urlencoded::parse(body.as_slice())
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect::<Vec<(String, String)>>()
+        })
+        .unwrap_or_default();
+
+    for (section, params) in [("query", query), ("form", form)] {
+        if params.is_empty() {
+            continue;
+        }
+
+        let pairs = params
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.to_owned()))
+            .collect::<Vec<(&str, String)>>();
+        eprintln!("{}:\n{}", section, output::key_values(&pairs));
+    }
+}
+
+fn is_crashed(crashed: &Option<watch::Receiver<bool>>) -> bool {
+    crashed.as_ref().is_some_and(|crashed| *crashed.borrow())
+}
+
+/// Resolves once the browser crashed. Never resolves for pages without a crash signal
+async fn wait_for_crash(crashed: Option<watch::Receiver<bool>>) {
+    match crashed {
+        Some(mut crashed) => {
+            if crashed.wait_for(|crashed| *crashed).await.is_err() {
+                // The handler task is gone without signalling, so the browser is too
+                log::debug!("Browser handler dropped its crash signal");
+            }
+        }
+        None => std::future::pending().await,
+    }
 }
 
 impl Page {
     pub fn new(page: CPage) -> Self {
-        Page { page }
+        Page {
+            page,
+            options: PageOptions::default(),
+            launcher: None,
+            crashed: None,
+        }
+    }
+
+    pub(crate) fn with_launcher(
+        mut self,
+        launcher: Arc<Launcher>,
+        crashed: watch::Receiver<bool>,
+    ) -> Self {
+        self.launcher = Some(launcher);
+        self.crashed = Some(crashed);
+        self
+    }
+
+    pub fn with_options(mut self, options: PageOptions) -> Self {
+        self.options = options;
+        self
     }
 
+    /// Runs the flow and resumes it once from the authorization url in a new browser, if Chromium crashes meanwhile
     async fn process_request<TResponse, F>(
         &self,
         timeout: u64,
         authorization_url: Url,
         callback_url: Url,
+        callback_method: &'static str,
+        f: F,
+    ) -> Result<TResponse>
+    where
+        TResponse: Send + Clone + Sync + 'static,
+        F: Send
+            + Clone
+            + Fn(Arc<EventRequestPaused>) -> Result<Option<TResponse>, CallbackError>
+            + 'static,
+    {
+        let result = self
+            .process_request_on(
+                &self.page,
+                self.crashed.clone(),
+                timeout,
+                authorization_url.to_owned(),
+                callback_url.to_owned(),
+                callback_method,
+                f.clone(),
+            )
+            .await;
+
+        match (result, &self.launcher) {
+            (Err(e), Some(launcher)) if e.is::<RequestError>() && is_crashed(&self.crashed) => {
+                log::debug!("Resuming the flow after: {:#}", e);
+                let (page, crashed) = launcher.open_page().await?;
+                output::status("Resuming", "the authorization in a new browser");
+
+                self.process_request_on(
+                    &page,
+                    Some(crashed),
+                    timeout,
+                    authorization_url,
+                    callback_url,
+                    callback_method,
+                    f,
+                )
+                .await
+            }
+            (result, _) => result,
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn process_request_on<TResponse, F>(
+        &self,
+        page: &CPage,
+        crashed: Option<watch::Receiver<bool>>,
+        timeout: u64,
+        authorization_url: Url,
+        callback_url: Url,
+        callback_method: &'static str,
         f: F,
     ) -> Result<TResponse>
     where
         TResponse: Send + Clone + Sync + 'static,
-        F: Send + Fn(Arc<EventRequestPaused>) -> Option<TResponse> + 'static,
+        F: Send + Fn(Arc<EventRequestPaused>) -> Result<Option<TResponse>, CallbackError> + 'static,
     {
+        let har = match self.options.capture_har {
+            Some(_) => Some(record_har(page, !self.options.har_include_secrets).await?),
+            None => None,
+        };
+        set_extra_headers(page, &self.options.extra_headers).await?;
+        let saml = submit_saml_post_bindings(page).await?;
         let (tx_browser, rx_browser) = oneshot::channel();
-        let mut request_paused = self.page.event_listener::<EventRequestPaused>().await?;
-        let intercept_page = self.page.clone();
+        let mut request_paused = page.event_listener::<EventRequestPaused>().await?;
+        let intercept_page = page.clone();
         let callback_url = callback_url.to_owned();
+        let echo_callback = self.options.echo_callback;
+        let strict_csrf = self.options.strict_csrf;
         tokio::spawn(async move {
             while let Some(event) = request_paused.next().await {
                 let request_url = Url::parse(&event.request.url).unwrap();
-                if request_url.origin() == callback_url.origin()
-                    && request_url.path() == callback_url.path()
-                {
-                    log::debug!("Received request to `--callback-url` {}", callback_url);
-
-                    let response = f(event.clone());
-
-                    if let Err(e) = intercept_page
-                        .execute(
-                            FulfillRequestParams::builder()
-                                .request_id(event.request_id.clone())
-                                .body(BASE64_STANDARD.encode(if response.is_some() {
-                                    CONTENT_OK
-                                } else {
-                                    CONTENT_NOT_OK
-                                }))
-                                .response_code(200)
-                                .build()
-                                .unwrap(),
-                        )
-                        .await
-                    {
-                        log::error!("Failed to fullfill request: {e}");
+                let route = route(
+                    &event.request.method,
+                    &request_url,
+                    &callback_url,
+                    callback_method,
+                );
+
+                if echo_callback && route != CallbackRoute::Continue {
+                    echo_request(&event, &request_url);
+                }
+
+                let (response_code, body, response) = match route {
+                    CallbackRoute::Continue => {
+                        if let Err(e) = intercept_page
+                            .execute(ContinueRequestParams::new(event.request_id.clone()))
+                            .await
+                        {
+                            log::error!("Failed to continue request: {e}");
+                        }
+                        continue;
                     }
+                    CallbackRoute::Callback => {
+                        log::debug!("Received request to `--callback-url` {}", callback_url);
 
-                    if let Some(response) = response {
-                        let _ = tx_browser.send(response);
-                        break;
+                        let response = match f(event.clone()) {
+                            Ok(response) => response.map(Ok),
+                            Err(e) if strict_csrf || e.is_malformed() => Some(Err(e)),
+                            Err(e) => {
+                                log::debug!("{}. Ignoring...", e);
+                                None
+                            }
+                        };
+                        let (response_code, body) = match &response {
+                            Some(Ok(_)) => (200, CONTENT_OK.to_owned()),
+                            Some(Err(e)) if e.is_malformed() => (400, bad_request(e)),
+                            _ if echo_callback => (200, CONTENT_ECHO.to_owned()),
+                            _ => (200, CONTENT_NOT_OK.to_owned()),
+                        };
+                        (response_code, body, response)
+                    }
+                    _ if echo_callback => (200, CONTENT_ECHO.to_owned(), None),
+                    CallbackRoute::Preflight => (204, String::new(), None),
+                    CallbackRoute::NotFound => {
+                        log::debug!(
+                            "Ignoring {} {} on the callback origin",
+                            event.request.method,
+                            request_url
+                        );
+                        (404, String::new(), None)
                     }
-                } else if let Err(e) = intercept_page
-                    .execute(ContinueRequestParams::new(event.request_id.clone()))
+                };
+
+                if let Err(e) = intercept_page
+                    .execute(
+                        FulfillRequestParams::builder()
+                            .request_id(event.request_id.clone())
+                            .body(BASE64_STANDARD.encode(body))
+                            .response_code(response_code)
+                            .build()
+                            .unwrap(),
+                    )
                     .await
                 {
-                    log::error!("Failed to continue request: {e}");
+                    log::error!("Failed to fullfill request: {e}");
+                }
+
+                if let Some(response) = response {
+                    let _ = tx_browser.send(response);
+                    break;
                 }
             }
         });
 
         log::debug!("Opening authorization page {}", authorization_url);
-        self.page.goto(authorization_url.as_str()).await?;
+        progress::notify(|observer| observer.on_waiting_for_user(&authorization_url));
+        if let Err(e) = page.goto(authorization_url.as_str()).await {
+            if is_crashed(&crashed) {
+                return Err(RequestError::BrowserCrashed.into());
+            }
+
+            self.save_failure(page).await;
+            saml.abort();
+            self.save_har(har);
+            return Err(e.into());
+        }
 
         let response = tokio::select! {
             _ = sleep(Duration::from_millis(timeout)) => {
@@ -104,7 +619,10 @@ impl Page {
                 Err::<TResponse, anyhow::Error>(RequestError::Timeout.into())
             }
             Ok(response) = rx_browser => {
-                Ok::<TResponse, anyhow::Error>(response)
+                response.map_err(anyhow::Error::from)
+            }
+            _ = wait_for_crash(crashed.clone()) => {
+                Err::<TResponse, anyhow::Error>(RequestError::BrowserCrashed.into())
             }
             // _ = &mut self.rx_handle => {
             //     log::debug!("User closed the browser");
@@ -112,43 +630,93 @@ impl Page {
             // }
         };
 
+        if response.is_err() && !is_crashed(&crashed) {
+            self.save_failure(page).await;
+        }
+        saml.abort();
+        self.save_har(har);
+
         response
     }
 
+    /// `--capture-har` of the flow. Never fails the flow on its own
+    fn save_har(&self, har: Option<(Arc<Mutex<Har>>, tokio::task::JoinHandle<()>)>) {
+        let (Some(path), Some((har, handle))) = (&self.options.capture_har, har) else {
+            return;
+        };
+        handle.abort();
+
+        match har.lock().unwrap().write(path) {
+            Ok(()) => output::status(
+                "Saved",
+                &format!("network activity of the flow to {}", path.display()),
+            ),
+            Err(e) => output::warning(&format!("Cannot save the HAR file: {:#}", e)),
+        }
+    }
+
+    /// `--debug-screenshot` of a failed flow. Never fails the flow on its own
+    async fn save_failure(&self, page: &CPage) {
+        let Some(dir) = &self.options.debug_screenshot else {
+            return;
+        };
+
+        match save_failure(page, dir).await {
+            Ok(path) => output::status(
+                "Saved",
+                &format!("screenshot of the failed flow to {}", path.display()),
+            ),
+            Err(e) => output::warning(&format!(
+                "Cannot save a screenshot of the failed flow: {:#}",
+                e
+            )),
+        }
+    }
+
     pub async fn get_code(
         &self,
         timeout: u64,
         authorization_url: Url,
         callback_url: Url,
         csrf_token: CsrfToken,
-    ) -> Result<String> {
-        self.process_request(timeout, authorization_url, callback_url, move |event| {
-            let request_url = Url::parse(&event.request.url).unwrap();
-            let state = request_url.query_pairs().find(|qp| qp.0.eq("state"));
-            let code = request_url.query_pairs().find(|qp| qp.0.eq("code"));
-
-            match (state, code) {
-                (Some((_, state)), Some((_, code))) => {
-                    if state == *csrf_token.secret() {
+    ) -> Result<AuthorizationResponse> {
+        self.process_request(
+            timeout,
+            authorization_url,
+            callback_url,
+            "GET",
+            move |event| {
+                let request_url = Url::parse(&event.request.url).unwrap();
+                let state = request_url.query_pairs().find(|qp| qp.0.eq("state"));
+                let code = request_url.query_pairs().find(|qp| qp.0.eq("code"));
+                let iss = request_url.query_pairs().find(|qp| qp.0.eq("iss"));
+                let session_state = request_url
+                    .query_pairs()
+                    .find(|qp| qp.0.eq("session_state"));
+
+                verify_state(state.as_ref().map(|(_, state)| state.as_ref()), &csrf_token)?;
+
+                match code {
+                    Some((_, code)) => {
                         let code = code.to_string();
                         log::debug!("Given code: {}", code);
+                        progress::notify(|observer| observer.on_code_received());
 
-                        Some(code)
-                    } else {
-                        log::debug!("Incorrect CSRF token. Ignoring...");
-
-                        None
+                        Ok(Some(AuthorizationResponse {
+                            code,
+                            iss: iss.map(|(_, iss)| iss.to_string()),
+                            session_state: session_state
+                                .map(|(_, session_state)| session_state.to_string()),
+                        }))
                     }
-                }
-                _ => {
-                    log::debug!(
-                        "Call to server without a state and/or a code parameter. Ignoring..."
-                    );
+                    None => {
+                        log::debug!("Call to server without a code parameter. Ignoring...");
 
-                    None
+                        Ok(None)
+                    }
                 }
-            }
-        })
+            },
+        )
         .await
     }
 
@@ -163,64 +731,19 @@ impl Page {
             timeout,
             authorization_url,
             callback_url,
+            "POST",
             move |event| match event.request.method.as_str() {
                 "POST" => {
-                    let entries = event.request.post_data_entries.as_ref().unwrap();
-
-                    let body = entries
-                        .iter()
-                        .map(|s| {
-                            BASE64_STANDARD
-                                .decode(s.bytes.as_ref().unwrap().as_ref() as &[u8])
-                                .unwrap()
-                        })
-                        .collect::<Vec<Vec<u8>>>()
-                        .join("&".as_bytes());
-
-                    let form_params =
-                        form_urlencoded::parse(body.as_slice())
-                            .collect::<Vec<(Cow<str>, Cow<str>)>>();
-
-                    let (_, access_token) = form_params
-                        .iter()
-                        .find(|(name, _value)| name == "access_token")
-                        .expect("Cannot find access_token in the HTTP Post request.");
-
-                    let (_, expires_in) = form_params
-                        .iter()
-                        .find(|(name, _value)| name == "expires_in")
-                        .expect("Cannot find expires_in in the HTTP Post request.");
-
-                    let (_, state) = form_params
-                        .iter()
-                        .find(|(name, _value)| name == "state")
-                        .expect("Cannot find state in the HTTP Post request.");
-
-                    if state == csrf_token.secret() {
-                        Some(TokenInfo {
-                            access_token: access_token.to_string(),
-                            refresh_token: None,
-                            expires: Some(
-                                SystemTime::now().add(Duration::from_secs(
-                                    expires_in
-                                        .parse::<u64>()
-                                        .expect("expires_in is an incorrect number"),
-                                )),
-                            ),
-                            scope: None,
-                        })
-                    } else {
-                        log::debug!("Incorrect CSRF token. Aborting...");
-
-                        None
-                    }
+                    let body = form_post_body(&event)?;
+
+                    form_post_token(&body, &csrf_token).map(Some)
                 }
                 _ => {
                     log::debug!(
                         "Call to server without a state and/or a code parameter. Ignoring..."
                     );
 
-                    None
+                    Ok(None)
                 }
             },
         )
@@ -232,3 +755,102 @@ impl Page {
         Ok(())
     }
 }
+
+#[async_trait(?Send)]
+impl CodeSource for Page {
+    async fn get_code(
+        &self,
+        timeout: u64,
+        authorization_url: Url,
+        callback_url: Url,
+        csrf_token: CsrfToken,
+    ) -> Result<AuthorizationResponse> {
+        Page::get_code(self, timeout, authorization_url, callback_url, csrf_token).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth_browser::StateError;
+
+    #[test]
+    fn it_completes_only_on_callback_path_and_method() {
+        let callback_url = Url::parse("http://localhost:8080/callback").unwrap();
+        let url = |url: &str| Url::parse(url).unwrap();
+
+        assert_eq!(
+            route(
+                "GET",
+                &url("http://localhost:8080/callback?code=1"),
+                &callback_url,
+                "GET"
+            ),
+            CallbackRoute::Callback
+        );
+        assert_eq!(
+            route(
+                "GET",
+                &url("http://localhost:8080/favicon.ico"),
+                &callback_url,
+                "GET"
+            ),
+            CallbackRoute::NotFound
+        );
+        assert_eq!(
+            route(
+                "HEAD",
+                &url("http://localhost:8080/callback"),
+                &callback_url,
+                "GET"
+            ),
+            CallbackRoute::NotFound
+        );
+        assert_eq!(
+            route(
+                "OPTIONS",
+                &url("http://localhost:8080/callback"),
+                &callback_url,
+                "POST"
+            ),
+            CallbackRoute::Preflight
+        );
+        assert_eq!(
+            route(
+                "GET",
+                &url("https://idp.com/authorize"),
+                &callback_url,
+                "GET"
+            ),
+            CallbackRoute::Continue
+        );
+    }
+
+    #[test]
+    fn it_rejects_malformed_form_post_callbacks() {
+        let csrf_token = CsrfToken::new("xyz".to_owned());
+
+        let token_info = form_post_token(
+            b"access_token=at&expires_in=3600&state=xyz&token_type=Bearer",
+            &csrf_token,
+        )
+        .unwrap();
+        assert_eq!(token_info.access_token, "at");
+        assert_eq!(token_info.extra["token_type"], "Bearer");
+
+        assert_eq!(
+            form_post_token(b"expires_in=3600&state=xyz", &csrf_token).unwrap_err(),
+            CallbackError::MissingField("access_token")
+        );
+        assert_eq!(
+            form_post_token(b"access_token=at&expires_in=soon&state=xyz", &csrf_token).unwrap_err(),
+            CallbackError::InvalidNumber("expires_in", "soon".to_owned())
+        );
+        assert_eq!(
+            form_post_token(b"access_token=at&expires_in=3600&state=abc", &csrf_token).unwrap_err(),
+            CallbackError::State(StateError::Incorrect)
+        );
+        assert!(CallbackError::MissingField("access_token").is_malformed());
+        assert!(!CallbackError::State(StateError::Missing).is_malformed());
+    }
+}
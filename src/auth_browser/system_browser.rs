@@ -0,0 +1,478 @@
+use anyhow::{Context, Result, anyhow, bail};
+use async_trait::async_trait;
+use futures::StreamExt;
+use futures::stream::FuturesUnordered;
+use oauth2::CsrfToken;
+use std::io::ErrorKind;
+use std::net::IpAddr;
+use std::process::Command;
+use std::time::Duration;
+use tokio::io::AsyncWrite;
+use tokio::time::sleep;
+use url::{Position, Url};
+
+use super::{AuthorizationResponse, CodeSource, verify_state};
+use crate::callback_mux::{self, Registry};
+use crate::loopback;
+use crate::output;
+use crate::progress;
+use crate::providers::errors::ProviderError;
+
+const CONTENT_OK: &str =
+    "<html><head></head><body><h1>OK</h1><p>You can close this window</p></body></html>";
+const CONTENT_NOT_OK: &str = "<html><head></head><body><h1>NOT OK</h1></body></html>";
+
+/// Connections sending no request within this time are closed, ex. preconnects of browsers
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often a run waiting for the shared callback port checks for its callback and whether the port is free
+const MULTIPLEX_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Opens the authorization url in a browser doken doesn't control. `env` is passed to the browser
+pub trait Opener {
+    fn open(&self, url: &Url, env: &[(&str, String)]) -> Result<()>;
+}
+
+/// Runs `--browser-cmd` through the shell. `{url}` is replaced with the quoted url, otherwise it's appended
+pub struct CommandOpener {
+    command: String,
+}
+
+impl CommandOpener {
+    pub fn new(command: &str) -> Self {
+        CommandOpener {
+            command: command.to_owned(),
+        }
+    }
+}
+
+fn command_line(command: &str, url: &Url) -> String {
+    let quoted = if cfg!(windows) {
+        format!("\"{}\"", url)
+    } else {
+        format!("'{}'", url.as_str().replace('\'', r"'\''"))
+    };
+
+    if command.contains("{url}") {
+        command.replace("{url}", &quoted)
+    } else {
+        format!("{} {}", command, quoted)
+    }
+}
+
+impl Opener for CommandOpener {
+    fn open(&self, url: &Url, env: &[(&str, String)]) -> Result<()> {
+        let command_line = command_line(&self.command, url);
+        log::debug!("Opening the authorization url with `{}`", command_line);
+
+        let mut command = if cfg!(windows) {
+            let mut command = Command::new("cmd");
+            command.arg("/C").arg(command_line);
+            command
+        } else {
+            let mut command = Command::new("sh");
+            command.arg("-c").arg(command_line);
+            command
+        };
+
+        // Browsers may run until closed, so the command isn't awaited
+        command
+            .envs(env.iter().cloned())
+            .spawn()
+            .with_context(|| format!("Cannot run `--browser-cmd` {}", self.command))?;
+
+        Ok(())
+    }
+}
+
+/// Code or error sent to the callback path of the flow. `None` if the request is ignored
+pub(super) fn evaluate_callback(
+    request_url: &Url,
+    csrf_token: &CsrfToken,
+    strict_csrf: bool,
+) -> Option<Result<AuthorizationResponse>> {
+    let param = |name: &str| {
+        request_url
+            .query_pairs()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.to_string())
+    };
+    let state = verify_state(param("state").as_deref(), csrf_token);
+
+    match (state, param("code"), param("error")) {
+        // Errors may come without a state, ex. when the IdP rejects the client
+        (_, _, Some(error)) => Some(Err(anyhow!(ProviderError::new(
+            error,
+            param("error_description"),
+            param("error_uri"),
+        )))),
+        (Err(e), _, _) if strict_csrf => Some(Err(e.into())),
+        (Err(e), _, _) => {
+            log::debug!("{}. Ignoring...", e);
+            None
+        }
+        (Ok(()), Some(code), _) => {
+            progress::notify(|observer| observer.on_code_received());
+            Some(Ok(AuthorizationResponse {
+                code,
+                iss: param("iss"),
+                session_state: param("session_state"),
+            }))
+        }
+        (Ok(()), None, _) => {
+            log::debug!("Call to server without a code parameter. Ignoring...");
+            None
+        }
+    }
+}
+
+/// Receives the code on a loopback server, because requests of the external browser cannot be intercepted
+pub struct SystemBrowser {
+    opener: Box<dyn Opener>,
+    strict_csrf: bool,
+    multiplex_callback: bool,
+    bind_address: Vec<IpAddr>,
+    callback_socket: bool,
+}
+
+impl SystemBrowser {
+    pub fn new(opener: Box<dyn Opener>) -> Self {
+        SystemBrowser {
+            opener,
+            strict_csrf: false,
+            multiplex_callback: false,
+            bind_address: vec![],
+            callback_socket: false,
+        }
+    }
+
+    /// Shares the callback port with concurrent runs. The callback url has to carry a flow path of `callback_mux`
+    pub fn with_multiplex_callback(mut self, multiplex_callback: bool) -> Self {
+        self.multiplex_callback = multiplex_callback;
+        self
+    }
+
+    /// Listens on these addresses instead of the ones of the callback url's host
+    pub fn with_bind_address(mut self, bind_address: Vec<IpAddr>) -> Self {
+        self.bind_address = bind_address;
+        self
+    }
+
+    /// Receives the callback on an ephemeral unix domain socket relayed by `doken callback-relay` instead of a port
+    pub fn with_callback_socket(mut self, callback_socket: bool) -> Self {
+        self.callback_socket = callback_socket;
+        self
+    }
+
+    /// Fails on the first callback with a wrong or missing state instead of waiting for another one
+    pub fn with_strict_csrf(mut self, strict_csrf: bool) -> Self {
+        self.strict_csrf = strict_csrf;
+        self
+    }
+
+    fn evaluate(
+        &self,
+        request_url: &Url,
+        csrf_token: &CsrfToken,
+    ) -> Option<Result<AuthorizationResponse>> {
+        evaluate_callback(request_url, csrf_token, self.strict_csrf)
+    }
+
+    async fn respond_html<S: AsyncWrite + Unpin>(
+        stream: &mut S,
+        status: &str,
+        body: &str,
+    ) -> Result<()> {
+        loopback::respond(
+            stream,
+            &format!(
+                "HTTP/1.1 {}\r\nContent-Type: text/html\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            ),
+        )
+        .await
+    }
+
+    async fn respond_not_found<S: AsyncWrite + Unpin>(stream: &mut S) -> Result<()> {
+        loopback::respond(
+            stream,
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        )
+        .await
+    }
+
+    /// Answers a single request to the callback port. `None` if the flow goes on waiting
+    async fn serve_callback(
+        &self,
+        mut stream: Box<dyn loopback::Connection>,
+        callback_url: &Url,
+        csrf_token: &CsrfToken,
+        registry: Option<&Registry>,
+    ) -> Result<Option<Result<AuthorizationResponse>>> {
+        let head = loopback::read_head(&mut stream).await?;
+
+        let request_url = match loopback::request_line(&head) {
+            Some(("GET", target)) => callback_url.join(target).ok(),
+            _ => None,
+        };
+
+        match request_url {
+            Some(request_url) if request_url.path() == callback_url.path() => {
+                let result = self.evaluate(&request_url, csrf_token);
+
+                // Rejected callbacks fail `doken callback-relay` and the browser command running it
+                let (status, body) = match result {
+                    Some(Ok(_)) => ("200 OK", CONTENT_OK),
+                    _ => ("400 Bad Request", CONTENT_NOT_OK),
+                };
+                Self::respond_html(&mut stream, status, body).await?;
+
+                return Ok(result);
+            }
+            Some(request_url) => match (registry, callback_mux::flow_id(request_url.path())) {
+                (Some(registry), Some(flow_id)) if registry.is_registered(flow_id) => {
+                    log::debug!("Handing the callback over to flow {}", flow_id);
+                    registry.deliver(flow_id, &request_url[Position::BeforePath..])?;
+                    Self::respond_html(&mut stream, "200 OK", CONTENT_OK).await?;
+                }
+                _ => Self::respond_not_found(&mut stream).await?,
+            },
+            None => Self::respond_not_found(&mut stream).await?,
+        }
+
+        Ok(None)
+    }
+
+    /// Serves the callback port. With `registry` callbacks of other flows waiting for the port are handed over to them.
+    /// Connections are served concurrently, as browsers open idle ones ahead of the callback
+    async fn wait_for_code(
+        &self,
+        listener: loopback::Listener,
+        callback_url: &Url,
+        csrf_token: &CsrfToken,
+        registry: Option<&Registry>,
+    ) -> Result<AuthorizationResponse> {
+        let mut connections = FuturesUnordered::new();
+
+        loop {
+            tokio::select! {
+                stream = listener.accept() => {
+                    connections.push(tokio::time::timeout(
+                        CONNECTION_TIMEOUT,
+                        self.serve_callback(stream?, callback_url, csrf_token, registry),
+                    ));
+                }
+                Some(served) = connections.next() => match served {
+                    Ok(Ok(Some(result))) => return result,
+                    Ok(Ok(None)) => {}
+                    Ok(Err(e)) => log::debug!("Cannot serve a callback connection: {:#}", e),
+                    Err(_) => log::debug!("Closed a callback connection sending no request"),
+                },
+            }
+        }
+    }
+
+    /// Result of a callback handed over by the run serving the port
+    fn delivered(
+        &self,
+        registry: &Registry,
+        flow_id: &str,
+        callback_url: &Url,
+        csrf_token: &CsrfToken,
+    ) -> Option<Result<AuthorizationResponse>> {
+        let target = registry.take_delivered(flow_id)?;
+        let request_url = callback_url.join(&target).ok()?;
+
+        self.evaluate(&request_url, csrf_token)
+    }
+
+    /// Waits for the callback on a port shared with concurrent runs, serving it whenever it's free
+    async fn wait_for_multiplexed_code(
+        &self,
+        address: (&str, u16),
+        callback_url: &Url,
+        csrf_token: &CsrfToken,
+    ) -> Result<AuthorizationResponse> {
+        let registry = Registry::new()?;
+        let flow_id = callback_mux::flow_id(callback_url.path())
+            .context("`--callback-url` has no flow path")?
+            .to_owned();
+        registry.register(&flow_id)?;
+
+        let result = loop {
+            let listener = match loopback::bind(address.0, address.1, &self.bind_address).await {
+                Ok(listener) => Some(listener),
+                Err(e) if e.kind() == ErrorKind::AddrInUse => None,
+                Err(e) => {
+                    break Err(anyhow!(e).context(format!(
+                        "Cannot listen for the callback on {}:{}",
+                        address.0, address.1
+                    )));
+                }
+            };
+
+            if let Some(result) = self.delivered(&registry, &flow_id, callback_url, csrf_token) {
+                break result;
+            }
+
+            match listener {
+                Some(listener) => {
+                    log::debug!("Serving callbacks of concurrent flows");
+                    break self
+                        .wait_for_code(listener, callback_url, csrf_token, Some(&registry))
+                        .await;
+                }
+                None => sleep(MULTIPLEX_POLL_INTERVAL).await,
+            }
+        };
+
+        registry.unregister(&flow_id);
+        result
+    }
+
+    /// Runs `--browser-cmd` with `DOKEN_CALLBACK_SOCKET_PATH` and `DOKEN_CALLBACK_RELAY`, so it can hand
+    /// the callback url over to `doken callback-relay` instead of a server listening on a port
+    #[cfg(unix)]
+    async fn get_code_over_socket(
+        &self,
+        timeout: u64,
+        authorization_url: Url,
+        callback_url: Url,
+        csrf_token: CsrfToken,
+    ) -> Result<AuthorizationResponse> {
+        let socket = loopback::EphemeralSocket::create()
+            .context("Cannot create a directory for the callback socket")?;
+        let listener = socket
+            .bind()
+            .with_context(|| format!("Cannot listen on {}", socket.path().display()))?;
+        let relay = format!(
+            "'{}' callback-relay",
+            std::env::current_exe()?
+                .display()
+                .to_string()
+                .replace('\'', r"'\''")
+        );
+
+        self.opener.open(
+            &authorization_url,
+            &[
+                (
+                    "DOKEN_CALLBACK_SOCKET_PATH",
+                    socket.path().display().to_string(),
+                ),
+                ("DOKEN_CALLBACK_RELAY", relay),
+            ],
+        )?;
+        progress::notify(|observer| observer.on_waiting_for_user(&authorization_url));
+        output::status(
+            "Waiting",
+            &format!(
+                "for the callback on {}. Authorize at {}",
+                socket.path().display(),
+                authorization_url
+            ),
+        );
+
+        tokio::time::timeout(
+            Duration::from_millis(timeout),
+            self.wait_for_code(listener, &callback_url, &csrf_token, None),
+        )
+        .await
+        .map_err(|_| anyhow!("No requests with required data. Timeout."))?
+    }
+
+    #[cfg(not(unix))]
+    async fn get_code_over_socket(
+        &self,
+        _timeout: u64,
+        _authorization_url: Url,
+        _callback_url: Url,
+        _csrf_token: CsrfToken,
+    ) -> Result<AuthorizationResponse> {
+        bail!("`--callback-socket` requires unix domain sockets, which this platform doesn't have")
+    }
+}
+
+#[async_trait(?Send)]
+impl CodeSource for SystemBrowser {
+    async fn get_code(
+        &self,
+        timeout: u64,
+        authorization_url: Url,
+        callback_url: Url,
+        csrf_token: CsrfToken,
+    ) -> Result<AuthorizationResponse> {
+        if self.callback_socket {
+            return self
+                .get_code_over_socket(timeout, authorization_url, callback_url, csrf_token)
+                .await;
+        }
+
+        if callback_url.scheme() != "http" {
+            bail!("`--browser-cmd` requires an `http://` loopback `--callback-url`");
+        }
+
+        let host = callback_url
+            .host_str()
+            .context("`--callback-url` has no host")?;
+        let port = callback_url.port_or_known_default().unwrap_or(80);
+        let listener = match self.multiplex_callback {
+            true => None,
+            false => Some(loopback::bind(host, port, &self.bind_address).await.with_context(|| {
+                format!(
+                    "Cannot listen for the callback on {}:{}. Concurrent runs can share it with `--multiplex-callback`",
+                    host, port
+                )
+            })?),
+        };
+
+        self.opener.open(&authorization_url, &[])?;
+        progress::notify(|observer| observer.on_waiting_for_user(&authorization_url));
+        output::status(
+            "Waiting",
+            &format!("for the callback. Authorize at {}", authorization_url),
+        );
+
+        let code = async {
+            match listener {
+                Some(listener) => {
+                    self.wait_for_code(listener, &callback_url, &csrf_token, None)
+                        .await
+                }
+                None => {
+                    self.wait_for_multiplexed_code((host, port), &callback_url, &csrf_token)
+                        .await
+                }
+            }
+        };
+
+        tokio::time::timeout(Duration::from_millis(timeout), code)
+            .await
+            .map_err(|_| anyhow!("No requests with required data. Timeout."))?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_puts_quoted_url_into_command() {
+        let url = Url::parse("https://idp.com/authorize?a=1&b=2").unwrap();
+
+        if cfg!(windows) {
+            return;
+        }
+
+        assert_eq!(
+            command_line("firefox --private-window {url}", &url),
+            "firefox --private-window 'https://idp.com/authorize?a=1&b=2'"
+        );
+        assert_eq!(
+            command_line("wslview", &url),
+            "wslview 'https://idp.com/authorize?a=1&b=2'"
+        );
+    }
+}
@@ -0,0 +1,122 @@
+use anyhow::{Context, Result, anyhow};
+use async_trait::async_trait;
+use oauth2::CsrfToken;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use tao::event::{Event, WindowEvent};
+use tao::event_loop::{ControlFlow, EventLoopBuilder};
+use tao::platform::run_return::EventLoopExtRunReturn;
+use tao::window::WindowBuilder;
+use url::Url;
+use wry::WebViewBuilder;
+
+use super::system_browser::evaluate_callback;
+use super::{AuthorizationResponse, CodeSource};
+use crate::progress;
+
+/// How often the event loop checks for the callback and the timeout
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Signs in within a native window (WKWebView on macOS, WebView2 on Windows) instead of the
+/// controlled Chromium. Navigations to the callback url are stopped and evaluated, so nothing has
+/// to listen on it
+pub struct WebView {
+    strict_csrf: bool,
+}
+
+impl WebView {
+    pub fn new() -> Self {
+        WebView { strict_csrf: false }
+    }
+
+    /// Fails on the first callback with a wrong or missing state instead of waiting for another one
+    pub fn with_strict_csrf(mut self, strict_csrf: bool) -> Self {
+        self.strict_csrf = strict_csrf;
+        self
+    }
+
+    /// Runs the window until the callback is navigated to. The event loop has to run on the main
+    /// thread, which is the one polling the root future of the runtime
+    fn run(
+        &self,
+        timeout: u64,
+        authorization_url: Url,
+        callback_url: Url,
+        csrf_token: CsrfToken,
+    ) -> Result<AuthorizationResponse> {
+        let mut event_loop = EventLoopBuilder::new().build();
+        let window = WindowBuilder::new()
+            .with_title("doken")
+            .build(&event_loop)
+            .context("Cannot open the sign-in window")?;
+
+        let result: Rc<RefCell<Option<Result<AuthorizationResponse>>>> = Default::default();
+        let navigation_result = result.clone();
+        let strict_csrf = self.strict_csrf;
+        let _webview = WebViewBuilder::new()
+            .with_url(authorization_url.as_str())
+            .with_navigation_handler(move |url| {
+                let Ok(url) = Url::parse(&url) else {
+                    return true;
+                };
+
+                if url.origin() != callback_url.origin() || url.path() != callback_url.path() {
+                    return true;
+                }
+
+                log::debug!("Received navigation to `--callback-url` {}", callback_url);
+                if let Some(response) = evaluate_callback(&url, &csrf_token, strict_csrf) {
+                    navigation_result.replace(Some(response));
+                }
+                false
+            })
+            .build(&window)
+            .context("Cannot show the sign-in page in the window")?;
+
+        progress::notify(|observer| observer.on_waiting_for_user(&authorization_url));
+        let deadline = Instant::now() + Duration::from_millis(timeout);
+        event_loop.run_return(|event, _, control_flow| {
+            *control_flow = ControlFlow::WaitUntil(Instant::now() + POLL_INTERVAL);
+
+            if let Event::WindowEvent {
+                event: WindowEvent::CloseRequested,
+                ..
+            } = event
+            {
+                result.replace(Some(Err(anyhow!("The user closed the sign-in window"))));
+            } else if Instant::now() >= deadline {
+                result.replace(Some(Err(anyhow!("Timed out waiting for the sign-in"))));
+            }
+
+            if result.borrow().is_some() {
+                *control_flow = ControlFlow::Exit;
+            }
+        });
+
+        result
+            .take()
+            .unwrap_or_else(|| Err(anyhow!("The sign-in window closed without a callback")))
+    }
+}
+
+impl Default for WebView {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait(?Send)]
+impl CodeSource for WebView {
+    async fn get_code(
+        &self,
+        timeout: u64,
+        authorization_url: Url,
+        callback_url: Url,
+        csrf_token: CsrfToken,
+    ) -> Result<AuthorizationResponse> {
+        tokio::task::block_in_place(|| {
+            self.run(timeout, authorization_url, callback_url, csrf_token)
+        })
+    }
+}
@@ -0,0 +1,180 @@
+use anyhow::{Result, bail};
+
+use crate::args::{Args, Arguments};
+use crate::grant::Grant;
+use crate::http_client;
+use crate::openidc_discovery::{ProviderMetadata, get_provider_metadata};
+use crate::output;
+
+/// Picks the grant for `--grant auto`: Authorization Code with PKCE > Device Code >
+/// Client Credentials.
+/// Without a discovery document every grant is assumed to be supported
+pub fn detect(args: &Arguments, metadata: &ProviderMetadata) -> Result<(Grant, String)> {
+    let supports = |grant_type: &str| {
+        args.discovery_url.is_none()
+            || metadata
+                .grant_types_supported
+                .iter()
+                .any(|g| g == grant_type)
+    };
+
+    if args.callback_url.is_some() && supports("authorization_code") {
+        let has_s256 = metadata.code_challenge_methods_supported.is_empty()
+            || metadata
+                .code_challenge_methods_supported
+                .iter()
+                .any(|method| method == "S256");
+
+        return Ok(if has_s256 {
+            (
+                Grant::AuthorizationCodeWithPkce,
+                "`--callback-url` is set and the provider supports `authorization_code`".to_owned(),
+            )
+        } else {
+            (
+                Grant::AuthorizationCode,
+                "`--callback-url` is set, but the provider doesn't support S256 PKCE".to_owned(),
+            )
+        });
+    }
+
+    let has_device_endpoint = match &args.discovery_url {
+        Some(_) => metadata.device_authorization_endpoint.is_some(),
+        None => args.device_authorization_url.is_some(),
+    };
+    if has_device_endpoint && supports("urn:ietf:params:oauth:grant-type:device_code") {
+        return Ok((
+            Grant::DeviceCode,
+            "the provider has a device authorization endpoint and supports `device_code`"
+                .to_owned(),
+        ));
+    }
+
+    if args.client_secret.is_some() && supports("client_credentials") {
+        return Ok((
+            Grant::ClientCredentials,
+            "a client secret is set and the provider supports `client_credentials`".to_owned(),
+        ));
+    }
+
+    bail!(
+        "Cannot pick a grant automatically. Provider supports: {}. Set `--callback-url` for a browser based grant, `--device-authorization-url` for the device code grant, `--client-secret` for client credentials or choose `--grant` explicitly",
+        metadata.grant_types_supported.join(", ")
+    )
+}
+
+/// Arguments with `--grant auto` replaced by the detected grant
+pub async fn resolve(args: &Arguments) -> Result<Arguments> {
+    let metadata = match &args.discovery_url {
+        Some(discovery_url) => {
+            get_provider_metadata(
+                &http_client::builder(args)?.build()?,
+                discovery_url.to_owned(),
+                &args.discovery_map,
+            )
+            .await?
+        }
+        None => ProviderMetadata::default(),
+    };
+
+    let (grant, reason) = detect(args, &metadata)?;
+    output::status(
+        "Using",
+        &format!(
+            "`{}` grant, because {}",
+            serde_variant::to_variant_name(&grant).unwrap_or_default(),
+            reason
+        ),
+    );
+
+    let args = Arguments {
+        grant,
+        ..args.to_owned()
+    };
+    Args::assert_grant_specific_arguments(&args);
+
+    Ok(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata(grant_types: &[&str]) -> ProviderMetadata {
+        ProviderMetadata {
+            grant_types_supported: grant_types.iter().map(|g| g.to_string()).collect(),
+            code_challenge_methods_supported: vec!["S256".to_owned()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn it_prefers_pkce_over_client_credentials() {
+        let args = Arguments {
+            discovery_url: Some("https://idp/.well-known/openid-configuration".to_owned()),
+            callback_url: Some("http://localhost:8080/callback".to_owned()),
+            client_secret: Some("secret".to_owned()),
+            ..Default::default()
+        };
+        let both = metadata(&["authorization_code", "client_credentials"]);
+
+        assert!(matches!(
+            detect(&args, &both).unwrap().0,
+            Grant::AuthorizationCodeWithPkce
+        ));
+        assert!(matches!(
+            detect(&args, &metadata(&["client_credentials"])).unwrap().0,
+            Grant::ClientCredentials
+        ));
+
+        let args = Arguments {
+            client_secret: None,
+            ..args
+        };
+        assert!(detect(&args, &metadata(&["client_credentials"])).is_err());
+    }
+
+    #[test]
+    fn it_prefers_device_code_over_client_credentials() {
+        const DEVICE_CODE: &str = "urn:ietf:params:oauth:grant-type:device_code";
+        let args = Arguments {
+            discovery_url: Some("https://idp/.well-known/openid-configuration".to_owned()),
+            client_secret: Some("secret".to_owned()),
+            ..Default::default()
+        };
+        let with_endpoint = |grant_types: &[&str]| ProviderMetadata {
+            device_authorization_endpoint: Some("https://idp/device".to_owned()),
+            ..metadata(grant_types)
+        };
+
+        assert!(matches!(
+            detect(&args, &with_endpoint(&[DEVICE_CODE, "client_credentials"]))
+                .unwrap()
+                .0,
+            Grant::DeviceCode
+        ));
+        assert!(matches!(
+            detect(&args, &with_endpoint(&["client_credentials"]))
+                .unwrap()
+                .0,
+            Grant::ClientCredentials
+        ));
+        assert!(matches!(
+            detect(&args, &metadata(&[DEVICE_CODE, "client_credentials"]))
+                .unwrap()
+                .0,
+            Grant::ClientCredentials
+        ));
+
+        let args = Arguments {
+            callback_url: Some("http://localhost:8080/callback".to_owned()),
+            ..args
+        };
+        assert!(matches!(
+            detect(&args, &with_endpoint(&["authorization_code", DEVICE_CODE]))
+                .unwrap()
+                .0,
+            Grant::AuthorizationCodeWithPkce
+        ));
+    }
+}
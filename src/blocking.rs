@@ -0,0 +1,48 @@
+//! Synchronous variants of the library API for programs without an async runtime, ex. build scripts.
+//! Every call runs on its own runtime, so they cannot be used from within an async context.
+
+use anyhow::Result;
+use std::future::Future;
+use tokio::sync::Mutex;
+
+use crate::args::Arguments;
+use crate::auth_browser::browser::Browser;
+use crate::token_info::TokenInfo;
+
+fn block_on<F: Future>(future: F) -> Result<F::Output> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+
+    Ok(runtime.block_on(future))
+}
+
+/// Blocking [`crate::get_token`]. A browser is started only if the flow requires it
+pub fn get_token(args: Arguments) -> Result<String> {
+    block_on(async {
+        let auth_browser = Mutex::new(
+            Browser::new(args.headless()).with_ephemeral_profile(args.ephemeral_browser_profile),
+        );
+        crate::get_token(args, auth_browser.lock().await).await
+    })?
+}
+
+/// Blocking [`crate::get_token_info`]
+pub fn get_token_info(args: &Arguments) -> Result<TokenInfo> {
+    block_on(async {
+        let auth_browser = Mutex::new(
+            Browser::new(args.headless()).with_ephemeral_profile(args.ephemeral_browser_profile),
+        );
+        crate::get_token_info(args, auth_browser.lock().await).await
+    })?
+}
+
+/// Same as [`crate::cached_token_info`], which doesn't need a runtime
+pub fn cached_token_info(state_key: &str) -> Result<Option<TokenInfo>> {
+    crate::cached_token_info(state_key)
+}
+
+/// Blocking [`crate::refresh_token_info`]
+pub fn refresh_token_info(args: &Arguments) -> Result<TokenInfo> {
+    block_on(crate::refresh_token_info(args))?
+}
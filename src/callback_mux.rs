@@ -0,0 +1,135 @@
+//! Sharing one callback port between concurrent runs. Every flow gets its own `/cb/<flow-id>`
+//! callback path. The run listening on the port answers its own callback and hands callbacks
+//! of other flows over through files in `~/.doken/callbacks`. Once it's done, another waiting
+//! run takes the port over.
+
+use anyhow::{Context, Result};
+use rand::distr::{Alphanumeric, SampleString};
+use std::fs;
+use std::path::PathBuf;
+use url::Url;
+
+use crate::permissions;
+
+const CALLBACK_PREFIX: &str = "/cb/";
+
+/// `--callback-url` with a new flow path, ex. `http://localhost:8081/cb/3kTz9q0LxA2m`
+pub fn with_flow_path(callback_url: &str) -> Result<String> {
+    let callback_url = Url::parse(callback_url).context("`--callback-url` is not a valid url")?;
+    let flow_id = Alphanumeric.sample_string(&mut rand::rng(), 12);
+
+    Ok(callback_url
+        .join(&format!("{}{}", CALLBACK_PREFIX, flow_id))?
+        .to_string())
+}
+
+/// Id of the flow a callback path belongs to
+pub fn flow_id(path: &str) -> Option<&str> {
+    path.strip_prefix(CALLBACK_PREFIX)
+        .filter(|flow_id| !flow_id.is_empty() && flow_id.chars().all(|c| c.is_ascii_alphanumeric()))
+}
+
+/// Flows waiting for their callback, `<flow-id>.pending`, and callbacks handed over to them, `<flow-id>.callback`
+pub struct Registry {
+    dir: PathBuf,
+}
+
+impl Registry {
+    pub fn new() -> Result<Self> {
+        let mut dir = home::home_dir().context("Couldn't access $HOME_DIR")?;
+        dir.push(".doken/callbacks");
+
+        Ok(Registry::in_dir(dir))
+    }
+
+    fn in_dir(dir: PathBuf) -> Self {
+        Registry { dir }
+    }
+
+    fn path(&self, flow_id: &str, extension: &str) -> PathBuf {
+        self.dir.join(format!("{}.{}", flow_id, extension))
+    }
+
+    /// Callbacks carry codes, so only the current user may enter the directory
+    fn create_dir(&self) -> Result<()> {
+        permissions::create_private_dir(&self.dir)?;
+        Ok(())
+    }
+
+    pub fn register(&self, flow_id: &str) -> Result<()> {
+        self.create_dir()?;
+        permissions::write_private(&self.path(flow_id, "pending"), b"")?;
+        Ok(())
+    }
+
+    pub fn unregister(&self, flow_id: &str) {
+        let _ = fs::remove_file(self.path(flow_id, "pending"));
+        let _ = fs::remove_file(self.path(flow_id, "callback"));
+    }
+
+    pub fn is_registered(&self, flow_id: &str) -> bool {
+        self.path(flow_id, "pending").exists()
+    }
+
+    /// Hands the request target of a callback over to the run waiting for it
+    pub fn deliver(&self, flow_id: &str, target: &str) -> Result<()> {
+        let path = self.path(flow_id, "callback");
+        // Written aside first, so the waiting run never reads a partial file
+        let partial = self.path(flow_id, "partial");
+        permissions::write_private(&partial, target.as_bytes())?;
+        fs::rename(partial, path)?;
+        Ok(())
+    }
+
+    /// Request target of a callback handed over by the run listening on the port
+    pub fn take_delivered(&self, flow_id: &str) -> Option<String> {
+        let path = self.path(flow_id, "callback");
+        let target = fs::read_to_string(&path).ok()?;
+        let _ = fs::remove_file(path);
+
+        Some(target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(unix)]
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn it_hands_callbacks_over_to_registered_flows() {
+        let callback_url = with_flow_path("http://localhost:8081/callback").unwrap();
+        let callback_url = Url::parse(&callback_url).unwrap();
+        let own = flow_id(callback_url.path()).unwrap();
+        assert_eq!(own.len(), 12);
+        assert_eq!(flow_id("/callback"), None);
+        assert_eq!(flow_id("/cb/../state"), None);
+
+        let dir = tempfile::tempdir().unwrap();
+        let registry = Registry::in_dir(dir.path().join("callbacks"));
+        registry.register(own).unwrap();
+        assert!(registry.is_registered(own));
+        #[cfg(unix)]
+        assert_eq!(
+            fs::metadata(dir.path().join("callbacks"))
+                .unwrap()
+                .permissions()
+                .mode()
+                & 0o777,
+            0o700
+        );
+        assert!(!registry.is_registered("other"));
+
+        assert_eq!(registry.take_delivered(own), None);
+        registry.deliver(own, "/cb/x?code=abc&state=xyz").unwrap();
+        assert_eq!(
+            registry.take_delivered(own).as_deref(),
+            Some("/cb/x?code=abc&state=xyz")
+        );
+        assert_eq!(registry.take_delivered(own), None);
+
+        registry.unregister(own);
+        assert!(!registry.is_registered(own));
+    }
+}
@@ -0,0 +1,187 @@
+use anyhow::{Result, bail};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::token_info::TokenInfo;
+
+/// Claim a cached token has to carry, given as `name=value`. Array claims (ex. `roles=admin`) have to
+/// contain the value, other claims have to be equal to it. Nested claims are separated with dots,
+/// ex. `realm_access.roles=admin`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(try_from = "String", into = "String")]
+pub struct RequiredClaim {
+    pub path: String,
+    pub value: String,
+}
+
+impl FromStr for RequiredClaim {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let Some((path, value)) = s.split_once('=') else {
+            bail!("`{}` is not a `name=value` claim", s);
+        };
+
+        if path.trim().is_empty() {
+            bail!("`{}` has no claim name", s);
+        }
+
+        Ok(RequiredClaim {
+            path: path.trim().to_owned(),
+            value: value.trim().to_owned(),
+        })
+    }
+}
+
+impl TryFrom<String> for RequiredClaim {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self> {
+        value.parse()
+    }
+}
+
+impl From<RequiredClaim> for String {
+    fn from(claim: RequiredClaim) -> Self {
+        claim.to_string()
+    }
+}
+
+impl fmt::Display for RequiredClaim {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.path, self.value)
+    }
+}
+
+fn matches(claim: &Value, expected: &str) -> bool {
+    match claim {
+        Value::String(value) => value == expected,
+        Value::Array(values) => values.iter().any(|value| matches(value, expected)),
+        Value::Bool(value) => expected.parse() == Ok(*value),
+        Value::Number(value) => expected
+            .parse()
+            .is_ok_and(|expected: serde_json::Number| expected == *value),
+        Value::Null | Value::Object(_) => false,
+    }
+}
+
+impl RequiredClaim {
+    fn is_satisfied_by(&self, claims: &Value) -> bool {
+        self.path
+            .split('.')
+            .try_fold(claims, |claims, name| claims.get(name))
+            .is_some_and(|claim| matches(claim, &self.value))
+    }
+}
+
+/// Payload of a JWT. The signature isn't verified, tokens come either from the provider or the state file
+pub(crate) fn unverified_claims(token: &str) -> Option<Value> {
+    let payload = token.split('.').nth(1)?;
+    let payload = URL_SAFE_NO_PAD.decode(payload).ok()?;
+
+    serde_json::from_slice(&payload).ok()
+}
+
+/// Claims of the id_token and the access_token, in that order, if they're JWTs
+fn token_claims(token_info: &TokenInfo) -> Vec<Value> {
+    [
+        token_info
+            .extra
+            .get("id_token")
+            .and_then(|token| token.as_str()),
+        Some(token_info.access_token.as_str()),
+    ]
+    .into_iter()
+    .flatten()
+    .filter_map(unverified_claims)
+    .collect()
+}
+
+/// Claims naming the tenant of multi-tenant providers: Entra ID's `tid` or `tenant_id`/`tenant` of others
+const TENANT_CLAIMS: [&str; 3] = ["tid", "tenant_id", "tenant"];
+
+/// Tenant the token was issued for, read from the id_token first
+pub fn tenant(token_info: &TokenInfo) -> Option<String> {
+    token_claims(token_info).iter().find_map(|claims| {
+        TENANT_CLAIMS
+            .iter()
+            .find_map(|name| claims.get(name).and_then(|tenant| tenant.as_str()))
+            .map(|tenant| tenant.to_owned())
+    })
+}
+
+/// Required claims that neither the id_token nor the access_token of the token info carries
+pub fn unmet<'a>(token_info: &TokenInfo, required: &'a [RequiredClaim]) -> Vec<&'a RequiredClaim> {
+    let tokens = token_claims(token_info);
+
+    required
+        .iter()
+        .filter(|claim| !tokens.iter().any(|claims| claim.is_satisfied_by(claims)))
+        .collect()
+}
+
+/// Joins claims for messages ex. `tid=abc, roles=admin`
+pub fn describe(claims: &[&RequiredClaim]) -> String {
+    claims
+        .iter()
+        .map(|claim| format!("`{}`", claim))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn jwt(claims: Value) -> String {
+        format!(
+            "e30.{}.signature",
+            URL_SAFE_NO_PAD.encode(claims.to_string())
+        )
+    }
+
+    #[test]
+    fn it_checks_required_claims_of_cached_tokens() {
+        let mut extra = HashMap::new();
+        extra.insert(
+            "id_token".to_owned(),
+            Value::String(jwt(serde_json::json!({ "tid": "tenant-a" }))),
+        );
+        let token_info = TokenInfo {
+            access_token: jwt(serde_json::json!({
+                "roles": ["reader", "admin"],
+                "realm_access": { "roles": ["ops"] },
+                "email_verified": true
+            })),
+            refresh_token: None,
+            expires: None,
+            scope: None,
+            requested_scope: None,
+            extra,
+        };
+        let required = |claims: &[&str]| -> Vec<RequiredClaim> {
+            claims.iter().map(|claim| claim.parse().unwrap()).collect()
+        };
+
+        let satisfied = required(&[
+            "tid=tenant-a",
+            "roles=admin",
+            "realm_access.roles=ops",
+            "email_verified=true",
+        ]);
+        assert!(unmet(&token_info, &satisfied).is_empty());
+
+        let unsatisfied = required(&["tid=tenant-b", "roles=owner", "missing=x"]);
+        assert_eq!(unmet(&token_info, &unsatisfied).len(), 3);
+
+        assert_eq!(tenant(&token_info).as_deref(), Some("tenant-a"));
+
+        assert!("no-value".parse::<RequiredClaim>().is_err());
+        assert!("=value".parse::<RequiredClaim>().is_err());
+    }
+}
@@ -0,0 +1,115 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::time::{Duration, SystemTime};
+
+use crate::args::Arguments;
+use crate::pkcs11::Pkcs11Signer;
+use crate::signer::{PemSigner, Signer, sign_jwt};
+use crate::yubikey::YubiKeySigner;
+
+/// `client_assertion_type` of `private_key_jwt` <https://www.rfc-editor.org/rfc/rfc7523#section-2.2>
+pub const CLIENT_ASSERTION_TYPE: &str = "urn:ietf:params:oauth:client-assertion-type:jwt-bearer";
+
+/// Assertions are made for a single request
+const LIFETIME: Duration = Duration::from_secs(60);
+
+/// Claims of a client assertion <https://www.rfc-editor.org/rfc/rfc7523#section-3>
+#[derive(Serialize, Debug, PartialEq)]
+struct Claims<'a> {
+    iss: &'a str,
+    sub: &'a str,
+    aud: &'a str,
+    jti: String,
+    iat: u64,
+    exp: u64,
+}
+
+fn claims<'a>(
+    client_id: &'a str,
+    token_endpoint: &'a str,
+    jti: String,
+    now: SystemTime,
+) -> Claims<'a> {
+    let iat = now
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    Claims {
+        iss: client_id,
+        sub: client_id,
+        aud: token_endpoint,
+        jti,
+        iat,
+        exp: iat + LIFETIME.as_secs(),
+    }
+}
+
+/// Signer of `--client-assertion-key`: a YubiKey PIV slot for `yubikey:<slot>`, a key of a PKCS#11
+/// token for `pkcs11:` URIs, otherwise a PEM file
+pub fn signer(args: &Arguments) -> Result<Box<dyn Signer>> {
+    let key = args
+        .client_assertion_key
+        .as_deref()
+        .context("`private-key-jwt` client authentication requires `--client-assertion-key`")?;
+    let key_id = args.client_assertion_kid.to_owned();
+
+    if key.starts_with("pkcs11:") {
+        return Ok(Box::new(Pkcs11Signer::from_uri(
+            key,
+            args.client_assertion_alg,
+            key_id,
+            args.prompt_options(),
+        )?));
+    }
+
+    Ok(match key.strip_prefix("yubikey:") {
+        Some(slot) => Box::new(YubiKeySigner::new(
+            slot,
+            args.client_assertion_alg,
+            key_id,
+            args.prompt_options(),
+        )?),
+        None => Box::new(PemSigner::from_file(
+            key,
+            args.client_assertion_alg,
+            key_id,
+        )?),
+    })
+}
+
+/// Signed JWT authenticating the client at `token_endpoint`
+pub fn client_assertion(
+    signer: &dyn Signer,
+    client_id: &str,
+    token_endpoint: &str,
+    jti: String,
+) -> Result<String> {
+    sign_jwt(
+        signer,
+        &claims(client_id, token_endpoint, jti, SystemTime::now()),
+    )
+    .context("Cannot sign the client assertion")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_makes_single_use_claims_for_the_token_endpoint() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+        assert_eq!(
+            claims("my-client", "https://idp/token", "jti-1".to_owned(), now),
+            Claims {
+                iss: "my-client",
+                sub: "my-client",
+                aud: "https://idp/token",
+                jti: "jti-1".to_owned(),
+                iat: 1_700_000_000,
+                exp: 1_700_000_060,
+            }
+        );
+    }
+}
@@ -0,0 +1,78 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, ValueEnum, Clone, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ClientAuthMethod {
+    /// `client_secret_basic`: client_id and client_secret in HTTP Basic authorization header. More: <https://www.rfc-editor.org/rfc/rfc6749#section-2.3.1>
+    Basic,
+    /// `client_secret_post`: client_id and client_secret in the request body. More: <https://www.rfc-editor.org/rfc/rfc6749#section-2.3.1>
+    Post,
+    /// `none`: public client sending only client_id in the request body
+    None,
+    /// `private_key_jwt`: JWT signed with `--client-assertion-key` in the request body. More: <https://openid.net/specs/openid-connect-core-1_0.html#ClientAuthentication>
+    PrivateKeyJwt,
+}
+
+impl ClientAuthMethod {
+    /// Name used in `token_endpoint_auth_methods_supported` of the discovery document
+    pub fn name(&self) -> &'static str {
+        match self {
+            ClientAuthMethod::Basic => "client_secret_basic",
+            ClientAuthMethod::Post => "client_secret_post",
+            ClientAuthMethod::None => "none",
+            ClientAuthMethod::PrivateKeyJwt => "private_key_jwt",
+        }
+    }
+
+    /// Method used when `--client-auth-method` isn't given. `client_secret_basic` is the default
+    /// of OAuth 2.0, unless the provider advertises only `client_secret_post`
+    pub fn negotiate(supported: &[String], has_secret: bool) -> ClientAuthMethod {
+        let supports = |method: ClientAuthMethod| supported.iter().any(|m| m == method.name());
+
+        if !has_secret {
+            ClientAuthMethod::None
+        } else if !supported.is_empty()
+            && !supports(ClientAuthMethod::Basic)
+            && supports(ClientAuthMethod::Post)
+        {
+            ClientAuthMethod::Post
+        } else {
+            ClientAuthMethod::Basic
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_negotiates_method_from_discovery() {
+        let post_only = vec![
+            "client_secret_post".to_owned(),
+            "private_key_jwt".to_owned(),
+        ];
+        let both = vec![
+            "client_secret_post".to_owned(),
+            "client_secret_basic".to_owned(),
+        ];
+
+        assert_eq!(
+            ClientAuthMethod::negotiate(&post_only, true),
+            ClientAuthMethod::Post
+        );
+        assert_eq!(
+            ClientAuthMethod::negotiate(&both, true),
+            ClientAuthMethod::Basic
+        );
+        assert_eq!(
+            ClientAuthMethod::negotiate(&[], true),
+            ClientAuthMethod::Basic
+        );
+        assert_eq!(
+            ClientAuthMethod::negotiate(&post_only, false),
+            ClientAuthMethod::None
+        );
+    }
+}
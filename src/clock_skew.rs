@@ -0,0 +1,103 @@
+use clap::ValueEnum;
+use oauth2::http::HeaderMap;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime};
+
+use crate::args::Arguments;
+use crate::http_client::parse_http_date;
+use crate::output;
+
+/// What a local clock that differs from the provider's by more than `--max-clock-skew` does to the run
+#[derive(Serialize, Deserialize, ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ClockSkewMode {
+    /// Prints a warning
+    #[default]
+    Warn,
+    /// Prints a warning and converts expirations given in the provider's time, ex. the `exp` claim, to the local clock
+    Adjust,
+    /// Carries on silently
+    Ignore,
+}
+
+/// `doken daemon` refreshes many times, the warning is printed once
+static WARNED: AtomicBool = AtomicBool::new(false);
+
+/// Seconds the provider's clock is ahead of the local one by the `Date` header of its response,
+/// negative when it's behind
+pub fn measure(headers: &HeaderMap, received: SystemTime) -> Option<i64> {
+    let date = parse_http_date(headers.get("date")?.to_str().ok()?.trim())?;
+
+    Some(match date.duration_since(received) {
+        Ok(ahead) => ahead.as_secs() as i64,
+        Err(behind) => -(behind.duration().as_secs() as i64),
+    })
+}
+
+/// Warns about a skew over `--max-clock-skew`. Returns the skew expirations are corrected with,
+/// which only `--clock-skew adjust` does
+pub fn check(args: &Arguments, skew: i64) -> Option<i64> {
+    if args.clock_skew == ClockSkewMode::Ignore {
+        return None;
+    }
+
+    if skew.unsigned_abs() > args.max_clock_skew && !WARNED.swap(true, Ordering::Relaxed) {
+        output::warning(&format!(
+            "The local clock is {} {} the provider's. {}",
+            humantime::format_duration(Duration::from_secs(skew.unsigned_abs())),
+            if skew > 0 { "behind" } else { "ahead of" },
+            match args.clock_skew {
+                ClockSkewMode::Adjust => "Expirations are converted to the local clock",
+                _ => "Fix it or pass `--clock-skew adjust`",
+            }
+        ));
+    }
+
+    (args.clock_skew == ClockSkewMode::Adjust).then_some(skew)
+}
+
+/// Local time of a timestamp given in the provider's time
+pub fn to_local(time: SystemTime, skew: i64) -> SystemTime {
+    let skew_duration = Duration::from_secs(skew.unsigned_abs());
+
+    match skew > 0 {
+        true => time
+            .checked_sub(skew_duration)
+            .unwrap_or(SystemTime::UNIX_EPOCH),
+        false => time + skew_duration,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use oauth2::http::HeaderValue;
+
+    #[test]
+    fn it_measures_the_skew_by_the_date_header() {
+        let received = humantime::parse_rfc3339("2015-10-21T07:28:00Z").unwrap();
+        let headers = |date: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert("date", HeaderValue::from_static(date));
+            headers
+        };
+
+        assert_eq!(
+            measure(&headers("Wed, 21 Oct 2015 09:28:00 GMT"), received),
+            Some(7_200)
+        );
+        assert_eq!(
+            measure(&headers("Wed, 21 Oct 2015 07:27:30 GMT"), received),
+            Some(-30)
+        );
+        assert_eq!(measure(&headers("yesterday"), received), None);
+        assert_eq!(measure(&HeaderMap::new(), received), None);
+
+        assert_eq!(
+            to_local(received + Duration::from_secs(7_200), 7_200),
+            received
+        );
+        assert_eq!(to_local(received - Duration::from_secs(30), -30), received);
+    }
+}
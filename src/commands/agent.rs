@@ -0,0 +1,212 @@
+use anyhow::{Context, Result, bail};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::process::Command;
+use std::time::Duration;
+use std::{env, fs};
+
+use crate::output;
+
+const LABEL: &str = "io.github.riddleman.doken";
+
+fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn plist_path() -> Result<PathBuf> {
+    let mut path = home::home_dir().context("Cannot find the home directory")?;
+    path.push(format!("Library/LaunchAgents/{}.plist", LABEL));
+    Ok(path)
+}
+
+/// Agent started at login in the GUI session, where the login keychain is unlocked, and restarted when it fails
+fn plist(program_arguments: &[String], log_path: &str) -> String {
+    let program_arguments = program_arguments
+        .iter()
+        .map(|argument| format!("        <string>{}</string>", xml_escape(argument)))
+        .collect::<Vec<String>>()
+        .join("\n");
+
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>Label</key>
+    <string>{label}</string>
+    <key>ProgramArguments</key>
+    <array>
+{program_arguments}
+    </array>
+    <key>EnvironmentVariables</key>
+    <dict>
+        <key>NO_COLOR</key>
+        <string>1</string>
+    </dict>
+    <key>RunAtLoad</key>
+    <true/>
+    <key>KeepAlive</key>
+    <dict>
+        <key>SuccessfulExit</key>
+        <false/>
+    </dict>
+    <key>LimitLoadToSessionType</key>
+    <string>Aqua</string>
+    <key>ProcessType</key>
+    <string>Background</string>
+    <key>StandardOutPath</key>
+    <string>{log_path}</string>
+    <key>StandardErrorPath</key>
+    <string>{log_path}</string>
+</dict>
+</plist>
+"#,
+        label = LABEL,
+        log_path = xml_escape(log_path),
+    )
+}
+
+fn daemon_arguments(
+    exe: &str,
+    keep_alive_interval: Duration,
+    health_listen: Option<SocketAddr>,
+) -> Vec<String> {
+    let mut arguments = vec![
+        exe.to_owned(),
+        "daemon".to_owned(),
+        "--keep-alive-interval".to_owned(),
+        humantime::format_duration(keep_alive_interval).to_string(),
+    ];
+
+    if let Some(health_listen) = health_listen {
+        arguments.push("--health-listen".to_owned());
+        arguments.push(health_listen.to_string());
+    }
+
+    arguments
+}
+
+fn launchctl(args: &[&str]) -> Result<()> {
+    log::debug!("Running launchctl {:?}", args);
+
+    let output = Command::new("launchctl")
+        .args(args)
+        .output()
+        .context("Cannot run `launchctl`")?;
+
+    if !output.status.success() {
+        bail!(
+            "`launchctl {}` failed: {}",
+            args.first().unwrap_or(&""),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    Ok(())
+}
+
+/// launchd domain of the logged in user, ex. `gui/501`
+fn gui_domain() -> Result<String> {
+    let output = Command::new("id")
+        .arg("-u")
+        .output()
+        .context("Cannot determine the current user id")?;
+
+    Ok(format!(
+        "gui/{}",
+        String::from_utf8_lossy(&output.stdout).trim()
+    ))
+}
+
+fn assert_macos() -> Result<()> {
+    if !cfg!(target_os = "macos") {
+        bail!(
+            "`doken agent` installs a launchd agent. Use `doken service install` on Windows or a systemd unit on Linux"
+        );
+    }
+
+    Ok(())
+}
+
+/// Writes a launchd agent running `doken daemon` at login and loads it right away
+pub fn install(keep_alive_interval: Duration, health_listen: Option<SocketAddr>) -> Result<()> {
+    assert_macos()?;
+
+    let exe = env::current_exe().context("Cannot find the doken executable")?;
+    let mut log_path = home::home_dir().context("Cannot find the home directory")?;
+    log_path.push("Library/Logs/doken.log");
+
+    let plist_path = plist_path()?;
+    if let Some(dir) = plist_path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(
+        &plist_path,
+        plist(
+            &daemon_arguments(&exe.to_string_lossy(), keep_alive_interval, health_listen),
+            &log_path.to_string_lossy(),
+        ),
+    )?;
+
+    let domain = gui_domain()?;
+    // A previous version of the agent may be loaded
+    let _ = launchctl(&["bootout", &format!("{}/{}", domain, LABEL)]);
+    launchctl(&["bootstrap", &domain, &plist_path.to_string_lossy()])?;
+
+    output::status(
+        "Installed",
+        &format!(
+            "launchd agent `{}` running `doken daemon` at login. Its output goes to {}",
+            LABEL,
+            log_path.display()
+        ),
+    );
+
+    Ok(())
+}
+
+/// Unloads the agent and removes its plist
+pub fn uninstall() -> Result<()> {
+    assert_macos()?;
+
+    let _ = launchctl(&["bootout", &format!("{}/{}", gui_domain()?, LABEL)]);
+
+    let plist_path = plist_path()?;
+    if plist_path.exists() {
+        fs::remove_file(&plist_path)?;
+    }
+
+    output::status("Uninstalled", &format!("launchd agent `{}`", LABEL));
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_renders_an_agent_running_the_daemon() {
+        let arguments = daemon_arguments(
+            "/Users/a&b/.cargo/bin/doken",
+            Duration::from_secs(4 * 60 * 60),
+            None,
+        );
+        assert_eq!(
+            arguments,
+            vec![
+                "/Users/a&b/.cargo/bin/doken",
+                "daemon",
+                "--keep-alive-interval",
+                "4h"
+            ]
+        );
+
+        let plist = plist(&arguments, "/Users/a&b/Library/Logs/doken.log");
+        assert!(plist.contains("<string>/Users/a&amp;b/.cargo/bin/doken</string>"));
+        assert!(plist.contains("<string>Aqua</string>"));
+    }
+}
@@ -0,0 +1,192 @@
+use anyhow::{Context, Result, bail};
+use futures::StreamExt;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use crate::args::Arguments;
+use crate::file_state::FileState;
+use crate::grant::Grant;
+use crate::oauth_client::OAuthClient;
+use crate::output;
+use crate::retrievers::client_credentials_retriever::ClientCredentialsRetriever;
+use crate::retrievers::token_retriever::TokenRetriever;
+use crate::token_info::TokenInfo;
+
+/// Nearest-rank percentile of sorted durations
+fn percentile(sorted: &[Duration], percent: usize) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+
+    let rank = (percent * sorted.len()).div_ceil(100).max(1);
+    sorted[rank - 1]
+}
+
+fn millis(duration: Duration) -> String {
+    format!("{:.1}ms", duration.as_secs_f64() * 1000.0)
+}
+
+async fn exchange(
+    oauth_client: &OAuthClient<'_>,
+    refresh_token: Option<&str>,
+) -> Result<TokenInfo> {
+    match refresh_token {
+        Some(refresh_token) => Ok(TokenInfo::from_token_response(
+            oauth_client.refresh_token(refresh_token.to_owned()).await?,
+        )),
+        None => {
+            ClientCredentialsRetriever::new(oauth_client)
+                .retrieve()
+                .await
+        }
+    }
+}
+
+pub async fn run(args: Arguments, requests: usize, concurrency: usize) -> Result<()> {
+    let oauth_client = OAuthClient::new(&args).await?;
+    let mut file_state = FileState::new()?;
+
+    let refresh_token = match args.grant {
+        Grant::ClientCredentials => None,
+        _ => Some(
+            file_state
+                .read_token_info(&args.state_key())
+                .and_then(|token_info| token_info.refresh_token)
+                .with_context(|| {
+                    format!(
+                        "No refresh_token cached for `{}`. Get a token first or use `--grant client-credentials`",
+                        args.state_key()
+                    )
+                })?,
+        ),
+    };
+
+    // Rotated refresh tokens are revoked with their whole family when reused, so each
+    // refresh redeems the token returned by the previous one
+    let concurrency = match refresh_token {
+        Some(_) if concurrency > 1 => {
+            output::warning("Refresh tokens are exchanged one at a time, ignoring `--concurrency`");
+            1
+        }
+        _ => concurrency.max(1),
+    };
+
+    output::status(
+        "Benchmarking",
+        &format!(
+            "{} {} exchanges, {} at a time",
+            requests,
+            if refresh_token.is_some() {
+                "refresh_token"
+            } else {
+                "client credentials"
+            },
+            concurrency
+        ),
+    );
+
+    let started = Instant::now();
+    let mut durations = vec![];
+    let mut errors: BTreeMap<String, usize> = BTreeMap::new();
+
+    match refresh_token {
+        Some(mut refresh_token) => {
+            let mut last_token_info = None;
+
+            for _ in 0..requests {
+                let start = Instant::now();
+                match exchange(&oauth_client, Some(&refresh_token)).await {
+                    Ok(mut token_info) => {
+                        durations.push(start.elapsed());
+                        match &token_info.refresh_token {
+                            Some(rotated) => refresh_token = rotated.to_owned(),
+                            None => token_info.refresh_token = Some(refresh_token.to_owned()),
+                        }
+                        last_token_info = Some(token_info);
+                    }
+                    Err(e) => *errors.entry(format!("{:#}", e)).or_default() += 1,
+                }
+            }
+
+            // The cached refresh_token may have been rotated away, the latest one is kept instead
+            if let Some(token_info) = last_token_info {
+                file_state.upsert_token_info(args.state_key(), token_info)?;
+            }
+        }
+        None => {
+            let results = futures::stream::iter(0..requests)
+                .map(|_| {
+                    let oauth_client = &oauth_client;
+
+                    async move {
+                        let start = Instant::now();
+                        let result = exchange(oauth_client, None).await;
+
+                        (start.elapsed(), result)
+                    }
+                })
+                .buffer_unordered(concurrency)
+                .collect::<Vec<_>>()
+                .await;
+
+            for (duration, result) in results {
+                match result {
+                    Ok(_) => durations.push(duration),
+                    Err(e) => *errors.entry(format!("{:#}", e)).or_default() += 1,
+                }
+            }
+        }
+    }
+    let elapsed = started.elapsed();
+
+    for (error, count) in &errors {
+        output::warning(&format!("{}x {}", count, error));
+    }
+
+    durations.sort();
+    let failed = errors.values().sum::<usize>();
+
+    println!(
+        "{}",
+        output::key_values(&[
+            ("requests", requests.to_string()),
+            (
+                "errors",
+                format!(
+                    "{} ({:.1}%)",
+                    failed,
+                    failed as f64 * 100.0 / requests.max(1) as f64
+                )
+            ),
+            ("p50", millis(percentile(&durations, 50))),
+            ("p90", millis(percentile(&durations, 90))),
+            ("p99", millis(percentile(&durations, 99))),
+            ("max", millis(durations.last().copied().unwrap_or_default())),
+            (
+                "throughput",
+                format!("{:.1}/s", requests as f64 / elapsed.as_secs_f64())
+            ),
+        ])
+    );
+
+    if durations.is_empty() && requests > 0 {
+        bail!("All exchanges failed");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_computes_nearest_rank_percentiles() {
+        let durations: Vec<Duration> = (1..=10).map(Duration::from_millis).collect();
+
+        assert_eq!(percentile(&durations, 50), Duration::from_millis(5));
+        assert_eq!(percentile(&durations, 90), Duration::from_millis(9));
+        assert_eq!(percentile(&durations, 99), Duration::from_millis(10));
+        assert_eq!(percentile(&[], 50), Duration::ZERO);
+    }
+}
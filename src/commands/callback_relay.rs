@@ -0,0 +1,33 @@
+use anyhow::{Result, bail};
+use std::path::Path;
+use url::Url;
+
+#[cfg(unix)]
+use crate::loopback;
+
+/// Hands `url` over to the doken run waiting for the callback on `--callback-socket`
+#[cfg(unix)]
+pub async fn run(socket: &Path, url: Url) -> Result<()> {
+    use anyhow::Context;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let mut stream = tokio::net::UnixStream::connect(socket)
+        .await
+        .with_context(|| format!("Cannot connect to the callback socket {}", socket.display()))?;
+    stream
+        .write_all(loopback::relayed_request(&url).as_bytes())
+        .await?;
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await?;
+
+    match loopback::request_line(&response) {
+        Some((_, "200")) => Ok(()),
+        _ => bail!("doken didn't accept the callback {}", url),
+    }
+}
+
+#[cfg(not(unix))]
+pub async fn run(_socket: &Path, _url: Url) -> Result<()> {
+    bail!("`doken callback-relay` requires unix domain sockets, which this platform doesn't have")
+}
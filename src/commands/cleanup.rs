@@ -0,0 +1,270 @@
+use anyhow::{Context, Result, bail};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, SystemTime};
+
+use crate::auth_browser::browser::{
+    self, EPHEMERAL_USER_DATA_DIR_PREFIX, USER_DATA_DIR_PREFIX, user_data_dir,
+};
+use crate::output;
+
+/// Profile used by doken versions launching Chromium with chromiumoxide's default directory
+const LEGACY_PROFILE: &str = "chromiumoxide-runner";
+/// Files Chromium leaves behind after a crash, making the next launch think the profile is in use
+const SINGLETON_FILES: [&str; 3] = ["SingletonLock", "SingletonSocket", "SingletonCookie"];
+
+#[derive(Debug, Clone)]
+struct Process {
+    pid: u32,
+    ppid: u32,
+    command: String,
+}
+
+fn parse_processes(text: &str) -> Vec<Process> {
+    text.lines()
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            let pid = parts.next()?.parse().ok()?;
+            let ppid = parts.next()?.parse().ok()?;
+
+            Some(Process {
+                pid,
+                ppid,
+                command: parts.collect::<Vec<&str>>().join(" "),
+            })
+        })
+        .collect()
+}
+
+fn processes() -> Result<Vec<Process>> {
+    let output = if cfg!(windows) {
+        Command::new("powershell")
+            .args([
+                "-NoProfile",
+                "-Command",
+                "Get-CimInstance Win32_Process | ForEach-Object { \"$($_.ProcessId) $($_.ParentProcessId) $($_.CommandLine)\" }",
+            ])
+            .output()
+    } else {
+        Command::new("ps").args(["-axo", "pid=,ppid=,command="]).output()
+    }
+    .context("Cannot list processes")?;
+
+    Ok(parse_processes(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// `--user-data-dir` of a Chromium command line, cut at the next argument
+fn user_data_dir_of(command: &str) -> Option<&str> {
+    let (_, rest) = command.split_once("--user-data-dir=")?;
+
+    rest.split(" --").next()
+}
+
+/// Main Chromium processes using doken's profile whose owning doken run, as written to the profile
+/// on launch, is gone. Browsers of profiles without an owner are left alone. Their helpers exit with them
+fn orphaned_browsers(
+    processes: &[Process],
+    profile_arg: &str,
+    owner: impl Fn(&Path) -> Option<u32>,
+) -> Vec<u32> {
+    let browsers: HashSet<u32> = processes
+        .iter()
+        .filter(|process| process.command.contains(profile_arg))
+        .map(|process| process.pid)
+        .collect();
+
+    processes
+        .iter()
+        .filter(|process| browsers.contains(&process.pid) && !browsers.contains(&process.ppid))
+        .filter(|process| {
+            user_data_dir_of(&process.command)
+                .and_then(|dir| owner(Path::new(dir)))
+                .is_some_and(|owner| !processes.iter().any(|process| process.pid == owner))
+        })
+        .map(|process| process.pid)
+        .collect()
+}
+
+/// Per-run profiles last modified longer than `max_age` ago
+fn ephemeral_profiles(max_age: Duration) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(std::env::temp_dir()) else {
+        return vec![];
+    };
+
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            entry
+                .file_name()
+                .to_string_lossy()
+                .starts_with(EPHEMERAL_USER_DATA_DIR_PREFIX)
+        })
+        .filter(|entry| {
+            entry
+                .metadata()
+                .and_then(|metadata| metadata.modified())
+                .is_ok_and(|modified| {
+                    SystemTime::now()
+                        .duration_since(modified)
+                        .unwrap_or_default()
+                        >= max_age
+                })
+        })
+        .map(|entry| entry.path())
+        .collect()
+}
+
+fn is_used(profile: &Path, processes: &[Process]) -> bool {
+    let profile_arg = format!("--user-data-dir={}", profile.display());
+
+    processes
+        .iter()
+        .any(|process| process.command.contains(&profile_arg))
+}
+
+/// Removes per-run profiles older than `max_age` that no running browser uses. Never fails the run,
+/// processes are listed only if there's something to remove
+pub fn remove_stale_ephemeral_profiles(max_age: Duration) -> Vec<PathBuf> {
+    let profiles = ephemeral_profiles(max_age);
+    if profiles.is_empty() {
+        return vec![];
+    }
+
+    let Ok(processes) = processes() else {
+        return vec![];
+    };
+
+    profiles
+        .into_iter()
+        .filter(|profile| !is_used(profile, &processes))
+        .filter(|profile| {
+            let removed = fs::remove_dir_all(profile).is_ok();
+            log::debug!(
+                "Removing stale browser profile {}: {}",
+                profile.display(),
+                removed
+            );
+            removed
+        })
+        .collect()
+}
+
+fn kill(pid: u32) -> Result<()> {
+    let status = if cfg!(windows) {
+        Command::new("taskkill")
+            .args(["/T", "/F", "/PID", &pid.to_string()])
+            .status()
+    } else {
+        Command::new("kill").arg(pid.to_string()).status()
+    }?;
+
+    if !status.success() {
+        bail!("Cannot stop process {}", pid);
+    }
+
+    Ok(())
+}
+
+/// Stops orphaned Chromium processes of doken, removes locks they left behind and old temporary profiles.
+/// The state file needs no cleanup, its locks are released by the OS together with the process
+pub fn run(browser_profile: bool) -> Result<()> {
+    let profile = user_data_dir();
+    let profile_arg = format!("--user-data-dir={}", profile.display());
+    let processes = processes()?;
+
+    // Browsers of both the persistent and per-run profiles
+    let any_profile_arg = format!(
+        "--user-data-dir={}",
+        std::env::temp_dir().join(USER_DATA_DIR_PREFIX).display()
+    );
+    let orphans = orphaned_browsers(&processes, &any_profile_arg, browser::owner);
+    for pid in &orphans {
+        match kill(*pid) {
+            Ok(()) => output::status("Stopped", &format!("orphaned Chromium process {}", pid)),
+            Err(e) => output::warning(&format!("{:#}", e)),
+        }
+    }
+
+    let in_use = processes
+        .iter()
+        .any(|process| process.command.contains(&profile_arg) && !orphans.contains(&process.pid));
+
+    if in_use {
+        output::warning(&format!(
+            "{} is used by a running doken, leaving it as is",
+            profile.display()
+        ));
+    } else if browser_profile && profile.exists() {
+        fs::remove_dir_all(&profile)?;
+        output::status("Removed", &format!("{}", profile.display()));
+    } else {
+        for file in SINGLETON_FILES.map(|file| profile.join(file)) {
+            // Singleton files are symlinks pointing to nowhere after a crash, so `exists` can't be used
+            if fs::remove_file(&file).is_ok() {
+                output::status("Removed", &format!("stale lock {}", file.display()));
+            }
+        }
+    }
+
+    // Profiles of the stopped orphans are free now
+    for removed in remove_stale_ephemeral_profiles(Duration::ZERO) {
+        output::status("Removed", &format!("{}", removed.display()));
+    }
+
+    let legacy = std::env::temp_dir().join(LEGACY_PROFILE);
+    if legacy.exists() {
+        fs::remove_dir_all(&legacy)?;
+        output::status("Removed", &format!("{}", legacy.display()));
+    }
+
+    output::status("Cleaned", "up doken's leftovers");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_finds_browsers_whose_owner_is_gone() {
+        let processes = parse_processes(
+            "    1     0 /sbin/init
+  100     1 /usr/local/bin/dk --profile work
+  101   100 chromium --user-data-dir=/tmp/doken-ephemeral-100-0000abcd --no-first-run
+  102   101 chromium --type=renderer --user-data-dir=/tmp/doken-ephemeral-100-0000abcd
+  200     1 chromium --user-data-dir=/tmp/doken-browser --no-first-run
+  201   200 chromium --type=renderer --user-data-dir=/tmp/doken-browser
+  300     1 chromium --user-data-dir=/tmp/doken-ephemeral-300-0000abcd
+  400     1 chromium --user-data-dir=/home/me/.config/chromium",
+        );
+        let owner = |dir: &Path| match dir.to_str() {
+            Some("/tmp/doken-ephemeral-100-0000abcd") => Some(100),
+            Some("/tmp/doken-browser") => Some(150),
+            _ => None,
+        };
+
+        assert_eq!(
+            orphaned_browsers(&processes, "--user-data-dir=/tmp/doken-", owner),
+            vec![200]
+        );
+    }
+
+    #[test]
+    fn it_keeps_ephemeral_profiles_of_running_browsers() {
+        let processes = parse_processes(
+            "  100     1 chromium --user-data-dir=/tmp/doken-ephemeral-100-0000abcd --no-first-run",
+        );
+
+        assert!(is_used(
+            Path::new("/tmp/doken-ephemeral-100-0000abcd"),
+            &processes
+        ));
+        assert!(!is_used(
+            Path::new("/tmp/doken-ephemeral-200-0000abcd"),
+            &processes
+        ));
+    }
+}
@@ -0,0 +1,201 @@
+use anyhow::{Result, bail};
+use clap::{Command, CommandFactory, Subcommand};
+use clap_complete::Shell;
+use std::collections::BTreeSet;
+
+use crate::args::{Arguments, DokenCommand};
+use crate::config_file::ConfigFile;
+use crate::file_state::FileState;
+
+const BASH: &str = r#"_doken_dynamic() {
+    local IFS=$'\n'
+    COMPREPLY=($(doken __complete -- "${COMP_WORDS[@]:1:COMP_CWORD}" 2>/dev/null))
+}
+complete -o default -F _doken_dynamic doken
+"#;
+
+const ZSH: &str = r#"#compdef doken
+_doken_dynamic() {
+    local -a candidates
+    candidates=("${(@f)$(doken __complete -- "${(@)words[2,CURRENT]}" 2>/dev/null)}")
+    compadd -a candidates
+}
+compdef _doken_dynamic doken
+"#;
+
+const FISH: &str = r#"complete -c doken -f -a '(doken __complete -- (commandline -opc)[2..-1] (commandline -ct) 2>/dev/null)'
+"#;
+
+/// Values known only at runtime, read from ~/.doken/config.toml and the state file
+struct Values {
+    profiles: Vec<String>,
+    audiences: Vec<String>,
+}
+
+impl Values {
+    async fn read() -> Values {
+        let config = ConfigFile::new().read().await;
+        let state_keys = FileState::new()
+            .map(|mut file_state| file_state.state_keys())
+            .unwrap_or_default();
+
+        // State keys of audiences and resources are `<client_id>#<audiences>`
+        let audiences: BTreeSet<String> = state_keys
+            .iter()
+            .filter_map(|state_key| state_key.split_once('#'))
+            .flat_map(|(_, audiences)| audiences.split_whitespace())
+            .chain(
+                config
+                    .profile
+                    .values()
+                    .filter_map(|profile| profile.audience.as_deref()),
+            )
+            .map(|audience| audience.to_owned())
+            .collect();
+
+        Values {
+            profiles: config
+                .profile
+                .into_keys()
+                .collect::<BTreeSet<_>>()
+                .into_iter()
+                .collect(),
+            audiences: audiences.into_iter().collect(),
+        }
+    }
+}
+
+/// The top level command, with subcommands attached as in `--help`
+fn command() -> Command {
+    DokenCommand::augment_subcommands(Arguments::command()).name("doken")
+}
+
+/// Candidates for the last of `words`, the command line after `doken` up to the cursor
+fn candidates(command: &Command, words: &[String], values: &Values) -> Vec<String> {
+    let (current, before) = match words.split_last() {
+        Some((current, before)) => (current.as_str(), before),
+        None => ("", &[][..]),
+    };
+
+    let mut command = command;
+    for word in before {
+        match command.find_subcommand(word) {
+            Some(subcommand) => command = subcommand,
+            None => break,
+        }
+    }
+
+    let option = before
+        .last()
+        .and_then(|word| word.strip_prefix("--"))
+        .and_then(|long| {
+            command
+                .get_arguments()
+                .find(|arg| arg.get_long() == Some(long))
+        })
+        .filter(|arg| arg.get_action().takes_values());
+
+    let all: Vec<String> = match option {
+        Some(arg) => match arg.get_id().as_str() {
+            "profile" | "upstream_profile" => values.profiles.clone(),
+            "audience" => values.audiences.clone(),
+            _ => arg
+                .get_possible_values()
+                .iter()
+                .filter(|value| !value.is_hide_set())
+                .map(|value| value.get_name().to_owned())
+                .collect(),
+        },
+        None if current.starts_with('-') => command
+            .get_arguments()
+            .filter(|arg| !arg.is_hide_set())
+            .filter_map(|arg| arg.get_long())
+            .map(|long| format!("--{}", long))
+            .collect(),
+        None => command
+            .get_subcommands()
+            .filter(|subcommand| !subcommand.is_hide_set())
+            .map(|subcommand| subcommand.get_name().to_owned())
+            .collect(),
+    };
+
+    all.into_iter()
+        .filter(|candidate| candidate.starts_with(current))
+        .collect()
+}
+
+/// Prints a completion script. Dynamic ones ask `doken __complete` for profiles and audiences
+pub fn run(shell: Shell, dynamic: bool) -> Result<()> {
+    if !dynamic {
+        clap_complete::generate(shell, &mut command(), "doken", &mut std::io::stdout());
+        return Ok(());
+    }
+
+    let script = match shell {
+        Shell::Bash => BASH,
+        Shell::Zsh => ZSH,
+        Shell::Fish => FISH,
+        shell => bail!(
+            "`--dynamic` completions support bash, zsh and fish, not {}",
+            shell
+        ),
+    };
+    print!("{}", script);
+
+    Ok(())
+}
+
+/// Answers the dynamic completion scripts, one candidate per line
+pub async fn complete(words: Vec<String>) -> Result<()> {
+    let values = Values::read().await;
+
+    for candidate in candidates(&command(), &words, &values) {
+        println!("{}", candidate);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(words: &[&str]) -> Vec<String> {
+        words.iter().map(|word| word.to_string()).collect()
+    }
+
+    #[test]
+    fn it_completes_profiles_audiences_and_values() {
+        let command = command();
+        let values = Values {
+            profiles: words(&["dev", "prod"]),
+            audiences: words(&["api://billing", "https://graph.microsoft.com"]),
+        };
+
+        assert_eq!(
+            candidates(&command, &words(&["--profile", "p"]), &values),
+            words(&["prod"])
+        );
+        assert_eq!(
+            candidates(&command, &words(&["--audience", "api"]), &values),
+            words(&["api://billing"])
+        );
+        assert_eq!(
+            candidates(&command, &words(&["--provider", "a"]), &values),
+            words(&["adfs", "azure"])
+        );
+        assert_eq!(
+            candidates(&command, &words(&["watch", "--profile", ""]), &values),
+            words(&["dev", "prod"])
+        );
+        assert_eq!(
+            candidates(&command, &words(&["--discovery-u"]), &values),
+            words(&["--discovery-url"])
+        );
+        assert_eq!(
+            candidates(&command, &words(&["tok"]), &values),
+            words(&["token"])
+        );
+        assert!(!candidates(&command, &words(&[""]), &values).contains(&"__complete".to_owned()));
+    }
+}
@@ -0,0 +1,580 @@
+use anyhow::{Result, anyhow, bail};
+use std::collections::BTreeSet;
+use std::env;
+
+use crate::client_auth_method::ClientAuthMethod;
+use crate::config_file::{self, Config, ConfigFile, Profile};
+use crate::grant::Grant;
+use crate::http_client::parse_extra_header;
+use crate::openidc_discovery::parse_discovery_mapping;
+use crate::output;
+
+#[derive(Debug, PartialEq)]
+enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug)]
+struct Problem {
+    line: Option<usize>,
+    severity: Severity,
+    message: String,
+}
+
+/// 1-based line of a `[section]` header or of a `key = ` inside of it
+fn line_of(text: &str, section: &str, key: Option<&str>) -> Option<usize> {
+    let quoted = section.replacen('.', ".\"", 1) + "\"";
+    let mut lines = text.lines().enumerate().skip_while(|(_, line)| {
+        let header = line.trim().trim_start_matches('[').trim_end_matches(']');
+        !(line.trim().starts_with('[') && (header == section || header == quoted))
+    });
+
+    let (header_index, _) = lines.next()?;
+
+    let Some(key) = key else {
+        return Some(header_index + 1);
+    };
+
+    lines
+        .take_while(|(_, line)| !line.trim().starts_with('['))
+        .find(|(_, line)| {
+            line.trim()
+                .strip_prefix(key)
+                .is_some_and(|rest| rest.trim_start().starts_with('='))
+        })
+        .map(|(index, _)| index + 1)
+}
+
+fn line_of_offset(text: &str, offset: usize) -> usize {
+    text[..offset.min(text.len())].matches('\n').count() + 1
+}
+
+struct ProfileLint<'a> {
+    text: &'a str,
+    name: &'a str,
+    problems: Vec<Problem>,
+}
+
+impl ProfileLint<'_> {
+    fn report(&mut self, key: Option<&str>, severity: Severity, message: String) {
+        let section = format!("profile.{}", self.name);
+        let line = key
+            .and_then(|key| line_of(self.text, &section, Some(key)))
+            .or_else(|| line_of(self.text, &section, None));
+
+        self.problems.push(Problem {
+            line,
+            severity,
+            message: format!("profile `{}`: {}", self.name, message),
+        });
+    }
+
+    fn require(&mut self, present: bool, message: &str) {
+        if !present {
+            self.report(None, Severity::Warning, message.to_owned());
+        }
+    }
+
+    fn check_urls(&mut self, profile: &Profile) {
+        let has_oauth2 = profile.token_url.is_some() && profile.authorization_url.is_some();
+
+        self.require(
+            has_oauth2 || profile.discovery_url.is_some(),
+            "`discovery_url` or `token_url` and `authorization_url` have to be set or passed in the command line",
+        );
+        self.require(
+            profile.callback_url.is_some(),
+            "`callback_url` has to be set or passed in the command line",
+        );
+    }
+
+    fn check_token_url(&mut self, profile: &Profile) {
+        self.require(
+            profile.token_url.is_some() || profile.discovery_url.is_some(),
+            "`token_url` or `discovery_url` has to be set or passed in the command line",
+        );
+    }
+
+    fn check_grant(&mut self, profile: &Profile, config: &Config) {
+        let grant = profile
+            .grant
+            .clone()
+            .unwrap_or(Grant::AuthorizationCodeWithPkce);
+
+        match grant {
+            Grant::AuthorizationCodeWithPkce | Grant::AuthorizationCode => self.check_urls(profile),
+            Grant::OktaSessionToken => {
+                self.check_urls(profile);
+                self.require(
+                    profile.username.is_some(),
+                    "`username` is required by `okta-session-token` grant",
+                );
+            }
+            Grant::ResourceOwnerPasswordClientCredentials => {
+                self.check_token_url(profile);
+                self.require(
+                    profile.client_secret.is_some(),
+                    "`client_secret` has to be set or passed with `--client-secret-stdin`",
+                );
+                self.require(
+                    profile.username.is_some(),
+                    "`username` is required by `resource-owner-password-client-credentials` grant",
+                );
+                self.require(
+                    profile.password.is_some(),
+                    "`password` has to be set or passed with `--password-stdin`",
+                );
+            }
+            Grant::ClientCredentials => {
+                self.check_token_url(profile);
+                self.require(
+                    profile.client_secret.is_some(),
+                    "`client_secret` has to be set or passed with `--client-secret-stdin`",
+                );
+            }
+            Grant::Implicit => {
+                if profile.token_url.is_some() {
+                    self.report(
+                        Some("token_url"),
+                        Severity::Error,
+                        "`token_url` cannot be used with `implicit` grant".to_owned(),
+                    );
+                }
+
+                self.require(
+                    profile.authorization_url.is_some() || profile.discovery_url.is_some(),
+                    "`authorization_url` or `discovery_url` has to be set or passed in the command line",
+                );
+                self.require(
+                    profile.callback_url.is_some(),
+                    "`callback_url` has to be set or passed in the command line",
+                );
+            }
+            Grant::JwtBearer => {
+                self.check_token_url(profile);
+                self.require(
+                    profile.assertion_key_file.is_some(),
+                    "`assertion_key_file` is required by `jwt-bearer` grant",
+                );
+            }
+            Grant::Saml2Bearer => {
+                self.check_token_url(profile);
+                self.require(
+                    profile.saml_assertion_file.is_some(),
+                    "`saml_assertion_file` is required by `saml2-bearer` grant",
+                );
+            }
+            Grant::DeviceCode => {
+                self.check_token_url(profile);
+                self.require(
+                    profile.device_authorization_url.is_some() || profile.discovery_url.is_some(),
+                    "`device_authorization_url` or `discovery_url` has to be set or passed in the command line",
+                );
+            }
+            Grant::Auto => self.require(
+                profile.discovery_url.is_some(),
+                "`auto` grant relies on `grant_types_supported`, so `discovery_url` should be set",
+            ),
+            Grant::TokenExchange => {
+                self.check_token_url(profile);
+
+                match profile.upstream_profile.as_deref() {
+                    None => self.require(
+                        false,
+                        "`upstream_profile` has to be set or passed with `--upstream-profile`",
+                    ),
+                    Some(upstream) => match config.profile.get(upstream) {
+                        None => self.report(
+                            Some("upstream_profile"),
+                            Severity::Error,
+                            format!("upstream profile `{}` doesn't exist", upstream),
+                        ),
+                        Some(Profile {
+                            grant: Some(Grant::TokenExchange),
+                            ..
+                        }) => self.report(
+                            Some("upstream_profile"),
+                            Severity::Error,
+                            format!(
+                                "upstream profile `{}` cannot use `token-exchange` grant itself",
+                                upstream
+                            ),
+                        ),
+                        Some(_) => {}
+                    },
+                }
+            }
+        }
+    }
+
+    fn check(&mut self, profile: &Profile, raw: Option<&toml::Value>, config: &Config) {
+        if let Some(raw) = raw.and_then(|raw| raw.as_table()) {
+            let known = config_file::profile_keys();
+
+            for key in raw.keys().filter(|key| !known.contains(*key)) {
+                self.report(
+                    Some(key),
+                    Severity::Error,
+                    format!("unknown option `{}`", key),
+                );
+            }
+
+            for key in REDACTED_KEYS {
+                let plain = raw
+                    .get(key)
+                    .and_then(|value| value.as_str())
+                    .is_some_and(|value| !value.contains("${"));
+
+                if plain {
+                    self.report(
+                        Some(key),
+                        Severity::Warning,
+                        format!(
+                            "`{}` is stored in plain text. Consider an environment variable reference, ex. `\"${{MY_SECRET}}\"`",
+                            key
+                        ),
+                    );
+                }
+            }
+        }
+
+        if profile.discovery_url.is_some()
+            && (profile.token_url.is_some() || profile.authorization_url.is_some())
+        {
+            self.report(
+                Some("discovery_url"),
+                Severity::Error,
+                "`discovery_url` cannot be used with `token_url` or `authorization_url`".to_owned(),
+            );
+        }
+
+        if profile.client_id.is_none() {
+            self.require(false, "`client_id` has to be passed in the command line");
+        }
+
+        if profile.client_auth_method == Some(ClientAuthMethod::None)
+            && profile.client_secret.is_some()
+        {
+            self.report(
+                Some("client_auth_method"),
+                Severity::Warning,
+                "`client_secret` is never sent with `client_auth_method = \"none\"`".to_owned(),
+            );
+        }
+
+        if profile.client_auth_method == Some(ClientAuthMethod::PrivateKeyJwt)
+            && profile.client_assertion_key.is_none()
+        {
+            self.report(
+                Some("client_auth_method"),
+                Severity::Error,
+                "`client_auth_method = \"private-key-jwt\"` requires `client_assertion_key`"
+                    .to_owned(),
+            );
+        }
+
+        for (field, path) in profile.discovery_map.iter().flatten() {
+            if let Err(e) = parse_discovery_mapping(&format!("{}={}", field, path)) {
+                let section = format!("profile.{}.discovery_map", self.name);
+                self.problems.push(Problem {
+                    line: line_of(self.text, &section, Some(field)),
+                    severity: Severity::Error,
+                    message: format!("profile `{}`: {}", self.name, e),
+                });
+            }
+        }
+
+        for (name, value) in profile.extra_headers.iter().flatten() {
+            if let Err(e) = parse_extra_header(&format!("{}: {}", name, value)) {
+                let section = format!("profile.{}.extra_headers", self.name);
+                self.problems.push(Problem {
+                    line: line_of(self.text, &section, Some(name)),
+                    severity: Severity::Error,
+                    message: format!("profile `{}`: {}", self.name, e),
+                });
+            }
+        }
+
+        if let Some(Err(e)) = profile
+            .scope
+            .as_deref()
+            .map(|scope| config_file::expand_scope_aliases(scope, &config.scopes))
+        {
+            self.report(Some("scope"), Severity::Error, e.to_string());
+        }
+
+        if profile.discovery_map.is_some() && profile.discovery_url.is_none() {
+            self.require(false, "`discovery_map` is used only with `discovery_url`");
+        }
+
+        if profile
+            .fallback_grant
+            .as_ref()
+            .is_some_and(|fallback_grant| fallback_grant.uses_browser())
+        {
+            self.report(
+                Some("fallback_grant"),
+                Severity::Error,
+                "`fallback_grant` cannot need a browser".to_owned(),
+            );
+        }
+
+        self.check_grant(profile, config);
+    }
+}
+
+fn lint_text(text: &str) -> Vec<Problem> {
+    let raw = match toml::from_str::<toml::Value>(text) {
+        Ok(raw) => raw,
+        Err(e) => {
+            return vec![Problem {
+                line: e.span().map(|span| line_of_offset(text, span.start)),
+                severity: Severity::Error,
+                message: e.message().to_owned(),
+            }];
+        }
+    };
+
+    let (config, missing) = match config_file::parse(text) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            return vec![Problem {
+                line: None,
+                severity: Severity::Error,
+                message: format!("{:#}", e),
+            }];
+        }
+    };
+
+    let mut problems: Vec<Problem> = missing
+        .into_iter()
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .map(|name| Problem {
+            line: text
+                .find(&format!("${{{}}}", name))
+                .map(|offset| line_of_offset(text, offset)),
+            severity: Severity::Error,
+            message: format!("environment variable `{}` is not set", name),
+        })
+        .collect();
+
+    let mut names: Vec<&String> = config.profile.keys().collect();
+    names.sort();
+
+    for name in names {
+        let mut profile_lint = ProfileLint {
+            text,
+            name,
+            problems: vec![],
+        };
+        let raw_profile = raw.get("profile").and_then(|profiles| profiles.get(name));
+
+        profile_lint.check(&config.profile[name], raw_profile, &config);
+        problems.extend(profile_lint.problems);
+    }
+
+    problems.sort_by_key(|problem| problem.line);
+    problems
+}
+
+/// Validates ~/.doken/config.toml without running any flow and reports all problems at once
+pub async fn lint() -> Result<()> {
+    let config_file = ConfigFile::new();
+    let path = config_file.path().to_string_lossy().to_string();
+    let text = config_file.read_text().await?;
+
+    let problems = lint_text(&text);
+
+    for problem in &problems {
+        let location = match problem.line {
+            Some(line) => format!("{}:{}", path, line),
+            None => path.to_owned(),
+        };
+        let message = format!("{}: {}", location, problem.message);
+
+        match problem.severity {
+            Severity::Error => output::error(&anyhow!(message)),
+            Severity::Warning => output::warning(&message),
+        }
+    }
+
+    let errors = problems
+        .iter()
+        .filter(|problem| problem.severity == Severity::Error)
+        .count();
+
+    if errors > 0 {
+        bail!(
+            "{} has {} error(s) and {} warning(s)",
+            path,
+            errors,
+            problems.len() - errors
+        );
+    }
+
+    output::status(
+        "Checked",
+        &format!("{} with {} warning(s)", path, problems.len()),
+    );
+
+    Ok(())
+}
+
+const REDACTED_KEYS: [&str; 4] = ["client_secret", "password", "otp_secret", "webhook_secret"];
+
+fn display_value(key: &str, value: &toml::Value) -> String {
+    match value {
+        _ if REDACTED_KEYS.contains(&key) => "********".to_owned(),
+        toml::Value::String(value) => value.to_owned(),
+        value => value.to_string(),
+    }
+}
+
+/// Options of a profile with their sources. Plain `DOKEN_<OPTION>` variables apply only to options the profile doesn't set
+fn effective_options(
+    name: &str,
+    profile: &Profile,
+    fields: &[config_file::EnvProfileField],
+    vars: &[(String, String)],
+) -> Result<Vec<(String, String)>> {
+    let values = toml::Value::try_from(profile).map_err(|e| anyhow!(e))?;
+    let values = values.as_table().cloned().unwrap_or_default();
+    let mut options = vec![];
+
+    for key in config_file::profile_keys() {
+        let env_field = fields
+            .iter()
+            .find(|field| field.profile == name && field.key == key);
+
+        let (value, source) = match (values.get(&key), env_field) {
+            (Some(value), Some(field)) => (display_value(&key, value), field.var.to_owned()),
+            (Some(value), None) => (display_value(&key, value), "config file".to_owned()),
+            (None, _) => {
+                let var = format!("DOKEN_{}", key.to_uppercase());
+                match vars.iter().find(|(name, _)| *name == var) {
+                    Some((_, value)) => (
+                        display_value(&key, &toml::Value::String(value.to_owned())),
+                        var,
+                    ),
+                    None => continue,
+                }
+            }
+        };
+
+        options.push((key, format!("{}  ({})", value, source)));
+    }
+
+    Ok(options)
+}
+
+/// Prints the merged configuration with secrets redacted and where each value came from
+pub async fn show_effective(profile: Option<String>) -> Result<()> {
+    let config_file = ConfigFile::new();
+    let vars: Vec<(String, String)> = env::vars().collect();
+    let fields = config_file::env_profile_fields(vars.iter().cloned());
+    let config = config_file::with_env_profiles(config_file.read_file().await, &fields)?;
+
+    let mut names: Vec<&String> = config.profile.keys().collect();
+    names.sort();
+
+    if let Some(profile) = &profile {
+        names.retain(|name| *name == profile);
+
+        if names.is_empty() {
+            bail!("The given profile `{}` doesn't exist", profile);
+        }
+    }
+
+    let sections = names
+        .into_iter()
+        .map(|name| {
+            let options = effective_options(name, &config.profile[name], &fields, &vars)?;
+            let pairs: Vec<(&str, String)> = options
+                .iter()
+                .map(|(key, value)| (key.as_str(), value.to_owned()))
+                .collect();
+
+            Ok(format!(
+                "[profile.{}]\n{}",
+                name,
+                output::key_values(&pairs)
+            ))
+        })
+        .collect::<Result<Vec<String>>>()?;
+
+    println!("{}", sections.join("\n\n"));
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_reports_problems_with_line_numbers() {
+        let text = r#"[profile.implicit]
+client_id = "id"
+grant = "implicit"
+discovery_url = "https://idp/.well-known/openid-configuration"
+token_url = "https://idp/token"
+callback_url = "http://localhost:8080/callback"
+
+[profile."exchange"]
+client_id = "id"
+grant = "token-exchange"
+token_url = "https://idp/token"
+upstream_profile = "missing"
+secret = "${DOKEN_LINT_TEST_UNSET}"
+"#;
+
+        let problems: Vec<(Option<usize>, Severity)> = lint_text(text)
+            .into_iter()
+            .map(|problem| (problem.line, problem.severity))
+            .collect();
+
+        assert_eq!(
+            problems,
+            vec![
+                (Some(4), Severity::Error),
+                (Some(5), Severity::Error),
+                (Some(12), Severity::Error),
+                (Some(13), Severity::Error),
+                (Some(13), Severity::Error),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_shows_sources_and_redacts_secrets() {
+        let fields = vec![config_file::EnvProfileField {
+            profile: "work".to_owned(),
+            key: "client_secret".to_owned(),
+            value: "secret".to_owned(),
+            var: "DOKEN_PROFILE_WORK_CLIENT_SECRET".to_owned(),
+        }];
+        let profile = Profile {
+            client_id: Some("client".to_owned()),
+            client_secret: Some("secret".to_owned()),
+            ..Default::default()
+        };
+        let vars = vec![
+            ("DOKEN_SCOPE".to_owned(), "openid".to_owned()),
+            ("DOKEN_CLIENT_ID".to_owned(), "ignored".to_owned()),
+        ];
+
+        assert_eq!(
+            effective_options("work", &profile, &fields, &vars).unwrap(),
+            vec![
+                ("client_id".to_owned(), "client  (config file)".to_owned()),
+                (
+                    "client_secret".to_owned(),
+                    "********  (DOKEN_PROFILE_WORK_CLIENT_SECRET)".to_owned()
+                ),
+                ("scope".to_owned(), "openid  (DOKEN_SCOPE)".to_owned()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,466 @@
+use anyhow::{Context, Result, bail};
+use futures::future::LocalBoxFuture;
+use futures::stream::FuturesUnordered;
+use futures::{FutureExt, StreamExt};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use rand::Rng;
+use serde_json::Value;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::Duration;
+use tokio::sync::{mpsc, watch};
+
+use crate::args::Arguments;
+use crate::commands::token::refresh;
+use crate::config_file::{self, Config, ConfigFile, Profile};
+use crate::file_state::FileState;
+use crate::health::{self, StateKeys};
+use crate::jwks;
+use crate::jwt::VerificationPolicy;
+use crate::keychain;
+use crate::logout;
+use crate::oauth_client::OAuthClient;
+use crate::output;
+use crate::single_flight;
+use crate::systemd;
+use crate::throttling::{self, Backoff};
+use crate::webhook::{self, WebhookEvent};
+
+/// Wait before JWKS are fetched again after a failure
+const JWKS_RETRY_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Wait after a change of the config file before it's read
+const RELOAD_DELAY: Duration = Duration::from_millis(300);
+
+/// Spreads keep-alive calls of many profiles (and many machines) by ±10% of the interval
+fn jittered(interval: Duration) -> Duration {
+    let jitter = interval.as_secs_f64() * 0.1;
+
+    Duration::from_secs_f64(interval.as_secs_f64() + rand::rng().random_range(-jitter..=jitter))
+}
+
+/// Uses the refresh_token to slide its expiry window, or calls userinfo to keep the session alive
+async fn keep_alive(args: &Arguments) -> Result<&'static str> {
+    let _flight = single_flight::lock(&args.state_key()).await;
+    // The state file isn't kept locked during network calls, other profiles' tasks use it too
+    let token_info = FileState::new()?
+        .read_token_info(&args.state_key())
+        .context("No token cached. Run doken with this profile first")?;
+
+    if token_info.refresh_token.is_some() {
+        let token_info = refresh(args, token_info).await?;
+        webhook::notify(args, WebhookEvent::refreshed(args, &token_info)).await;
+        FileState::new()?.upsert_token_info(args.state_key(), token_info)?;
+
+        return Ok("refreshed");
+    }
+
+    let oauth_client = OAuthClient::new(args).await?;
+    let Some(userinfo_endpoint) = &oauth_client.metadata().userinfo_endpoint else {
+        bail!("No refresh_token cached and the provider has no `userinfo_endpoint`");
+    };
+
+    oauth_client
+        .http()
+        .get(userinfo_endpoint)
+        .bearer_auth(&token_info.access_token)
+        .send()
+        .await?
+        .error_for_status()
+        .context("userinfo call failed")?;
+
+    Ok("called userinfo")
+}
+
+async fn keep_alive_loop(
+    name: String,
+    args: Arguments,
+    interval: Duration,
+    mut stop: watch::Receiver<()>,
+) {
+    let mut backoff = Backoff::default();
+    let mut wait = jittered(interval);
+
+    loop {
+        // Stops only between keep-alives, so a rotated refresh_token is always stored
+        tokio::select! {
+            _ = tokio::time::sleep(wait) => {}
+            _ = stop.changed() => return,
+        }
+
+        wait = match keep_alive(&args).await {
+            Ok(action) => {
+                output::status("Kept alive", &format!("profile `{}` ({})", name, action));
+                backoff.reset();
+                jittered(interval)
+            }
+            Err(e) => {
+                output::warning(&format!("Cannot keep profile `{}` alive: {:#}", name, e));
+                webhook::notify(&args, WebhookEvent::failed(&args, &e)).await;
+
+                // Retried earlier than the next keep-alive, but never sooner than the provider asks to
+                match throttling::throttled(&e) {
+                    Some(throttled) => {
+                        let delay = backoff.delay(throttled).min(interval);
+                        output::status(
+                            "Backing off",
+                            &format!(
+                                "profile `{}` for {}",
+                                name,
+                                humantime::format_duration(Duration::from_secs(delay.as_secs()))
+                            ),
+                        );
+                        delay
+                    }
+                    None => jittered(interval),
+                }
+            }
+        };
+    }
+}
+
+/// Revalidates JWKS of the profile's provider. `None` if it publishes none
+async fn refresh_jwks(args: &Arguments) -> Result<Option<Duration>> {
+    let oauth_client = OAuthClient::new(args).await?;
+    let Some(jwks_uri) = &oauth_client.metadata().jwks_uri else {
+        return Ok(None);
+    };
+
+    jwks::refresh(oauth_client.http(), jwks_uri).await.map(Some)
+}
+
+/// Keeps the provider's keys fresh in the background, so id_tokens and logout tokens are verified
+/// without waiting for JWKS and keys of a rotation are known before they're used
+async fn jwks_refresh_loop(name: String, args: Arguments, mut stop: watch::Receiver<()>) {
+    loop {
+        let wait = match refresh_jwks(&args).await {
+            Ok(Some(wait)) => wait,
+            Ok(None) => return,
+            Err(e) => {
+                log::debug!("Cannot refresh JWKS of profile `{}`: {:?}", name, e);
+                JWKS_RETRY_INTERVAL
+            }
+        };
+
+        tokio::select! {
+            _ = tokio::time::sleep(wait) => {}
+            _ = stop.changed() => return,
+        }
+    }
+}
+
+/// `keep_alive = true` profiles of the config with their secrets resolved, skipping broken ones
+async fn kept_alive_profiles(config: &Config) -> Vec<(String, Profile, Arguments)> {
+    let mut profiles: Vec<_> = config
+        .profile
+        .iter()
+        .filter(|(_, profile)| profile.keep_alive.unwrap_or_default())
+        .collect();
+    profiles.sort_by_key(|(name, _)| name.to_owned());
+
+    let mut kept_alive = vec![];
+    for (name, profile) in profiles {
+        let Some(args) = profile.to_arguments(name) else {
+            output::warning(&format!("Skipping profile `{}` without client_id", name));
+            continue;
+        };
+
+        match args
+            .with_secrets(|key| systemd::credential(Some(name), key))
+            .with_secrets(|key| keychain::credential(name, key))
+            .with_client_secret_from()
+            .await
+            .and_then(|args| args.with_scope_aliases(&config.scopes))
+        {
+            Ok(args) => kept_alive.push((name.to_owned(), profile.to_owned(), args)),
+            Err(e) => output::warning(&format!("Skipping profile `{}`: {:#}", name, e)),
+        }
+    }
+
+    kept_alive
+}
+
+/// Profile a task runs for. Dropping `stop` ends the task
+struct Running {
+    settings: Value,
+    _stop: watch::Sender<()>,
+}
+
+/// Tasks keeping the profile alive and its provider's keys fresh
+fn start(
+    name: &str,
+    args: &Arguments,
+    keep_alive_interval: Duration,
+    logout_listen: Option<SocketAddr>,
+) -> (watch::Sender<()>, LocalBoxFuture<'static, ()>) {
+    let (stop, stopped) = watch::channel(());
+    // Logout tokens are verified even without `--allowed-algs` or `--pin-kid`
+    let jwks = (logout_listen.is_some() || VerificationPolicy::from_args(args).is_some())
+        .then(|| jwks_refresh_loop(name.to_owned(), args.to_owned(), stopped.clone()));
+    let keep_alive = keep_alive_loop(
+        name.to_owned(),
+        args.to_owned(),
+        keep_alive_interval,
+        stopped,
+    );
+
+    let task = async move {
+        futures::future::join(keep_alive, async {
+            if let Some(jwks) = jwks {
+                jwks.await
+            }
+        })
+        .await;
+    };
+
+    (stop, task.boxed_local())
+}
+
+/// Profiles to start, restart and stop to go from the running settings to the configured ones
+fn plan(
+    running: &HashMap<String, Value>,
+    configured: &HashMap<String, Value>,
+) -> (Vec<String>, Vec<String>, Vec<String>) {
+    let mut started = vec![];
+    let mut restarted = vec![];
+    for (name, settings) in configured {
+        match running.get(name) {
+            None => started.push(name.to_owned()),
+            Some(running) if running != settings => restarted.push(name.to_owned()),
+            Some(_) => {}
+        }
+    }
+    let mut stopped: Vec<String> = running
+        .keys()
+        .filter(|name| !configured.contains_key(*name))
+        .cloned()
+        .collect();
+
+    started.sort();
+    restarted.sort();
+    stopped.sort();
+
+    (started, restarted, stopped)
+}
+
+/// Sends on every change of the config file. Editors replace the file, so its directory is watched
+fn watch_config(path: &Path) -> Result<(RecommendedWatcher, mpsc::UnboundedReceiver<()>)> {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    let file_name = path.file_name().map(|name| name.to_owned());
+
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+        let changed = event.is_ok_and(|event| {
+            !matches!(event.kind, EventKind::Access(_))
+                && event
+                    .paths
+                    .iter()
+                    .any(|path| path.file_name() == file_name.as_deref())
+        });
+        if changed {
+            let _ = sender.send(());
+        }
+    })
+    .context("Cannot watch the config file")?;
+    watcher
+        .watch(
+            path.parent().context("The config file has no directory")?,
+            RecursiveMode::NonRecursive,
+        )
+        .context("Cannot watch the config file")?;
+
+    Ok((watcher, receiver))
+}
+
+/// Tasks of the running profiles and what the health and logout endpoints know about them
+struct Supervisor {
+    running: HashMap<String, Running>,
+    keep_alive_interval: Duration,
+    logout_listen: Option<SocketAddr>,
+    state_keys: StateKeys,
+}
+
+type Plan = (Vec<String>, Vec<String>, Vec<String>);
+
+impl Supervisor {
+    /// Starts tasks of new and changed profiles, which are returned, and stops tasks of changed and
+    /// removed ones once their current keep-alive is done
+    fn apply(
+        &mut self,
+        profiles: Vec<(String, Profile, Arguments)>,
+        kept_alive: &RefCell<Vec<(String, Arguments)>>,
+    ) -> (Vec<LocalBoxFuture<'static, ()>>, Plan) {
+        let configured: HashMap<String, Value> = profiles
+            .iter()
+            .map(|(name, profile, _)| {
+                (
+                    name.to_owned(),
+                    serde_json::to_value(profile).unwrap_or_default(),
+                )
+            })
+            .collect();
+        let running = self
+            .running
+            .iter()
+            .map(|(name, running)| (name.to_owned(), running.settings.to_owned()))
+            .collect();
+        let (started, restarted, stopped) = plan(&running, &configured);
+
+        for name in stopped.iter().chain(&restarted) {
+            self.running.remove(name);
+        }
+
+        let mut tasks = vec![];
+        for (name, _, args) in &profiles {
+            if !started.contains(name) && !restarted.contains(name) {
+                continue;
+            }
+
+            let (stop, task) = start(name, args, self.keep_alive_interval, self.logout_listen);
+            self.running.insert(
+                name.to_owned(),
+                Running {
+                    settings: configured[name].to_owned(),
+                    _stop: stop,
+                },
+            );
+            tasks.push(task);
+        }
+
+        *self.state_keys.write().unwrap() = profiles
+            .iter()
+            .map(|(_, _, args)| args.state_key())
+            .collect();
+        *kept_alive.borrow_mut() = profiles
+            .into_iter()
+            .map(|(name, _, args)| (name, args))
+            .collect();
+
+        (tasks, (started, restarted, stopped))
+    }
+}
+
+/// Runs in the foreground until stopped, keeping sessions of `keep_alive = true` profiles alive.
+/// Changes of ~/.doken/config.toml are applied without a restart
+pub async fn run(
+    keep_alive_interval: Duration,
+    health_listen: Option<SocketAddr>,
+    logout_listen: Option<SocketAddr>,
+) -> Result<()> {
+    let config_file = ConfigFile::new();
+    let profiles = kept_alive_profiles(&config_file.read().await).await;
+    if profiles.is_empty() {
+        bail!("No profiles with `keep_alive = true` in ~/.doken/config.toml");
+    }
+
+    let kept_alive = RefCell::new(vec![]);
+    let mut supervisor = Supervisor {
+        running: HashMap::new(),
+        keep_alive_interval,
+        logout_listen,
+        state_keys: StateKeys::default(),
+    };
+    let (started, _) = supervisor.apply(profiles, &kept_alive);
+    let mut tasks: FuturesUnordered<_> = started.into_iter().collect();
+
+    if let Some(health_listen) = health_listen {
+        health::spawn(health_listen, supervisor.state_keys.clone()).await?;
+    }
+
+    let logout_listener = match logout_listen {
+        Some(logout_listen) => Some(logout::bind(logout_listen).await?),
+        None => None,
+    };
+
+    output::status(
+        "Running",
+        &format!(
+            "daemon keeping {} profile(s) alive every ~{}",
+            tasks.len(),
+            humantime::format_duration(keep_alive_interval)
+        ),
+    );
+
+    let (_watcher, mut changes) = watch_config(config_file.path())?;
+    let supervise = async {
+        loop {
+            tokio::select! {
+                Some(()) = tasks.next() => {}
+                Some(()) = changes.recv() => {
+                    // Editors write in several steps, the config is read once they're done
+                    tokio::time::sleep(RELOAD_DELAY).await;
+                    while changes.try_recv().is_ok() {}
+
+                    let text = config_file.read_text().await.unwrap_or_default();
+                    if let Err(e) = config_file::parse(&text) {
+                        output::warning(&format!("Not reloading the invalid config: {:#}", e));
+                        continue;
+                    }
+
+                    let profiles = kept_alive_profiles(&config_file.read().await).await;
+                    let (started, (started_names, restarted, stopped)) =
+                        supervisor.apply(profiles, &kept_alive);
+                    tasks.extend(started);
+
+                    for (label, names) in [
+                        ("Started", started_names),
+                        ("Restarted", restarted),
+                        ("Stopped", stopped),
+                    ] {
+                        for name in names {
+                            output::status(
+                                label,
+                                &format!("profile `{}` after a config change", name),
+                            );
+                        }
+                    }
+                }
+                else => break,
+            }
+        }
+    };
+    let logout = async {
+        if let Some(listener) = logout_listener {
+            logout::serve(listener, &kept_alive).await;
+        }
+    };
+    futures::future::join(supervise, logout).await;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn it_plans_only_changes_of_the_config() {
+        let settings = |pairs: &[(&str, Value)]| -> HashMap<String, Value> {
+            pairs
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_owned()))
+                .collect()
+        };
+        let running = settings(&[
+            ("same", json!({ "client_id": "a" })),
+            ("changed", json!({ "client_id": "b" })),
+            ("removed", json!({ "client_id": "c" })),
+        ]);
+        let configured = settings(&[
+            ("same", json!({ "client_id": "a" })),
+            ("changed", json!({ "client_id": "b", "scope": "openid" })),
+            ("added", json!({ "client_id": "d" })),
+        ]);
+
+        assert_eq!(
+            plan(&running, &configured),
+            (
+                vec!["added".to_owned()],
+                vec!["changed".to_owned()],
+                vec!["removed".to_owned()]
+            )
+        );
+    }
+}
@@ -0,0 +1,65 @@
+use anyhow::{Context, Result};
+use oauth2::PkceCodeVerifier;
+use url::Url;
+
+use crate::args::Arguments;
+use crate::file_state::FileState;
+use crate::jwt;
+use crate::oauth_client::OAuthClient;
+use crate::output;
+use crate::single_flight;
+use crate::token_info::TokenInfo;
+
+/// Codes are often copied together with the whole callback url, ex. from the address bar of a
+/// mobile browser, so the `code` parameter of a url is used
+fn code_of(value: &str) -> Result<String> {
+    let value = value.trim();
+
+    match Url::parse(value) {
+        Ok(url) => url
+            .query_pairs()
+            .find(|(name, _)| name == "code")
+            .map(|(_, code)| code.into_owned())
+            .with_context(|| format!("`{}` has no `code` parameter", url)),
+        Err(_) => Ok(value.to_owned()),
+    }
+}
+
+/// Exchanges an authorization code obtained outside of doken, ex. through a deep link of a mobile
+/// app, with the client settings of `args`. The token is cached like one of a flow run by doken
+pub async fn run(args: Arguments, code: String, code_verifier: Option<String>) -> Result<()> {
+    let code = code_of(&code)?;
+    let oauth_client = OAuthClient::new(&args).await?;
+
+    let token_info = TokenInfo::from_token_response(
+        oauth_client
+            .exchange_code(&code, code_verifier.map(PkceCodeVerifier::new))
+            .await?,
+    )
+    .with_requested_scope(&args.scope);
+    jwt::verify_token_info(&token_info, &args, &oauth_client).await?;
+
+    {
+        let _flight = single_flight::lock(&args.state_key()).await;
+        FileState::new()?.upsert_token_info(args.state_key(), token_info.to_owned())?;
+    }
+
+    println!("{}", output::render(&token_info, &args)?);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_takes_the_code_of_a_callback_url() {
+        assert_eq!(
+            code_of("https://app.com/callback?state=abc&code=xyz%2F1").unwrap(),
+            "xyz/1"
+        );
+        assert_eq!(code_of(" xyz ").unwrap(), "xyz");
+        assert!(code_of("https://app.com/callback?error=access_denied").is_err());
+    }
+}
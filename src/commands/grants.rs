@@ -0,0 +1,37 @@
+use anyhow::Result;
+
+use crate::args::Arguments;
+use crate::grant_capabilities::{Support, grant_capabilities};
+use crate::http_client;
+use crate::openidc_discovery::get_provider_metadata;
+use crate::output;
+
+pub async fn run(discovery_url: String, json: bool) -> Result<()> {
+    let http = http_client::builder(&Arguments::default())?.build()?;
+    let metadata = get_provider_metadata(&http, discovery_url, &[]).await?;
+    let capabilities = grant_capabilities(&metadata);
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&capabilities)?);
+        return Ok(());
+    }
+
+    let pairs = capabilities
+        .iter()
+        .map(|capability| {
+            let supported = match capability.supported {
+                Support::Yes => "yes",
+                Support::No => "no",
+                Support::Unknown => "unknown",
+            };
+
+            (
+                serde_variant::to_variant_name(&capability.grant).unwrap_or_default(),
+                format!("{:<7}  {}", supported, capability.reason),
+            )
+        })
+        .collect::<Vec<_>>();
+    println!("{}", output::key_values(&pairs));
+
+    Ok(())
+}
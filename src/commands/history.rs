@@ -0,0 +1,115 @@
+use anyhow::Result;
+use std::time::{Duration, SystemTime};
+
+use crate::history::{self, HistoryEntry, HistorySettings, TokenSource};
+use crate::output;
+
+fn source(entry: &HistoryEntry) -> &'static str {
+    match entry.source {
+        Some(TokenSource::Cache) => "cache",
+        Some(TokenSource::Refresh) => "refresh",
+        Some(TokenSource::Flow) if entry.interactive => "interactive flow",
+        Some(TokenSource::Flow) => "flow",
+        None => "failed",
+    }
+}
+
+fn line(entry: &HistoryEntry, utc: bool) -> String {
+    let mut line = format!(
+        "{}  {:<16}  {:<32}  {:<16}  {:>8}",
+        output::timestamp(entry.time, utc),
+        entry.profile.as_deref().unwrap_or(&entry.state_key),
+        entry.grant,
+        source(entry),
+        format!("{}ms", entry.duration_ms),
+    );
+
+    if let Some(error) = &entry.error {
+        line.push_str(&format!("  {}", error));
+    }
+
+    line
+}
+
+/// Prints the latest acquisitions, optionally of one profile or client_id only
+pub fn show(profile: Option<String>, limit: usize, utc: bool) -> Result<()> {
+    if !history::read_settings().enabled {
+        output::warning("History is off. Run `doken history on` to start recording");
+    }
+
+    let entries: Vec<HistoryEntry> = history::read_entries()?
+        .into_iter()
+        .filter(|entry| {
+            profile.as_ref().is_none_or(|profile| {
+                entry.profile.as_ref() == Some(profile) || &entry.state_key == profile
+            })
+        })
+        .collect();
+
+    for entry in entries.iter().skip(entries.len().saturating_sub(limit)) {
+        println!("{}", line(entry, utc));
+    }
+
+    Ok(())
+}
+
+pub fn on() -> Result<()> {
+    history::write_settings(&HistorySettings { enabled: true })?;
+
+    output::status(
+        "Enabled",
+        &format!(
+            "history recorded in {}",
+            history::entries_path()?.to_string_lossy()
+        ),
+    );
+
+    Ok(())
+}
+
+pub fn off() -> Result<()> {
+    history::write_settings(&HistorySettings { enabled: false })?;
+
+    output::status("Disabled", "history. Recorded entries are kept");
+
+    Ok(())
+}
+
+pub fn prune(older_than: Duration) -> Result<()> {
+    let removed = history::prune(SystemTime::now() - older_than)?;
+
+    output::status(
+        "Pruned",
+        &format!(
+            "{} history entries older than {}",
+            removed,
+            humantime::format_duration(older_than)
+        ),
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_shows_why_a_token_was_acquired() {
+        let entry = HistoryEntry {
+            time: SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+            profile: Some("corp".to_owned()),
+            state_key: "my-client".to_owned(),
+            grant: "authorization-code-with-pkce".to_owned(),
+            source: Some(TokenSource::Flow),
+            interactive: true,
+            duration_ms: 2400,
+            error: None,
+        };
+
+        assert_eq!(
+            line(&entry, true),
+            "2023-11-14T22:13:20Z  corp              authorization-code-with-pkce      interactive flow    2400ms"
+        );
+    }
+}
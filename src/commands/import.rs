@@ -0,0 +1,576 @@
+use anyhow::{Context, Result, bail};
+use clap::ValueEnum;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::time::SystemTime;
+use std::{env, fs};
+use url::Url;
+
+use crate::client_auth_method::ClientAuthMethod;
+use crate::config_file::{self, ConfigFile, Profile};
+use crate::file_state::FileState;
+use crate::grant::Grant;
+use crate::interop::CacheFormat;
+use crate::output;
+use crate::providers::Provider;
+use crate::token_info::TokenInfo;
+
+/// Public client of the Azure CLI
+const AZ_CLIENT_ID: &str = "04b07795-8ddb-461a-bbee-02f9e1bf7b46";
+const AZ_SCOPE: &str =
+    "https://management.core.windows.net//.default offline_access openid profile";
+
+const GOOGLE_AUTHORIZATION_URL: &str = "https://accounts.google.com/o/oauth2/auth";
+const GOOGLE_TOKEN_URL: &str = "https://oauth2.googleapis.com/token";
+const GCLOUD_SCOPE: &str = "openid https://www.googleapis.com/auth/cloud-platform";
+
+/// Tool whose configuration `doken import` turns into profiles
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum ImportSource {
+    /// oauth2c command line given after `--`, as oauth2c keeps no configuration
+    Oauth2c,
+    /// Clients of the tokens cached in ~/.oauth2l
+    Oauth2l,
+    /// Tenants the Azure CLI is signed in to
+    Az,
+    /// Application default credentials of gcloud
+    Gcloud,
+}
+
+/// Profile made from the configuration of another tool, with its refresh token if it cached one
+#[derive(Debug)]
+struct Imported {
+    name: String,
+    profile: Profile,
+    refresh_token: Option<String>,
+}
+
+/// Profile names are written as bare TOML keys
+fn profile_name(prefix: &str, name: &str) -> String {
+    let name: String = name
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect();
+
+    format!("{}-{}", prefix, name.trim_matches('-'))
+}
+
+fn home() -> Result<PathBuf> {
+    home::home_dir().context("Cannot find the home directory")
+}
+
+fn read_json<T: for<'de> Deserialize<'de>>(path: &PathBuf) -> Result<T> {
+    let text =
+        fs::read_to_string(path).with_context(|| format!("Cannot read {}", path.display()))?;
+
+    // The Azure CLI writes its files with a BOM
+    serde_json::from_str(text.trim_start_matches('\u{feff}'))
+        .with_context(|| format!("Cannot parse {}", path.display()))
+}
+
+/// Values of `--flag value` and `--flag=value` options and positional arguments. Flags not
+/// followed by a value are `"true"`
+fn parse_command_line(args: &[String]) -> (HashMap<String, String>, Vec<String>) {
+    let mut options = HashMap::new();
+    let mut positional = vec![];
+    let mut args = args.iter().peekable();
+
+    while let Some(arg) = args.next() {
+        let Some(flag) = arg.strip_prefix("--") else {
+            positional.push(arg.to_owned());
+            continue;
+        };
+
+        let (flag, value) = match flag.split_once('=') {
+            Some((flag, value)) => (flag, value.to_owned()),
+            None => match args.next_if(|next| !next.starts_with("--")) {
+                Some(value) => (flag, value.to_owned()),
+                None => (flag, "true".to_owned()),
+            },
+        };
+        options.insert(flag.to_owned(), value);
+    }
+
+    (options, positional)
+}
+
+/// Profile of an oauth2c command line, ex. `https://idp.com --client-id app --grant-type client_credentials`
+fn from_oauth2c(args: &[String]) -> Result<Imported> {
+    let (options, positional) = parse_command_line(args);
+    let issuer = positional
+        .first()
+        .context("Pass the oauth2c command line after `--`, ex. `doken import --from oauth2c -- https://idp.com --client-id app`")?;
+    let host = Url::parse(issuer)
+        .context("The first argument of oauth2c has to be the issuer url")?
+        .host_str()
+        .unwrap_or_default()
+        .to_owned();
+    let option = |name: &str| options.get(name).map(|value| value.to_owned());
+
+    let pkce = options.get("pkce").is_some_and(|pkce| pkce == "true");
+    let grant = match options
+        .get("grant-type")
+        .map(String::as_str)
+        .unwrap_or("authorization_code")
+    {
+        "authorization_code" if pkce => Grant::AuthorizationCodeWithPkce,
+        "authorization_code" => Grant::AuthorizationCode,
+        "implicit" => Grant::Implicit,
+        "client_credentials" => Grant::ClientCredentials,
+        "password" => Grant::ResourceOwnerPasswordClientCredentials,
+        "urn:ietf:params:oauth:grant-type:device_code" => Grant::DeviceCode,
+        "urn:ietf:params:oauth:grant-type:jwt-bearer" => Grant::JwtBearer,
+        grant => bail!("oauth2c's `{}` grant has no doken equivalent", grant),
+    };
+    let client_auth_method = match options.get("auth-method").map(String::as_str) {
+        None => None,
+        Some("client_secret_basic") => Some(ClientAuthMethod::Basic),
+        Some("client_secret_post") => Some(ClientAuthMethod::Post),
+        Some("none") => Some(ClientAuthMethod::None),
+        Some("private_key_jwt") => Some(ClientAuthMethod::PrivateKeyJwt),
+        Some(method) => bail!(
+            "oauth2c's `{}` client authentication has no doken equivalent",
+            method
+        ),
+    };
+
+    // oauth2c signs both client assertions and jwt-bearer assertions with `--signing-key`
+    let (client_assertion_key, assertion_key_file) = match (&grant, &client_auth_method) {
+        (Grant::JwtBearer, _) => (None, option("signing-key")),
+        (_, Some(ClientAuthMethod::PrivateKeyJwt)) => (option("signing-key"), None),
+        _ => (None, None),
+    };
+
+    Ok(Imported {
+        name: profile_name("oauth2c", &host),
+        profile: Profile {
+            grant: Some(grant),
+            discovery_url: Some(format!(
+                "{}/.well-known/openid-configuration",
+                issuer.trim_end_matches('/')
+            )),
+            client_id: option("client-id"),
+            client_secret: option("client-secret"),
+            client_auth_method,
+            client_assertion_key,
+            assertion_key_file,
+            callback_url: option("redirect-url"),
+            username: option("username"),
+            scope: option("scopes").map(|scopes| scopes.replace(',', " ")),
+            audience: option("audience"),
+            ..Default::default()
+        },
+        refresh_token: None,
+    })
+}
+
+/// String `name` of `value` or its nested objects. oauth2l keeps the credentials JSON of the
+/// client as a string in its settings, so JSON strings are looked into too
+fn find_string(value: &Value, name: &str) -> Option<String> {
+    match value {
+        Value::Object(map) => map
+            .get(name)
+            .and_then(Value::as_str)
+            .map(str::to_owned)
+            .or_else(|| map.values().find_map(|value| find_string(value, name))),
+        Value::String(text) if text.trim_start().starts_with('{') => {
+            find_string(&serde_json::from_str(text).ok()?, name)
+        }
+        _ => None,
+    }
+}
+
+/// Profiles of the clients in the settings oauth2l keys its cache by. Their tokens are imported by
+/// `interop_cache = "oauth2l"` on the first run
+fn from_oauth2l(cache: &Map<String, Value>) -> Vec<Imported> {
+    let mut clients = BTreeMap::new();
+    for key in cache.keys() {
+        let Ok(settings) = serde_json::from_str::<Value>(key) else {
+            continue;
+        };
+        let Some(client_id) = find_string(&settings, "client_id") else {
+            continue;
+        };
+
+        let find = |names: &[&str]| names.iter().find_map(|name| find_string(&settings, name));
+        clients.entry(client_id.to_owned()).or_insert(Profile {
+            grant: Some(Grant::AuthorizationCodeWithPkce),
+            authorization_url: find(&["auth_uri"]),
+            token_url: find(&["token_uri"]),
+            client_id: Some(client_id),
+            client_secret: find(&["client_secret"]),
+            scope: find(&["Scope", "scope"]).map(|scope| scope.replace(',', " ")),
+            interop_cache: Some(CacheFormat::Oauth2l),
+            ..Default::default()
+        });
+    }
+
+    clients
+        .into_values()
+        .map(|profile| {
+            // Google client ids are `<number>.apps.googleusercontent.com`
+            let client_id = profile.client_id.as_deref().unwrap_or_default();
+            let name = client_id.split('.').next().unwrap_or(client_id);
+
+            Imported {
+                name: profile_name("oauth2l", name),
+                profile,
+                refresh_token: None,
+            }
+        })
+        .collect()
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AzureProfile {
+    #[serde(default)]
+    subscriptions: Vec<AzureSubscription>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AzureSubscription {
+    tenant_id: String,
+}
+
+#[derive(Deserialize, Default)]
+struct MsalCache {
+    #[serde(default, rename = "RefreshToken")]
+    refresh_token: HashMap<String, MsalRefreshToken>,
+}
+
+#[derive(Deserialize)]
+struct MsalRefreshToken {
+    secret: String,
+    client_id: String,
+    /// `<object id>.<tenant id>`
+    #[serde(default)]
+    home_account_id: String,
+}
+
+/// Azure CLI refresh token of the tenant's account. Entra ID redeems it in other tenants of
+/// the user too, so the token of the only signed in account is taken otherwise
+fn az_refresh_token(cache: &MsalCache, tenant: &str) -> Option<String> {
+    let tokens: Vec<&MsalRefreshToken> = cache
+        .refresh_token
+        .values()
+        .filter(|token| token.client_id == AZ_CLIENT_ID)
+        .collect();
+
+    let own = tokens
+        .iter()
+        .find(|token| token.home_account_id.ends_with(&format!(".{}", tenant)));
+    if let Some(token) = own {
+        return Some(token.secret.to_owned());
+    }
+
+    let accounts: BTreeSet<&str> = tokens
+        .iter()
+        .map(|token| token.home_account_id.as_str())
+        .collect();
+    if accounts.len() > 1 {
+        output::warning(&format!(
+            "The Azure CLI is signed in with several accounts, tenant `{}` is imported without a refresh token",
+            tenant
+        ));
+        return None;
+    }
+
+    tokens.first().map(|token| token.secret.to_owned())
+}
+
+/// A profile of the Azure CLI's own client per tenant with a subscription
+fn from_az(profile: &AzureProfile, cache: &MsalCache) -> Vec<Imported> {
+    let mut tenants: Vec<&str> = profile
+        .subscriptions
+        .iter()
+        .map(|subscription| subscription.tenant_id.as_str())
+        .collect();
+    tenants.sort();
+    tenants.dedup();
+
+    tenants
+        .into_iter()
+        .map(|tenant| Imported {
+            name: profile_name("az", tenant),
+            profile: Profile {
+                grant: Some(Grant::AuthorizationCodeWithPkce),
+                provider: Some(Provider::Azure),
+                discovery_url: Some(format!(
+                    "https://login.microsoftonline.com/{}/v2.0/.well-known/openid-configuration",
+                    tenant
+                )),
+                callback_url: Some("http://localhost:8400".to_owned()),
+                client_id: Some(AZ_CLIENT_ID.to_owned()),
+                scope: Some(AZ_SCOPE.to_owned()),
+                tenant: Some(tenant.to_owned()),
+                ..Default::default()
+            },
+            refresh_token: az_refresh_token(cache, tenant),
+        })
+        .collect()
+}
+
+#[derive(Deserialize)]
+struct ApplicationDefaultCredentials {
+    #[serde(rename = "type")]
+    kind: String,
+    client_id: Option<String>,
+    client_secret: Option<String>,
+    refresh_token: Option<String>,
+}
+
+fn from_gcloud(credentials: ApplicationDefaultCredentials) -> Result<Imported> {
+    if credentials.kind != "authorized_user" {
+        bail!(
+            "Only `authorized_user` application default credentials are imported, not `{}`",
+            credentials.kind
+        );
+    }
+
+    Ok(Imported {
+        name: "gcloud".to_owned(),
+        profile: Profile {
+            grant: Some(Grant::AuthorizationCodeWithPkce),
+            authorization_url: Some(GOOGLE_AUTHORIZATION_URL.to_owned()),
+            token_url: Some(GOOGLE_TOKEN_URL.to_owned()),
+            callback_url: Some("http://localhost:8085/".to_owned()),
+            client_id: credentials.client_id,
+            client_secret: credentials.client_secret,
+            scope: Some(GCLOUD_SCOPE.to_owned()),
+            ..Default::default()
+        },
+        refresh_token: credentials.refresh_token,
+    })
+}
+
+fn gcloud_dir() -> Result<PathBuf> {
+    if let Some(dir) = env::var_os("CLOUDSDK_CONFIG") {
+        return Ok(dir.into());
+    }
+
+    match env::var_os("APPDATA") {
+        Some(app_data) if cfg!(windows) => Ok(PathBuf::from(app_data).join("gcloud")),
+        _ => Ok(home()?.join(".config/gcloud")),
+    }
+}
+
+fn read_source(source: ImportSource, args: &[String]) -> Result<Vec<Imported>> {
+    match source {
+        ImportSource::Oauth2c => Ok(vec![from_oauth2c(args)?]),
+        ImportSource::Oauth2l => Ok(from_oauth2l(&read_json(&home()?.join(".oauth2l"))?)),
+        ImportSource::Az => {
+            let dir = match env::var_os("AZURE_CONFIG_DIR") {
+                Some(dir) => PathBuf::from(dir),
+                None => home()?.join(".azure"),
+            };
+            let cache = read_json(&dir.join("msal_token_cache.json")).unwrap_or_else(|e| {
+                log::debug!("No Azure CLI refresh tokens: {:#}", e);
+                MsalCache::default()
+            });
+
+            Ok(from_az(&read_json(&dir.join("azureProfile.json"))?, &cache))
+        }
+        ImportSource::Gcloud => Ok(vec![from_gcloud(read_json(
+            &gcloud_dir()?.join("application_default_credentials.json"),
+        )?)?]),
+    }
+}
+
+/// Config text with `[profile.<name>]` tables of the imported profiles appended
+fn append_profiles(text: &str, imported: &[Imported]) -> Result<String> {
+    let mut text = text.to_owned();
+    for imported in imported {
+        if !text.is_empty() && !text.ends_with('\n') {
+            text.push('\n');
+        }
+        if !text.is_empty() {
+            text.push('\n');
+        }
+
+        text.push_str(&format!(
+            "[profile.{}]\n{}",
+            imported.name,
+            toml::to_string(&imported.profile)?
+        ));
+    }
+
+    Ok(text)
+}
+
+/// Refresh token stored as an expired token, so the first run redeems it instead of logging in
+fn store_refresh_token(imported: &Imported, refresh_token: &str) -> Result<()> {
+    let args = imported
+        .profile
+        .to_arguments(&imported.name)
+        .context("The imported profile has no client_id")?;
+
+    FileState::new()?.upsert_token_info(
+        args.state_key(),
+        TokenInfo {
+            access_token: String::new(),
+            refresh_token: Some(refresh_token.to_owned()),
+            expires: Some(SystemTime::UNIX_EPOCH),
+            scope: None,
+            requested_scope: None,
+            extra: Default::default(),
+        },
+    )
+}
+
+/// Creates profiles from the configuration of another tool, with refresh tokens where it caches them
+pub async fn run(source: ImportSource, dry_run: bool, args: Vec<String>) -> Result<()> {
+    let config_file = ConfigFile::new();
+    let text = match fs::read_to_string(config_file.path()) {
+        Ok(text) => text,
+        Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+        Err(e) => {
+            return Err(e).with_context(|| format!("Cannot read {}", config_file.path().display()));
+        }
+    };
+    let (config, _) = config_file::parse(&text).context("Cannot parse the config file")?;
+
+    let (imported, existing): (Vec<Imported>, Vec<Imported>) = read_source(source, &args)?
+        .into_iter()
+        .partition(|imported| !config.profile.contains_key(&imported.name));
+    for imported in &existing {
+        output::warning(&format!(
+            "Profile `{}` already exists, skipping it",
+            imported.name
+        ));
+    }
+    if imported.is_empty() {
+        bail!("Nothing to import");
+    }
+
+    if dry_run {
+        print!("{}", append_profiles("", &imported)?);
+        return Ok(());
+    }
+
+    config_file.write_text(&append_profiles(&text, &imported)?)?;
+
+    for imported in &imported {
+        let with_token = match &imported.refresh_token {
+            Some(refresh_token) => {
+                store_refresh_token(imported, refresh_token)?;
+                " with its refresh token"
+            }
+            None => "",
+        };
+        output::status(
+            "Imported",
+            &format!("profile `{}`{}", imported.name, with_token),
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn it_translates_oauth2c_command_lines() {
+        let imported = from_oauth2c(&strings(&[
+            "https://idp.example.com/realms/corp/",
+            "--client-id",
+            "app",
+            "--client-secret=secret",
+            "--grant-type",
+            "authorization_code",
+            "--pkce",
+            "--scopes",
+            "openid,email",
+        ]))
+        .unwrap();
+
+        assert_eq!(imported.name, "oauth2c-idp-example-com");
+        assert!(matches!(
+            imported.profile.grant,
+            Some(Grant::AuthorizationCodeWithPkce)
+        ));
+        assert_eq!(
+            imported.profile.discovery_url.as_deref(),
+            Some("https://idp.example.com/realms/corp/.well-known/openid-configuration")
+        );
+        assert_eq!(imported.profile.client_secret.as_deref(), Some("secret"));
+        assert_eq!(imported.profile.scope.as_deref(), Some("openid email"));
+
+        assert!(from_oauth2c(&strings(&["https://idp", "--grant-type", "refresh_token"])).is_err());
+    }
+
+    #[test]
+    fn it_imports_clients_of_oauth2l_settings() {
+        let credentials = json!({ "installed": {
+            "client_id": "123.apps.googleusercontent.com",
+            "client_secret": "secret",
+            "token_uri": "https://oauth2.googleapis.com/token",
+        }});
+        let key = json!({ "CredentialsJSON": credentials.to_string(), "Scope": "openid,email" });
+        let mut cache = Map::new();
+        cache.insert(key.to_string(), json!({ "access_token": "token" }));
+        cache.insert("not settings".to_owned(), json!({}));
+
+        let imported = from_oauth2l(&cache);
+        assert_eq!(imported.len(), 1);
+        assert_eq!(imported[0].name, "oauth2l-123");
+        assert_eq!(imported[0].profile.client_secret.as_deref(), Some("secret"));
+        assert_eq!(imported[0].profile.scope.as_deref(), Some("openid email"));
+        assert_eq!(
+            imported[0].profile.interop_cache,
+            Some(CacheFormat::Oauth2l)
+        );
+    }
+
+    #[test]
+    fn it_imports_az_tenants_with_refresh_tokens() {
+        let profile: AzureProfile = serde_json::from_value(json!({ "subscriptions": [
+            { "id": "s1", "tenantId": "tenant-a" },
+            { "id": "s2", "tenantId": "tenant-b" },
+            { "id": "s3", "tenantId": "tenant-a" },
+        ]}))
+        .unwrap();
+        let cache: MsalCache = serde_json::from_value(json!({ "RefreshToken": {
+            "b": { "secret": "rt-b", "client_id": AZ_CLIENT_ID, "home_account_id": "user.tenant-b" },
+            "other": { "secret": "rt-other", "client_id": "other", "home_account_id": "user.tenant-a" },
+        }}))
+        .unwrap();
+
+        let imported = from_az(&profile, &cache);
+        assert_eq!(imported.len(), 2);
+        assert_eq!(imported[0].name, "az-tenant-a");
+        assert_eq!(imported[0].refresh_token.as_deref(), Some("rt-b"));
+        assert_eq!(imported[1].profile.tenant.as_deref(), Some("tenant-b"));
+
+        let accounts: MsalCache = serde_json::from_value(json!({ "RefreshToken": {
+            "b": { "secret": "rt-b", "client_id": AZ_CLIENT_ID, "home_account_id": "user.tenant-b" },
+            "c": { "secret": "rt-c", "client_id": AZ_CLIENT_ID, "home_account_id": "other.tenant-c" },
+        }}))
+        .unwrap();
+        assert_eq!(az_refresh_token(&accounts, "tenant-a"), None);
+        assert_eq!(
+            az_refresh_token(&accounts, "tenant-b").as_deref(),
+            Some("rt-b")
+        );
+
+        let text = append_profiles("[profile.corp]\nclient_id = \"x\"", &imported).unwrap();
+        let (config, _) = config_file::parse(&text).unwrap();
+        assert_eq!(config.profile.len(), 3);
+        assert_eq!(
+            config.profile["az-tenant-b"].client_id.as_deref(),
+            Some(AZ_CLIENT_ID)
+        );
+    }
+}
@@ -0,0 +1,123 @@
+use anyhow::Result;
+
+use crate::args::{
+    AgentCommand, Args, CacheCommand, ConfigCommand, DokenCommand, HistoryCommand, ServiceCommand,
+    TelemetryCommand, TokenCommand,
+};
+
+pub mod agent;
+pub mod bench;
+pub mod callback_relay;
+pub mod cleanup;
+pub mod completions;
+pub mod config;
+pub mod daemon;
+pub mod exchange_code;
+pub mod grants;
+pub mod history;
+pub mod import;
+pub mod refresh;
+pub mod rotate_secret;
+pub mod self_update;
+pub mod serve_oidc_proxy;
+pub mod service;
+pub mod status;
+pub mod telemetry;
+pub mod token;
+pub mod watch;
+
+pub async fn run(command: DokenCommand) -> Result<()> {
+    match command {
+        DokenCommand::SelfUpdate { check } => self_update::run(check).await,
+        DokenCommand::Status { client_id, utc, .. } => status::run(client_id, utc),
+        DokenCommand::Grants {
+            discovery_url,
+            json,
+        } => grants::run(discovery_url, json).await,
+        DokenCommand::Bench {
+            requests,
+            concurrency,
+            args,
+        } => bench::run(Args::prepare(args).await, requests, concurrency).await,
+        DokenCommand::ExchangeCode {
+            code,
+            code_verifier,
+            args,
+        } => exchange_code::run(Args::prepare(args).await, code, code_verifier).await,
+        DokenCommand::Refresh { store, args } => {
+            refresh::run(Args::prepare(args).await, store).await
+        }
+        DokenCommand::ServeOidcProxy {
+            listen,
+            shared_secret,
+            args,
+        } => serve_oidc_proxy::run(Args::prepare(args).await, listen, shared_secret).await,
+        DokenCommand::Watch {
+            duration,
+            health_listen,
+            args,
+        } => watch::run(Args::prepare(args).await, duration, health_listen).await,
+        DokenCommand::Daemon {
+            keep_alive_interval,
+            health_listen,
+            logout_listen,
+        } => daemon::run(keep_alive_interval, health_listen, logout_listen).await,
+        DokenCommand::Cleanup { browser_profile } => cleanup::run(browser_profile),
+        DokenCommand::Config {
+            command: ConfigCommand::Lint,
+        } => config::lint().await,
+        DokenCommand::Config {
+            command: ConfigCommand::ShowEffective { profile },
+        } => config::show_effective(profile).await,
+        DokenCommand::Telemetry { command } => match command {
+            TelemetryCommand::On { endpoint } => telemetry::on(endpoint),
+            TelemetryCommand::Off => telemetry::off(),
+            TelemetryCommand::Status => telemetry::status(),
+        },
+        DokenCommand::Service { command } => match command {
+            ServiceCommand::Install {
+                keep_alive_interval,
+                health_listen,
+            } => service::install(keep_alive_interval, health_listen),
+            ServiceCommand::Uninstall => service::uninstall(),
+        },
+        DokenCommand::Agent { command } => match command {
+            AgentCommand::Install {
+                keep_alive_interval,
+                health_listen,
+            } => agent::install(keep_alive_interval, health_listen),
+            AgentCommand::Uninstall => agent::uninstall(),
+        },
+        DokenCommand::History {
+            command,
+            profile,
+            limit,
+            utc,
+        } => match command {
+            None => history::show(profile, limit, utc),
+            Some(HistoryCommand::On) => history::on(),
+            Some(HistoryCommand::Off) => history::off(),
+            Some(HistoryCommand::Prune { older_than }) => history::prune(older_than),
+        },
+        DokenCommand::Token {
+            command:
+                TokenCommand::Cache {
+                    command: CacheCommand::Warm { concurrency },
+                },
+        } => token::warm(concurrency).await,
+        DokenCommand::Token {
+            command: TokenCommand::Print { field, args },
+        } => token::print(Args::prepare(args).await, field).await,
+        DokenCommand::RotateSecret { profile, keep_old } => {
+            rotate_secret::run(profile, keep_old).await
+        }
+        DokenCommand::Import {
+            from,
+            dry_run,
+            args,
+        } => import::run(from, dry_run, args).await,
+        DokenCommand::Completions { shell, dynamic } => completions::run(shell, dynamic),
+        DokenCommand::Complete { words } => completions::complete(words).await,
+        DokenCommand::CallbackRelay { url, socket } => callback_relay::run(&socket, url).await,
+    }
+}
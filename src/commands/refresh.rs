@@ -0,0 +1,41 @@
+use anyhow::Result;
+
+use crate::args::Arguments;
+use crate::file_state::FileState;
+use crate::jwt;
+use crate::oauth_client::OAuthClient;
+use crate::output;
+use crate::retrievers::refresh_token_retriever::{
+    RefreshTokenRetriever, external_refresh_token, read_refresh_token,
+};
+use crate::retrievers::token_retriever::TokenRetriever;
+use crate::single_flight;
+
+/// Redeems a refresh token kept outside of doken with the client settings of `args`. The cache is
+/// left untouched unless `store` is set
+pub async fn run(args: Arguments, store: bool) -> Result<()> {
+    let refresh_token = match external_refresh_token(&args)? {
+        Some(refresh_token) => refresh_token,
+        None => read_refresh_token(&args)?,
+    };
+
+    let oauth_client = OAuthClient::new(&args).await?;
+    let token_info = RefreshTokenRetriever::new(&oauth_client, refresh_token)
+        .retrieve()
+        .await?
+        .with_requested_scope(&args.scope);
+    jwt::verify_token_info(&token_info, &args, &oauth_client).await?;
+
+    if store {
+        let _flight = single_flight::lock(&args.state_key()).await;
+        FileState::new()?.upsert_token_info(args.state_key(), token_info.to_owned())?;
+        output::status("Stored", &format!("token of `{}`", args.state_key()));
+    }
+
+    match args.format {
+        Some(_) => println!("{}", output::render(&token_info, &args)?),
+        None => println!("{}", serde_json::to_string(&token_info)?),
+    }
+
+    Ok(())
+}
@@ -0,0 +1,372 @@
+use anyhow::{Context, Result, bail};
+use serde::Deserialize;
+use serde_json::json;
+use std::time::{Duration, SystemTime};
+use url::Url;
+
+use crate::args::Arguments;
+use crate::config_file::{self, ConfigFile};
+use crate::keychain;
+use crate::oauth_client::OAuthClient;
+use crate::output;
+use crate::providers::Provider;
+use crate::systemd;
+
+const GRAPH_URL: &str = "https://graph.microsoft.com/v1.0";
+const GRAPH_SCOPE: &str = "https://graph.microsoft.com/.default";
+
+/// New secrets of Entra ID apps take a while to be accepted everywhere
+const VERIFY_ATTEMPTS: u32 = 6;
+const VERIFY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Management API able to issue a new secret of the client
+#[derive(Debug, PartialEq)]
+enum ManagementApi {
+    /// Keycloak admin REST API of the realm, ex. `https://idp.com/admin/realms/corp`
+    Keycloak { admin_url: String },
+    /// Microsoft Graph app registration
+    Azure,
+}
+
+/// Keycloak endpoints live under `<base>/realms/<realm>`, its admin API under `<base>/admin/realms/<realm>`
+fn keycloak_admin_url(url: &str) -> Option<String> {
+    let url = Url::parse(url).ok()?;
+    let (base, rest) = url.path().split_once("/realms/")?;
+    let realm = rest.split('/').next().filter(|realm| !realm.is_empty())?;
+
+    Some(format!(
+        "{}{}/admin/realms/{}",
+        &url[..url::Position::BeforePath],
+        base,
+        realm
+    ))
+}
+
+fn management_api(args: &Arguments) -> Result<ManagementApi> {
+    if args.provider == Provider::Azure {
+        return Ok(ManagementApi::Azure);
+    }
+
+    args.discovery_url
+        .iter()
+        .chain(&args.token_url)
+        .find_map(|url| keycloak_admin_url(url))
+        .map(|admin_url| ManagementApi::Keycloak { admin_url })
+        .context("Rotation is supported for Keycloak realms and `--provider azure` app registrations only")
+}
+
+#[derive(Deserialize)]
+struct KeycloakClient {
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct KeycloakSecret {
+    value: String,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+struct PasswordCredential {
+    key_id: String,
+    hint: Option<String>,
+    secret_text: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Application {
+    password_credentials: Vec<PasswordCredential>,
+}
+
+/// Token of the client itself for the management API. Its service account needs
+/// `manage-clients` in Keycloak or `Application.ReadWrite.OwnedBy` in Entra ID
+async fn management_token(args: &Arguments, api: &ManagementApi) -> Result<String> {
+    let args = Arguments {
+        scope: match api {
+            ManagementApi::Keycloak { .. } => "openid".to_owned(),
+            ManagementApi::Azure => GRAPH_SCOPE.to_owned(),
+        },
+        audience: None,
+        ..args.to_owned()
+    };
+    let oauth_client = OAuthClient::new(&args).await?;
+    let token = oauth_client
+        .exchange_client_credentials()
+        .await
+        .context("Cannot get a token for the management API with the current secret")?;
+
+    Ok(oauth2::TokenResponse::access_token(&token)
+        .secret()
+        .to_owned())
+}
+
+fn app_url(client_id: &str) -> String {
+    format!("{}/applications(appId='{}')", GRAPH_URL, client_id)
+}
+
+/// Issues a new secret. Keycloak invalidates the previous one right away, Entra ID keeps it
+async fn issue_secret(
+    http: &reqwest::Client,
+    api: &ManagementApi,
+    token: &str,
+    client_id: &str,
+) -> Result<(String, Option<String>)> {
+    match api {
+        ManagementApi::Keycloak { admin_url } => {
+            let clients: Vec<KeycloakClient> = http
+                .get(format!("{}/clients", admin_url))
+                .query(&[("clientId", client_id)])
+                .bearer_auth(token)
+                .send()
+                .await?
+                .error_for_status()
+                .context("Cannot look the client up in the admin API")?
+                .json()
+                .await?;
+            let client = clients
+                .first()
+                .context("The admin API doesn't know the client")?;
+
+            let secret: KeycloakSecret = http
+                .post(format!("{}/clients/{}/client-secret", admin_url, client.id))
+                .bearer_auth(token)
+                .send()
+                .await?
+                .error_for_status()
+                .context("Cannot regenerate the client secret")?
+                .json()
+                .await?;
+
+            Ok((secret.value, None))
+        }
+        ManagementApi::Azure => {
+            let display_name = format!(
+                "doken rotate-secret {}",
+                humantime::format_rfc3339_seconds(SystemTime::now())
+            );
+            let credential: PasswordCredential = http
+                .post(format!("{}/addPassword", app_url(client_id)))
+                .bearer_auth(token)
+                .json(&json!({ "passwordCredential": { "displayName": display_name } }))
+                .send()
+                .await?
+                .error_for_status()
+                .context("Cannot add a client secret to the app registration")?
+                .json()
+                .await?;
+
+            Ok((
+                credential
+                    .secret_text
+                    .context("Microsoft Graph returned no secretText")?,
+                Some(credential.key_id),
+            ))
+        }
+    }
+}
+
+/// Graph shows only the first 3 characters of secrets. They are shared by chance, so the
+/// credential is known to be the old one only when no other matches them
+fn stale_credential<'a>(
+    credentials: &'a [PasswordCredential],
+    old_secret: &str,
+    new_key_id: &str,
+) -> Option<&'a PasswordCredential> {
+    let old_hint: String = old_secret.chars().take(3).collect();
+
+    let mut matching = credentials
+        .iter()
+        .filter(|credential| credential.key_id != new_key_id)
+        .filter(|credential| credential.hint.as_deref() == Some(old_hint.as_str()));
+
+    match (matching.next(), matching.next()) {
+        (Some(credential), None) => Some(credential),
+        _ => None,
+    }
+}
+
+async fn remove_old_secrets(
+    http: &reqwest::Client,
+    token: &str,
+    client_id: &str,
+    old_secret: &str,
+    new_key_id: &str,
+) -> Result<()> {
+    let application: Application = http
+        .get(app_url(client_id))
+        .query(&[("$select", "passwordCredentials")])
+        .bearer_auth(token)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+    let Some(credential) =
+        stale_credential(&application.password_credentials, old_secret, new_key_id)
+    else {
+        output::warning(
+            "Cannot tell which secret of the app registration is the old one, all are kept as with `--keep-old`",
+        );
+        return Ok(());
+    };
+
+    http.post(format!("{}/removePassword", app_url(client_id)))
+        .bearer_auth(token)
+        .json(&json!({ "keyId": credential.key_id }))
+        .send()
+        .await?
+        .error_for_status()
+        .context("Cannot remove the old client secret")?;
+    output::status("Removed", &format!("old secret {}", credential.key_id));
+
+    Ok(())
+}
+
+/// Saves the new secret where the profile keeps a plain one. Otherwise it's printed, so it can be stored where the old one came from
+async fn store_secret(profile: &str, secret: &str) -> Result<()> {
+    let config_file = ConfigFile::new();
+    let text = config_file.read_text().await.unwrap_or_default();
+
+    match config_file::replace_profile_value(&text, profile, "client_secret", secret) {
+        Some(text) => {
+            if let Err(e) = config_file.write_text(&text) {
+                output::warning(
+                    "The new secret couldn't be saved. Store the one printed below, the old secret may not work anymore",
+                );
+                println!("{}", secret);
+                return Err(e);
+            }
+            output::status(
+                "Updated",
+                &format!("`client_secret` of profile `{}`", profile),
+            );
+        }
+        None => {
+            output::warning(
+                "The profile has no plain `client_secret`. Store the new secret printed below where the old one came from",
+            );
+            println!("{}", secret);
+        }
+    }
+
+    Ok(())
+}
+
+async fn verify(args: &Arguments, secret: &str) -> Result<()> {
+    let args = Arguments {
+        client_secret: Some(secret.to_owned()),
+        ..args.to_owned()
+    };
+    let oauth_client = OAuthClient::new(&args).await?;
+
+    let mut attempt = 1;
+    loop {
+        match oauth_client.exchange_client_credentials().await {
+            Ok(_) => return Ok(()),
+            Err(e) if attempt >= VERIFY_ATTEMPTS => {
+                return Err(e).context("The new secret isn't accepted by the token endpoint");
+            }
+            Err(e) => log::debug!("New secret not accepted yet: {:#}", e),
+        }
+
+        attempt += 1;
+        tokio::time::sleep(VERIFY_INTERVAL).await;
+    }
+}
+
+/// Issues a new client secret of the profile, stores it and checks a token can still be obtained
+pub async fn run(profile: String, keep_old: bool) -> Result<()> {
+    let config = ConfigFile::new().read().await;
+    let args = config
+        .profile
+        .get(&profile)
+        .with_context(|| format!("Profile `{}` isn't defined", profile))?
+        .to_arguments(&profile)
+        .context("The profile has no client_id")?
+        .with_secrets(|key| systemd::credential(Some(&profile), key))
+        .with_secrets(|key| keychain::credential(&profile, key))
+        .with_client_secret_from()
+        .await?;
+    let Some(old_secret) = args.client_secret.to_owned() else {
+        bail!("The profile has no client secret to rotate");
+    };
+
+    let api = management_api(&args)?;
+    let token = management_token(&args, &api).await?;
+    let http = OAuthClient::new(&args).await?.http().to_owned();
+
+    let (secret, key_id) = issue_secret(&http, &api, &token, &args.client_id).await?;
+    output::status("Issued", &format!("a new secret of `{}`", args.client_id));
+    // Stored before anything else can fail, as Keycloak doesn't accept the old one anymore
+    store_secret(&profile, &secret).await?;
+
+    verify(&args, &secret).await?;
+    output::status("Verified", "a token is issued with the new secret");
+
+    if let (Some(key_id), false) = (key_id, keep_old) {
+        remove_old_secrets(&http, &token, &args.client_id, &old_secret, &key_id).await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_finds_the_management_api() {
+        assert_eq!(
+            keycloak_admin_url("https://idp.com/auth/realms/corp/.well-known/openid-configuration"),
+            Some("https://idp.com/auth/admin/realms/corp".to_owned())
+        );
+        assert_eq!(
+            keycloak_admin_url("http://localhost:8080/realms/corp/protocol/openid-connect/token"),
+            Some("http://localhost:8080/admin/realms/corp".to_owned())
+        );
+        assert_eq!(keycloak_admin_url("https://idp.com/oauth/token"), None);
+
+        let args = Arguments {
+            provider: Provider::Azure,
+            ..Default::default()
+        };
+        assert_eq!(management_api(&args).unwrap(), ManagementApi::Azure);
+    }
+
+    #[test]
+    fn it_removes_only_credentials_of_the_old_secret() {
+        let credential = |key_id: &str, hint: &str| PasswordCredential {
+            key_id: key_id.to_owned(),
+            hint: Some(hint.to_owned()),
+            secret_text: None,
+        };
+        let credentials = [
+            credential("old", "abc"),
+            credential("new", "abc"),
+            credential("other", "xyz"),
+        ];
+
+        assert_eq!(
+            stale_credential(&credentials, "abcdef", "new"),
+            Some(&credentials[0])
+        );
+    }
+
+    #[test]
+    fn it_keeps_credentials_sharing_the_hint() {
+        let credential = |key_id: &str, hint: &str| PasswordCredential {
+            key_id: key_id.to_owned(),
+            hint: Some(hint.to_owned()),
+            secret_text: None,
+        };
+        let credentials = [
+            credential("old", "abc"),
+            credential("other-deployment", "abc"),
+            credential("new", "abc"),
+        ];
+
+        assert_eq!(stale_credential(&credentials, "abcdef", "new"), None);
+    }
+}
@@ -0,0 +1,282 @@
+use anyhow::{Context, Result, anyhow, bail};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, SystemTime};
+use std::{env, fs};
+
+use crate::output;
+
+const LATEST_RELEASE_URL: &str = "https://api.github.com/repos/RiddleMan/doken/releases/latest";
+const CURRENT_VERSION: &str = env!("CARGO_PKG_VERSION");
+const CHECK_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Deserialize, Debug)]
+struct Release {
+    tag_name: String,
+    assets: Vec<ReleaseAsset>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ReleaseAsset {
+    name: String,
+    browser_download_url: String,
+}
+
+fn http_client(timeout: Duration) -> Result<reqwest::Client> {
+    reqwest::Client::builder()
+        .user_agent(concat!("doken/", env!("CARGO_PKG_VERSION")))
+        .timeout(timeout)
+        .build()
+        .context("Failed to create HTTP client")
+}
+
+async fn latest_release(client: &reqwest::Client) -> Result<Release> {
+    client
+        .get(LATEST_RELEASE_URL)
+        .send()
+        .await
+        .context("Couldn't reach out to GitHub releases")?
+        .error_for_status()
+        .context("Failed to get the latest release")?
+        .json::<Release>()
+        .await
+        .context("Couldn't process the latest release information")
+}
+
+/// Target triple of the release artifact built by cargo-dist for the current platform
+fn target() -> Option<&'static str> {
+    match (env::consts::OS, env::consts::ARCH) {
+        ("linux", "x86_64") if cfg!(target_env = "musl") => Some("x86_64-unknown-linux-musl"),
+        ("linux", "x86_64") => Some("x86_64-unknown-linux-gnu"),
+        ("macos", "x86_64") => Some("x86_64-apple-darwin"),
+        ("macos", "aarch64") => Some("aarch64-apple-darwin"),
+        ("windows", "x86_64") => Some("x86_64-pc-windows-msvc"),
+        _ => None,
+    }
+}
+
+fn parse_version(version: &str) -> Option<(u64, u64, u64)> {
+    let version = version.trim_start_matches('v');
+    let version = version.split(['-', '+']).next()?;
+    let mut parts = version.split('.').map(|part| part.parse::<u64>().ok());
+
+    Some((parts.next()??, parts.next()??, parts.next()??))
+}
+
+fn is_newer(latest: &str, current: &str) -> bool {
+    match (parse_version(latest), parse_version(current)) {
+        (Some(latest), Some(current)) => latest > current,
+        _ => false,
+    }
+}
+
+async fn download(client: &reqwest::Client, release: &Release, name: &str) -> Result<Vec<u8>> {
+    let asset = release
+        .assets
+        .iter()
+        .find(|asset| asset.name == name)
+        .with_context(|| format!("Release {} has no `{}` artifact", release.tag_name, name))?;
+
+    log::debug!("Downloading {}", asset.browser_download_url);
+
+    let bytes = client
+        .get(&asset.browser_download_url)
+        .send()
+        .await
+        .with_context(|| format!("Failed to download `{}`", name))?
+        .error_for_status()?
+        .bytes()
+        .await?;
+
+    Ok(bytes.to_vec())
+}
+
+/// Catches corrupted downloads only. The checksum comes from the same release as the archive,
+/// so it doesn't prove who published it
+fn check_integrity(archive: &[u8], checksum_file: &[u8]) -> Result<()> {
+    let expected = String::from_utf8_lossy(checksum_file)
+        .split_whitespace()
+        .next()
+        .map(|checksum| checksum.to_lowercase())
+        .context("The checksum file is empty")?;
+    let actual = format!("{:x}", Sha256::digest(archive));
+
+    if expected != actual {
+        bail!(
+            "The download is corrupted, its checksum doesn't match the published one. Expected: {}, actual: {}",
+            expected,
+            actual
+        );
+    }
+
+    Ok(())
+}
+
+fn find_binary(dir: &Path, name: &str) -> Result<PathBuf> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+
+        if path.is_dir() {
+            if let Ok(binary) = find_binary(&path, name) {
+                return Ok(binary);
+            }
+        } else if path.file_name().is_some_and(|file_name| file_name == name) {
+            return Ok(path);
+        }
+    }
+
+    Err(anyhow!("Cannot find `{}` in the release archive", name))
+}
+
+fn install(archive: &[u8], archive_name: &str, work_dir: &Path) -> Result<()> {
+    let archive_path = work_dir.join(archive_name);
+    fs::write(&archive_path, archive)?;
+
+    // Both GNU tar and bsdtar (shipped with Windows 10+) unpack .tar.xz and .zip archives
+    let status = Command::new("tar")
+        .arg("-xf")
+        .arg(&archive_path)
+        .arg("-C")
+        .arg(work_dir)
+        .status()
+        .context("Failed to run `tar` to unpack the release")?;
+
+    if !status.success() {
+        bail!("`tar` failed to unpack {}", archive_name);
+    }
+
+    let binary = find_binary(work_dir, &format!("doken{}", env::consts::EXE_SUFFIX))?;
+
+    self_replace::self_replace(&binary).context("Failed to replace the current executable")?;
+
+    Ok(())
+}
+
+async fn update(client: &reqwest::Client, release: &Release) -> Result<()> {
+    let target = target().context("Self-update is not supported on this platform")?;
+    let archive_name = if cfg!(windows) {
+        format!("doken-{}.zip", target)
+    } else {
+        format!("doken-{}.tar.xz", target)
+    };
+
+    let archive = download(client, release, &archive_name).await?;
+    let checksum = download(client, release, &format!("{}.sha256", archive_name)).await?;
+
+    check_integrity(&archive, &checksum)?;
+    log::debug!("Checksum of {} matches the published one", archive_name);
+
+    let work_dir = env::temp_dir().join(format!("doken-self-update-{}", std::process::id()));
+    fs::create_dir_all(&work_dir)?;
+
+    let result = install(&archive, &archive_name, &work_dir);
+    let _ = fs::remove_dir_all(&work_dir);
+
+    result
+}
+
+pub async fn run(check: bool) -> Result<()> {
+    let client = http_client(Duration::from_secs(300))?;
+    let release = latest_release(&client).await?;
+
+    if !is_newer(&release.tag_name, CURRENT_VERSION) {
+        output::status("Up to date", &format!("doken {}", CURRENT_VERSION));
+        return Ok(());
+    }
+
+    if check {
+        output::status(
+            "Available",
+            &format!(
+                "doken {} (current: {}). Run `doken self-update` to install it.",
+                release.tag_name, CURRENT_VERSION
+            ),
+        );
+        return Ok(());
+    }
+
+    update(&client, &release).await?;
+    output::status("Updated", &format!("doken to {}", release.tag_name));
+
+    Ok(())
+}
+
+fn last_check_path() -> Option<PathBuf> {
+    let mut path = home::home_dir()?;
+    path.push(".doken/last-update-check");
+    Some(path)
+}
+
+/// Prints a one-line hint when a newer release exists. Runs at most once a day,
+/// only in an interactive terminal and can be disabled with `DOKEN_NO_UPDATE_CHECK`
+pub async fn notify_if_outdated() {
+    if env::var("DOKEN_NO_UPDATE_CHECK").is_ok() || !std::io::stderr().is_terminal() {
+        return;
+    }
+
+    let Some(last_check_path) = last_check_path() else {
+        return;
+    };
+
+    let checked_recently = fs::metadata(&last_check_path)
+        .and_then(|metadata| metadata.modified())
+        .map(|modified| {
+            SystemTime::now()
+                .duration_since(modified)
+                .unwrap_or_default()
+                < CHECK_INTERVAL
+        })
+        .unwrap_or(false);
+
+    if checked_recently {
+        return;
+    }
+
+    if let Some(dir) = last_check_path.parent() {
+        let _ = fs::create_dir_all(dir);
+    }
+    let _ = fs::write(&last_check_path, CURRENT_VERSION);
+
+    let release = match http_client(Duration::from_secs(2)) {
+        Ok(client) => latest_release(&client).await,
+        Err(e) => Err(e),
+    };
+
+    match release {
+        Ok(release) if is_newer(&release.tag_name, CURRENT_VERSION) => output::status(
+            "Available",
+            &format!(
+                "doken {} (current: {}). Run `doken self-update` to install it.",
+                release.tag_name, CURRENT_VERSION
+            ),
+        ),
+        Ok(_) => {}
+        Err(e) => log::debug!("Update check failed: {:?}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_compares_release_versions() {
+        assert!(is_newer("v0.9.0", "0.8.0"));
+        assert!(is_newer("1.0.0", "0.8.12"));
+        assert!(!is_newer("v0.8.0", "0.8.0"));
+        assert!(!is_newer("v0.7.9", "0.8.0"));
+        assert!(!is_newer("not-a-version", "0.8.0"));
+    }
+
+    #[test]
+    fn it_checks_integrity_against_checksum_file() {
+        let archive = b"doken";
+        let checksum = format!("{:x}  doken.tar.xz\n", Sha256::digest(archive));
+
+        assert!(check_integrity(archive, checksum.as_bytes()).is_ok());
+        assert!(check_integrity(b"corrupted", checksum.as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,167 @@
+use anyhow::{Context, Result};
+use futures::StreamExt;
+use futures::stream::FuturesUnordered;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+use url::{Host, Url};
+
+use crate::args::Arguments;
+use crate::auth_browser::browser::Browser;
+use crate::loopback;
+use crate::systemd;
+use crate::{check_permissions, get_token_info, output};
+
+fn response(access_token: &Result<String>) -> String {
+    match access_token {
+        Ok(access_token) => format!(
+            "HTTP/1.1 200 OK\r\nAuthorization: Bearer {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            access_token
+        ),
+        Err(e) => {
+            let body = format!("doken couldn't get a token: {:#}\n", e);
+            format!(
+                "HTTP/1.1 401 Unauthorized\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+        }
+    }
+}
+
+/// Requests taking longer to arrive are dropped, so idle clients don't hold connections open
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+const SHARED_SECRET_HEADER: &str = "x-doken-proxy-secret";
+
+/// Web pages may point a name of theirs at the loopback address (DNS rebinding), so only
+/// requests addressing the proxy by the listen address or as a loopback host are answered
+fn is_allowed_host(host: Option<&str>, listen: SocketAddr) -> bool {
+    let Some(url) = host.and_then(|host| Url::parse(&format!("http://{}/", host)).ok()) else {
+        return false;
+    };
+
+    match url.host() {
+        Some(Host::Domain(domain)) => domain.eq_ignore_ascii_case("localhost"),
+        Some(Host::Ipv4(ip)) => ip.is_loopback() || IpAddr::V4(ip) == listen.ip(),
+        Some(Host::Ipv6(ip)) => ip.is_loopback() || IpAddr::V6(ip) == listen.ip(),
+        None => false,
+    }
+}
+
+fn forbidden(reason: &str) -> String {
+    format!(
+        "HTTP/1.1 403 Forbidden\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        reason.len(),
+        reason
+    )
+}
+
+async fn handle(
+    mut stream: TcpStream,
+    args: &Arguments,
+    listen: SocketAddr,
+    shared_secret: Option<&str>,
+    auth_browser: &Mutex<Browser>,
+) -> Result<()> {
+    let head = tokio::time::timeout(REQUEST_TIMEOUT, loopback::read_head(&mut stream))
+        .await
+        .context("No request was sent in time")??;
+    log::debug!(
+        "Forward-auth request: {}",
+        head.lines().next().unwrap_or_default()
+    );
+
+    if !is_allowed_host(loopback::header(&head, "host"), listen) {
+        output::warning("Rejecting a forward-auth request for another host");
+        return loopback::respond(&mut stream, &forbidden("unexpected Host\n")).await;
+    }
+    if shared_secret
+        .is_some_and(|secret| loopback::header(&head, SHARED_SECRET_HEADER) != Some(secret))
+    {
+        output::warning("Rejecting a forward-auth request without the shared secret");
+        return loopback::respond(&mut stream, &forbidden("missing shared secret\n")).await;
+    }
+
+    let access_token = get_token_info(args, auth_browser.lock().await)
+        .await
+        .map(|token_info| token_info.access_token);
+
+    if let Err(e) = &access_token {
+        output::warning(&format!("Rejecting forward-auth request: {:#}", e));
+    }
+
+    loopback::respond(&mut stream, &response(&access_token)).await
+}
+
+/// Answers forward-auth requests concurrently. Tokens come from the cache, so that's fast enough for local development.
+/// Interactive grants open the browser only when there's no token to refresh
+pub async fn run(args: Arguments, listen: SocketAddr, shared_secret: Option<String>) -> Result<()> {
+    check_permissions(&args)?;
+
+    let listener = systemd::bind(listen).await?;
+    let listen = listener.local_addr()?;
+    let auth_browser = Mutex::new(
+        Browser::new(args.headless()).with_ephemeral_profile(args.ephemeral_browser_profile),
+    );
+
+    output::status(
+        "Listening",
+        &format!("on http://{} for forward-auth requests", listen),
+    );
+
+    // Getting tokens isn't `Send`, so connections are served concurrently on this task
+    let mut connections = FuturesUnordered::new();
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let (stream, peer) = accepted?;
+                log::debug!("Connection from {}", peer);
+
+                let (args, auth_browser) = (&args, &auth_browser);
+                let shared_secret = shared_secret.as_deref();
+                connections.push(async move {
+                    if let Err(e) = handle(stream, args, listen, shared_secret, auth_browser).await {
+                        log::debug!("Connection from {} failed: {:?}", peer, e);
+                    }
+                });
+            }
+            Some(()) = connections.next() => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    #[test]
+    fn it_answers_with_authorization_header_or_401() {
+        assert!(
+            response(&Ok("token".to_owned()))
+                .starts_with("HTTP/1.1 200 OK\r\nAuthorization: Bearer token\r\n")
+        );
+
+        let rejected = response(&Err(anyhow!("No refresh_token cached")));
+        assert!(rejected.starts_with("HTTP/1.1 401 Unauthorized\r\n"));
+        assert!(rejected.ends_with("No refresh_token cached\n"));
+    }
+
+    #[test]
+    fn it_answers_only_requests_for_the_proxy_itself() {
+        let listen: SocketAddr = "127.0.0.1:4181".parse().unwrap();
+
+        assert!(is_allowed_host(Some("127.0.0.1:4181"), listen));
+        assert!(is_allowed_host(Some("localhost:4181"), listen));
+        assert!(is_allowed_host(Some("[::1]:4181"), listen));
+        assert!(is_allowed_host(
+            Some("10.0.0.5:4181"),
+            "10.0.0.5:4181".parse().unwrap()
+        ));
+        assert!(!is_allowed_host(Some("attacker.example:4181"), listen));
+        assert!(!is_allowed_host(Some("10.0.0.5:4181"), listen));
+        assert!(!is_allowed_host(None, listen));
+    }
+}
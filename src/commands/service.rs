@@ -0,0 +1,196 @@
+use anyhow::{Context, Result, bail};
+use std::net::SocketAddr;
+use std::process::Command;
+use std::time::Duration;
+use std::{env, fs};
+
+use crate::output;
+
+const TASK_NAME: &str = "doken\\daemon";
+/// Event Log source the daemon's output is written with
+const EVENT_SOURCE: &str = "doken";
+
+fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// PowerShell running the daemon and writing each line it prints to the Application Event Log
+fn daemon_script(
+    exe: &str,
+    keep_alive_interval: Duration,
+    health_listen: Option<SocketAddr>,
+) -> String {
+    let mut daemon = format!(
+        "& '{}' daemon --keep-alive-interval {}",
+        exe.replace('\'', "''"),
+        humantime::format_duration(keep_alive_interval)
+    );
+    if let Some(health_listen) = health_listen {
+        daemon.push_str(&format!(" --health-listen {}", health_listen));
+    }
+
+    format!(
+        "$env:NO_COLOR = '1'; {} 2>&1 | ForEach-Object {{ eventcreate /L APPLICATION /T INFORMATION /SO {} /ID 1 /D \"$_\" | Out-Null }}",
+        daemon, EVENT_SOURCE
+    )
+}
+
+/// Task started at logon of the user, restarted on failure and never stopped by the time limit
+fn task_xml(user: &str, script: &str) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-16"?>
+<Task version="1.2" xmlns="http://schemas.microsoft.com/windows/2004/02/mit/task">
+  <RegistrationInfo>
+    <Description>Keeps doken sessions alive</Description>
+  </RegistrationInfo>
+  <Triggers>
+    <LogonTrigger>
+      <Enabled>true</Enabled>
+      <UserId>{user}</UserId>
+    </LogonTrigger>
+  </Triggers>
+  <Principals>
+    <Principal id="Author">
+      <UserId>{user}</UserId>
+      <LogonType>InteractiveToken</LogonType>
+      <RunLevel>LeastPrivilege</RunLevel>
+    </Principal>
+  </Principals>
+  <Settings>
+    <MultipleInstancesPolicy>IgnoreNew</MultipleInstancesPolicy>
+    <DisallowStartIfOnBatteries>false</DisallowStartIfOnBatteries>
+    <StopIfGoingOnBatteries>false</StopIfGoingOnBatteries>
+    <ExecutionTimeLimit>PT0S</ExecutionTimeLimit>
+    <Hidden>true</Hidden>
+    <RestartOnFailure>
+      <Interval>PT5M</Interval>
+      <Count>3</Count>
+    </RestartOnFailure>
+  </Settings>
+  <Actions Context="Author">
+    <Exec>
+      <Command>powershell.exe</Command>
+      <Arguments>-NoProfile -WindowStyle Hidden -Command "{script}"</Arguments>
+    </Exec>
+  </Actions>
+</Task>
+"#,
+        user = xml_escape(user),
+        script = xml_escape(&script.replace('"', "\\\"")),
+    )
+}
+
+fn schtasks(args: &[&str]) -> Result<()> {
+    log::debug!("Running schtasks {:?}", args);
+
+    let output = Command::new("schtasks")
+        .args(args)
+        .output()
+        .context("Cannot run `schtasks`")?;
+
+    if !output.status.success() {
+        bail!(
+            "`schtasks {}` failed: {}",
+            args.first().unwrap_or(&""),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    Ok(())
+}
+
+fn assert_windows() -> Result<()> {
+    if !cfg!(windows) {
+        bail!(
+            "`doken service` registers a Windows scheduled task. Use `doken agent install` on macOS or a systemd unit on Linux"
+        );
+    }
+
+    Ok(())
+}
+
+/// Registers a scheduled task running `doken daemon` at logon of the current user and starts it right away
+pub fn install(keep_alive_interval: Duration, health_listen: Option<SocketAddr>) -> Result<()> {
+    assert_windows()?;
+
+    let exe = env::current_exe().context("Cannot find the doken executable")?;
+    let user = match (env::var("USERDOMAIN"), env::var("USERNAME")) {
+        (Ok(domain), Ok(name)) => format!("{}\\{}", domain, name),
+        (_, Ok(name)) => name,
+        _ => bail!("Cannot determine the current user from `USERNAME`"),
+    };
+
+    let xml = task_xml(
+        &user,
+        &daemon_script(&exe.to_string_lossy(), keep_alive_interval, health_listen),
+    );
+    let xml_path = env::temp_dir().join(format!("doken-task-{}.xml", std::process::id()));
+    // Task Scheduler reads UTF-16 task definitions
+    let bytes: Vec<u8> = [0xFEFF_u16]
+        .into_iter()
+        .chain(xml.encode_utf16())
+        .flat_map(u16::to_le_bytes)
+        .collect();
+    fs::write(&xml_path, bytes)?;
+
+    let result = schtasks(&[
+        "/Create",
+        "/TN",
+        TASK_NAME,
+        "/XML",
+        &xml_path.to_string_lossy(),
+        "/F",
+    ]);
+    let _ = fs::remove_file(&xml_path);
+    result?;
+
+    schtasks(&["/Run", "/TN", TASK_NAME])?;
+
+    output::status(
+        "Installed",
+        &format!(
+            "scheduled task `{}` running `doken daemon` at logon. Its output goes to the Application Event Log (source `{}`)",
+            TASK_NAME, EVENT_SOURCE
+        ),
+    );
+
+    Ok(())
+}
+
+/// Stops and removes the scheduled task
+pub fn uninstall() -> Result<()> {
+    assert_windows()?;
+
+    // Not running is fine, the task is removed anyway
+    let _ = schtasks(&["/End", "/TN", TASK_NAME]);
+    schtasks(&["/Delete", "/TN", TASK_NAME, "/F"])?;
+
+    output::status("Uninstalled", &format!("scheduled task `{}`", TASK_NAME));
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_renders_a_logon_task_running_the_daemon() {
+        let script = daemon_script(
+            "C:\\Users\\O'Neil\\doken.exe",
+            Duration::from_secs(2 * 60 * 60),
+            Some("127.0.0.1:4182".parse().unwrap()),
+        );
+        assert!(script.starts_with(
+            "$env:NO_COLOR = '1'; & 'C:\\Users\\O''Neil\\doken.exe' daemon --keep-alive-interval 2h --health-listen 127.0.0.1:4182 2>&1"
+        ));
+
+        let xml = task_xml("CORP\\a&b", &script);
+        assert!(xml.contains("<UserId>CORP\\a&amp;b</UserId>"));
+        assert!(xml.contains("/D \\&quot;$_\\&quot;"));
+    }
+}
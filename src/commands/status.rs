@@ -0,0 +1,58 @@
+use anyhow::Result;
+use std::time::SystemTime;
+
+use crate::cached_token_info;
+use crate::output;
+
+fn describe_expiry(expires: Option<SystemTime>, utc: bool) -> (&'static str, String) {
+    let Some(expires) = expires else {
+        return ("valid", "unknown".to_owned());
+    };
+
+    let status = if expires > SystemTime::now() {
+        "valid"
+    } else {
+        "expired"
+    };
+
+    (
+        status,
+        format!(
+            "{} ({})",
+            output::relative(expires),
+            output::timestamp(expires, utc)
+        ),
+    )
+}
+
+pub fn run(client_id: String, utc: bool) -> Result<()> {
+    let pairs = match cached_token_info(&client_id)? {
+        Some(token_info) => {
+            let (status, expires) = describe_expiry(token_info.expires, utc);
+
+            vec![
+                ("client_id", client_id),
+                ("status", status.to_owned()),
+                ("expires", expires),
+                ("scope", token_info.scope.unwrap_or_default()),
+                (
+                    "refresh_token",
+                    if token_info.refresh_token.is_some() {
+                        "present"
+                    } else {
+                        "absent"
+                    }
+                    .to_owned(),
+                ),
+            ]
+        }
+        None => vec![
+            ("client_id", client_id),
+            ("status", "no cached token".to_owned()),
+        ],
+    };
+
+    println!("{}", output::key_values(&pairs));
+
+    Ok(())
+}
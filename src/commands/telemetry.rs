@@ -0,0 +1,57 @@
+use anyhow::Result;
+
+use crate::grant::Grant;
+use crate::output;
+use crate::telemetry::{self, TelemetrySettings, UsageEvent};
+
+fn disclosure() -> String {
+    format!(
+        "Each run reports only: {}",
+        serde_json::to_string(&UsageEvent::new(&Grant::AuthorizationCodeWithPkce, None))
+            .unwrap_or_default()
+    )
+}
+
+pub fn on(endpoint: String) -> Result<()> {
+    // Fails early on unsupported endpoints
+    telemetry::sink(&endpoint)?;
+
+    telemetry::write_settings(&TelemetrySettings {
+        enabled: true,
+        endpoint: Some(endpoint.to_owned()),
+    })?;
+
+    output::status("Enabled", &format!("telemetry sent to {}", endpoint));
+    eprintln!("{}", disclosure());
+
+    Ok(())
+}
+
+pub fn off() -> Result<()> {
+    telemetry::write_settings(&TelemetrySettings {
+        enabled: false,
+        ..telemetry::read_settings()
+    })?;
+
+    output::status("Disabled", "telemetry");
+
+    Ok(())
+}
+
+pub fn status() -> Result<()> {
+    let settings = telemetry::read_settings();
+
+    println!(
+        "{}",
+        output::key_values(&[
+            (
+                "telemetry",
+                if settings.enabled { "on" } else { "off" }.to_owned()
+            ),
+            ("endpoint", settings.endpoint.unwrap_or("-".to_owned())),
+            ("reported", disclosure()),
+        ])
+    );
+
+    Ok(())
+}
@@ -0,0 +1,124 @@
+use anyhow::{Context, Result};
+use futures::StreamExt;
+use std::collections::HashSet;
+use tokio::sync::Mutex;
+
+use crate::args::Arguments;
+use crate::auth_browser::browser::Browser;
+use crate::config_file::ConfigFile;
+use crate::file_state::FileState;
+use crate::oauth_client::OAuthClient;
+use crate::output;
+use crate::output_template;
+use crate::single_flight;
+use crate::token_info::TokenInfo;
+use crate::{check_permissions, get_token_info};
+
+/// Refreshes the cached token. The refreshed one keeps the consent, so the requested scope too
+pub(crate) async fn refresh(args: &Arguments, cached: TokenInfo) -> Result<TokenInfo> {
+    let refresh_token = cached
+        .refresh_token
+        .clone()
+        .context("No refresh_token cached")?;
+    let oauth_client = OAuthClient::new(args).await?;
+    let mut token_info =
+        TokenInfo::from_token_response(oauth_client.refresh_token(refresh_token.clone()).await?)
+            .with_session_of(&cached);
+
+    // Providers that don't rotate refresh tokens return only a new access_token
+    if token_info.refresh_token.is_none() {
+        token_info.refresh_token = Some(refresh_token);
+    }
+    token_info.requested_scope = cached.requested_scope;
+
+    Ok(token_info)
+}
+
+/// Refreshes cached tokens of all profiles from ~/.doken/config.toml that have a refresh_token
+pub async fn warm(concurrency: usize) -> Result<()> {
+    let config = ConfigFile::new().read().await;
+    let file_state = FileState::new()?;
+
+    let mut profiles: Vec<_> = config.profile.iter().collect();
+    profiles.sort_by_key(|(name, _)| name.to_owned());
+
+    let mut state_keys = HashSet::new();
+    let mut candidates = vec![];
+
+    for (name, profile) in profiles {
+        let Some(args) = profile.to_arguments(name) else {
+            output::warning(&format!("Skipping profile `{}` without client_id", name));
+            continue;
+        };
+        let args = match args.with_scope_aliases(&config.scopes) {
+            Ok(args) => args,
+            Err(e) => {
+                output::warning(&format!("Skipping profile `{}`: {:#}", name, e));
+                continue;
+            }
+        };
+
+        // The state is kept per client_id (and audience of exchanged tokens), so it's enough to refresh it once
+        if !state_keys.insert(args.state_key()) {
+            continue;
+        }
+
+        match file_state
+            .read_token_info(&args.state_key())
+            .filter(|token_info| token_info.refresh_token.is_some())
+        {
+            Some(_) => candidates.push((name.to_owned(), args)),
+            None => log::debug!(
+                "Profile `{}` has no refresh_token cached. Skipping...",
+                name
+            ),
+        }
+    }
+
+    let results = futures::stream::iter(candidates)
+        .map(|(name, args)| async move {
+            let result = warm_profile(&args).await;
+
+            (name, result)
+        })
+        .buffer_unordered(concurrency.max(1))
+        .collect::<Vec<_>>()
+        .await;
+
+    for (name, result) in results {
+        match result {
+            Ok(()) => output::status("Warmed", &format!("profile `{}`", name)),
+            Err(e) => output::warning(&format!("Cannot warm profile `{}`: {:#}", name, e)),
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads, refreshes and writes the token under one lock, so a concurrent run never refreshes the
+/// same (possibly rotated) refresh_token
+async fn warm_profile(args: &Arguments) -> Result<()> {
+    let _flight = single_flight::lock(&args.state_key()).await;
+    // The state file isn't kept locked during network calls, other profiles' refreshes use it too
+    let token_info = FileState::new()?
+        .read_token_info(&args.state_key())
+        .context("No token cached anymore")?;
+
+    let token_info = refresh(args, token_info).await?;
+    FileState::new()?.upsert_token_info(args.state_key(), token_info)?;
+
+    Ok(())
+}
+
+/// Prints one field of the token, so scripts don't need `jq` and the rest of the response isn't exposed
+pub async fn print(args: Arguments, field: String) -> Result<()> {
+    check_permissions(&args)?;
+
+    let auth_browser = Mutex::new(
+        Browser::new(args.headless()).with_ephemeral_profile(args.ephemeral_browser_profile),
+    );
+    let token_info = get_token_info(&args, auth_browser.lock().await).await?;
+
+    println!("{}", output_template::field(&token_info, &field)?);
+    Ok(())
+}
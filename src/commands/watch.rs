@@ -0,0 +1,135 @@
+use anyhow::Result;
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::Mutex;
+
+use crate::args::Arguments;
+use crate::auth_browser::browser::Browser;
+use crate::health;
+use crate::throttling::{self, Backoff};
+use crate::token_info::TokenInfo;
+use crate::webhook::{self, WebhookEvent};
+use crate::{check_permissions, get_token_info, output, refresh_token_info};
+
+/// New token is printed that long before the previous one expires
+const REFRESH_MARGIN: Duration = Duration::from_secs(60);
+
+/// How long to wait before the token has to be replaced
+fn refresh_in(token_info: &TokenInfo, now: SystemTime) -> Option<Duration> {
+    let expires = token_info.expires?;
+
+    Some(
+        expires
+            .duration_since(now)
+            .unwrap_or_default()
+            .saturating_sub(REFRESH_MARGIN),
+    )
+}
+
+/// Prints the token as a JSON line right away and again after each refresh until `duration` elapses
+pub async fn run(
+    args: Arguments,
+    duration: Duration,
+    health_listen: Option<SocketAddr>,
+) -> Result<()> {
+    check_permissions(&args)?;
+
+    if let Some(health_listen) = health_listen {
+        health::spawn(health_listen, Arc::new(RwLock::new(vec![args.state_key()]))).await?;
+    }
+
+    let deadline = Instant::now() + duration;
+    let auth_browser = Mutex::new(
+        Browser::new(args.headless()).with_ephemeral_profile(args.ephemeral_browser_profile),
+    );
+    let mut token_info = get_token_info(&args, auth_browser.lock().await).await?;
+    let mut backoff = Backoff::default();
+
+    loop {
+        println!("{}", serde_json::to_string(&token_info)?);
+
+        let Some(refresh_in) = refresh_in(&token_info, SystemTime::now()) else {
+            output::status("Stopped", "watching, the token never expires");
+            return Ok(());
+        };
+
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if refresh_in >= remaining {
+            tokio::time::sleep(remaining).await;
+            return Ok(());
+        }
+        tokio::time::sleep(refresh_in).await;
+
+        let refreshed = loop {
+            let refreshed = match token_info.refresh_token {
+                Some(_) => refresh_token_info(&args).await,
+                // The cached token is still valid for a while, so a new one has to be forced
+                None => {
+                    let args = Arguments {
+                        force: true,
+                        ..args.to_owned()
+                    };
+                    get_token_info(&args, auth_browser.lock().await).await
+                }
+            };
+
+            let Some(throttled) = refreshed.as_ref().err().and_then(throttling::throttled) else {
+                backoff.reset();
+                break refreshed;
+            };
+            let delay = backoff.delay(throttled);
+            if Instant::now() + delay >= deadline {
+                break refreshed;
+            }
+
+            output::status(
+                "Backing off",
+                &format!(
+                    "for {}, the provider is throttling refreshes",
+                    humantime::format_duration(Duration::from_secs(delay.as_secs()))
+                ),
+            );
+            tokio::time::sleep(delay).await;
+        };
+        token_info = match refreshed {
+            Ok(token_info) => {
+                webhook::notify(&args, WebhookEvent::refreshed(&args, &token_info)).await;
+                token_info
+            }
+            Err(e) => {
+                webhook::notify(&args, WebhookEvent::failed(&args, &e)).await;
+                return Err(e);
+            }
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn it_refreshes_before_expiry() {
+        let now = SystemTime::now();
+        let token_info = |expires| TokenInfo {
+            access_token: "token".to_owned(),
+            refresh_token: None,
+            expires,
+            scope: None,
+            requested_scope: None,
+            extra: HashMap::new(),
+        };
+
+        assert_eq!(
+            refresh_in(&token_info(Some(now + Duration::from_secs(300))), now),
+            Some(Duration::from_secs(240))
+        );
+        assert_eq!(
+            refresh_in(&token_info(Some(now + Duration::from_secs(30))), now),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(refresh_in(&token_info(None), now), None);
+    }
+}
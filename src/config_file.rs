@@ -1,17 +1,33 @@
 use anyhow::{Context, Result, anyhow};
-use std::{collections::HashMap, env, path::PathBuf};
+use std::collections::{BTreeSet, HashMap};
+use std::{env, path::PathBuf};
 use tokio::fs;
 
+use jsonwebtoken::Algorithm;
 use serde::{Deserialize, Serialize};
 use serde_variant::to_variant_name;
 
+use crate::args::Arguments;
+use crate::claims::RequiredClaim;
+use crate::client_auth_method::ClientAuthMethod;
+use crate::clock_skew::ClockSkewMode;
 use crate::grant::Grant;
+use crate::hooks::{HookEnv, HookFailure};
+use crate::interop::CacheFormat;
+use crate::permissions;
+use crate::providers::Provider;
 
-#[derive(Deserialize, Serialize, Clone, Debug)]
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
 pub struct Profile {
     /// Authentication Grant
     pub grant: Option<Grant>,
 
+    /// Grant used instead of a browser based `grant` when no browser can be shown
+    pub fallback_grant: Option<Grant>,
+
+    /// Identity provider specific behaviour
+    pub provider: Option<Provider>,
+
     /// OAuth 2.0 token exchange url
     pub token_url: Option<String>,
 
@@ -21,34 +37,453 @@ pub struct Profile {
     /// OpenID Connect discovery url
     pub discovery_url: Option<String>,
 
+    /// OAuth 2.0 device authorization url of `device-code` grant
+    pub device_authorization_url: Option<String>,
+
+    /// JSON paths of discovery document fields for providers with non-standard documents, ex. `token_endpoint = "$.endpoints.token"`
+    pub discovery_map: Option<HashMap<String, String>>,
+
+    /// HTTP headers sent with token requests and by the controlled browser, ex. `extra_headers = { "X-Device-Id" = "${DEVICE_ID}" }`
+    pub extra_headers: Option<HashMap<String, String>>,
+
     /// Callback URL that's been set for your application
     pub callback_url: Option<String>,
 
+    /// Command opening the authorization url instead of the controlled browser, ex. `firefox --private-window {url}`
+    pub browser_cmd: Option<String>,
+
     /// OAuth 2.0 Client Identifier <https://www.rfc-editor.org/rfc/rfc6749#section-2.2>
     pub client_id: Option<String>,
 
     /// OAuth 2.0 Client Secret. Please use `--client-secret-stdin`, because it's not get stored in a shell history.  <https://www.rfc-editor.org/rfc/rfc6749#section-2.3.1>
     pub client_secret: Option<String>,
 
+    /// Password manager reference of the client secret, ex. `op://<vault>/<item>/<field>` or `bw://<item>/<field>`
+    pub client_secret_from: Option<String>,
+
+    /// How the client authenticates at the token endpoint: `basic`, `post`, `none` or `private-key-jwt`
+    pub client_auth_method: Option<ClientAuthMethod>,
+
+    /// PEM private key, `yubikey:<slot>` or `pkcs11:` URI signing client assertions of `private_key_jwt`
+    pub client_assertion_key: Option<String>,
+
+    /// Signing algorithm of client assertions, `RS256` by default
+    pub client_assertion_alg: Option<Algorithm>,
+
+    /// `kid` of client assertions
+    pub client_assertion_kid: Option<String>,
+
+    /// Private key signing assertions of `jwt-bearer` grant: a PEM file, a private JWK or a Google service account key file
+    pub assertion_key_file: Option<String>,
+
+    /// `sub` of `jwt-bearer` assertions
+    pub assertion_subject: Option<String>,
+
+    /// `aud` of `jwt-bearer` assertions
+    pub assertion_audience: Option<String>,
+
+    /// SAML assertion of `saml2-bearer` grant, as XML or base64
+    pub saml_assertion_file: Option<String>,
+
     /// OAuth 2.0 Resource Owner Password Client Credentials Grant's username <https://www.rfc-editor.org/rfc/rfc6749#section-4.3.2>
     pub username: Option<String>,
 
     /// OAuth 2.0 Resource Owner Password Client Credentials Grant's password <https://www.rfc-editor.org/rfc/rfc6749#section-4.3.2>
     pub password: Option<String>,
 
+    /// Base32 TOTP secret for `resource-owner-password-client-credentials` grant, ex. `"${MY_OTP_SECRET}"`
+    pub otp_secret: Option<String>,
+
+    /// Form parameter carrying the one-time password instead of appending it to the password
+    pub otp_param: Option<String>,
+
     /// OAuth 2.0 Scope <https://www.rfc-editor.org/rfc/rfc6749#section-3.3>
     pub scope: Option<String>,
 
     /// OpenID Connect requested aud
     pub audience: Option<String>,
 
+    /// RFC 8707 resource indicators, ex. `["https://api.my-app.com"]`
+    pub resource: Option<Vec<String>>,
+
+    /// Profile whose access_token is exchanged with `token-exchange` grant
+    pub upstream_profile: Option<String>,
+
+    /// Tenant of a multi-tenant provider the token has to be issued for, ex. Entra ID's `tid`
+    pub tenant: Option<String>,
+
+    /// Shows the requested scopes and asks for confirmation before a new flow is started
+    pub confirm_scopes: Option<bool>,
+
+    /// Signing algorithms accepted for the id_token, ex. `["RS256", "ES256"]`
+    pub allowed_algs: Option<Vec<Algorithm>>,
+
+    /// Key ids the id_token has to be signed with
+    pub pin_kid: Option<Vec<String>>,
+
+    /// Claims a cached token has to carry, ex. `["tid=<tenant_id>", "roles=admin"]`
+    pub require_claim: Option<Vec<RequiredClaim>>,
+
+    /// Token cache of another CLI tool to import the token from, ex. `oauth2l` or `kubelogin`
+    pub interop_cache: Option<CacheFormat>,
+
+    /// Url a JSON event is POSTed to whenever `doken daemon` or `doken watch` refresh the token or fail to
+    pub webhook_url: Option<String>,
+
+    /// Secret webhook events are signed with, ex. `"${MY_WEBHOOK_SECRET}"`
+    pub webhook_secret: Option<String>,
+
+    /// Command run before a token is refreshed or a new flow starts, ex. `"wg-quick up work"`
+    pub pre_hook: Option<String>,
+
+    /// Command run after a token is refreshed or a new flow ends, reading the token as JSON on stdin
+    pub post_hook: Option<String>,
+
+    /// Milliseconds a hook may run before it's killed
+    pub hook_timeout: Option<u64>,
+
+    /// `abort` (default) or `warn` when a hook fails
+    pub hook_failure: Option<HookFailure>,
+
+    /// `inherit` (default) or `clean` environment of hooks
+    pub hook_env: Option<HookEnv>,
+
+    /// Proxy used for all requests to the provider. System proxy settings are used by default
+    pub proxy: Option<String>,
+
+    /// Additional trusted CA certificates (PEM bundle)
+    pub ca_cert: Option<String>,
+
     /// Authorization Code, Authorization Code with PKCE and Implicit Grants' timeout,
     pub timeout: Option<u64>,
+
+    /// `warn` (default), `adjust` or `ignore` when the local clock is off
+    pub clock_skew: Option<ClockSkewMode>,
+
+    /// Seconds the local clock may differ from the provider's
+    pub max_clock_skew: Option<u64>,
+
+    /// `doken daemon` periodically refreshes the token (or calls userinfo) to keep the session alive
+    pub keep_alive: Option<bool>,
+}
+
+impl Profile {
+    /// Arguments described only by the profile, without taking environment variables into account.
+    /// Returns `None` if the profile has no `client_id`
+    pub fn to_arguments(&self, name: &str) -> Option<Arguments> {
+        Some(Arguments {
+            grant: self
+                .grant
+                .clone()
+                .unwrap_or(Grant::AuthorizationCodeWithPkce),
+            fallback_grant: self.fallback_grant.clone(),
+            provider: self.provider.clone().unwrap_or_default(),
+            token_url: self.token_url.clone(),
+            authorization_url: self.authorization_url.clone(),
+            discovery_url: self.discovery_url.clone(),
+            device_authorization_url: self.device_authorization_url.clone(),
+            discovery_map: self
+                .discovery_map
+                .iter()
+                .flatten()
+                .map(|(field, path)| (field.to_owned(), path.to_owned()))
+                .collect(),
+            extra_header: self
+                .extra_headers
+                .iter()
+                .flatten()
+                .map(|(name, value)| (name.to_owned(), value.to_owned()))
+                .collect(),
+            callback_url: self.callback_url.clone(),
+            browser_cmd: self.browser_cmd.clone(),
+            client_id: self.client_id.clone()?,
+            client_secret: self.client_secret.clone(),
+            client_secret_from: self.client_secret_from.clone(),
+            client_auth_method: self.client_auth_method.clone(),
+            client_assertion_key: self.client_assertion_key.clone(),
+            client_assertion_alg: self.client_assertion_alg.unwrap_or(Algorithm::RS256),
+            client_assertion_kid: self.client_assertion_kid.clone(),
+            assertion_key_file: self.assertion_key_file.clone(),
+            assertion_subject: self.assertion_subject.clone(),
+            assertion_audience: self.assertion_audience.clone(),
+            saml_assertion_file: self.saml_assertion_file.clone(),
+            username: self.username.clone(),
+            password: self.password.clone(),
+            otp_secret: self.otp_secret.clone(),
+            otp_param: self.otp_param.clone(),
+            scope: self
+                .scope
+                .clone()
+                .unwrap_or_else(|| "offline_access".to_owned()),
+            audience: self.audience.clone(),
+            resource: self.resource.clone().unwrap_or_default(),
+            upstream_profile: self.upstream_profile.clone(),
+            tenant: self.tenant.clone(),
+            confirm_scopes: self.confirm_scopes.unwrap_or_default(),
+            allowed_algs: self.allowed_algs.clone().unwrap_or_default(),
+            pin_kid: self.pin_kid.clone().unwrap_or_default(),
+            require_claim: self.require_claim.clone().unwrap_or_default(),
+            interop_cache: self.interop_cache,
+            webhook_url: self.webhook_url.clone(),
+            webhook_secret: self.webhook_secret.clone(),
+            pre_hook: self.pre_hook.clone(),
+            post_hook: self.post_hook.clone(),
+            hook_timeout: self.hook_timeout.unwrap_or(30_000),
+            hook_failure: self.hook_failure.unwrap_or_default(),
+            hook_env: self.hook_env.unwrap_or_default(),
+            proxy: self.proxy.clone(),
+            ca_cert: self.ca_cert.clone(),
+            timeout: self.timeout.unwrap_or(30_000),
+            clock_skew: self.clock_skew.unwrap_or_default(),
+            max_clock_skew: self.max_clock_skew.unwrap_or(300),
+            profile: Some(name.to_owned()),
+            ..Default::default()
+        })
+    }
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct Config {
     pub profile: HashMap<String, Profile>,
+
+    /// Scope aliases used as `--scope @<name>`, ex. `api-read = ["api://x/read", "openid"]`
+    #[serde(default)]
+    pub scopes: HashMap<String, Vec<String>>,
+}
+
+/// Scope with `@<name>` aliases replaced by their scopes. Repeated scopes are kept once
+pub fn expand_scope_aliases(scope: &str, aliases: &HashMap<String, Vec<String>>) -> Result<String> {
+    let mut scopes: Vec<&str> = vec![];
+
+    for item in scope.split_whitespace() {
+        let expanded = match item.strip_prefix('@') {
+            Some(name) => aliases
+                .get(name)
+                .with_context(|| format!("Scope alias `{}` isn't defined in [scopes]", name))?
+                .iter()
+                .map(String::as_str)
+                .collect(),
+            None => vec![item],
+        };
+
+        for scope in expanded {
+            if !scopes.contains(&scope) {
+                scopes.push(scope);
+            }
+        }
+    }
+
+    Ok(scopes.join(" "))
+}
+
+/// Replaces `${NAME}` references with environment variables. Returns names of unset variables, which are left as is
+pub fn expand_env(text: &str) -> (String, Vec<String>) {
+    let mut result = String::new();
+    let mut missing = vec![];
+    let mut rest = text;
+
+    while let Some(start) = rest.find("${") {
+        let Some(end) = rest[start..].find('}') else {
+            break;
+        };
+        let name = &rest[start + 2..start + end];
+
+        result.push_str(&rest[..start]);
+        match env::var(name) {
+            Ok(value) => result.push_str(&value),
+            Err(_) => {
+                missing.push(name.to_owned());
+                result.push_str(&rest[start..=start + end]);
+            }
+        }
+        rest = &rest[start + end + 1..];
+    }
+
+    result.push_str(rest);
+    (result, missing)
+}
+
+fn expand_values(value: &mut toml::Value, missing: &mut Vec<String>) {
+    match value {
+        toml::Value::String(text) => {
+            let (expanded, unset) = expand_env(text);
+            *text = expanded;
+            missing.extend(unset);
+        }
+        toml::Value::Array(values) => values
+            .iter_mut()
+            .for_each(|value| expand_values(value, missing)),
+        toml::Value::Table(table) => table
+            .iter_mut()
+            .for_each(|(_, value)| expand_values(value, missing)),
+        _ => {}
+    }
+}
+
+/// Parses the config with `${NAME}` references in string values expanded. Returns names of unset variables too
+pub fn parse(text: &str) -> Result<(Config, Vec<String>)> {
+    let mut value = toml::from_str::<toml::Value>(text).map_err(|e| anyhow!(e))?;
+    let mut missing = vec![];
+    expand_values(&mut value, &mut missing);
+
+    let config = value.try_into::<Config>().map_err(|e| anyhow!(e))?;
+
+    Ok((config, missing))
+}
+
+/// Config text with a plain string option of the profile replaced, keeping comments and the
+/// layout. `None` if the profile doesn't set the option or takes it from a `${NAME}` reference
+pub fn replace_profile_value(text: &str, profile: &str, key: &str, value: &str) -> Option<String> {
+    let headers = [
+        format!("[profile.{}]", profile),
+        format!("[profile.\"{}\"]", profile),
+    ];
+    let mut in_profile = false;
+    let mut replaced = false;
+
+    let lines: Vec<String> = text
+        .lines()
+        .map(|line| {
+            let trimmed = line.trim();
+            if trimmed.starts_with('[') {
+                in_profile = headers.iter().any(|header| header == trimmed);
+                return line.to_owned();
+            }
+
+            let current = trimmed
+                .split_once('=')
+                .filter(|(name, _)| in_profile && !replaced && name.trim() == key)
+                .map(|(_, current)| current.trim());
+            match current {
+                Some(current) if current.starts_with('"') && !current.contains("${") => {
+                    replaced = true;
+                    format!("{} = {}", key, toml::Value::String(value.to_owned()))
+                }
+                _ => line.to_owned(),
+            }
+        })
+        .collect();
+
+    replaced.then(|| lines.join("\n") + if text.ends_with('\n') { "\n" } else { "" })
+}
+
+/// Prefix of environment variables defining profiles, ex. `DOKEN_PROFILE_WORK_CLIENT_ID`
+const ENV_PROFILE_PREFIX: &str = "DOKEN_PROFILE_";
+
+/// Names of all `Profile` options
+pub fn profile_keys() -> BTreeSet<String> {
+    serde_json::to_value(Profile::default())
+        .ok()
+        .and_then(|value| value.as_object().map(|keys| keys.keys().cloned().collect()))
+        .unwrap_or_default()
+}
+
+/// Profile option set by a `DOKEN_PROFILE_<NAME>_<OPTION>` environment variable
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnvProfileField {
+    pub profile: String,
+    pub key: String,
+    pub value: String,
+    pub var: String,
+}
+
+/// Profile options of `DOKEN_PROFILE_<NAME>_<OPTION>` variables. Names are lowercased and may contain underscores,
+/// the longest option matching the end of the variable wins
+pub fn env_profile_fields(vars: impl Iterator<Item = (String, String)>) -> Vec<EnvProfileField> {
+    let keys = profile_keys();
+    let mut fields: Vec<EnvProfileField> = vars
+        .filter_map(|(var, value)| {
+            let rest = var.strip_prefix(ENV_PROFILE_PREFIX)?;
+            let key = keys
+                .iter()
+                .filter(|key| {
+                    rest.strip_suffix(&key.to_uppercase())
+                        .is_some_and(|name| name.len() > 1 && name.ends_with('_'))
+                })
+                .max_by_key(|key| key.len())?;
+            let name = &rest[..rest.len() - key.len() - 1];
+
+            Some(EnvProfileField {
+                profile: name.to_lowercase(),
+                key: key.to_owned(),
+                value,
+                var,
+            })
+        })
+        .collect();
+
+    fields.sort_by(|a, b| a.var.cmp(&b.var));
+    fields
+}
+
+fn env_value(field: &EnvProfileField) -> Result<toml::Value> {
+    let value = &field.value;
+    let invalid = || format!("`{}` has an invalid value", field.var);
+
+    Ok(match field.key.as_str() {
+        "timeout" | "hook_timeout" | "max_clock_skew" => {
+            toml::Value::Integer(value.parse().with_context(invalid)?)
+        }
+        "confirm_scopes" | "keep_alive" => {
+            toml::Value::Boolean(value.parse().with_context(invalid)?)
+        }
+        "allowed_algs" | "pin_kid" | "require_claim" | "resource" => toml::Value::Array(
+            value
+                .split(',')
+                .map(|item| toml::Value::String(item.trim().to_owned()))
+                .collect(),
+        ),
+        "extra_headers" => toml::Value::Table(
+            value
+                .split(',')
+                .map(|header| {
+                    let (name, value) = header.split_once(':').with_context(invalid)?;
+                    Ok((
+                        name.trim().to_owned(),
+                        toml::Value::String(value.trim().to_owned()),
+                    ))
+                })
+                .collect::<Result<toml::Table>>()?,
+        ),
+        "discovery_map" => toml::Value::Table(
+            value
+                .split(',')
+                .map(|mapping| {
+                    let (field, path) = mapping.split_once('=').with_context(invalid)?;
+                    Ok((
+                        field.trim().to_owned(),
+                        toml::Value::String(path.trim().to_owned()),
+                    ))
+                })
+                .collect::<Result<toml::Table>>()?,
+        ),
+        _ => toml::Value::String(value.to_owned()),
+    })
+}
+
+/// Config with profiles of environment variables merged in. They take precedence over the file
+pub fn with_env_profiles(config: Config, fields: &[EnvProfileField]) -> Result<Config> {
+    if fields.is_empty() {
+        return Ok(config);
+    }
+
+    let mut value = toml::Value::try_from(config).map_err(|e| anyhow!(e))?;
+    let profiles = value
+        .get_mut("profile")
+        .and_then(|profiles| profiles.as_table_mut())
+        .context("Config has no profiles table")?;
+
+    for field in fields {
+        let profile = profiles
+            .entry(field.profile.to_owned())
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+
+        if let Some(profile) = profile.as_table_mut() {
+            profile.insert(field.key.to_owned(), env_value(field)?);
+        }
+    }
+
+    value.try_into::<Config>().map_err(|e| anyhow!(e))
 }
 
 pub struct ConfigFile {
@@ -71,31 +506,80 @@ impl ConfigFile {
         }
     }
 
-    async fn read(&self) -> Config {
+    pub fn path(&self) -> &PathBuf {
+        &self.file_path
+    }
+
+    pub async fn read_text(&self) -> Result<String> {
+        fs::read_to_string(&self.file_path)
+            .await
+            .with_context(|| format!("Cannot access {}", self.file_path.to_string_lossy()))
+    }
+
+    /// Replaces the config file at once, so a failed write leaves the previous one in place
+    pub fn write_text(&self, text: &str) -> Result<()> {
+        let path = &self.file_path;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        let temp_path = path.with_extension("toml.tmp");
+        let write = || -> std::io::Result<()> {
+            let mut file = permissions::open_private(&temp_path)?;
+            file.set_len(0)?;
+            std::io::Write::write_all(&mut file, text.as_bytes())?;
+            file.sync_all()?;
+            std::fs::rename(&temp_path, path)
+        };
+
+        write()
+            .map_err(|e| {
+                let _ = std::fs::remove_file(&temp_path);
+                anyhow!(e)
+            })
+            .with_context(|| format!("Cannot write {}", path.display()))
+    }
+
+    /// Profiles of the config file only, without `DOKEN_PROFILE_*` environment variables
+    pub async fn read_file(&self) -> Config {
         log::debug!("Reading the state file");
-        let text = fs::read_to_string(&self.file_path).await.context(format!(
-            "Cannot access {}",
-            self.file_path.to_string_lossy()
-        ));
+        let text = self.read_text().await;
 
-        let profile = HashMap::new();
+        let empty = || Config {
+            profile: HashMap::new(),
+            scopes: HashMap::new(),
+        };
         match text {
-            Ok(text) => toml::from_str::<Config>(&text).unwrap_or_else(|e| {
+            Ok(text) => parse(&text).map(|(config, _)| config).unwrap_or_else(|e| {
                 log::warn!(
                     "Cannot parse config file {}. Error: {:?}",
                     &self.file_path.to_string_lossy(),
-                    anyhow!(e)
+                    e
                 );
 
-                Config { profile }
+                empty()
             }),
             Err(e) => {
                 log::warn!("{}", e);
-                Config { profile }
+                empty()
             }
         }
     }
 
+    pub async fn read(&self) -> Config {
+        let config = self.read_file().await;
+        let fields = env_profile_fields(env::vars());
+
+        with_env_profiles(config.clone(), &fields).unwrap_or_else(|e| {
+            log::warn!(
+                "Cannot apply `{}*` variables. Error: {:?}",
+                ENV_PROFILE_PREFIX,
+                e
+            );
+            config
+        })
+    }
+
     pub async fn apply_profile(&self, profile: Option<String>) -> Result<()> {
         let config = self.read().await;
 
@@ -112,12 +596,51 @@ impl ConfigFile {
                 }
             }
 
+            if let Some(fallback_grant) = &profile.fallback_grant {
+                unsafe {
+                    env::set_var(
+                        "DOKEN_FALLBACK_GRANT",
+                        to_variant_name(&fallback_grant).unwrap(),
+                    );
+                }
+            }
+
+            if let Some(provider) = &profile.provider {
+                unsafe {
+                    env::set_var("DOKEN_PROVIDER", to_variant_name(&provider).unwrap());
+                }
+            }
+
             if let Some(discovery_url) = &profile.discovery_url {
                 unsafe {
                     env::set_var("DOKEN_DISCOVERY_URL", discovery_url);
                 }
             }
 
+            if let Some(discovery_map) = &profile.discovery_map {
+                let value = discovery_map
+                    .iter()
+                    .map(|(field, path)| format!("{}={}", field, path))
+                    .collect::<Vec<String>>()
+                    .join(",");
+
+                unsafe {
+                    env::set_var("DOKEN_DISCOVERY_MAP", value);
+                }
+            }
+
+            if let Some(extra_headers) = &profile.extra_headers {
+                let value = extra_headers
+                    .iter()
+                    .map(|(name, value)| format!("{}: {}", name, value))
+                    .collect::<Vec<String>>()
+                    .join(",");
+
+                unsafe {
+                    env::set_var("DOKEN_EXTRA_HEADERS", value);
+                }
+            }
+
             if let Some(token_url) = &profile.token_url {
                 unsafe {
                     env::set_var("DOKEN_TOKEN_URL", token_url);
@@ -130,12 +653,24 @@ impl ConfigFile {
                 }
             }
 
+            if let Some(device_authorization_url) = &profile.device_authorization_url {
+                unsafe {
+                    env::set_var("DOKEN_DEVICE_AUTHORIZATION_URL", device_authorization_url);
+                }
+            }
+
             if let Some(callback_url) = &profile.callback_url {
                 unsafe {
                     env::set_var("DOKEN_CALLBACK_URL", callback_url);
                 }
             }
 
+            if let Some(browser_cmd) = &profile.browser_cmd {
+                unsafe {
+                    env::set_var("DOKEN_BROWSER_CMD", browser_cmd);
+                }
+            }
+
             if let Some(client_id) = &profile.client_id {
                 unsafe {
                     env::set_var("DOKEN_CLIENT_ID", client_id);
@@ -148,6 +683,66 @@ impl ConfigFile {
                 }
             }
 
+            if let Some(client_secret_from) = &profile.client_secret_from {
+                unsafe {
+                    env::set_var("DOKEN_CLIENT_SECRET_FROM", client_secret_from);
+                }
+            }
+
+            if let Some(client_auth_method) = &profile.client_auth_method {
+                unsafe {
+                    env::set_var(
+                        "DOKEN_CLIENT_AUTH_METHOD",
+                        to_variant_name(&client_auth_method).unwrap(),
+                    );
+                }
+            }
+
+            if let Some(client_assertion_key) = &profile.client_assertion_key {
+                unsafe {
+                    env::set_var("DOKEN_CLIENT_ASSERTION_KEY", client_assertion_key);
+                }
+            }
+
+            if let Some(client_assertion_alg) = &profile.client_assertion_alg {
+                unsafe {
+                    env::set_var(
+                        "DOKEN_CLIENT_ASSERTION_ALG",
+                        format!("{:?}", client_assertion_alg),
+                    );
+                }
+            }
+
+            if let Some(client_assertion_kid) = &profile.client_assertion_kid {
+                unsafe {
+                    env::set_var("DOKEN_CLIENT_ASSERTION_KID", client_assertion_kid);
+                }
+            }
+
+            if let Some(assertion_key_file) = &profile.assertion_key_file {
+                unsafe {
+                    env::set_var("DOKEN_ASSERTION_KEY_FILE", assertion_key_file);
+                }
+            }
+
+            if let Some(assertion_subject) = &profile.assertion_subject {
+                unsafe {
+                    env::set_var("DOKEN_ASSERTION_SUBJECT", assertion_subject);
+                }
+            }
+
+            if let Some(assertion_audience) = &profile.assertion_audience {
+                unsafe {
+                    env::set_var("DOKEN_ASSERTION_AUDIENCE", assertion_audience);
+                }
+            }
+
+            if let Some(saml_assertion_file) = &profile.saml_assertion_file {
+                unsafe {
+                    env::set_var("DOKEN_SAML_ASSERTION_FILE", saml_assertion_file);
+                }
+            }
+
             if let Some(username) = &profile.username {
                 unsafe {
                     env::set_var("DOKEN_USERNAME", username);
@@ -160,6 +755,18 @@ impl ConfigFile {
                 }
             }
 
+            if let Some(otp_secret) = &profile.otp_secret {
+                unsafe {
+                    env::set_var("DOKEN_OTP_SECRET", otp_secret);
+                }
+            }
+
+            if let Some(otp_param) = &profile.otp_param {
+                unsafe {
+                    env::set_var("DOKEN_OTP_PARAM", otp_param);
+                }
+            }
+
             if let Some(scope) = &profile.scope {
                 unsafe {
                     env::set_var("DOKEN_SCOPE", scope);
@@ -172,13 +779,233 @@ impl ConfigFile {
                 }
             }
 
+            if let Some(resource) = &profile.resource {
+                unsafe {
+                    env::set_var("DOKEN_RESOURCE", resource.join(","));
+                }
+            }
+
+            if let Some(upstream_profile) = &profile.upstream_profile {
+                unsafe {
+                    env::set_var("DOKEN_UPSTREAM_PROFILE", upstream_profile);
+                }
+            }
+
+            if let Some(tenant) = &profile.tenant {
+                unsafe {
+                    env::set_var("DOKEN_TENANT", tenant);
+                }
+            }
+
+            if let Some(confirm_scopes) = &profile.confirm_scopes {
+                unsafe {
+                    env::set_var("DOKEN_CONFIRM_SCOPES", confirm_scopes.to_string());
+                }
+            }
+
+            if let Some(allowed_algs) = &profile.allowed_algs {
+                let value = allowed_algs
+                    .iter()
+                    .map(|alg| format!("{:?}", alg))
+                    .collect::<Vec<String>>()
+                    .join(",");
+
+                unsafe {
+                    env::set_var("DOKEN_ALLOWED_ALGS", value);
+                }
+            }
+
+            if let Some(pin_kid) = &profile.pin_kid {
+                unsafe {
+                    env::set_var("DOKEN_PIN_KID", pin_kid.join(","));
+                }
+            }
+
+            if let Some(require_claim) = &profile.require_claim {
+                let value = require_claim
+                    .iter()
+                    .map(|claim| claim.to_string())
+                    .collect::<Vec<String>>()
+                    .join(",");
+
+                unsafe {
+                    env::set_var("DOKEN_REQUIRE_CLAIM", value);
+                }
+            }
+
+            if let Some(interop_cache) = &profile.interop_cache {
+                unsafe {
+                    env::set_var(
+                        "DOKEN_INTEROP_CACHE",
+                        to_variant_name(&interop_cache).unwrap(),
+                    );
+                }
+            }
+
+            if let Some(webhook_url) = &profile.webhook_url {
+                unsafe {
+                    env::set_var("DOKEN_WEBHOOK_URL", webhook_url);
+                }
+            }
+
+            if let Some(webhook_secret) = &profile.webhook_secret {
+                unsafe {
+                    env::set_var("DOKEN_WEBHOOK_SECRET", webhook_secret);
+                }
+            }
+
+            if let Some(pre_hook) = &profile.pre_hook {
+                unsafe {
+                    env::set_var("DOKEN_PRE_HOOK", pre_hook);
+                }
+            }
+
+            if let Some(post_hook) = &profile.post_hook {
+                unsafe {
+                    env::set_var("DOKEN_POST_HOOK", post_hook);
+                }
+            }
+
+            if let Some(hook_timeout) = &profile.hook_timeout {
+                unsafe {
+                    env::set_var("DOKEN_HOOK_TIMEOUT", hook_timeout.to_string());
+                }
+            }
+
+            if let Some(hook_failure) = &profile.hook_failure {
+                unsafe {
+                    env::set_var(
+                        "DOKEN_HOOK_FAILURE",
+                        to_variant_name(&hook_failure).unwrap(),
+                    );
+                }
+            }
+
+            if let Some(hook_env) = &profile.hook_env {
+                unsafe {
+                    env::set_var("DOKEN_HOOK_ENV", to_variant_name(&hook_env).unwrap());
+                }
+            }
+
+            if let Some(proxy) = &profile.proxy {
+                unsafe {
+                    env::set_var("DOKEN_PROXY", proxy);
+                }
+            }
+
+            if let Some(ca_cert) = &profile.ca_cert {
+                unsafe {
+                    env::set_var("DOKEN_CA_CERT", ca_cert);
+                }
+            }
+
             if let Some(timeout) = &profile.timeout {
                 unsafe {
                     env::set_var("DOKEN_TIMEOUT", timeout.to_string());
                 }
             }
+
+            if let Some(clock_skew) = &profile.clock_skew {
+                unsafe {
+                    env::set_var("DOKEN_CLOCK_SKEW", to_variant_name(&clock_skew).unwrap());
+                }
+            }
+
+            if let Some(max_clock_skew) = &profile.max_clock_skew {
+                unsafe {
+                    env::set_var("DOKEN_MAX_CLOCK_SKEW", max_clock_skew.to_string());
+                }
+            }
         }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_merges_profiles_from_environment_variables() {
+        let vars = [
+            ("DOKEN_PROFILE_WORK_CLIENT_ID", "env-client"),
+            ("DOKEN_PROFILE_CI_BOT_CLIENT_SECRET", "secret"),
+            ("DOKEN_PROFILE_CI_BOT_TIMEOUT", "1000"),
+            ("DOKEN_PROFILE_CI_BOT_PIN_KID", "a, b"),
+            ("DOKEN_CLIENT_ID", "not-a-profile"),
+        ]
+        .map(|(var, value)| (var.to_owned(), value.to_owned()));
+        let fields = env_profile_fields(vars.into_iter());
+
+        assert_eq!(fields.len(), 4);
+        assert_eq!(fields[0].profile, "ci_bot");
+        assert_eq!(fields[0].key, "client_secret");
+
+        let (config, _) = parse(
+            r#"[profile.work]
+client_id = "file-client"
+scope = "openid"
+"#,
+        )
+        .unwrap();
+        let config = with_env_profiles(config, &fields).unwrap();
+
+        let work = &config.profile["work"];
+        assert_eq!(work.client_id.as_deref(), Some("env-client"));
+        assert_eq!(work.scope.as_deref(), Some("openid"));
+
+        let ci_bot = &config.profile["ci_bot"];
+        assert_eq!(ci_bot.timeout, Some(1000));
+        assert_eq!(ci_bot.pin_kid, Some(vec!["a".to_owned(), "b".to_owned()]));
+    }
+
+    #[test]
+    fn it_replaces_plain_values_of_a_profile() {
+        let text = r#"# Automation clients
+[profile.ci]
+client_id = "ci-client"
+client_secret = "old" # rotated by doken
+
+[profile.other]
+client_secret = "other"
+"#;
+
+        assert_eq!(
+            replace_profile_value(text, "ci", "client_secret", "new").unwrap(),
+            text.replace(r#""old" # rotated by doken"#, r#""new""#)
+        );
+        assert_eq!(
+            replace_profile_value(text, "missing", "client_secret", "new"),
+            None
+        );
+        assert_eq!(
+            replace_profile_value(
+                "[profile.ci]\nclient_secret = \"${CI_SECRET}\"\n",
+                "ci",
+                "client_secret",
+                "new"
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn it_expands_scope_aliases() {
+        let (config, _) = parse(
+            r#"[profile.work]
+client_id = "work"
+
+[scopes]
+api-read = ["api://x/read", "openid"]
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            expand_scope_aliases("openid @api-read offline_access", &config.scopes).unwrap(),
+            "openid api://x/read offline_access"
+        );
+        assert!(expand_scope_aliases("@missing", &config.scopes).is_err());
+    }
+}
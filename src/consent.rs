@@ -0,0 +1,66 @@
+use anyhow::{Result, bail};
+
+use crate::args::Arguments;
+use crate::openidc_discovery::ProviderMetadata;
+use crate::output;
+use crate::prompt::confirm;
+
+/// Descriptions of scopes defined by OpenID Connect <https://openid.net/specs/openid-connect-core-1_0.html#ScopeClaims>
+const SCOPE_DESCRIPTIONS: [(&str, &str); 6] = [
+    ("openid", "Sign you in and read your user identifier"),
+    (
+        "profile",
+        "Read your name, picture and other profile details",
+    ),
+    ("email", "Read your email address"),
+    ("address", "Read your postal address"),
+    ("phone", "Read your phone number"),
+    (
+        "offline_access",
+        "Keep access while you're away via a refresh_token",
+    ),
+];
+
+fn describe(scope: &str, metadata: &ProviderMetadata) -> String {
+    let description = SCOPE_DESCRIPTIONS
+        .iter()
+        .find(|(name, _)| *name == scope)
+        .map(|(_, description)| description.to_string())
+        .unwrap_or_else(|| "Defined by the provider".to_owned());
+
+    if !metadata.scopes_supported.is_empty()
+        && !metadata.scopes_supported.iter().any(|name| name == scope)
+    {
+        format!("{} (not advertised by the provider)", description)
+    } else {
+        description
+    }
+}
+
+/// Shows what's going to be requested and asks to continue. Used with `--confirm-scopes`
+pub fn confirm_scopes(args: &Arguments, metadata: &ProviderMetadata) -> Result<()> {
+    let scopes: Vec<(&str, String)> = args
+        .scope
+        .split_whitespace()
+        .map(|scope| (scope, describe(scope, metadata)))
+        .collect();
+
+    output::status(
+        "Requesting",
+        &format!("scopes for client_id {}", args.client_id),
+    );
+    eprintln!("{}", output::key_values(&scopes));
+
+    if let Some(audience) = &args.audience {
+        eprintln!(
+            "{}",
+            output::key_values(&[("audience", audience.to_owned())])
+        );
+    }
+
+    if !confirm(&args.prompt_options(), "Continue with these scopes?")? {
+        bail!("Requested scopes haven't been confirmed");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,87 @@
+//! Heuristics telling where doken runs, used to pick defaults that flags can override
+
+use std::path::Path;
+use std::{env, fs};
+
+fn is_set(name: &str) -> bool {
+    env::var_os(name).is_some_and(|value| !value.is_empty())
+}
+
+/// Variables set by popular CI services. Most of them set `CI` too
+const CI_VARS: [&str; 6] = [
+    "CI",
+    "GITHUB_ACTIONS",
+    "GITLAB_CI",
+    "BUILDKITE",
+    "JENKINS_URL",
+    "TF_BUILD",
+];
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Environment {
+    pub ssh: bool,
+    pub container: bool,
+    pub wsl: bool,
+    pub ci: bool,
+    /// A graphical session is available. Always assumed on macOS and Windows
+    pub display: bool,
+}
+
+impl Environment {
+    pub fn detect() -> Self {
+        let environment = Environment {
+            ssh: is_set("SSH_CONNECTION") || is_set("SSH_TTY"),
+            container: Path::new("/.dockerenv").exists()
+                || Path::new("/run/.containerenv").exists()
+                || is_set("container"),
+            wsl: cfg!(target_os = "linux")
+                && (is_set("WSL_DISTRO_NAME")
+                    || fs::read_to_string("/proc/sys/kernel/osrelease")
+                        .is_ok_and(|release| release.to_lowercase().contains("microsoft"))),
+            ci: CI_VARS.iter().any(|name| {
+                env::var(name).is_ok_and(|value| value != "false" && !value.is_empty())
+            }),
+            display: !cfg!(target_os = "linux") || is_set("DISPLAY") || is_set("WAYLAND_DISPLAY"),
+        };
+
+        log::debug!("Detected environment: {:?}", environment);
+        environment
+    }
+
+    /// Why a browser window cannot be shown to the user. `None` if it most likely can
+    pub fn browser_unavailable(&self) -> Option<&'static str> {
+        if self.ci {
+            Some("doken runs in CI")
+        } else if self.ssh {
+            Some("doken runs in an SSH session")
+        } else if !self.display {
+            Some("neither DISPLAY nor WAYLAND_DISPLAY is set")
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_explains_why_browser_is_unavailable() {
+        let desktop = Environment {
+            display: true,
+            ..Default::default()
+        };
+        let ssh = Environment {
+            ssh: true,
+            ..desktop.clone()
+        };
+
+        assert_eq!(desktop.browser_unavailable(), None);
+        assert_eq!(
+            ssh.browser_unavailable(),
+            Some("doken runs in an SSH session")
+        );
+        assert!(Environment::default().browser_unavailable().is_some());
+    }
+}
@@ -0,0 +1,23 @@
+use crate::output;
+
+/// Decisions taken while looking for a token, printed with `--explain`
+#[derive(Debug, Default)]
+pub struct Explanation {
+    steps: Vec<String>,
+}
+
+impl Explanation {
+    pub fn step(&mut self, step: impl Into<String>) {
+        let step = step.into();
+        log::debug!("{}", step);
+        self.steps.push(step);
+    }
+
+    pub fn print(&self, state_key: &str) {
+        output::status("Explaining", &format!("token of `{}`", state_key));
+
+        for (index, step) in self.steps.iter().enumerate() {
+            eprintln!("  {}. {}", index + 1, step);
+        }
+    }
+}
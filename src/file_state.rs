@@ -1,8 +1,9 @@
+use crate::permissions;
+use crate::progress;
 use crate::token_info::TokenInfo;
 use anyhow::{Context, Result};
 use file_guard::{FileGuard, Lock};
 use serde::{Deserialize, Serialize};
-use std::fs::OpenOptions;
 use std::io::{Read, Seek, Write};
 use std::path::PathBuf;
 use std::sync::Arc;
@@ -23,24 +24,19 @@ pub struct FileState {
 }
 
 impl FileState {
-    pub fn new() -> Result<FileState> {
-        let home_path = match home::home_dir() {
+    pub fn path() -> PathBuf {
+        match home::home_dir() {
             Some(mut home_dir) => {
                 home_dir.push(".doken.json");
                 home_dir
             }
 
             None => panic!("Couldn't access $HOME_DIR"),
-        };
+        }
+    }
 
-        let file = Arc::new(
-            OpenOptions::new()
-                .write(true)
-                .read(true)
-                .create(true)
-                .truncate(false)
-                .open(home_path)?,
-        );
+    pub fn new() -> Result<FileState> {
+        let file = Arc::new(permissions::open_private(&Self::path())?);
 
         let guard1 = file_guard::lock(file.clone(), Lock::Exclusive, 0, 1)?;
         let guard2 = file_guard::lock(file.clone(), Lock::Shared, 0, 1)?;
@@ -53,14 +49,7 @@ impl FileState {
     }
 
     pub fn _from(file_path: PathBuf) -> Result<FileState> {
-        let file = Arc::new(
-            OpenOptions::new()
-                .write(true)
-                .read(true)
-                .create(true)
-                .truncate(false)
-                .open(file_path)?,
-        );
+        let file = Arc::new(permissions::open_private(&file_path)?);
 
         let guard1 = file_guard::lock(file.clone(), Lock::Exclusive, 0, 1)?;
         let guard2 = file_guard::lock(file.clone(), Lock::Shared, 0, 1)?;
@@ -106,6 +95,26 @@ impl FileState {
         state.data.get(client_id).cloned()
     }
 
+    /// Keys of all cached tokens
+    pub fn state_keys(&mut self) -> Vec<String> {
+        self.read().data.into_keys().collect()
+    }
+
+    /// Token of the same client cached for another resource that still has a refresh_token, the latest one first
+    pub fn read_related_refresh_token_info(&mut self, client_id: &str) -> Option<TokenInfo> {
+        let prefix = format!("{}#", client_id);
+        let state = self.read();
+
+        state
+            .data
+            .into_iter()
+            .filter(|(key, token_info)| {
+                (key == client_id || key.starts_with(&prefix)) && token_info.refresh_token.is_some()
+            })
+            .map(|(_, token_info)| token_info)
+            .max_by_key(|token_info| token_info.expires)
+    }
+
     pub fn upsert_token_info(&mut self, client_id: String, token_info: TokenInfo) -> Result<()> {
         log::debug!(
             "Saving token info: {:#?} for client_id: {} to the state",
@@ -114,9 +123,10 @@ impl FileState {
         );
         let mut state = self.read();
 
-        state.data.insert(client_id, token_info);
+        state.data.insert(client_id.to_owned(), token_info);
 
         self.write(&state)?;
+        progress::notify(|observer| observer.on_cache_write(&client_id));
 
         Ok(())
     }
@@ -171,6 +181,8 @@ mod tests {
                     refresh_token: None,
                     expires: None,
                     scope: None,
+                    requested_scope: None,
+                    extra: HashMap::new(),
                 },
             )
             .unwrap();
@@ -206,6 +218,8 @@ mod tests {
                     refresh_token: Some("test-refresh-token".to_owned()),
                     expires: Some(SystemTime::UNIX_EPOCH),
                     scope: Some("email-profile".to_owned()),
+                    requested_scope: None,
+                    extra: HashMap::new(),
                 },
             )
             .unwrap();
@@ -230,6 +244,56 @@ mod tests {
         assert_eq!(content, uglify(expected));
     }
 
+    #[test]
+    fn it_writes_provider_specific_fields() {
+        let (_tmp_dir, tmp_path) = get_tmp_path().unwrap();
+        let mut file_state = FileState::_from(tmp_path.to_owned()).unwrap();
+        const CLIENT_ID: &str = "test-client-id";
+
+        file_state
+            .upsert_token_info(
+                CLIENT_ID.to_owned(),
+                TokenInfo {
+                    access_token: "test-access-token".to_owned(),
+                    refresh_token: None,
+                    expires: None,
+                    scope: None,
+                    requested_scope: None,
+                    extra: HashMap::from([(
+                        "ext_expires_in".to_owned(),
+                        serde_json::Value::from(3600),
+                    )]),
+                },
+            )
+            .unwrap();
+
+        let content = fs::read_to_string(tmp_path).unwrap_or_default();
+
+        let expected = r#"{
+  "version": 1,
+  "data": {
+    "test-client-id": {
+      "access_token": "test-access-token",
+      "refresh_token": null,
+      "expires": null,
+      "scope": null,
+      "extra": {
+        "ext_expires_in": 3600
+      }
+    }
+  }
+}"#;
+
+        assert_eq!(content, uglify(expected));
+
+        let token_info = file_state.read_token_info(&CLIENT_ID.to_owned()).unwrap();
+
+        assert_eq!(
+            token_info.extra.get("ext_expires_in"),
+            Some(&serde_json::Value::from(3600))
+        );
+    }
+
     #[test]
     fn it_overwrites_state_of_client_id() {
         let (_tmp_dir, tmp_path) = get_tmp_path().unwrap();
@@ -244,6 +308,8 @@ mod tests {
                     refresh_token: None,
                     expires: None,
                     scope: None,
+                    requested_scope: None,
+                    extra: HashMap::new(),
                 },
             )
             .unwrap();
@@ -255,6 +321,8 @@ mod tests {
                     refresh_token: None,
                     expires: None,
                     scope: None,
+                    requested_scope: None,
+                    extra: HashMap::new(),
                 },
             )
             .unwrap();
@@ -290,6 +358,8 @@ mod tests {
                     refresh_token: None,
                     expires: None,
                     scope: None,
+                    requested_scope: None,
+                    extra: HashMap::new(),
                 },
             )
             .unwrap();
@@ -339,6 +409,8 @@ mod tests {
                     refresh_token: None,
                     expires: None,
                     scope: None,
+                    requested_scope: None,
+                    extra: HashMap::new(),
                 },
             )
             .unwrap();
@@ -375,6 +447,8 @@ mod tests {
             refresh_token: Some("test-refresh-token".to_owned()),
             expires: Some(SystemTime::UNIX_EPOCH),
             scope: Some("email-profile".to_owned()),
+            requested_scope: None,
+            extra: HashMap::new(),
         };
 
         file_state
@@ -385,6 +459,8 @@ mod tests {
                     refresh_token: None,
                     expires: None,
                     scope: None,
+                    requested_scope: None,
+                    extra: HashMap::new(),
                 },
             )
             .unwrap();
@@ -405,4 +481,54 @@ mod tests {
         assert_eq!(actual_token_info.expires, expected_token_info.expires);
         assert_eq!(actual_token_info.scope, expected_token_info.scope);
     }
+
+    #[test]
+    fn it_reads_refresh_token_of_another_resource_of_the_same_client() {
+        let (_tmp_dir, tmp_path) = get_tmp_path().unwrap();
+        let mut file_state = FileState::_from(tmp_path.to_owned()).unwrap();
+        let token_info = |refresh_token: Option<&str>, expires: u64| TokenInfo {
+            access_token: "test-access-token".to_owned(),
+            refresh_token: refresh_token.map(str::to_owned),
+            expires: Some(SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(expires)),
+            scope: None,
+            requested_scope: None,
+            extra: HashMap::new(),
+        };
+
+        file_state
+            .upsert_token_info(
+                "test-client-id#https://graph.microsoft.com".to_owned(),
+                token_info(Some("older-refresh-token"), 1),
+            )
+            .unwrap();
+        file_state
+            .upsert_token_info(
+                "test-client-id#api://my-api".to_owned(),
+                token_info(Some("newer-refresh-token"), 2),
+            )
+            .unwrap();
+        file_state
+            .upsert_token_info(
+                "test-client-id#api://no-refresh".to_owned(),
+                token_info(None, 3),
+            )
+            .unwrap();
+        file_state
+            .upsert_token_info(
+                "test-client-id-2".to_owned(),
+                token_info(Some("other-client-refresh-token"), 4),
+            )
+            .unwrap();
+
+        let related = file_state
+            .read_related_refresh_token_info("test-client-id")
+            .unwrap();
+
+        assert_eq!(related.refresh_token.unwrap(), "newer-refresh-token");
+        assert!(
+            file_state
+                .read_related_refresh_token_info("unknown-client-id")
+                .is_none()
+        );
+    }
 }
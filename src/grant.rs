@@ -14,4 +14,26 @@ pub enum Grant {
     ResourceOwnerPasswordClientCredentials,
     /// Client credentials Grant. More: <https://www.rfc-editor.org/rfc/rfc6749#section-4.4>
     ClientCredentials,
+    /// Okta's Authentication API sessionToken exchanged for a code without a browser. More: <https://developer.okta.com/docs/reference/api/authn/>
+    OktaSessionToken,
+    /// Device Authorization Grant, the user enters a code on another device. More: <https://www.rfc-editor.org/rfc/rfc8628>
+    DeviceCode,
+    /// JWT Bearer grant, a JWT signed with `--assertion-key-file` exchanged for a token. More: <https://www.rfc-editor.org/rfc/rfc7523#section-2.1>
+    JwtBearer,
+    /// SAML 2.0 Bearer grant, a SAML assertion issued by the IdP exchanged for a token. More: <https://www.rfc-editor.org/rfc/rfc7522>
+    Saml2Bearer,
+    /// Token Exchange of a token acquired with `--upstream-profile` at another issuer. More: <https://www.rfc-editor.org/rfc/rfc8693>
+    TokenExchange,
+    /// Picks a grant based on `grant_types_supported` of the discovery document and given arguments: Authorization Code with PKCE > Device Code > Client Credentials
+    Auto,
+}
+
+impl Grant {
+    /// Grants completed by the user in the controlled browser
+    pub fn uses_browser(&self) -> bool {
+        matches!(
+            self,
+            Grant::AuthorizationCodeWithPkce | Grant::AuthorizationCode | Grant::Implicit
+        )
+    }
 }
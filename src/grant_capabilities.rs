@@ -0,0 +1,250 @@
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::grant::Grant;
+use crate::openidc_discovery::ProviderMetadata;
+
+/// Whether the provider supports a grant according to its discovery document
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Support {
+    Yes,
+    No,
+    /// The document doesn't tell, so only a flow can
+    Unknown,
+}
+
+/// Support of one doken grant with the reason and the discovery fields it's based on
+#[derive(Serialize, Clone, Debug)]
+pub struct GrantCapability {
+    pub grant: Grant,
+    pub supported: Support,
+    pub reason: String,
+    pub fields: Vec<&'static str>,
+}
+
+const TOKEN_EXCHANGE: &str = "urn:ietf:params:oauth:grant-type:token-exchange";
+const JWT_BEARER: &str = "urn:ietf:params:oauth:grant-type:jwt-bearer";
+const SAML2_BEARER: &str = "urn:ietf:params:oauth:grant-type:saml2-bearer";
+const DEVICE_CODE: &str = "urn:ietf:params:oauth:grant-type:device_code";
+
+/// Grant types of the document. `None` if it omits `grant_types_supported`, in which case
+/// `ProviderMetadata` carries the OpenID Connect default
+fn announced_grant_types(metadata: &ProviderMetadata) -> Option<&[String]> {
+    metadata
+        .document
+        .get("grant_types_supported")
+        .map(|_| metadata.grant_types_supported.as_slice())
+}
+
+fn grant_type(metadata: &ProviderMetadata, grant_type: &str) -> (Support, String) {
+    match announced_grant_types(metadata) {
+        Some(grant_types) if grant_types.iter().any(|g| g == grant_type) => (
+            Support::Yes,
+            format!("`{}` is in grant_types_supported", grant_type),
+        ),
+        Some(_) => (
+            Support::No,
+            format!("`{}` is not in grant_types_supported", grant_type),
+        ),
+        None if grant_type == "authorization_code" || grant_type == "implicit" => (
+            Support::Yes,
+            "grant_types_supported is omitted, which defaults to `authorization_code` and `implicit`"
+                .to_owned(),
+        ),
+        None => (
+            Support::Unknown,
+            "grant_types_supported is omitted".to_owned(),
+        ),
+    }
+}
+
+/// Discovery fields the support of the grant is based on
+fn fields(grant: &Grant) -> Vec<&'static str> {
+    let mut fields = match grant {
+        Grant::AuthorizationCodeWithPkce => vec![
+            "grant_types_supported",
+            "code_challenge_methods_supported",
+            "authorization_endpoint",
+        ],
+        Grant::AuthorizationCode => vec!["grant_types_supported", "authorization_endpoint"],
+        Grant::Implicit => vec![
+            "grant_types_supported",
+            "response_types_supported",
+            "authorization_endpoint",
+        ],
+        Grant::OktaSessionToken => vec![],
+        Grant::DeviceCode => vec!["grant_types_supported", "device_authorization_endpoint"],
+        _ => vec!["grant_types_supported"],
+    };
+
+    // Every grant but Implicit redeems something at the token endpoint
+    if !matches!(grant, Grant::Implicit) {
+        fields.push("token_endpoint");
+    }
+
+    fields
+}
+
+fn support(metadata: &ProviderMetadata, grant: &Grant) -> (Support, String) {
+    let (supported, reason) = match grant {
+        Grant::AuthorizationCodeWithPkce => {
+            let methods = &metadata.code_challenge_methods_supported;
+
+            match grant_type(metadata, "authorization_code") {
+                (Support::Yes, reason) if methods.is_empty() => (
+                    Support::Unknown,
+                    format!(
+                        "{}, but code_challenge_methods_supported is omitted",
+                        reason
+                    ),
+                ),
+                (Support::Yes, _) if !methods.iter().any(|method| method == "S256") => (
+                    Support::No,
+                    "`S256` is not in code_challenge_methods_supported".to_owned(),
+                ),
+                support => support,
+            }
+        }
+        Grant::AuthorizationCode => grant_type(metadata, "authorization_code"),
+        Grant::Implicit => {
+            let response_types = &metadata.response_types_supported;
+
+            match grant_type(metadata, "implicit") {
+                (Support::Yes, _)
+                    if !response_types.is_empty()
+                        && !response_types.iter().any(|types| types == "token") =>
+                {
+                    (
+                        Support::No,
+                        "`token` is not in response_types_supported".to_owned(),
+                    )
+                }
+                support => support,
+            }
+        }
+        Grant::ResourceOwnerPasswordClientCredentials => grant_type(metadata, "password"),
+        Grant::ClientCredentials => grant_type(metadata, "client_credentials"),
+        Grant::TokenExchange => grant_type(metadata, TOKEN_EXCHANGE),
+        Grant::JwtBearer => grant_type(metadata, JWT_BEARER),
+        Grant::Saml2Bearer => grant_type(metadata, SAML2_BEARER),
+        Grant::DeviceCode => match grant_type(metadata, DEVICE_CODE) {
+            (Support::No, reason) => (Support::No, reason),
+            _ if metadata.device_authorization_endpoint.is_none() => (
+                Support::No,
+                "device_authorization_endpoint is missing".to_owned(),
+            ),
+            support => support,
+        },
+        Grant::OktaSessionToken => (
+            Support::Unknown,
+            "Okta's Authentication API isn't announced in discovery documents".to_owned(),
+        ),
+        Grant::Auto => (Support::Yes, "picks one of the supported grants".to_owned()),
+    };
+
+    match supported {
+        Support::No => (supported, reason),
+        _ if !matches!(grant, Grant::Implicit) && metadata.token_endpoint.is_none() => {
+            (Support::No, "token_endpoint is missing".to_owned())
+        }
+        _ if grant.uses_browser() && metadata.authorization_endpoint.is_none() => {
+            (Support::No, "authorization_endpoint is missing".to_owned())
+        }
+        _ => (supported, reason),
+    }
+}
+
+/// Support of every doken grant but `auto` by the provider described by `metadata`, ex. for
+/// onboarding screens of wrapper UIs. Get the metadata with
+/// [`get_provider_metadata`](crate::openidc_discovery::get_provider_metadata)
+pub fn grant_capabilities(metadata: &ProviderMetadata) -> Vec<GrantCapability> {
+    Grant::value_variants()
+        .iter()
+        .filter(|grant| !matches!(grant, Grant::Auto))
+        .map(|grant| {
+            let (supported, reason) = support(metadata, grant);
+
+            GrantCapability {
+                grant: grant.to_owned(),
+                supported,
+                reason,
+                fields: fields(grant),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::openidc_discovery::{CacheMetadata, parse_provider_metadata};
+    use serde_json::json;
+
+    fn supported(metadata: &ProviderMetadata) -> Vec<(String, Support)> {
+        grant_capabilities(metadata)
+            .into_iter()
+            .map(|capability| {
+                (
+                    serde_variant::to_variant_name(&capability.grant)
+                        .unwrap()
+                        .to_owned(),
+                    capability.supported,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn it_tells_supported_grants_apart() {
+        let metadata = parse_provider_metadata(
+            json!({
+                "authorization_endpoint": "https://idp/authorize",
+                "token_endpoint": "https://idp/token",
+                "grant_types_supported": ["authorization_code", "client_credentials"],
+                "response_types_supported": ["code"],
+            }),
+            &[],
+            CacheMetadata::default(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            supported(&metadata),
+            vec![
+                ("authorization-code-with-pkce".to_owned(), Support::Unknown),
+                ("authorization-code".to_owned(), Support::Yes),
+                ("implicit".to_owned(), Support::No),
+                (
+                    "resource-owner-password-client-credentials".to_owned(),
+                    Support::No
+                ),
+                ("client-credentials".to_owned(), Support::Yes),
+                ("okta-session-token".to_owned(), Support::Unknown),
+                ("device-code".to_owned(), Support::No),
+                ("jwt-bearer".to_owned(), Support::No),
+                ("saml2-bearer".to_owned(), Support::No),
+                ("token-exchange".to_owned(), Support::No),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_falls_back_to_defaults_of_omitted_grant_types() {
+        let metadata = parse_provider_metadata(
+            json!({
+                "authorization_endpoint": "https://idp/authorize",
+                "code_challenge_methods_supported": ["S256"],
+            }),
+            &[],
+            CacheMetadata::default(),
+        )
+        .unwrap();
+        let capabilities = grant_capabilities(&metadata);
+
+        assert_eq!(capabilities[0].supported, Support::No);
+        assert_eq!(capabilities[0].reason, "token_endpoint is missing");
+        assert_eq!(capabilities[2].supported, Support::Yes);
+        assert_eq!(capabilities[4].supported, Support::No);
+    }
+}
@@ -0,0 +1,447 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use serde_json::Value;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::SystemTime;
+use url::Url;
+
+use crate::permissions;
+
+const REDACTED: &str = "[REDACTED]";
+
+/// Headers carrying sessions or credentials
+const SECRET_HEADERS: [&str; 4] = [
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "set-cookie",
+];
+
+/// Query and form parameters carrying codes, tokens or credentials
+const SECRET_PARAMS: [&str; 13] = [
+    "code",
+    "access_token",
+    "id_token",
+    "refresh_token",
+    "client_secret",
+    "client_assertion",
+    "assertion",
+    "password",
+    "passwd",
+    "otp",
+    "sessiontoken",
+    "samlresponse",
+    "samlrequest",
+];
+
+/// HTTP Archive 1.2 <http://www.softwareishard.com/blog/har-12-spec/> of the automated browser
+#[derive(Serialize, Debug)]
+pub struct Har {
+    log: Log,
+    #[serde(skip)]
+    redact: bool,
+    /// Entries waiting for a response by the CDP request id
+    #[serde(skip)]
+    pending: HashMap<String, (usize, f64)>,
+}
+
+#[derive(Serialize, Debug)]
+struct Log {
+    version: &'static str,
+    creator: Creator,
+    entries: Vec<Entry>,
+}
+
+#[derive(Serialize, Debug)]
+struct Creator {
+    name: &'static str,
+    version: &'static str,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Entry {
+    started_date_time: String,
+    time: f64,
+    request: Request,
+    response: Response,
+    cache: Value,
+    timings: Timings,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+struct NameValue {
+    name: String,
+    value: String,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Request {
+    method: String,
+    url: String,
+    http_version: String,
+    cookies: Vec<NameValue>,
+    headers: Vec<NameValue>,
+    query_string: Vec<NameValue>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    post_data: Option<PostData>,
+    headers_size: i64,
+    body_size: i64,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct PostData {
+    mime_type: String,
+    text: String,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Response {
+    status: i64,
+    status_text: String,
+    http_version: String,
+    cookies: Vec<NameValue>,
+    headers: Vec<NameValue>,
+    content: Content,
+    #[serde(rename = "redirectURL")]
+    redirect_url: String,
+    headers_size: i64,
+    body_size: i64,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Content {
+    size: i64,
+    mime_type: String,
+}
+
+#[derive(Serialize, Debug)]
+struct Timings {
+    send: f64,
+    wait: f64,
+    receive: f64,
+}
+
+/// Request of the browser, as reported by `Network.requestWillBeSent`
+pub struct SentRequest<'a> {
+    pub id: &'a str,
+    pub time: SystemTime,
+    /// Monotonic seconds, only used to measure the duration
+    pub timestamp: f64,
+    pub method: &'a str,
+    pub url: &'a str,
+    pub headers: &'a Value,
+    pub post_data: Option<String>,
+}
+
+/// Response received by the browser, as reported by `Network.responseReceived`
+/// or as the redirect response of the next `Network.requestWillBeSent`
+pub struct ReceivedResponse<'a> {
+    pub id: &'a str,
+    pub timestamp: f64,
+    pub status: i64,
+    pub status_text: &'a str,
+    pub protocol: Option<&'a str>,
+    pub headers: &'a Value,
+    pub mime_type: &'a str,
+}
+
+fn is_secret(name: &str, secrets: &[&str]) -> bool {
+    secrets.contains(&name.to_lowercase().as_str())
+}
+
+impl Har {
+    /// `redact` replaces cookies, credentials, codes and tokens
+    pub fn new(redact: bool) -> Self {
+        Har {
+            log: Log {
+                version: "1.2",
+                creator: Creator {
+                    name: "doken",
+                    version: env!("CARGO_PKG_VERSION"),
+                },
+                entries: vec![],
+            },
+            redact,
+            pending: HashMap::new(),
+        }
+    }
+
+    fn value(&self, secret: bool, value: &str) -> String {
+        if self.redact && secret {
+            REDACTED.to_owned()
+        } else {
+            value.to_owned()
+        }
+    }
+
+    fn headers(&self, headers: &Value) -> Vec<NameValue> {
+        let Some(headers) = headers.as_object() else {
+            return vec![];
+        };
+
+        headers
+            .iter()
+            .map(|(name, value)| {
+                let value = value.as_str().unwrap_or_default();
+                NameValue {
+                    name: name.to_owned(),
+                    // Redirects carry codes and implicit tokens back to the client
+                    value: if name.eq_ignore_ascii_case("location") {
+                        self.url(value)
+                    } else {
+                        self.value(is_secret(name, &SECRET_HEADERS), value)
+                    },
+                }
+            })
+            .collect()
+    }
+
+    fn params<'a>(
+        &self,
+        pairs: impl Iterator<Item = (Cow<'a, str>, Cow<'a, str>)>,
+    ) -> Vec<NameValue> {
+        pairs
+            .map(|(name, value)| NameValue {
+                value: self.value(is_secret(&name, &SECRET_PARAMS), &value),
+                name: name.into_owned(),
+            })
+            .collect()
+    }
+
+    fn url(&self, url: &str) -> String {
+        let Ok(mut url) = Url::parse(url) else {
+            return url.to_owned();
+        };
+
+        if self.redact && url.query().is_some() {
+            let query = self.params(url.query_pairs());
+            url.query_pairs_mut()
+                .clear()
+                .extend_pairs(query.iter().map(|param| (&param.name, &param.value)));
+        }
+
+        // Implicit and hybrid flows return tokens in the fragment
+        if let Some(fragment) = url.fragment().filter(|_| self.redact) {
+            let params = self.params(form_urlencoded::parse(fragment.as_bytes()));
+            let fragment = form_urlencoded::Serializer::new(String::new())
+                .extend_pairs(params.iter().map(|param| (&param.name, &param.value)))
+                .finish();
+            url.set_fragment(Some(&fragment));
+        }
+
+        url.to_string()
+    }
+
+    fn post_data(&self, headers: &Value, text: String) -> PostData {
+        let mime_type = headers
+            .as_object()
+            .and_then(|headers| {
+                headers
+                    .iter()
+                    .find(|(name, _)| name.eq_ignore_ascii_case("content-type"))
+            })
+            .and_then(|(_, value)| value.as_str())
+            .unwrap_or_default()
+            .to_owned();
+
+        let text = if !self.redact {
+            text
+        } else if mime_type.starts_with("application/x-www-form-urlencoded") {
+            let params = self.params(form_urlencoded::parse(text.as_bytes()));
+            form_urlencoded::Serializer::new(String::new())
+                .extend_pairs(params.iter().map(|param| (&param.name, &param.value)))
+                .finish()
+        } else {
+            // Bodies of other types cannot be redacted field by field
+            REDACTED.to_owned()
+        };
+
+        PostData { mime_type, text }
+    }
+
+    pub fn request(&mut self, request: SentRequest) {
+        let url = self.url(request.url);
+        let query_string = Url::parse(&url)
+            .map(|url| {
+                url.query_pairs()
+                    .map(|(name, value)| NameValue {
+                        name: name.into_owned(),
+                        value: value.into_owned(),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        let post_data = request
+            .post_data
+            .map(|text| self.post_data(request.headers, text));
+
+        let entry = Entry {
+            started_date_time: humantime::format_rfc3339_millis(request.time).to_string(),
+            time: 0.0,
+            request: Request {
+                method: request.method.to_owned(),
+                url,
+                http_version: String::new(),
+                cookies: vec![],
+                headers: self.headers(request.headers),
+                query_string,
+                body_size: post_data
+                    .as_ref()
+                    .map(|post_data| post_data.text.len() as i64)
+                    .unwrap_or_default(),
+                post_data,
+                headers_size: -1,
+            },
+            response: Response {
+                // Responses that never came, ex. because the flow timed out
+                status: 0,
+                status_text: String::new(),
+                http_version: String::new(),
+                cookies: vec![],
+                headers: vec![],
+                content: Content {
+                    size: -1,
+                    mime_type: String::new(),
+                },
+                redirect_url: String::new(),
+                headers_size: -1,
+                body_size: -1,
+            },
+            cache: Value::Object(Default::default()),
+            timings: Timings {
+                send: 0.0,
+                wait: -1.0,
+                receive: 0.0,
+            },
+        };
+
+        self.log.entries.push(entry);
+        self.pending.insert(
+            request.id.to_owned(),
+            (self.log.entries.len() - 1, request.timestamp),
+        );
+    }
+
+    pub fn response(&mut self, response: ReceivedResponse) {
+        let Some((index, started)) = self.pending.remove(response.id) else {
+            return;
+        };
+
+        let headers = self.headers(response.headers);
+        let redirect_url = headers
+            .iter()
+            .find(|header| header.name.eq_ignore_ascii_case("location"))
+            .map(|header| header.value.to_owned())
+            .unwrap_or_default();
+        let time = ((response.timestamp - started) * 1000.0).max(0.0);
+
+        let entry = &mut self.log.entries[index];
+        entry.time = time;
+        entry.timings.wait = time;
+        entry.request.http_version = response.protocol.unwrap_or_default().to_owned();
+        entry.response = Response {
+            status: response.status,
+            status_text: response.status_text.to_owned(),
+            http_version: response.protocol.unwrap_or_default().to_owned(),
+            cookies: vec![],
+            headers,
+            content: Content {
+                size: -1,
+                mime_type: response.mime_type.to_owned(),
+            },
+            redirect_url,
+            headers_size: -1,
+            body_size: -1,
+        };
+    }
+
+    /// Writes the archive readable only by the current user, as it may contain sessions
+    pub fn write(&self, path: &Path) -> Result<()> {
+        permissions::write_private(path, serde_json::to_string_pretty(self)?.as_bytes())
+            .with_context(|| format!("Cannot write {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn record(redact: bool) -> Value {
+        let mut har = Har::new(redact);
+        har.request(SentRequest {
+            id: "1",
+            time: SystemTime::UNIX_EPOCH,
+            timestamp: 10.0,
+            method: "POST",
+            url: "https://idp.example.com/login?client_id=my-client",
+            headers: &json!({
+                "Cookie": "session=abc",
+                "Content-Type": "application/x-www-form-urlencoded"
+            }),
+            post_data: Some("username=john&password=secret".to_owned()),
+        });
+        har.response(ReceivedResponse {
+            id: "1",
+            timestamp: 10.25,
+            status: 302,
+            status_text: "Found",
+            protocol: Some("http/1.1"),
+            headers: &json!({ "Location": "http://localhost:8080/?code=abc&state=xyz" }),
+            mime_type: "text/html",
+        });
+
+        serde_json::to_value(&har).unwrap()
+    }
+
+    #[test]
+    fn it_redacts_credentials_and_codes() {
+        let entry = &record(true)["log"]["entries"][0];
+
+        assert_eq!(entry["time"], json!(250.0));
+        assert_eq!(
+            entry["request"]["headers"][1],
+            json!({ "name": "Cookie", "value": REDACTED })
+        );
+        assert_eq!(
+            entry["request"]["postData"]["text"],
+            json!("username=john&password=%5BREDACTED%5D")
+        );
+        assert_eq!(
+            entry["response"]["redirectURL"],
+            json!("http://localhost:8080/?code=%5BREDACTED%5D&state=xyz")
+        );
+        assert_eq!(
+            entry["response"]["headers"][0],
+            json!({
+                "name": "Location",
+                "value": "http://localhost:8080/?code=%5BREDACTED%5D&state=xyz"
+            })
+        );
+        assert_eq!(
+            Har::new(true).url("http://localhost:8080/#access_token=abc&state=xyz"),
+            "http://localhost:8080/#access_token=%5BREDACTED%5D&state=xyz"
+        );
+    }
+
+    #[test]
+    fn it_keeps_secrets_on_demand() {
+        let entry = &record(false)["log"]["entries"][0];
+
+        assert_eq!(
+            entry["request"]["postData"]["text"],
+            json!("username=john&password=secret")
+        );
+        assert_eq!(
+            entry["response"]["redirectURL"],
+            json!("http://localhost:8080/?code=abc&state=xyz")
+        );
+    }
+}
@@ -0,0 +1,147 @@
+use anyhow::Result;
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use tokio::net::TcpStream;
+
+use crate::file_state::FileState;
+use crate::loopback;
+use crate::output;
+use crate::systemd;
+use crate::token_info::TokenInfo;
+
+/// Probes taking longer are dropped
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// State keys `/readyz` checks. The daemon updates them when its config is reloaded
+pub type StateKeys = Arc<RwLock<Vec<String>>>;
+
+/// Keys of the given state keys without a cached token valid at `now`
+fn not_ready(
+    state_keys: &[String],
+    mut read: impl FnMut(&String) -> Option<TokenInfo>,
+    now: SystemTime,
+) -> Vec<String> {
+    state_keys
+        .iter()
+        .filter(|state_key| {
+            read(state_key)
+                .is_none_or(|token_info| token_info.expires.is_some_and(|expires| expires <= now))
+        })
+        .cloned()
+        .collect()
+}
+
+fn plain(status: &str, body: &str) -> String {
+    format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+fn response(path: &str, not_ready: impl FnOnce() -> Vec<String>) -> String {
+    match path.split('?').next().unwrap_or_default() {
+        "/healthz" => plain("200 OK", "ok\n"),
+        "/readyz" => match not_ready().as_slice() {
+            [] => plain("200 OK", "ok\n"),
+            missing => plain(
+                "503 Service Unavailable",
+                &format!("no valid token for: {}\n", missing.join(", ")),
+            ),
+        },
+        _ => plain("404 Not Found", "not found\n"),
+    }
+}
+
+async fn handle(mut stream: TcpStream, state_keys: &[String]) -> Result<()> {
+    let head = loopback::read_head(&mut stream).await?;
+    let (_, path) = loopback::request_line(&head).unwrap_or_default();
+
+    let response = response(path, || {
+        // The state file isn't kept open, the monitored tokens are written by other tasks
+        let mut file_state = FileState::new().ok();
+        not_ready(
+            state_keys,
+            |state_key| file_state.as_mut()?.read_token_info(state_key),
+            SystemTime::now(),
+        )
+    });
+
+    loopback::respond(&mut stream, &response).await
+}
+
+/// Serves `/healthz` (the process is running) and `/readyz` (every state key has a valid cached token)
+/// in the background, so supervisors like systemd or Kubernetes can gate dependent services
+pub async fn spawn(listen: SocketAddr, state_keys: StateKeys) -> Result<()> {
+    let listener = systemd::bind(listen).await?;
+
+    output::status(
+        "Listening",
+        &format!(
+            "on http://{} for /healthz and /readyz",
+            listener.local_addr()?
+        ),
+    );
+
+    tokio::spawn(async move {
+        loop {
+            let Ok((stream, peer)) = listener.accept().await else {
+                continue;
+            };
+
+            // Served concurrently, so a client sending nothing doesn't hold later probes up
+            let state_keys = state_keys.read().unwrap().clone();
+            tokio::spawn(async move {
+                match tokio::time::timeout(REQUEST_TIMEOUT, handle(stream, &state_keys)).await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => log::debug!("Health check from {} failed: {:?}", peer, e),
+                    Err(_) => log::debug!("Health check from {} sent no request in time", peer),
+                }
+            });
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn it_reports_readiness_of_cached_tokens() {
+        let now = SystemTime::now();
+        let token_info = |expires| TokenInfo {
+            access_token: "token".to_owned(),
+            refresh_token: None,
+            expires,
+            scope: None,
+            requested_scope: None,
+            extra: HashMap::new(),
+        };
+        let state_keys = ["valid", "expired", "missing", "never-expires"].map(str::to_owned);
+
+        let not_ready = not_ready(
+            &state_keys,
+            |state_key| match state_key.as_str() {
+                "valid" => Some(token_info(Some(now + Duration::from_secs(60)))),
+                "expired" => Some(token_info(Some(now - Duration::from_secs(60)))),
+                "never-expires" => Some(token_info(None)),
+                _ => None,
+            },
+            now,
+        );
+        assert_eq!(not_ready, vec!["expired".to_owned(), "missing".to_owned()]);
+
+        assert!(response("/healthz", || not_ready.clone()).starts_with("HTTP/1.1 200 OK"));
+        assert!(
+            response("/readyz", || not_ready.clone())
+                .starts_with("HTTP/1.1 503 Service Unavailable")
+        );
+        assert!(response("/readyz", Vec::new).starts_with("HTTP/1.1 200 OK"));
+        assert!(response("/other", Vec::new).starts_with("HTTP/1.1 404 Not Found"));
+    }
+}
@@ -0,0 +1,181 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_variant::to_variant_name;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use crate::args::Arguments;
+use crate::permissions::{append_private, write_private};
+use crate::settings;
+
+/// Opt-in settings. History isn't recorded when the file doesn't exist
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct HistorySettings {
+    pub enabled: bool,
+}
+
+/// Where the returned token came from
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum TokenSource {
+    /// Valid token of the state file
+    Cache,
+    /// Refreshed with the cached refresh_token
+    Refresh,
+    /// New flow of the grant
+    Flow,
+}
+
+/// One token acquisition. Never contains tokens nor secrets
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct HistoryEntry {
+    pub time: SystemTime,
+    pub profile: Option<String>,
+    pub state_key: String,
+    pub grant: String,
+    /// `None` when the acquisition failed
+    pub source: Option<TokenSource>,
+    /// Whether the user had to take part, ex. in a browser
+    pub interactive: bool,
+    pub duration_ms: u128,
+    pub error: Option<String>,
+}
+
+impl HistoryEntry {
+    pub fn new(
+        args: &Arguments,
+        result: &Result<TokenSource>,
+        duration: Duration,
+        time: SystemTime,
+    ) -> Self {
+        let source = result.as_ref().ok().copied();
+
+        HistoryEntry {
+            time,
+            profile: args.profile.to_owned(),
+            state_key: args.state_key(),
+            grant: to_variant_name(&args.grant).unwrap_or_default().to_owned(),
+            source,
+            interactive: match source {
+                Some(source) => source == TokenSource::Flow && args.grant.uses_browser(),
+                None => false,
+            },
+            duration_ms: duration.as_millis(),
+            error: result.as_ref().err().map(|e| e.to_string()),
+        }
+    }
+}
+
+pub fn read_settings() -> HistorySettings {
+    settings::read("history.json")
+}
+
+pub fn write_settings(settings: &HistorySettings) -> Result<()> {
+    settings::write("history.json", settings)
+}
+
+/// Entries are appended as JSON lines, so concurrent runs don't overwrite each other
+pub fn entries_path() -> Result<PathBuf> {
+    settings::doken_path("history.jsonl")
+}
+
+fn parse_entries(text: &str) -> Vec<HistoryEntry> {
+    text.lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect()
+}
+
+/// Recorded entries, the oldest first. Lines that cannot be parsed are skipped
+pub fn read_entries() -> Result<Vec<HistoryEntry>> {
+    match fs::read_to_string(entries_path()?) {
+        Ok(text) => Ok(parse_entries(&text)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(vec![]),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Rewrites the history without entries older than `cutoff`. Returns how many were removed
+pub fn prune(cutoff: SystemTime) -> Result<usize> {
+    let entries = read_entries()?;
+    let kept: Vec<&HistoryEntry> = entries
+        .iter()
+        .filter(|entry| entry.time >= cutoff)
+        .collect();
+
+    let mut text = String::new();
+    for entry in &kept {
+        text.push_str(&serde_json::to_string(entry)?);
+        text.push('\n');
+    }
+    write_private(&entries_path()?, text.as_bytes())?;
+
+    Ok(entries.len() - kept.len())
+}
+
+fn append(entry: &HistoryEntry) -> Result<()> {
+    let path = entries_path()?;
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut file = append_private(&path)?;
+    writeln!(file, "{}", serde_json::to_string(entry)?)?;
+
+    Ok(())
+}
+
+/// Appends the acquisition if history is enabled. Never fails the run
+pub fn record(args: &Arguments, result: &Result<TokenSource>, duration: Duration) {
+    if !read_settings().enabled {
+        return;
+    }
+
+    let entry = HistoryEntry::new(args, result, duration, SystemTime::now());
+    if let Err(e) = append(&entry) {
+        log::debug!("History entry wasn't recorded: {:?}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grant::Grant;
+    use anyhow::anyhow;
+
+    #[test]
+    fn it_records_acquisitions_without_tokens() {
+        let args = Arguments {
+            grant: Grant::AuthorizationCodeWithPkce,
+            client_id: "my-client".to_owned(),
+            profile: Some("corp".to_owned()),
+            ..Default::default()
+        };
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+        let flow = HistoryEntry::new(
+            &args,
+            &Ok(TokenSource::Flow),
+            Duration::from_millis(2400),
+            time,
+        );
+        assert!(flow.interactive);
+        assert_eq!(flow.grant, "authorization-code-with-pkce");
+        assert_eq!(flow.duration_ms, 2400);
+
+        let refresh = HistoryEntry::new(&args, &Ok(TokenSource::Refresh), Duration::ZERO, time);
+        assert!(!refresh.interactive);
+
+        let failed = HistoryEntry::new(&args, &Err(anyhow!("timed out")), Duration::ZERO, time);
+        assert_eq!(failed.source, None);
+        assert_eq!(failed.error.as_deref(), Some("timed out"));
+
+        let text = format!(
+            "{}\nnot json\n{}\n",
+            serde_json::to_string(&flow).unwrap(),
+            serde_json::to_string(&failed).unwrap()
+        );
+        assert_eq!(parse_entries(&text), vec![flow, failed]);
+    }
+}
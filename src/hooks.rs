@@ -0,0 +1,173 @@
+use anyhow::{Context, Result, anyhow, bail};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+use crate::args::Arguments;
+use crate::output;
+use crate::token_info::TokenInfo;
+
+/// Variables kept by `--hook-env clean`
+const CLEAN_ENV_VARS: [&str; 6] = ["PATH", "HOME", "USER", "LANG", "TMPDIR", "SYSTEMROOT"];
+
+/// What a failing or timed out hook does to the run
+#[derive(Serialize, Deserialize, ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum HookFailure {
+    /// Fails the run
+    #[default]
+    Abort,
+    /// Prints a warning and carries on
+    Warn,
+}
+
+/// Environment hooks run with
+#[derive(Serialize, Deserialize, ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum HookEnv {
+    /// Environment of doken without `DOKEN_*` variables, which carry secrets of profiles
+    #[default]
+    Inherit,
+    /// Only PATH, HOME, USER, LANG, TMPDIR and SYSTEMROOT
+    Clean,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HookStage {
+    Pre,
+    Post,
+}
+
+impl HookStage {
+    fn name(&self) -> &'static str {
+        match self {
+            HookStage::Pre => "pre",
+            HookStage::Post => "post",
+        }
+    }
+}
+
+fn shell(command_line: &str) -> Command {
+    if cfg!(windows) {
+        let mut command = Command::new("cmd");
+        command.arg("/C").arg(command_line);
+        command
+    } else {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(command_line);
+        command
+    }
+}
+
+/// Variables of the hook's environment, apart from `DOKEN_HOOK` and `DOKEN_HOOK_PROFILE`
+fn environment(
+    hook_env: HookEnv,
+    vars: impl Iterator<Item = (String, String)>,
+) -> Vec<(String, String)> {
+    vars.filter(|(name, _)| match hook_env {
+        HookEnv::Inherit => !name.starts_with("DOKEN_"),
+        HookEnv::Clean => CLEAN_ENV_VARS.contains(&name.to_uppercase().as_str()),
+    })
+    .collect()
+}
+
+async fn execute(
+    command_line: &str,
+    args: &Arguments,
+    stage: HookStage,
+    token_info: Option<&TokenInfo>,
+) -> Result<()> {
+    let mut command = shell(command_line);
+    command
+        .env_clear()
+        .envs(environment(args.hook_env, std::env::vars()))
+        .env("DOKEN_HOOK", stage.name())
+        .env(
+            "DOKEN_HOOK_PROFILE",
+            args.profile.as_deref().unwrap_or_default(),
+        )
+        .stdin(match token_info {
+            Some(_) => Stdio::piped(),
+            None => Stdio::null(),
+        })
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .kill_on_drop(true);
+
+    let mut child = command
+        .spawn()
+        .with_context(|| format!("Cannot run the {}-hook `{}`", stage.name(), command_line))?;
+
+    if let (Some(token_info), Some(mut stdin)) = (token_info, child.stdin.take()) {
+        let token = serde_json::to_vec(token_info)?;
+        // Hooks not reading the token close the pipe early, which isn't an error
+        let _ = stdin.write_all(&token).await;
+    }
+
+    let status = tokio::time::timeout(Duration::from_millis(args.hook_timeout), child.wait())
+        .await
+        .map_err(|_| anyhow!("didn't finish within {}ms", args.hook_timeout))??;
+
+    if !status.success() {
+        bail!("exited with {}", status);
+    }
+
+    Ok(())
+}
+
+/// Runs `--pre-hook` or `--post-hook`, if set. The post hook reads the token as JSON on stdin
+pub async fn run(args: &Arguments, stage: HookStage, token_info: Option<&TokenInfo>) -> Result<()> {
+    let command_line = match stage {
+        HookStage::Pre => &args.pre_hook,
+        HookStage::Post => &args.post_hook,
+    };
+    let Some(command_line) = command_line else {
+        return Ok(());
+    };
+
+    log::debug!("Running the {}-hook `{}`", stage.name(), command_line);
+    let result = execute(command_line, args, stage, token_info)
+        .await
+        .with_context(|| format!("The {}-hook `{}` failed", stage.name(), command_line));
+
+    match (result, args.hook_failure) {
+        (Err(e), HookFailure::Warn) => {
+            output::warning(&format!("{:#}", e));
+            Ok(())
+        }
+        (result, _) => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_keeps_secrets_of_profiles_out_of_hooks() {
+        let vars = || {
+            [
+                ("PATH", "/usr/bin"),
+                ("DOKEN_CLIENT_SECRET", "secret"),
+                ("AWS_PROFILE", "dev"),
+            ]
+            .into_iter()
+            .map(|(name, value)| (name.to_owned(), value.to_owned()))
+        };
+
+        assert_eq!(
+            environment(HookEnv::Inherit, vars()),
+            vec![
+                ("PATH".to_owned(), "/usr/bin".to_owned()),
+                ("AWS_PROFILE".to_owned(), "dev".to_owned()),
+            ]
+        );
+        assert_eq!(
+            environment(HookEnv::Clean, vars()),
+            vec![("PATH".to_owned(), "/usr/bin".to_owned())]
+        );
+    }
+}
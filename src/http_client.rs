@@ -0,0 +1,102 @@
+use anyhow::{Context, Result};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Certificate, ClientBuilder, Proxy};
+use std::fs;
+use std::time::SystemTime;
+
+use crate::args::Arguments;
+
+/// HTTP client settings shared by all the calls to the provider. By default system trust store and
+/// proxy settings (`HTTPS_PROXY` etc., macOS System Configuration and Windows registry) are respected
+pub fn builder(args: &Arguments) -> Result<ClientBuilder> {
+    let mut builder = reqwest::Client::builder();
+
+    if args.no_system_trust {
+        log::debug!("Ignoring certificates from the system trust store");
+        builder = builder.tls_built_in_native_certs(false);
+    }
+
+    if let Some(ca_cert) = &args.ca_cert {
+        let pem =
+            fs::read(ca_cert).with_context(|| format!("Cannot read `--ca-cert` {}", ca_cert))?;
+
+        for certificate in Certificate::from_pem_bundle(&pem)
+            .with_context(|| format!("`--ca-cert` {} is not a valid PEM bundle", ca_cert))?
+        {
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+
+    if args.no_system_proxy {
+        log::debug!("Ignoring system proxy settings");
+        builder = builder.no_proxy();
+    }
+
+    if let Some(proxy) = &args.proxy {
+        log::debug!("Using proxy {}", proxy);
+        builder = builder.proxy(Proxy::all(proxy).with_context(|| {
+            format!("`--proxy` is not a correct URL. Provided value: {}", proxy)
+        })?);
+    }
+
+    Ok(builder)
+}
+
+/// IMF-fixdate of HTTP headers, ex. `Wed, 21 Oct 2015 07:28:00 GMT`
+pub fn parse_http_date(value: &str) -> Option<SystemTime> {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let (_, date) = value.split_once(", ")?;
+    let [day, month, year, time, "GMT"] = date.split(' ').collect::<Vec<_>>()[..] else {
+        return None;
+    };
+    let month = MONTHS.iter().position(|name| *name == month)? + 1;
+
+    humantime::parse_rfc3339(&format!("{}-{:02}-{}T{}Z", year, month, day, time)).ok()
+}
+
+/// Parses `--extra-header` values in `<name>: <value>` form, ex. `X-Device-Id: 1234`
+pub fn parse_extra_header(value: &str) -> Result<(String, String)> {
+    let (name, value) = value
+        .split_once(':')
+        .context("Expected `<name>: <value>`, ex. `X-Device-Id: 1234`")?;
+    let (name, value) = (name.trim(), value.trim());
+
+    HeaderName::from_bytes(name.as_bytes())
+        .with_context(|| format!("`{}` is not a valid header name", name))?;
+    HeaderValue::from_str(value)
+        .with_context(|| format!("Header `{}` has an invalid value", name))?;
+
+    Ok((name.to_owned(), value.to_owned()))
+}
+
+/// `--extra-header`s sent along token requests, ex. device identification required by zero-trust proxies
+pub fn extra_headers(args: &Arguments) -> Result<HeaderMap> {
+    let mut headers = HeaderMap::new();
+
+    for (name, value) in &args.extra_header {
+        headers.append(
+            HeaderName::from_bytes(name.as_bytes())?,
+            HeaderValue::from_str(value)?,
+        );
+    }
+
+    Ok(headers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_extra_headers() {
+        assert_eq!(
+            parse_extra_header("X-Device-Id: 1234 ").unwrap(),
+            ("X-Device-Id".to_owned(), "1234".to_owned())
+        );
+        assert!(parse_extra_header("X-Device-Id=1234").is_err());
+        assert!(parse_extra_header("X Device: 1234").is_err());
+    }
+}
@@ -0,0 +1,215 @@
+use anyhow::{Context, Result, anyhow, bail};
+use base64::Engine;
+use base64::prelude::BASE64_STANDARD;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Where Secrets and ConfigMaps mounted into the pod are looked up first, as `<dir>/<name>/<key>` files
+const MOUNT_DIR: &str = "/var/run/secrets/doken";
+
+/// Keys read from the Secret or ConfigMap and the variables they set
+const KEYS: [(&str, &str); 3] = [
+    ("client_id", "DOKEN_CLIENT_ID"),
+    ("client_secret", "DOKEN_CLIENT_SECRET"),
+    ("issuer", "DOKEN_DISCOVERY_URL"),
+];
+
+#[derive(Debug, PartialEq)]
+enum Kind {
+    Secret,
+    ConfigMap,
+}
+
+/// `--from-k8s-secret` value, ex. `ci/oauth-client` or `ci/configmap/oauth-client`
+#[derive(Debug, PartialEq)]
+struct Reference {
+    namespace: String,
+    kind: Kind,
+    name: String,
+}
+
+impl Reference {
+    fn parse(value: &str) -> Result<Self> {
+        let parts: Vec<&str> = value.split('/').collect();
+
+        let (namespace, kind, name) = match parts[..] {
+            [namespace, name] => (namespace, Kind::Secret, name),
+            [namespace, "secret", name] => (namespace, Kind::Secret, name),
+            [namespace, "configmap", name] => (namespace, Kind::ConfigMap, name),
+            _ => bail!(
+                "`--from-k8s-secret` has to be `<namespace>/<name>` or `<namespace>/configmap/<name>`"
+            ),
+        };
+
+        if namespace.is_empty() || name.is_empty() {
+            bail!("`--from-k8s-secret` has an empty namespace or name");
+        }
+
+        Ok(Reference {
+            namespace: namespace.to_owned(),
+            kind,
+            name: name.to_owned(),
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct Resource {
+    #[serde(default)]
+    data: HashMap<String, String>,
+}
+
+/// Keys of a mounted Secret or ConfigMap, each a file named after it
+fn read_mounted(dir: &Path) -> Option<HashMap<String, String>> {
+    if !dir.is_dir() {
+        return None;
+    }
+
+    Some(
+        KEYS.iter()
+            .filter_map(|(key, _)| {
+                let value = fs::read_to_string(dir.join(key)).ok()?;
+                Some((
+                    key.to_string(),
+                    value.trim_end_matches(['\r', '\n']).to_owned(),
+                ))
+            })
+            .collect(),
+    )
+}
+
+/// Data of the resource as `kubectl` shows it. Secret values are base64 encoded
+fn parse_resource(kind: &Kind, json: &[u8]) -> Result<HashMap<String, String>> {
+    let resource: Resource = serde_json::from_slice(json).context("Unexpected `kubectl` output")?;
+
+    resource
+        .data
+        .into_iter()
+        .map(|(key, value)| {
+            let value = match kind {
+                Kind::Secret => String::from_utf8(BASE64_STANDARD.decode(value)?)?,
+                Kind::ConfigMap => value,
+            };
+            Ok((key, value))
+        })
+        .collect()
+}
+
+fn read_with_kubectl(reference: &Reference) -> Result<HashMap<String, String>> {
+    let kind = match reference.kind {
+        Kind::Secret => "secret",
+        Kind::ConfigMap => "configmap",
+    };
+    let output = Command::new("kubectl")
+        .args(["get", kind, &reference.name, "-n", &reference.namespace])
+        .args(["-o", "json"])
+        .output()
+        .context("Cannot run `kubectl`. Mount the resource or install kubectl")?;
+
+    if !output.status.success() {
+        return Err(anyhow!(
+            "{}",
+            String::from_utf8_lossy(&output.stderr).trim().to_owned()
+        ))
+        .with_context(|| {
+            format!(
+                "Cannot read {} `{}/{}`",
+                kind, reference.namespace, reference.name
+            )
+        });
+    }
+
+    parse_resource(&reference.kind, &output.stdout)
+}
+
+/// Client configuration of the referenced Secret or ConfigMap, mounted into the pod or fetched with `kubectl`
+fn read(reference: &Reference) -> Result<HashMap<String, String>> {
+    let mount_dir = env::var_os("DOKEN_K8S_MOUNT_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(MOUNT_DIR));
+
+    match read_mounted(&mount_dir.join(&reference.name)) {
+        Some(data) => {
+            log::debug!("Using the mounted `{}`", reference.name);
+            Ok(data)
+        }
+        None => read_with_kubectl(reference),
+    }
+}
+
+/// `client_id` and `client_secret` of the resource and the discovery url of its `issuer`
+fn variables(data: &HashMap<String, String>) -> Vec<(&'static str, String)> {
+    KEYS.iter()
+        .filter_map(|(key, var)| {
+            let value = data.get(*key)?.trim();
+            let value = match *key {
+                "issuer" => format!(
+                    "{}/.well-known/openid-configuration",
+                    value.trim_end_matches('/')
+                ),
+                _ => value.to_owned(),
+            };
+            Some((*var, value))
+        })
+        .collect()
+}
+
+/// Exposes the configuration of `--from-k8s-secret` as `DOKEN_*` variables, unless the environment or the profile sets them already
+pub fn apply(reference: &str) -> Result<()> {
+    let data = read(&Reference::parse(reference)?)?;
+
+    for (var, value) in variables(&data) {
+        if env::var_os(var).is_some() {
+            continue;
+        }
+
+        unsafe {
+            env::set_var(var, value);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_reads_client_configuration_of_secrets() {
+        assert_eq!(
+            Reference::parse("ci/configmap/oauth-client").unwrap(),
+            Reference {
+                namespace: "ci".to_owned(),
+                kind: Kind::ConfigMap,
+                name: "oauth-client".to_owned(),
+            }
+        );
+        assert!(Reference::parse("oauth-client").is_err());
+
+        let data = parse_resource(
+            &Kind::Secret,
+            br#"{"kind":"Secret","data":{"client_id":"bXktY2xpZW50","issuer":"aHR0cHM6Ly9pZHAuY29tLw=="}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            variables(&data),
+            vec![
+                ("DOKEN_CLIENT_ID", "my-client".to_owned()),
+                (
+                    "DOKEN_DISCOVERY_URL",
+                    "https://idp.com/.well-known/openid-configuration".to_owned()
+                ),
+            ]
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("client_secret"), "secret\n").unwrap();
+        assert_eq!(read_mounted(dir.path()).unwrap()["client_secret"], "secret");
+        assert_eq!(read_mounted(&dir.path().join("missing")), None);
+    }
+}
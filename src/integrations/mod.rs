@@ -0,0 +1,4 @@
+//! Sources of client configuration from platforms doken runs on, each behind its own feature flag
+
+#[cfg(feature = "kubernetes")]
+pub mod kubernetes;
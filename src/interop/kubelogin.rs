@@ -0,0 +1,138 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use super::{CacheAdapter, write_cache};
+use crate::claims::unverified_claims;
+use crate::token_info::TokenInfo;
+
+/// Token set kubelogin (`kubectl oidc-login`) stores in one file per issuer, client and scopes
+#[derive(Serialize, Deserialize, Debug)]
+struct TokenSet {
+    id_token: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    refresh_token: Option<String>,
+}
+
+fn is_issued_for(id_token: &str, client_id: &str) -> bool {
+    match unverified_claims(id_token).and_then(|claims| claims.get("aud").cloned()) {
+        Some(Value::String(aud)) => aud == client_id,
+        Some(Value::Array(aud)) => aud.iter().any(|aud| aud.as_str() == Some(client_id)),
+        _ => false,
+    }
+}
+
+/// `~/.kube/cache/oidc-login`. Files are named with a hash of kubelogin settings, so they're
+/// matched by the audience of their id_token instead
+pub struct Kubelogin {
+    dir: PathBuf,
+}
+
+impl Kubelogin {
+    pub fn new(dir: PathBuf) -> Self {
+        Kubelogin { dir }
+    }
+
+    /// Token sets of the client and their files
+    fn token_sets(&self, client_id: &str) -> Result<Vec<(PathBuf, TokenSet)>> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e).with_context(|| format!("Cannot read {}", self.dir.display())),
+        };
+
+        Ok(entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file())
+            .filter_map(|path| {
+                let token_set =
+                    serde_json::from_str::<TokenSet>(&fs::read_to_string(&path).ok()?).ok()?;
+                Some((path, token_set))
+            })
+            .filter(|(_, token_set)| is_issued_for(&token_set.id_token, client_id))
+            .collect())
+    }
+}
+
+impl CacheAdapter for Kubelogin {
+    /// kubelogin caches no access_token, so only its refresh_token is imported and redeemed right away
+    fn read(&self, client_id: &str) -> Result<Option<TokenInfo>> {
+        let token_info = self
+            .token_sets(client_id)?
+            .into_iter()
+            .find_map(|(_, token_set)| token_set.refresh_token)
+            .map(|refresh_token| TokenInfo {
+                access_token: String::new(),
+                refresh_token: Some(refresh_token),
+                expires: Some(SystemTime::UNIX_EPOCH),
+                scope: None,
+                requested_scope: None,
+                extra: Default::default(),
+            });
+
+        Ok(token_info)
+    }
+
+    /// Updates files of the client. New ones aren't created, as kubelogin wouldn't find them
+    fn write(&self, client_id: &str, token_info: &TokenInfo) -> Result<()> {
+        let Some(id_token) = token_info
+            .extra
+            .get("id_token")
+            .and_then(|id_token| id_token.as_str())
+        else {
+            return Ok(());
+        };
+
+        for (path, _) in self.token_sets(client_id)? {
+            let token_set = TokenSet {
+                id_token: id_token.to_owned(),
+                refresh_token: token_info.refresh_token.to_owned(),
+            };
+            write_cache(&path, &serde_json::to_string(&token_set)?)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::Engine;
+    use base64::prelude::BASE64_URL_SAFE_NO_PAD;
+    use serde_json::json;
+
+    fn id_token(aud: Value) -> String {
+        format!(
+            "eyJhbGciOiJub25lIn0.{}.",
+            BASE64_URL_SAFE_NO_PAD.encode(json!({ "aud": aud }).to_string())
+        )
+    }
+
+    #[test]
+    fn it_imports_refresh_tokens_of_the_client() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(
+            dir.path().join("0f1e2d"),
+            json!({ "id_token": id_token(json!(["kubernetes", "my-client"])), "refresh_token": "refresh" })
+                .to_string(),
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("3c4b5a"),
+            json!({ "id_token": id_token(json!("other-client")), "refresh_token": "other" })
+                .to_string(),
+        )
+        .unwrap();
+        let kubelogin = Kubelogin::new(dir.path().to_owned());
+
+        let token_info = kubelogin.read("my-client").unwrap().unwrap();
+        assert_eq!(token_info.refresh_token.as_deref(), Some("refresh"));
+        assert_eq!(token_info.expires, Some(SystemTime::UNIX_EPOCH));
+        assert!(kubelogin.read("unknown-client").unwrap().is_none());
+    }
+}
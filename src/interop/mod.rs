@@ -0,0 +1,95 @@
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::args::Arguments;
+use crate::explain::Explanation;
+use crate::file_state::FileState;
+use crate::output;
+use crate::permissions;
+use crate::token_info::TokenInfo;
+
+mod kubelogin;
+mod oauth2l;
+
+/// Token caches of other CLI tools doken reads when migrating to it and keeps up to date
+#[derive(Serialize, Deserialize, ValueEnum, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum CacheFormat {
+    /// ~/.oauth2l of Google's oauth2l
+    Oauth2l,
+    /// ~/.kube/cache/oidc-login of kubelogin (`kubectl oidc-login`)
+    Kubelogin,
+}
+
+trait CacheAdapter {
+    /// Token of the client, if the other tool cached one
+    fn read(&self, client_id: &str) -> Result<Option<TokenInfo>>;
+
+    fn write(&self, client_id: &str, token_info: &TokenInfo) -> Result<()>;
+}
+
+/// Writes a cache readable only by the current user, as it contains tokens
+fn write_cache(path: &Path, content: &str) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+
+    permissions::write_private(path, content.as_bytes())
+        .with_context(|| format!("Cannot write {}", path.display()))
+}
+
+fn adapter(format: CacheFormat) -> Result<Box<dyn CacheAdapter>> {
+    let home = home::home_dir().context("Cannot find the home directory")?;
+
+    Ok(match format {
+        CacheFormat::Oauth2l => Box::new(oauth2l::Oauth2l::new(home.join(".oauth2l"))),
+        CacheFormat::Kubelogin => Box::new(kubelogin::Kubelogin::new(
+            home.join(".kube/cache/oidc-login"),
+        )),
+    })
+}
+
+/// Copies the token of `--interop-cache` to the state, unless doken cached one already
+pub fn import(
+    args: &Arguments,
+    file_state: &mut FileState,
+    explanation: &mut Explanation,
+) -> Result<()> {
+    let Some(format) = args.interop_cache else {
+        return Ok(());
+    };
+    if file_state.read_token_info(&args.state_key()).is_some() {
+        return Ok(());
+    }
+
+    let format_name = serde_variant::to_variant_name(&format).unwrap_or_default();
+    match adapter(format)?.read(&args.client_id)? {
+        Some(token_info) => {
+            explanation.step(format!("Imported the token cached by `{}`", format_name));
+            file_state.upsert_token_info(
+                args.state_key(),
+                token_info.with_requested_scope(&args.scope),
+            )
+        }
+        None => {
+            explanation.step(format!(
+                "`{}` has no token of the client cached",
+                format_name
+            ));
+            Ok(())
+        }
+    }
+}
+
+/// Keeps the cache of `--interop-cache` up to date with a new or refreshed token. Never fails the run
+pub fn export(args: &Arguments, token_info: &TokenInfo) {
+    let Some(format) = args.interop_cache else {
+        return;
+    };
+
+    if let Err(e) = adapter(format).and_then(|adapter| adapter.write(&args.client_id, token_info)) {
+        output::warning(&format!("Cannot update the interop cache: {:#}", e));
+    }
+}
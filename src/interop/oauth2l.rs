@@ -0,0 +1,163 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value, json};
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use super::{CacheAdapter, write_cache};
+use crate::token_info::TokenInfo;
+
+/// Go `oauth2.Token` as oauth2l stores it
+#[derive(Serialize, Deserialize, Debug)]
+struct Token {
+    access_token: String,
+    #[serde(default)]
+    token_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    refresh_token: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expiry: Option<String>,
+}
+
+/// Go marshals times with the local offset, ex. `2024-05-01T14:00:00.123+02:00`
+fn parse_time(value: &str) -> Option<SystemTime> {
+    if value.ends_with('Z') {
+        return humantime::parse_rfc3339_weak(value).ok();
+    }
+
+    let split = value.rfind(['+', '-']).filter(|split| *split > 10)?;
+    let (time, offset) = value.split_at(split);
+    let (hours, minutes) = offset[1..].split_once(':')?;
+    let offset =
+        Duration::from_secs(hours.parse::<u64>().ok()? * 3600 + minutes.parse::<u64>().ok()? * 60);
+    let time = humantime::parse_rfc3339_weak(&format!("{}Z", time)).ok()?;
+
+    match value.as_bytes()[split] {
+        b'+' => time.checked_sub(offset),
+        _ => time.checked_add(offset),
+    }
+}
+
+/// `~/.oauth2l`, a JSON object of tokens keyed by the JSON of oauth2l settings, which include the client_id
+pub struct Oauth2l {
+    path: PathBuf,
+}
+
+impl Oauth2l {
+    pub fn new(path: PathBuf) -> Self {
+        Oauth2l { path }
+    }
+
+    fn read_cache(&self) -> Result<Map<String, Value>> {
+        match fs::read_to_string(&self.path) {
+            Ok(text) if text.trim().is_empty() => Ok(Map::new()),
+            Ok(text) => serde_json::from_str(&text)
+                .with_context(|| format!("Cannot parse {}", self.path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Map::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn keys_of(cache: &Map<String, Value>, client_id: &str) -> Vec<String> {
+        let quoted = format!("\"{}\"", client_id);
+
+        cache
+            .keys()
+            .filter(|key| key.contains(&quoted))
+            .cloned()
+            .collect()
+    }
+}
+
+impl CacheAdapter for Oauth2l {
+    fn read(&self, client_id: &str) -> Result<Option<TokenInfo>> {
+        let cache = self.read_cache()?;
+
+        let token_info = Oauth2l::keys_of(&cache, client_id)
+            .iter()
+            .filter_map(|key| serde_json::from_value::<Token>(cache[key].clone()).ok())
+            .map(|token| TokenInfo {
+                access_token: token.access_token,
+                refresh_token: token.refresh_token.filter(|token| !token.is_empty()),
+                expires: token.expiry.as_deref().and_then(parse_time),
+                scope: None,
+                requested_scope: None,
+                extra: Default::default(),
+            })
+            .max_by_key(|token_info| token_info.expires);
+
+        Ok(token_info)
+    }
+
+    fn write(&self, client_id: &str, token_info: &TokenInfo) -> Result<()> {
+        let mut cache = self.read_cache()?;
+        let token = serde_json::to_value(Token {
+            access_token: token_info.access_token.to_owned(),
+            token_type: "Bearer".to_owned(),
+            refresh_token: token_info.refresh_token.to_owned(),
+            expiry: token_info
+                .expires
+                .map(|expires| humantime::format_rfc3339_seconds(expires).to_string()),
+        })?;
+
+        let mut keys = Oauth2l::keys_of(&cache, client_id);
+        if keys.is_empty() {
+            // oauth2l keys cannot be reproduced, so doken's own entry is only found by doken
+            keys.push(json!({ "client_id": client_id }).to_string());
+        }
+        for key in keys {
+            cache.insert(key, token.clone());
+        }
+
+        write_cache(&self.path, &serde_json::to_string(&cache)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_go_times() {
+        let expected = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+        assert_eq!(parse_time("2023-11-14T22:13:20Z"), Some(expected));
+        assert_eq!(parse_time("2023-11-15T00:13:20+02:00"), Some(expected));
+        assert_eq!(parse_time("2023-11-14T17:13:20.000-05:00"), Some(expected));
+        assert_eq!(parse_time("0001-01-01T00:00:00Z"), None);
+    }
+
+    #[test]
+    fn it_reads_and_updates_tokens_of_the_client() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(".oauth2l");
+        fs::write(
+            &path,
+            r#"{"{\"CredentialsJSON\":{\"client_id\":\"my-client\"},\"Scope\":\"openid\"}":{"access_token":"old","token_type":"Bearer","refresh_token":"refresh","expiry":"2023-11-14T22:13:20Z"}}"#,
+        )
+        .unwrap();
+        let oauth2l = Oauth2l::new(path);
+
+        let token_info = oauth2l.read("my-client").unwrap().unwrap();
+        assert_eq!(token_info.access_token, "old");
+        assert_eq!(token_info.refresh_token.as_deref(), Some("refresh"));
+        assert!(oauth2l.read("other-client").unwrap().is_none());
+
+        oauth2l
+            .write(
+                "my-client",
+                &TokenInfo {
+                    access_token: "new".to_owned(),
+                    ..token_info
+                },
+            )
+            .unwrap();
+        let cache = oauth2l.read_cache().unwrap();
+        assert_eq!(cache.len(), 1);
+        assert_eq!(
+            oauth2l.read("my-client").unwrap().unwrap().access_token,
+            "new"
+        );
+    }
+}
@@ -0,0 +1,182 @@
+use anyhow::{Context, Result, anyhow};
+use jsonwebtoken::jwk::{Jwk, JwkSet};
+use reqwest::StatusCode;
+use reqwest::header::{CACHE_CONTROL, ETAG, IF_NONE_MATCH};
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::openidc_discovery::max_age;
+
+/// Unknown key ids trigger a refetch (key rotation), but not more often than this
+const MIN_REFETCH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Key sets without `Cache-Control` are revalidated after this long
+const DEFAULT_MAX_AGE: Duration = Duration::from_secs(60 * 60);
+
+struct CachedKeySet {
+    key_set: JwkSet,
+    fetched_at: Instant,
+    max_age: Duration,
+    etag: Option<String>,
+}
+
+impl CachedKeySet {
+    fn is_fresh(&self) -> bool {
+        self.fetched_at.elapsed() < self.max_age
+    }
+}
+
+static KEY_SETS: LazyLock<Mutex<HashMap<String, CachedKeySet>>> = LazyLock::new(Default::default);
+
+/// Key of `kid` from the cached key set. Stale sets count only with `stale_ok`
+fn cached_key(jwks_uri: &str, kid: Option<&str>, stale_ok: bool) -> Option<Jwk> {
+    let key_sets = KEY_SETS.lock().unwrap();
+    let cached = key_sets
+        .get(jwks_uri)
+        .filter(|cached| stale_ok || cached.is_fresh())?;
+
+    select(&cached.key_set, kid)
+}
+
+fn select(key_set: &JwkSet, kid: Option<&str>) -> Option<Jwk> {
+    match kid {
+        Some(kid) => key_set.find(kid).cloned(),
+        None if key_set.keys.len() == 1 => key_set.keys.first().cloned(),
+        None => None,
+    }
+}
+
+fn fetched_recently(jwks_uri: &str) -> bool {
+    KEY_SETS
+        .lock()
+        .unwrap()
+        .get(jwks_uri)
+        .is_some_and(|cached| cached.fetched_at.elapsed() < MIN_REFETCH_INTERVAL)
+}
+
+/// Time until the cached key set goes stale
+fn fresh_for(jwks_uri: &str) -> Duration {
+    KEY_SETS
+        .lock()
+        .unwrap()
+        .get(jwks_uri)
+        .map(|cached| cached.max_age.saturating_sub(cached.fetched_at.elapsed()))
+        .unwrap_or_default()
+}
+
+/// Fetches the key set, revalidating the cached one with `If-None-Match`
+async fn fetch(http: &reqwest::Client, jwks_uri: &str) -> Result<()> {
+    log::debug!("Fetching JWKS from {}", jwks_uri);
+
+    let etag = KEY_SETS
+        .lock()
+        .unwrap()
+        .get(jwks_uri)
+        .and_then(|cached| cached.etag.to_owned());
+    let mut request = http.get(jwks_uri);
+    if let Some(etag) = &etag {
+        request = request.header(IF_NONE_MATCH, etag);
+    }
+
+    let response = request
+        .send()
+        .await
+        .context("Couldn't reach out to `jwks_uri`")?;
+    let header = |name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.to_owned())
+    };
+    let max_age = header(CACHE_CONTROL)
+        .as_deref()
+        .and_then(max_age)
+        .unwrap_or(DEFAULT_MAX_AGE);
+
+    if response.status() == StatusCode::NOT_MODIFIED {
+        log::debug!("JWKS of {} has not changed", jwks_uri);
+
+        if let Some(cached) = KEY_SETS.lock().unwrap().get_mut(jwks_uri) {
+            cached.fetched_at = Instant::now();
+            cached.max_age = max_age;
+        }
+        return Ok(());
+    }
+
+    let etag = header(ETAG);
+    let key_set = response
+        .error_for_status()?
+        .json::<JwkSet>()
+        .await
+        .context("Couldn't process JWKS given by `jwks_uri`")?;
+
+    KEY_SETS.lock().unwrap().insert(
+        jwks_uri.to_owned(),
+        CachedKeySet {
+            key_set,
+            fetched_at: Instant::now(),
+            max_age,
+            etag,
+        },
+    );
+
+    Ok(())
+}
+
+/// Key of `kid` from the provider's JWKS. Keys are cached by `jwks_uri` as long as `Cache-Control`
+/// allows (an hour without it), then revalidated. Unknown `kid`s refetch the set
+pub async fn find_key(http: &reqwest::Client, jwks_uri: &str, kid: Option<&str>) -> Result<Jwk> {
+    if let Some(key) = cached_key(jwks_uri, kid, false) {
+        return Ok(key);
+    }
+
+    if !fetched_recently(jwks_uri) {
+        fetch(http, jwks_uri).await?;
+    }
+
+    cached_key(jwks_uri, kid, true).ok_or_else(|| match kid {
+        Some(kid) => anyhow!("The provider's JWKS has no key with kid `{}`", kid),
+        None => anyhow!("The token has no kid and the provider's JWKS has more than one key"),
+    })
+}
+
+/// Revalidates the key set once it's stale, so verifications of long running processes don't wait
+/// for the provider. Returns the time until it should be called again
+pub async fn refresh(http: &reqwest::Client, jwks_uri: &str) -> Result<Duration> {
+    if fresh_for(jwks_uri).is_zero() {
+        fetch(http, jwks_uri).await?;
+    }
+
+    Ok(fresh_for(jwks_uri).max(MIN_REFETCH_INTERVAL))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn it_uses_stale_keys_only_as_a_fallback() {
+        let key_set: JwkSet = serde_json::from_value(json!({
+            "keys": [{ "kty": "oct", "kid": "key-1", "k": "c2VjcmV0" }]
+        }))
+        .unwrap();
+        KEY_SETS.lock().unwrap().insert(
+            "https://idp/stale-jwks".to_owned(),
+            CachedKeySet {
+                key_set,
+                fetched_at: Instant::now(),
+                max_age: Duration::ZERO,
+                etag: Some("\"v1\"".to_owned()),
+            },
+        );
+
+        assert!(cached_key("https://idp/stale-jwks", Some("key-1"), false).is_none());
+        assert!(cached_key("https://idp/stale-jwks", Some("key-1"), true).is_some());
+        assert!(cached_key("https://idp/stale-jwks", Some("key-2"), true).is_none());
+        assert!(cached_key("https://idp/stale-jwks", None, true).is_some());
+        assert_eq!(fresh_for("https://idp/stale-jwks"), Duration::ZERO);
+    }
+}
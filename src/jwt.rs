@@ -0,0 +1,219 @@
+use anyhow::{Context, Result, bail};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use jsonwebtoken::{Algorithm, DecodingKey, Header, Validation};
+use serde_json::Value;
+use std::str::FromStr;
+
+use crate::args::Arguments;
+use crate::jwks;
+use crate::oauth_client::OAuthClient;
+use crate::token_info::TokenInfo;
+
+/// Accepted when only `--pin-kid` is given. Symmetric algorithms have to be allowed explicitly
+const ASYMMETRIC_ALGORITHMS: [Algorithm; 8] = [
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::PS384,
+    Algorithm::PS512,
+    Algorithm::ES256,
+    Algorithm::ES384,
+];
+
+/// Parses `--allowed-algs` values. `none` is never accepted
+pub fn parse_algorithm(value: &str) -> Result<Algorithm> {
+    Algorithm::from_str(value).map_err(|_| anyhow::anyhow!("Unsupported JWT algorithm `{}`", value))
+}
+
+fn is_symmetric(alg: Algorithm) -> bool {
+    matches!(alg, Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512)
+}
+
+/// Expected signing algorithms and key ids of tokens issued by the provider
+pub struct VerificationPolicy {
+    allowed_algs: Vec<Algorithm>,
+    pinned_kids: Vec<String>,
+}
+
+impl VerificationPolicy {
+    /// `None` unless `--allowed-algs` or `--pin-kid` is given
+    pub fn from_args(args: &Arguments) -> Option<Self> {
+        if args.allowed_algs.is_empty() && args.pin_kid.is_empty() {
+            return None;
+        }
+
+        Some(VerificationPolicy {
+            allowed_algs: if args.allowed_algs.is_empty() {
+                ASYMMETRIC_ALGORITHMS.to_vec()
+            } else {
+                args.allowed_algs.to_owned()
+            },
+            pinned_kids: args.pin_kid.to_owned(),
+        })
+    }
+
+    /// `--allowed-algs`/`--pin-kid` policy, or any asymmetric algorithm without them. For tokens
+    /// that have to be verified regardless, ex. logout tokens
+    pub fn from_args_or_asymmetric(args: &Arguments) -> Self {
+        Self::from_args(args).unwrap_or(VerificationPolicy {
+            allowed_algs: ASYMMETRIC_ALGORITHMS.to_vec(),
+            pinned_kids: vec![],
+        })
+    }
+
+    /// Rejects unsigned tokens before any parsing, as `alg` isn't a known algorithm then
+    fn check_raw_alg(token: &str) -> Result<()> {
+        let header = token.split('.').next().unwrap_or_default();
+        let header: Value = URL_SAFE_NO_PAD
+            .decode(header)
+            .ok()
+            .and_then(|header| serde_json::from_slice(&header).ok())
+            .context("The token header is not a valid JWT header")?;
+
+        if header
+            .get("alg")
+            .and_then(|alg| alg.as_str())
+            .is_none_or(|alg| alg.eq_ignore_ascii_case("none"))
+        {
+            bail!("The token is not signed (`alg` is `none` or missing)");
+        }
+
+        Ok(())
+    }
+
+    fn check(&self, header: &Header) -> Result<()> {
+        if !self.allowed_algs.contains(&header.alg) {
+            bail!(
+                "The token is signed with `{:?}`, which is not in `--allowed-algs`",
+                header.alg
+            );
+        }
+
+        if !self.pinned_kids.is_empty()
+            && !header
+                .kid
+                .as_ref()
+                .is_some_and(|kid| self.pinned_kids.contains(kid))
+        {
+            bail!(
+                "The token is signed with kid `{}`, which is not pinned with `--pin-kid`",
+                header.kid.as_deref().unwrap_or("<none>")
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Checks the header against the policy and verifies the signature with the provider's JWKS
+    /// (or the client secret for explicitly allowed HMAC algorithms). Claims are not validated here
+    pub async fn verify(
+        &self,
+        token: &str,
+        args: &Arguments,
+        oauth_client: &OAuthClient<'_>,
+    ) -> Result<()> {
+        Self::check_raw_alg(token)?;
+        let header = jsonwebtoken::decode_header(token).context("The token is not a valid JWT")?;
+        self.check(&header)?;
+
+        let key = if is_symmetric(header.alg) {
+            let secret = args
+                .client_secret
+                .as_deref()
+                .context("HMAC signed tokens can be verified only with a client secret")?;
+            DecodingKey::from_secret(secret.as_bytes())
+        } else {
+            let jwks_uri = oauth_client
+                .metadata()
+                .jwks_uri
+                .as_deref()
+                .context("Signature verification requires `--discovery-url` with `jwks_uri`")?;
+            let jwk = jwks::find_key(oauth_client.http(), jwks_uri, header.kid.as_deref()).await?;
+            DecodingKey::from_jwk(&jwk)?
+        };
+
+        let mut validation = Validation::new(header.alg);
+        validation.validate_exp = false;
+        validation.validate_aud = false;
+        validation.required_spec_claims.clear();
+
+        jsonwebtoken::decode::<Value>(token, &key, &validation)
+            .context("The token signature is invalid")?;
+
+        Ok(())
+    }
+}
+
+/// Verifies id_token of a freshly retrieved token with `--allowed-algs`/`--pin-kid` policy
+pub async fn verify_token_info(
+    token_info: &TokenInfo,
+    args: &Arguments,
+    oauth_client: &OAuthClient<'_>,
+) -> Result<()> {
+    let Some(policy) = VerificationPolicy::from_args(args) else {
+        return Ok(());
+    };
+
+    let Some(id_token) = token_info
+        .extra
+        .get("id_token")
+        .and_then(|token| token.as_str())
+    else {
+        log::debug!("No id_token in the response. Nothing to verify");
+        return Ok(());
+    };
+
+    policy
+        .verify(id_token, args, oauth_client)
+        .await
+        .context("id_token verification failed")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(alg: Algorithm, kid: Option<&str>) -> Header {
+        Header {
+            kid: kid.map(|kid| kid.to_owned()),
+            ..Header::new(alg)
+        }
+    }
+
+    #[test]
+    fn it_enforces_algorithms_and_pinned_kids() {
+        let policy = VerificationPolicy {
+            allowed_algs: ASYMMETRIC_ALGORITHMS.to_vec(),
+            pinned_kids: vec!["key-1".to_owned()],
+        };
+
+        assert!(
+            policy
+                .check(&header(Algorithm::RS256, Some("key-1")))
+                .is_ok()
+        );
+        assert!(
+            policy
+                .check(&header(Algorithm::RS256, Some("key-2")))
+                .is_err()
+        );
+        assert!(policy.check(&header(Algorithm::RS256, None)).is_err());
+        assert!(
+            policy
+                .check(&header(Algorithm::HS256, Some("key-1")))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn it_rejects_unsigned_tokens() {
+        let unsigned = format!("{}.e30.", URL_SAFE_NO_PAD.encode(r#"{"alg":"none"}"#));
+        let signed = format!("{}.e30.sig", URL_SAFE_NO_PAD.encode(r#"{"alg":"RS256"}"#));
+
+        assert!(VerificationPolicy::check_raw_alg(&unsigned).is_err());
+        assert!(VerificationPolicy::check_raw_alg(&signed).is_ok());
+        assert!(parse_algorithm("none").is_err());
+    }
+}
@@ -0,0 +1,201 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs;
+use std::time::{Duration, SystemTime};
+
+use crate::args::Arguments;
+use crate::signer::{PemSigner, sign_jwt};
+
+/// `grant_type` of the JWT Bearer grant <https://www.rfc-editor.org/rfc/rfc7523#section-2.1>
+pub const JWT_BEARER_GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:jwt-bearer";
+
+/// Assertions are made for a single request
+const LIFETIME: Duration = Duration::from_secs(5 * 60);
+
+/// Key file of a Google service account
+#[derive(Deserialize)]
+struct ServiceAccountKey {
+    private_key: String,
+    private_key_id: Option<String>,
+    client_email: String,
+}
+
+/// Key of `--assertion-key-file`: a PEM private key, a private JWK or a Google service account key
+pub struct AssertionKey {
+    signer: PemSigner,
+    /// `iss` the key file names, ex. the service account's email
+    issuer: Option<String>,
+    /// Google expects the scope in the assertion instead of the request
+    scope_claim: bool,
+}
+
+impl AssertionKey {
+    pub fn read(args: &Arguments) -> Result<AssertionKey> {
+        let path = args
+            .assertion_key_file
+            .as_deref()
+            .context("`jwt-bearer` grant requires `--assertion-key-file`")?;
+        let content =
+            fs::read(path).with_context(|| format!("Cannot read the assertion key {}", path))?;
+        let key_id = args.client_assertion_kid.to_owned();
+
+        let key = match serde_json::from_slice::<Value>(&content).ok() {
+            Some(json) if json["type"] == "service_account" => {
+                let key: ServiceAccountKey = serde_json::from_value(json)
+                    .with_context(|| format!("{} is not a valid service account key", path))?;
+
+                AssertionKey {
+                    signer: PemSigner::from_pem(
+                        key.private_key.as_bytes(),
+                        jsonwebtoken::Algorithm::RS256,
+                        key.private_key_id.or(key_id),
+                    )?,
+                    issuer: Some(key.client_email),
+                    scope_claim: true,
+                }
+            }
+            Some(json) => AssertionKey {
+                signer: PemSigner::from_jwk(&json, args.client_assertion_alg, key_id)
+                    .with_context(|| format!("{} is not a valid private JWK", path))?,
+                issuer: None,
+                scope_claim: false,
+            },
+            None => AssertionKey {
+                signer: PemSigner::from_pem(&content, args.client_assertion_alg, key_id)
+                    .with_context(|| {
+                        format!(
+                            "{} is not a valid {:?} private key",
+                            path, args.client_assertion_alg
+                        )
+                    })?,
+                issuer: None,
+                scope_claim: false,
+            },
+        };
+
+        Ok(key)
+    }
+}
+
+/// Claims of an authorization grant assertion <https://www.rfc-editor.org/rfc/rfc7523#section-3>
+#[derive(Serialize, Debug, PartialEq)]
+struct Claims<'a> {
+    iss: &'a str,
+    sub: &'a str,
+    aud: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scope: Option<&'a str>,
+    jti: String,
+    iat: u64,
+    exp: u64,
+}
+
+fn claims<'a>(
+    key: &'a AssertionKey,
+    args: &'a Arguments,
+    token_endpoint: &'a str,
+    jti: String,
+    now: SystemTime,
+) -> Claims<'a> {
+    let iss = key.issuer.as_deref().unwrap_or(&args.client_id);
+    let iat = now
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    Claims {
+        iss,
+        sub: args.assertion_subject.as_deref().unwrap_or(iss),
+        aud: args.assertion_audience.as_deref().unwrap_or(token_endpoint),
+        scope: key.scope_claim.then_some(args.scope.as_str()),
+        jti,
+        iat,
+        exp: iat + LIFETIME.as_secs(),
+    }
+}
+
+/// Signed JWT exchanged for a token at `token_endpoint`. `iss` is the client, or the service account
+/// of the key file, `sub` is `--assertion-subject` or the issuer itself
+pub fn assertion(
+    key: &AssertionKey,
+    args: &Arguments,
+    token_endpoint: &str,
+    jti: String,
+) -> Result<String> {
+    sign_jwt(
+        &key.signer,
+        &claims(key, args, token_endpoint, jti, SystemTime::now()),
+    )
+    .context("Cannot sign the assertion")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::Algorithm;
+    use serde_json::json;
+
+    #[test]
+    fn it_makes_claims_for_the_subject() {
+        let key = AssertionKey {
+            signer: PemSigner::from_jwk(
+                &json!({
+                    "kty": "EC",
+                    "crv": "P-256",
+                    "x": "f83OJ3D2xF1Bg8vub9tLe1gHMzV76e8Tus9uPHvRVEU",
+                    "y": "x_FEzRu9m36HLN_tue659LNpXW6pCyStikYjKIWI5a0",
+                    "d": "jpsQnnGQmL-YBIffH1136cLJQh6PMVeS3aPUa2RHfqE",
+                }),
+                Algorithm::ES256,
+                None,
+            )
+            .unwrap(),
+            issuer: None,
+            scope_claim: false,
+        };
+        let args = Arguments {
+            client_id: "my-client".to_owned(),
+            assertion_subject: Some("user@corp.com".to_owned()),
+            assertion_audience: Some("https://login.salesforce.com".to_owned()),
+            ..Default::default()
+        };
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+        assert_eq!(
+            claims(&key, &args, "https://idp/token", "jti-1".to_owned(), now),
+            Claims {
+                iss: "my-client",
+                sub: "user@corp.com",
+                aud: "https://login.salesforce.com",
+                scope: None,
+                jti: "jti-1".to_owned(),
+                iat: 1_700_000_000,
+                exp: 1_700_000_300,
+            }
+        );
+
+        let service_account = AssertionKey {
+            issuer: Some("bot@project.iam.gserviceaccount.com".to_owned()),
+            scope_claim: true,
+            ..key
+        };
+        let args = Arguments {
+            scope: "https://www.googleapis.com/auth/cloud-platform".to_owned(),
+            ..Default::default()
+        };
+        let claims = claims(
+            &service_account,
+            &args,
+            "https://idp/token",
+            "jti-2".to_owned(),
+            now,
+        );
+        assert_eq!(claims.sub, "bot@project.iam.gserviceaccount.com");
+        assert_eq!(claims.aud, "https://idp/token");
+        assert_eq!(
+            claims.scope,
+            Some("https://www.googleapis.com/auth/cloud-platform")
+        );
+    }
+}
@@ -0,0 +1,31 @@
+use std::process::Command;
+
+/// Keychain service doken's secrets are stored under
+const SERVICE: &str = "doken";
+
+/// Secret stored in the macOS login keychain as a generic password of the `doken` service and
+/// `<profile>.<key>` account. Items added with `-T /usr/bin/security` are readable without a prompt,
+/// which a launchd agent couldn't answer
+pub fn credential(profile: &str, key: &str) -> Option<String> {
+    if !cfg!(target_os = "macos") {
+        return None;
+    }
+
+    let account = format!("{}.{}", profile, key);
+    let output = Command::new("security")
+        .args(["find-generic-password", "-s", SERVICE, "-a", &account, "-w"])
+        .output()
+        .ok()?;
+
+    if !output.status.success() {
+        log::debug!("No `{}` in the keychain", account);
+        return None;
+    }
+
+    log::debug!("Using `{}` from the keychain", account);
+    Some(
+        String::from_utf8_lossy(&output.stdout)
+            .trim_end_matches(['\r', '\n'])
+            .to_owned(),
+    )
+}
@@ -1,83 +1,600 @@
 #![deny(warnings)]
 
-use crate::args::Arguments;
+use crate::args::{Args, Arguments};
 use crate::file_state::FileState;
 use crate::grant::Grant;
 use crate::oauth_client::OAuthClient;
 use crate::retrievers::authorization_code_retriever::AuthorizationCodeRetriever;
 use crate::retrievers::authorization_code_with_pkce_retriever::AuthorizationCodeWithPKCERetriever;
 use crate::retrievers::client_credentials_retriever::ClientCredentialsRetriever;
+use crate::retrievers::device_code_retriever::DeviceCodeRetriever;
 use crate::retrievers::file_retriever::FileRetriever;
 use crate::retrievers::implicit_retriever::ImplicitRetriever;
+use crate::retrievers::jwt_bearer_retriever::JwtBearerRetriever;
+use crate::retrievers::okta_session_token_retriever::OktaSessionTokenRetriever;
+use crate::retrievers::refresh_token_retriever::{RefreshTokenRetriever, external_refresh_token};
 use crate::retrievers::resource_owner_password_client_credentials_retriever::ResourceOwnerPasswordClientCredentialsRetriever;
+use crate::retrievers::saml2_bearer_retriever::Saml2BearerRetriever;
+use crate::retrievers::token_exchange_retriever::TokenExchangeRetriever;
 use crate::retrievers::token_retriever::TokenRetriever;
+use crate::token_info::TokenInfo;
 use anyhow::Context;
 use anyhow::Result;
+use anyhow::{anyhow, bail};
+use auth_browser::CodeSource;
 use auth_browser::browser::Browser;
-use tokio::sync::MutexGuard;
+use auth_browser::page::PageOptions;
+use auth_browser::system_browser::{CommandOpener, SystemBrowser};
+use config_file::ConfigFile;
+use explain::Explanation;
+use history::TokenSource;
+use hooks::HookStage;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::{Instant, SystemTime};
+use thiserror::Error;
+use tokio::sync::{Mutex, MutexGuard};
 
 pub mod args;
 pub mod auth_browser;
+mod auto_grant;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+mod callback_mux;
+pub mod claims;
+mod client_assertion;
+pub mod client_auth_method;
+mod clock_skew;
+pub mod commands;
 mod config_file;
+mod consent;
+pub mod environment;
+mod explain;
 mod file_state;
 pub mod grant;
+pub mod grant_capabilities;
+mod har;
+mod health;
+mod history;
+mod hooks;
+mod http_client;
+mod integrations;
+pub mod interop;
+mod jwks;
+mod jwt;
+mod jwt_bearer;
+mod keychain;
+pub mod logging;
+mod logout;
+mod loopback;
 mod oauth_client;
-mod openidc_discovery;
+pub mod openidc_discovery;
+pub mod output;
+pub mod output_schema;
+mod output_template;
+mod pending_flow;
+mod permissions;
+pub mod pkcs11;
+pub mod progress;
+pub mod prompt;
+mod providers;
 mod retrievers;
-mod token_info;
+mod saml_bearer;
+pub mod sdk_hints;
+mod secret_source;
+pub mod security_params;
+mod settings;
+pub mod signer;
+mod single_flight;
+mod systemd;
+pub mod telemetry;
+mod throttling;
+pub mod token_info;
+mod totp;
+mod webhook;
+mod yubikey;
+
+/// Warns (or fails with `--strict-permissions`) about files with tokens and secrets that other users can read
+pub(crate) fn check_permissions(args: &Arguments) -> Result<()> {
+    let paths = [FileState::path(), ConfigFile::new().path().to_owned()];
+
+    for path in permissions::exposed(&paths) {
+        let message = format!(
+            "{} is accessible by other users. Run `chmod 600 {}`",
+            path.display(),
+            path.display()
+        );
+
+        if args.strict_permissions {
+            bail!(message);
+        }
+
+        output::warning(&message);
+    }
+
+    Ok(())
+}
+
+/// Returned with `--silent-fail-cache-only` when a new flow would have to be started
+#[derive(Error, Debug)]
+#[error("No valid token cached. An interactive run of doken is required")]
+pub struct InteractionRequired;
+
+impl InteractionRequired {
+    /// Exit code of the `doken` binary, so editors can tell it apart from other failures
+    pub const EXIT_CODE: i32 = 3;
+}
+
+/// Cached token that's still valid, was requested with the same scopes and carries the required claims.
+/// Never refreshes nor writes the state
+pub fn valid_cached_token_info(args: &Arguments) -> Result<Option<TokenInfo>> {
+    let token_info = FileState::new()?.read_token_info(&args.state_key());
+
+    Ok(token_info.filter(|token_info| {
+        let (added, removed) = token_info.scope_changes(&args.scope);
+
+        added.is_empty()
+            && removed.is_empty()
+            && claims::unmet(token_info, &args.require_claim).is_empty()
+            && token_info
+                .expires
+                .is_some_and(|expires| expires > SystemTime::now())
+    }))
+}
 
 pub async fn get_token(args: Arguments, auth_browser: MutexGuard<'_, Browser>) -> Result<String> {
-    let oauth_client = OAuthClient::new(&args).await?;
+    check_permissions(&args)?;
+
+    if args.silent_fail_cache_only {
+        let token_info = valid_cached_token_info(&args)?.ok_or(InteractionRequired)?;
+        return output::render(&token_info, &args);
+    }
+
+    let token_info = get_token_info(&args, auth_browser).await?;
+
+    output::render(&token_info, &args)
+}
+
+/// Where diagnostics of failed flows are saved
+fn debug_dir() -> Option<PathBuf> {
+    let mut path = home::home_dir()?;
+    path.push(".doken/debug");
+    Some(path)
+}
+
+fn page_options(args: &Arguments) -> PageOptions {
+    PageOptions {
+        echo_callback: args.echo_callback,
+        debug_screenshot: args.debug_screenshot.then(debug_dir).flatten(),
+        capture_har: args.capture_har.to_owned(),
+        har_include_secrets: args.har_include_secrets,
+        strict_csrf: args.strict_csrf,
+        extra_headers: args.extra_header.to_owned(),
+    }
+}
+
+/// `--browser-cmd` opens a browser doken doesn't control, `--webview` a native window, otherwise a page of the controlled one is used
+async fn code_source(
+    args: &Arguments,
+    auth_browser: MutexGuard<'_, Browser>,
+) -> Result<Box<dyn CodeSource>> {
+    #[cfg(all(feature = "webview", any(target_os = "macos", windows)))]
+    if args.webview && args.browser_cmd.is_none() {
+        return Ok(Box::new(
+            auth_browser::webview::WebView::new().with_strict_csrf(args.strict_csrf),
+        ));
+    }
+
+    match &args.browser_cmd {
+        Some(browser_cmd) => Ok(Box::new(
+            SystemBrowser::new(Box::new(CommandOpener::new(browser_cmd)))
+                .with_strict_csrf(args.strict_csrf)
+                .with_multiplex_callback(args.multiplex_callback)
+                .with_callback_socket(args.callback_socket)
+                .with_bind_address(args.bind_address.to_owned()),
+        )),
+        None => Ok(Box::new(
+            auth_browser
+                .open_page()
+                .await?
+                .with_options(page_options(args)),
+        )),
+    }
+}
+
+/// Arguments with `--fallback-grant` when the controlled browser of the primary grant cannot be shown
+fn fallback_arguments(args: &Arguments) -> Result<Option<Arguments>> {
+    let Some(fallback_grant) = &args.fallback_grant else {
+        return Ok(None);
+    };
+
+    if !args.grant.uses_browser() || args.browser_cmd.is_some() {
+        return Ok(None);
+    }
+
+    let Some(reason) = args.browser_unavailable() else {
+        return Ok(None);
+    };
+
+    let name = serde_variant::to_variant_name(fallback_grant).unwrap_or_default();
+    if fallback_grant.uses_browser() {
+        bail!(
+            "`{}` cannot be a fallback grant, it needs a browser too",
+            name
+        );
+    }
+
+    output::status(
+        "Falling back",
+        &format!("to `{}` grant, because {}", name, reason),
+    );
+
+    let args = Arguments {
+        grant: fallback_grant.to_owned(),
+        fallback_grant: None,
+        ..args.to_owned()
+    };
+    Args::assert_grant_specific_arguments(&args);
+
+    Ok(Some(args))
+}
+
+/// Arguments of `--upstream-profile`. Connection settings are inherited from the downstream invocation
+async fn upstream_arguments(args: &Arguments) -> Result<Arguments> {
+    let name = args
+        .upstream_profile
+        .as_deref()
+        .context("--upstream-profile is required while used with `token-exchange` grant")?;
+    let config = ConfigFile::new().read().await;
+    let profile = config
+        .profile
+        .get(name)
+        .with_context(|| format!("The given upstream profile `{}` doesn't exist", name))?;
+    let upstream = profile
+        .to_arguments(name)
+        .with_context(|| format!("The upstream profile `{}` has no client_id", name))?
+        .with_scope_aliases(&config.scopes)?;
+
+    if let Grant::TokenExchange = upstream.grant {
+        bail!(
+            "The upstream profile `{}` cannot use `token-exchange` grant itself",
+            name
+        );
+    }
+
+    if upstream.state_key() == args.state_key() {
+        bail!(
+            "The upstream profile `{}` has to use a different client_id or `audience` has to be set for the exchange",
+            name
+        );
+    }
+
+    Ok(Arguments {
+        no_system_proxy: args.no_system_proxy,
+        no_system_trust: args.no_system_trust,
+        echo_callback: args.echo_callback,
+        debug: args.debug,
+        prompt_backend: args.prompt_backend.clone(),
+        assume_yes: args.assume_yes,
+        non_interactive: args.non_interactive,
+        proxy: upstream.proxy.clone().or_else(|| args.proxy.clone()),
+        ca_cert: upstream.ca_cert.clone().or_else(|| args.ca_cert.clone()),
+        ..upstream
+    })
+}
+
+/// Tokens of several profiles of ~/.doken/config.toml keyed by profile, fetched one after another in
+/// one process. Each profile's arguments come from [`Args::parse_profiles`], so they're checked as
+/// in a run with a single profile
+pub async fn get_profile_token_infos(
+    profile_args: &[Arguments],
+    auth_browser: &Mutex<Browser>,
+) -> Result<BTreeMap<String, TokenInfo>> {
+    let mut token_infos = BTreeMap::new();
+
+    for args in profile_args {
+        let name = args.profile.to_owned().unwrap_or_default();
+        let args = args
+            .to_owned()
+            .with_secrets(|key| keychain::credential(&name, key));
+        check_permissions(&args)?;
+
+        let result = match args.silent_fail_cache_only {
+            true => valid_cached_token_info(&args)
+                .and_then(|token_info| Ok(token_info.ok_or(InteractionRequired)?)),
+            false => get_token_info(&args, auth_browser.lock().await).await,
+        };
+        telemetry::report(&args.grant, result.as_ref().err()).await;
+
+        let token_info =
+            result.with_context(|| format!("Failed to get the token of profile `{}`", name))?;
+        token_infos.insert(name, token_info);
+    }
+
+    Ok(token_infos)
+}
+
+pub async fn get_token_info(
+    args: &Arguments,
+    auth_browser: MutexGuard<'_, Browser>,
+) -> Result<TokenInfo> {
+    if let Grant::Auto = args.grant {
+        let args = auto_grant::resolve(args).await?;
+        return Box::pin(get_token_info(&args, auth_browser)).await;
+    }
+
+    if let Some(args) = fallback_arguments(args)? {
+        return Box::pin(get_token_info(&args, auth_browser)).await;
+    }
+
+    if let Some(user) = &args.impersonate {
+        return impersonate(args, user, auth_browser).await;
+    }
+
+    // Hooks run around refreshes and flows only, a cached token doesn't need a VPN
+    if args.pre_hook.is_some()
+        && (args.force
+            || args.tenant_switch
+            || args.refresh_token.is_some()
+            || args.refresh_token_stdin
+            || valid_cached_token_info(args)?.is_none())
+    {
+        hooks::run(args, HookStage::Pre, None).await?;
+    }
+
+    let started = Instant::now();
+    let mut explanation = Explanation::default();
+    let result = acquire_token_info(args, auth_browser, &mut explanation).await;
+
+    if let Ok((token_info, TokenSource::Refresh | TokenSource::Flow)) = &result {
+        hooks::run(args, HookStage::Post, Some(token_info)).await?;
+    }
+
+    if args.explain {
+        if let Err(e) = &result {
+            explanation.step(format!("Failed: {:#}", e));
+        }
+        explanation.print(&args.state_key());
+    }
+    history::record(
+        args,
+        &result
+            .as_ref()
+            .map(|(_, source)| *source)
+            .map_err(|e| anyhow!("{:#}", e)),
+        started.elapsed(),
+    );
+
+    result.map(|(token_info, _)| {
+        progress::notify(|observer| observer.on_token_received(&args.state_key(), &token_info));
+        token_info
+    })
+}
+
+/// Token of another user issued by Keycloak for the token of the admin signing in. Never cached,
+/// as it's meant for testing what the user sees
+async fn impersonate(
+    args: &Arguments,
+    user: &str,
+    auth_browser: MutexGuard<'_, Browser>,
+) -> Result<TokenInfo> {
+    let admin_args = Arguments {
+        impersonate: None,
+        ..args.to_owned()
+    };
+    let admin = Box::pin(get_token_info(&admin_args, auth_browser))
+        .await
+        .context("Failed to retrieve the token of the impersonating user")?;
+
+    output::status("Impersonating", &format!("`{}`", user));
+    let oauth_client = OAuthClient::new(args).await?;
+    let token = oauth_client
+        .exchange_token(&admin.access_token, Some(user))
+        .await
+        .with_context(|| format!("Failed to impersonate `{}`", user))?;
+
+    Ok(TokenInfo::from_token_response(token).with_requested_scope(&args.scope))
+}
+
+async fn acquire_token_info(
+    args: &Arguments,
+    auth_browser: MutexGuard<'_, Browser>,
+    explanation: &mut Explanation,
+) -> Result<(TokenInfo, TokenSource)> {
+    let _flight = single_flight::lock(&args.state_key()).await;
+    let multiplexed;
+    let args = match (&args.callback_url, &args.browser_cmd) {
+        (Some(callback_url), Some(_)) if args.multiplex_callback => {
+            multiplexed = Arguments {
+                callback_url: Some(callback_mux::with_flow_path(callback_url)?),
+                ..args.clone()
+            };
+            &multiplexed
+        }
+        _ => args,
+    };
+    let oauth_client = OAuthClient::new(args).await?;
+
+    // Takes precedence over the cache and new flows, and leaves the state file alone
+    if let Some(refresh_token) = external_refresh_token(args)? {
+        explanation.step("Redeeming the refresh_token of `--refresh-token`, the cache is skipped");
+        let token_info = RefreshTokenRetriever::new(&oauth_client, refresh_token)
+            .retrieve()
+            .await
+            .context("Failed to redeem `--refresh-token`")?
+            .with_requested_scope(&args.scope)
+            .with_clock_skew(oauth_client.clock_skew());
+        jwt::verify_token_info(&token_info, args, &oauth_client).await?;
+
+        return Ok((token_info, TokenSource::Refresh));
+    }
+
     let mut file_state = FileState::new()?;
 
-    if !args.force {
-        let mut file_retriever = FileRetriever::new(&args, &oauth_client, &mut file_state);
+    if args.force {
+        explanation.step("The cache is skipped, because `--force` is set");
+    } else if args.tenant_switch {
+        explanation.step("The cache is skipped, because `--tenant-switch` is set");
+    } else {
+        interop::import(args, &mut file_state, explanation)?;
+        let cached_access_token = file_state
+            .read_token_info(&args.state_key())
+            .map(|token_info| token_info.access_token);
+        let mut file_retriever =
+            FileRetriever::new(args, &oauth_client, &mut file_state, explanation);
 
         let file_token_info = file_retriever.retrieve().await;
 
         if let Ok(file_token_info) = file_token_info {
-            return Ok(file_token_info.access_token);
+            let source = match cached_access_token == Some(file_token_info.access_token.to_owned())
+            {
+                true => TokenSource::Cache,
+                false => TokenSource::Refresh,
+            };
+            if source == TokenSource::Refresh {
+                interop::export(args, &file_token_info);
+            }
+
+            return Ok((file_token_info, source));
         }
     }
 
+    if args.non_interactive && args.grant.uses_browser() {
+        explanation.step("A new flow needs a browser, but `--non-interactive` is set");
+        return Err(InteractionRequired.into());
+    }
+
+    if args.non_interactive && matches!(args.grant, Grant::DeviceCode) {
+        explanation
+            .step("A new flow needs the user to enter a code, but `--non-interactive` is set");
+        return Err(InteractionRequired.into());
+    }
+
+    explanation.step(format!(
+        "Starting a new flow of `{}` grant{}",
+        serde_variant::to_variant_name(&args.grant).unwrap_or_default(),
+        match &args.browser_cmd {
+            Some(_) if args.grant.uses_browser() => " in the browser of `--browser-cmd`",
+            None if args.grant.uses_browser() => " in the controlled browser",
+            _ => "",
+        }
+    ));
+
+    if args.confirm_scopes {
+        consent::confirm_scopes(args, oauth_client.metadata())?;
+    }
+
     let mut retriever: Box<dyn TokenRetriever> = match args.grant {
         Grant::AuthorizationCodeWithPkce { .. } => {
-            let auth_page = auth_browser.open_page().await?;
-            drop(auth_browser);
             Box::new(AuthorizationCodeWithPKCERetriever::new(
-                &args,
+                args,
                 &oauth_client,
-                auth_page,
-            ))
-        }
-        Grant::AuthorizationCode { .. } => {
-            let auth_page = auth_browser.open_page().await?;
-            drop(auth_browser);
-            Box::new(AuthorizationCodeRetriever::new(
-                &args,
-                &oauth_client,
-                auth_page,
+                code_source(args, auth_browser).await?,
             ))
         }
+        Grant::AuthorizationCode { .. } => Box::new(AuthorizationCodeRetriever::new(
+            args,
+            &oauth_client,
+            code_source(args, auth_browser).await?,
+        )),
         Grant::Implicit => {
-            let auth_page = auth_browser.open_page().await?;
+            let auth_page = auth_browser
+                .open_page()
+                .await?
+                .with_options(page_options(args));
             drop(auth_browser);
-            Box::new(ImplicitRetriever::new(&args, &oauth_client, auth_page))
+            Box::new(ImplicitRetriever::new(args, &oauth_client, auth_page))
         }
         Grant::ResourceOwnerPasswordClientCredentials => Box::new(
             ResourceOwnerPasswordClientCredentialsRetriever::new(&oauth_client),
         ),
         Grant::ClientCredentials => Box::new(ClientCredentialsRetriever::new(&oauth_client)),
+        Grant::OktaSessionToken => Box::new(OktaSessionTokenRetriever::new(args, &oauth_client)),
+        Grant::DeviceCode => Box::new(DeviceCodeRetriever::new(&oauth_client)),
+        Grant::JwtBearer => Box::new(JwtBearerRetriever::new(args, &oauth_client)),
+        Grant::Saml2Bearer => Box::new(Saml2BearerRetriever::new(args, &oauth_client)),
+        Grant::Auto => bail!("`auto` grant has to be resolved before retrieving a token"),
+        Grant::TokenExchange => {
+            let upstream_args = upstream_arguments(args).await?;
+
+            // The upstream flow locks the state file on its own
+            drop(file_state);
+            let upstream = Box::pin(get_token_info(&upstream_args, auth_browser))
+                .await
+                .context("Failed to retrieve an upstream token")?;
+            file_state = FileState::new()?;
+
+            Box::new(TokenExchangeRetriever::new(
+                &oauth_client,
+                upstream.access_token,
+            ))
+        }
     };
 
     let token_info = retriever
         .retrieve()
         .await
         .context("Failed to retrieve a token")?;
+    jwt::verify_token_info(&token_info, args, &oauth_client).await?;
+    let token_info = token_info
+        .with_requested_scope(&args.scope)
+        .with_session(None)
+        .with_clock_skew(oauth_client.clock_skew());
+
+    let unmet = claims::unmet(&token_info, &args.require_claim);
+    if !unmet.is_empty() {
+        output::warning(&format!(
+            "The new token doesn't carry {} either. Check the account you signed in with",
+            claims::describe(&unmet)
+        ));
+    }
+
+    let tenant = claims::tenant(&token_info);
+    if let Some((expected, tenant)) = args
+        .tenant
+        .as_ref()
+        .zip(tenant.as_ref())
+        .filter(|(expected, tenant)| expected != tenant)
+    {
+        bail!(
+            "Signed in to tenant `{}` instead of `{}`. Pick the other one with `--tenant-switch`",
+            tenant,
+            expected
+        );
+    }
 
     file_state
-        .upsert_token_info(args.client_id.to_owned(), token_info.to_owned())
+        .upsert_token_info(args.state_key(), token_info.to_owned())
         .unwrap();
+    // Tagged with the tenant, so `--tenant` gets it after switching to another one
+    if let (None, Some(tenant)) = (&args.tenant, tenant) {
+        let tenant_args = Arguments {
+            tenant: Some(tenant),
+            ..args.to_owned()
+        };
+        file_state.upsert_token_info(tenant_args.state_key(), token_info.to_owned())?;
+    }
+    interop::export(args, &token_info);
+
+    Ok((token_info, TokenSource::Flow))
+}
+
+/// Token cached in the state file for the given key (`client_id`), without refreshing it
+pub fn cached_token_info(state_key: &str) -> Result<Option<TokenInfo>> {
+    Ok(FileState::new()?.read_token_info(&state_key.to_owned()))
+}
+
+/// Refreshes the cached token right away, even if it's still valid
+pub async fn refresh_token_info(args: &Arguments) -> Result<TokenInfo> {
+    let _flight = single_flight::lock(&args.state_key()).await;
+    let mut file_state = FileState::new()?;
+    let cached = file_state
+        .read_token_info(&args.state_key())
+        .filter(|token_info| token_info.refresh_token.is_some())
+        .ok_or_else(|| anyhow!("No refresh_token cached for `{}`", args.state_key()))?;
+
+    let token_info = commands::token::refresh(args, cached).await?;
+    file_state.upsert_token_info(args.state_key(), token_info.to_owned())?;
 
-    Ok(token_info.access_token)
+    Ok(token_info)
 }
@@ -0,0 +1,348 @@
+use anyhow::{Context, Result, bail};
+use futures::StreamExt;
+use futures::stream::FuturesUnordered;
+use serde_json::Value;
+use std::cell::RefCell;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::args::Arguments;
+use crate::claims::unverified_claims;
+use crate::file_state::FileState;
+use crate::jwt::VerificationPolicy;
+use crate::loopback;
+use crate::oauth_client::OAuthClient;
+use crate::output;
+use crate::single_flight;
+use crate::systemd;
+use crate::token_info::TokenInfo;
+
+/// `events` member of back-channel logout tokens <https://openid.net/specs/openid-connect-backchannel-1_0.html#LogoutToken>
+const BACKCHANNEL_LOGOUT_EVENT: &str = "http://schemas.openid.net/event/backchannel-logout";
+
+/// Clients sending their request slower are disconnected
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// End of an IdP session announced by the provider. Without `sid` every session of `sub` ended
+#[derive(Debug, PartialEq)]
+struct Logout {
+    iss: String,
+    sid: Option<String>,
+    sub: Option<String>,
+}
+
+impl Logout {
+    /// Whether the token belongs to the ended session. `session_state` of Keycloak equals `sid`
+    fn ends(&self, token_info: &TokenInfo) -> bool {
+        let field = |name: &str| token_info.extra.get(name).and_then(|value| value.as_str());
+        let id_token = field("id_token").and_then(unverified_claims);
+        let claim = |name: &str| {
+            id_token
+                .as_ref()
+                .and_then(|claims| claims.get(name))
+                .and_then(|value| value.as_str())
+        };
+
+        if claim("iss").is_some_and(|iss| iss != self.iss) {
+            return false;
+        }
+
+        match (&self.sid, &self.sub) {
+            (Some(sid), _) => [field("sid"), field("session_state"), claim("sid")]
+                .into_iter()
+                .flatten()
+                .any(|session| session == sid),
+            (None, Some(sub)) => claim("sub") == Some(sub.as_str()),
+            (None, None) => false,
+        }
+    }
+}
+
+/// Front-channel logout request, ex. `/frontchannel-logout?iss=https://idp&sid=abc`
+/// <https://openid.net/specs/openid-connect-frontchannel-1_0.html#RPLogout>
+fn front_channel(target: &str) -> Option<Logout> {
+    let url = url::Url::parse("http://localhost")
+        .ok()?
+        .join(target)
+        .ok()?;
+    let param = |name: &str| {
+        url.query_pairs()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned())
+    };
+
+    Some(Logout {
+        iss: param("iss")?,
+        sid: Some(param("sid")?),
+        sub: None,
+    })
+}
+
+/// Claims of a logout token the profile's client may accept. The signature is verified separately
+fn logout_claims(claims: &Value, client_id: &str) -> Result<Logout> {
+    let string = |name: &str| claims.get(name).and_then(|value| value.as_str());
+    let audience = match claims.get("aud") {
+        Some(Value::String(aud)) => aud == client_id,
+        Some(Value::Array(aud)) => aud.iter().any(|aud| aud.as_str() == Some(client_id)),
+        _ => false,
+    };
+
+    if !audience {
+        bail!("The logout token isn't meant for `{}`", client_id);
+    }
+    if claims.get("nonce").is_some() {
+        bail!("Logout tokens cannot carry a nonce");
+    }
+    if claims
+        .get("events")
+        .and_then(|events| events.get(BACKCHANNEL_LOGOUT_EVENT))
+        .is_none()
+    {
+        bail!("The token has no back-channel logout event");
+    }
+
+    let logout = Logout {
+        iss: string("iss")
+            .context("The logout token has no `iss`")?
+            .to_owned(),
+        sid: string("sid").map(|sid| sid.to_owned()),
+        sub: string("sub").map(|sub| sub.to_owned()),
+    };
+    if logout.sid.is_none() && logout.sub.is_none() {
+        bail!("The logout token has neither `sid` nor `sub`");
+    }
+
+    Ok(logout)
+}
+
+/// Back-channel logout request, a form with a `logout_token` signed by the provider of one of the profiles
+/// <https://openid.net/specs/openid-connect-backchannel-1_0.html#Validation>
+async fn back_channel(body: &str, profiles: &[(String, Arguments)]) -> Result<Logout> {
+    let token = url::form_urlencoded::parse(body.as_bytes())
+        .find(|(name, _)| name == "logout_token")
+        .map(|(_, token)| token.into_owned())
+        .context("The request has no `logout_token`")?;
+    let claims = unverified_claims(&token).context("The logout token is not a JWT")?;
+
+    for (_, args) in profiles {
+        let Ok(logout) = logout_claims(&claims, &args.client_id) else {
+            continue;
+        };
+
+        let oauth_client = OAuthClient::new(args).await?;
+        if oauth_client.metadata().issuer.as_deref() != Some(logout.iss.as_str()) {
+            continue;
+        }
+
+        VerificationPolicy::from_args_or_asymmetric(args)
+            .verify(&token, args, &oauth_client)
+            .await
+            .context("Invalid logout token")?;
+
+        return Ok(logout);
+    }
+
+    bail!("The logout token isn't meant for any of the profiles")
+}
+
+/// Removes cached tokens of the ended session. Returns names of the profiles that were logged out
+async fn invalidate(logout: &Logout, profiles: &[(String, Arguments)]) -> Result<Vec<String>> {
+    let mut logged_out = vec![];
+
+    for (name, args) in profiles {
+        let _flight = single_flight::lock(&args.state_key()).await;
+        let mut file_state = FileState::new()?;
+
+        if file_state
+            .read_token_info(&args.state_key())
+            .is_some_and(|token_info| logout.ends(&token_info))
+        {
+            file_state.clear_token_info(args.state_key())?;
+            logged_out.push(name.to_owned());
+        }
+    }
+
+    Ok(logged_out)
+}
+
+fn response(status: &str, content_type: &str, body: &str) -> String {
+    format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nCache-Control: no-store\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )
+}
+
+async fn handle(mut stream: TcpStream, profiles: &[(String, Arguments)]) -> Result<()> {
+    let head = tokio::time::timeout(REQUEST_TIMEOUT, loopback::read_head(&mut stream))
+        .await
+        .context("No request was sent in time")??;
+    let (method, target) = loopback::request_line(&head).unwrap_or_default();
+    let path = target.split('?').next().unwrap_or_default();
+
+    let logout = match (method, path) {
+        ("GET", "/frontchannel-logout") => {
+            front_channel(target).context("The request has no `iss` and `sid` parameters")
+        }
+        ("POST", "/backchannel-logout") => {
+            let body =
+                tokio::time::timeout(REQUEST_TIMEOUT, loopback::read_body(&mut stream, &head))
+                    .await
+                    .context("The request body wasn't sent in time")??;
+            back_channel(&body, profiles).await
+        }
+        _ => {
+            return loopback::respond(
+                &mut stream,
+                &response("404 Not Found", "text/plain", "not found\n"),
+            )
+            .await;
+        }
+    };
+
+    let result = match logout {
+        Ok(logout) => invalidate(&logout, profiles).await,
+        Err(e) => Err(e),
+    };
+    let response = match result {
+        Ok(logged_out) => {
+            for name in logged_out {
+                output::status("Logged out", &format!("profile `{}` by the IdP", name));
+            }
+            response("200 OK", "text/html", "")
+        }
+        Err(e) => {
+            output::warning(&format!("Rejected a logout notification: {:#}", e));
+            response(
+                "400 Bad Request",
+                "application/json",
+                r#"{"error":"invalid_request"}"#,
+            )
+        }
+    };
+
+    loopback::respond(&mut stream, &response).await
+}
+
+/// Listens for logout notifications of the IdP on `listen`
+pub async fn bind(listen: SocketAddr) -> Result<TcpListener> {
+    let listener = systemd::bind(listen).await?;
+
+    output::status(
+        "Listening",
+        &format!(
+            "on http://{} for /frontchannel-logout and /backchannel-logout",
+            listener.local_addr()?
+        ),
+    );
+
+    Ok(listener)
+}
+
+/// Answers `/frontchannel-logout` and `/backchannel-logout` concurrently, removing cached tokens of the
+/// profiles whose IdP session ended. Profiles are read per request, so the daemon can change them.
+/// Runs until the process stops
+pub async fn serve(listener: TcpListener, profiles: &RefCell<Vec<(String, Arguments)>>) {
+    let mut requests = FuturesUnordered::new();
+
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let Ok((stream, peer)) = accepted else {
+                    continue;
+                };
+
+                let profiles = profiles.borrow().clone();
+                requests.push(async move {
+                    if let Err(e) = handle(stream, &profiles).await {
+                        log::debug!("Logout request from {} failed: {:?}", peer, e);
+                    }
+                });
+            }
+            Some(()) = requests.next() => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::Engine;
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    fn token_info(id_token: Value, extra: &[(&str, &str)]) -> TokenInfo {
+        let mut fields: HashMap<String, Value> = extra
+            .iter()
+            .map(|(name, value)| (name.to_string(), Value::String(value.to_string())))
+            .collect();
+        fields.insert(
+            "id_token".to_owned(),
+            Value::String(format!(
+                "e30.{}.signature",
+                URL_SAFE_NO_PAD.encode(id_token.to_string())
+            )),
+        );
+
+        TokenInfo {
+            access_token: "token".to_owned(),
+            refresh_token: None,
+            expires: None,
+            scope: None,
+            requested_scope: None,
+            extra: fields,
+        }
+    }
+
+    #[test]
+    fn it_matches_logouts_with_cached_sessions() {
+        let logout = front_channel("/frontchannel-logout?iss=https%3A%2F%2Fidp&sid=abc").unwrap();
+        assert_eq!(
+            logout,
+            Logout {
+                iss: "https://idp".to_owned(),
+                sid: Some("abc".to_owned()),
+                sub: None,
+            }
+        );
+
+        assert!(logout.ends(&token_info(
+            json!({ "iss": "https://idp", "sid": "abc" }),
+            &[]
+        )));
+        assert!(logout.ends(&token_info(json!({}), &[("session_state", "abc")])));
+        assert!(!logout.ends(&token_info(
+            json!({ "iss": "https://other", "sid": "abc" }),
+            &[]
+        )));
+        assert!(!logout.ends(&token_info(json!({ "sid": "xyz" }), &[])));
+    }
+
+    #[test]
+    fn it_validates_claims_of_logout_tokens() {
+        let claims = json!({
+            "iss": "https://idp",
+            "aud": ["my-client"],
+            "sub": "user",
+            "events": { BACKCHANNEL_LOGOUT_EVENT: {} },
+        });
+
+        assert_eq!(
+            logout_claims(&claims, "my-client").unwrap().sub.as_deref(),
+            Some("user")
+        );
+        assert!(logout_claims(&claims, "other-client").is_err());
+
+        let mut with_nonce = claims.clone();
+        with_nonce["nonce"] = json!("n");
+        assert!(logout_claims(&with_nonce, "my-client").is_err());
+
+        let mut without_event = claims;
+        without_event["events"] = json!({});
+        assert!(logout_claims(&without_event, "my-client").is_err());
+    }
+}
@@ -0,0 +1,235 @@
+use anyhow::Result;
+use futures::future::select_all;
+use std::io::{self, ErrorKind};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+#[cfg(unix)]
+use std::os::unix::fs::DirBuilderExt;
+#[cfg(unix)]
+use std::path::PathBuf;
+#[cfg(unix)]
+use tokio::net::UnixListener;
+
+/// Request heads bigger than that are answered without reading the rest
+const MAX_HEAD_SIZE: usize = 16 * 1024;
+
+/// Stream a request arrives on, a TCP connection or one of a unix domain socket
+pub trait Connection: AsyncRead + AsyncWrite + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> Connection for T {}
+
+/// Reads the request head only. Requests doken serves locally carry no meaningful body
+pub async fn read_head<S: AsyncRead + Unpin>(stream: &mut S) -> Result<String> {
+    let mut head = vec![];
+    let mut buffer = [0; 1024];
+
+    while !head.windows(4).any(|window| window == b"\r\n\r\n") && head.len() < MAX_HEAD_SIZE {
+        let read = stream.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        head.extend_from_slice(&buffer[..read]);
+    }
+
+    Ok(String::from_utf8_lossy(&head).into_owned())
+}
+
+/// Body of the request of `head`, as long as its `Content-Length` is, but no longer than `MAX_HEAD_SIZE`.
+/// `head` may already hold its beginning
+pub async fn read_body<S: AsyncRead + Unpin>(stream: &mut S, head: &str) -> Result<String> {
+    let length = header(head, "content-length")
+        .and_then(|value| value.parse::<usize>().ok())
+        .unwrap_or_default()
+        .min(MAX_HEAD_SIZE);
+    let mut body = head
+        .split_once("\r\n\r\n")
+        .map(|(_, body)| body.as_bytes().to_vec())
+        .unwrap_or_default();
+    let mut buffer = [0; 1024];
+
+    while body.len() < length {
+        let read = stream.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        body.extend_from_slice(&buffer[..read]);
+    }
+    body.truncate(length);
+
+    Ok(String::from_utf8_lossy(&body).into_owned())
+}
+
+/// Value of the first header of the request `head` named `name`, ignoring case
+pub fn header<'a>(head: &'a str, name: &str) -> Option<&'a str> {
+    head.lines()
+        .skip(1)
+        .take_while(|line| !line.is_empty())
+        .filter_map(|line| line.split_once(':'))
+        .find(|(header, _)| header.trim().eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.trim())
+}
+
+/// Method and target of the request line, ex. `GET /callback?code=1`
+pub fn request_line(head: &str) -> Option<(&str, &str)> {
+    let mut parts = head.lines().next()?.split_whitespace();
+
+    Some((parts.next()?, parts.next()?))
+}
+
+pub async fn respond<S: AsyncWrite + Unpin>(stream: &mut S, response: &str) -> Result<()> {
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+
+    Ok(())
+}
+
+/// Listeners of every address a callback may arrive at
+pub enum Listener {
+    Tcp(Vec<TcpListener>),
+    /// `--callback-socket`, nothing listens on a port
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl Listener {
+    pub async fn accept(&self) -> io::Result<Box<dyn Connection>> {
+        match self {
+            Listener::Tcp(listeners) => {
+                let (stream, _) =
+                    select_all(listeners.iter().map(|listener| Box::pin(listener.accept())))
+                        .await
+                        .0?;
+                Ok(Box::new(stream))
+            }
+            #[cfg(unix)]
+            Listener::Unix(listener) => {
+                let (stream, _) = listener.accept().await?;
+                Ok(Box::new(stream))
+            }
+        }
+    }
+}
+
+/// Unix domain socket in a directory only the current user can enter. Both are removed when dropped
+#[cfg(unix)]
+pub struct EphemeralSocket {
+    dir: PathBuf,
+}
+
+#[cfg(unix)]
+impl EphemeralSocket {
+    pub fn create() -> io::Result<EphemeralSocket> {
+        let dir = std::env::temp_dir().join(format!("doken-callback-{}", std::process::id()));
+        // Left behind by a crashed process with the same id
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::DirBuilder::new().mode(0o700).create(&dir)?;
+
+        Ok(EphemeralSocket { dir })
+    }
+
+    pub fn path(&self) -> PathBuf {
+        self.dir.join("callback.sock")
+    }
+
+    pub fn bind(&self) -> io::Result<Listener> {
+        UnixListener::bind(self.path()).map(Listener::Unix)
+    }
+}
+
+#[cfg(unix)]
+impl Drop for EphemeralSocket {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Request `doken callback-relay` sends for a callback to `url`, as a browser would send it to a loopback server
+#[cfg(unix)]
+pub fn relayed_request(url: &url::Url) -> String {
+    format!(
+        "GET {} HTTP/1.1\r\nHost: doken\r\nConnection: close\r\n\r\n",
+        &url[url::Position::BeforePath..]
+    )
+}
+
+/// Addresses to listen on for callbacks to `host`. `localhost` resolves to either 127.0.0.1 or ::1
+/// depending on the system, and browsers don't always pick the same one, so loopback hosts get both
+fn addresses(host: &str, bind_address: &[IpAddr]) -> Option<Vec<IpAddr>> {
+    if !bind_address.is_empty() {
+        return Some(bind_address.to_vec());
+    }
+
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let loopback = host.eq_ignore_ascii_case("localhost")
+        || host.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback());
+
+    loopback.then(|| {
+        vec![
+            IpAddr::V4(Ipv4Addr::LOCALHOST),
+            IpAddr::V6(Ipv6Addr::LOCALHOST),
+        ]
+    })
+}
+
+/// Listens on `--bind-address`, both loopback addresses for loopback hosts or what `host` resolves
+/// to otherwise. Addresses the system doesn't have, ex. ::1 with IPv6 disabled, are skipped, but a
+/// port taken on any of them fails the bind, so the callback cannot land on another process
+pub async fn bind(host: &str, port: u16, bind_address: &[IpAddr]) -> io::Result<Listener> {
+    let Some(addresses) = addresses(host, bind_address) else {
+        return Ok(Listener::Tcp(vec![TcpListener::bind((host, port)).await?]));
+    };
+
+    let mut listeners = vec![];
+    let mut error = None;
+    for address in addresses {
+        match TcpListener::bind((address, port)).await {
+            Ok(listener) => listeners.push(listener),
+            Err(e) if e.kind() == ErrorKind::AddrInUse => return Err(e),
+            Err(e) => {
+                log::debug!("Cannot listen on {}: {}. Skipping...", address, e);
+                error.get_or_insert(e);
+            }
+        }
+    }
+
+    match (listeners.is_empty(), error) {
+        (true, Some(e)) => Err(e),
+        _ => Ok(Listener::Tcp(listeners)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_listens_on_both_loopback_addresses() {
+        let both = Some(vec![
+            IpAddr::V4(Ipv4Addr::LOCALHOST),
+            IpAddr::V6(Ipv6Addr::LOCALHOST),
+        ]);
+
+        assert_eq!(addresses("localhost", &[]), both);
+        assert_eq!(addresses("127.0.0.1", &[]), both);
+        assert_eq!(addresses("[::1]", &[]), both);
+        assert_eq!(addresses("my-app.test", &[]), None);
+        assert_eq!(
+            addresses("localhost", &[IpAddr::V4(Ipv4Addr::LOCALHOST)]),
+            Some(vec![IpAddr::V4(Ipv4Addr::LOCALHOST)])
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn it_relays_callbacks_as_loopback_requests() {
+        let url = url::Url::parse("com.corp.app:/callback?code=1&state=xyz").unwrap();
+        let request = relayed_request(&url);
+
+        assert_eq!(
+            request_line(&request),
+            Some(("GET", "/callback?code=1&state=xyz"))
+        );
+    }
+}
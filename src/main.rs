@@ -1,11 +1,22 @@
 #![deny(warnings)]
 
 use anyhow::Result;
+use doken::InteractionRequired;
 use doken::args::Args;
 use doken::auth_browser::browser::Browser;
+use doken::commands;
+use doken::commands::self_update::notify_if_outdated;
+use doken::get_profile_token_infos;
 use doken::get_token;
+use doken::logging;
+use doken::output;
+use doken::output_schema;
+use doken::progress::{self, JsonEventLog};
+use doken::prompt::InputRequired;
+use doken::telemetry;
 use std::env;
 use std::process::exit;
+use std::sync::Arc;
 use tokio::sync::Mutex;
 
 fn enable_debug_via_args() {
@@ -16,16 +27,81 @@ fn enable_debug_via_args() {
     }
 }
 
+async fn run() -> Result<()> {
+    // Printed before parsing, so it doesn't require `--client-id` and other arguments
+    if env::args().any(|arg| arg == "--output-schema") {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&output_schema::schema())?
+        );
+        return Ok(());
+    }
+
+    if let Some(command) = Args::parse_command().await {
+        return commands::run(command).await;
+    }
+
+    let update_check = tokio::spawn(notify_if_outdated());
+
+    let profiles = Args::profiles();
+    if profiles.len() > 1 {
+        let profile_args = Args::parse_profiles(&profiles).await;
+        // The browser and stdout are shared, so their flags are taken from the first profile
+        let args = &profile_args[0];
+
+        if args.progress_events {
+            progress::register(Arc::new(JsonEventLog));
+        }
+
+        let auth_browser = Mutex::new(
+            Browser::new(args.headless()).with_ephemeral_profile(args.ephemeral_browser_profile),
+        );
+        let result = get_profile_token_infos(&profile_args, &auth_browser)
+            .await
+            .and_then(|token_infos| Ok(serde_json::to_string(&token_infos)?))
+            .and_then(|output| output::limit(output, args.max_stdout_bytes));
+        println!("{}", result?);
+        let _ = update_check.await;
+
+        return Ok(());
+    }
+
+    let args = Args::parse().await;
+
+    if args.progress_events {
+        progress::register(Arc::new(JsonEventLog));
+    }
+
+    let grant = args.grant.clone();
+    let max_stdout_bytes = args.max_stdout_bytes;
+    let result = {
+        let auth_browser = Mutex::new(
+            Browser::new(args.headless()).with_ephemeral_profile(args.ephemeral_browser_profile),
+        );
+        get_token(args, auth_browser.lock().await).await
+    };
+    let result = result.and_then(|output| output::limit(output, max_stdout_bytes));
+    telemetry::report(&grant, result.as_ref().err()).await;
+    println!("{}", result?);
+    let _ = update_check.await;
+
+    Ok(())
+}
+
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() {
     enable_debug_via_args();
-    env_logger::init();
+    logging::init();
 
-    let args = Args::parse().await;
+    if let Err(e) = run().await {
+        output::error(&e);
 
-    {
-        let auth_browser = Mutex::new(Browser::new(false));
-        println!("{}", get_token(args, auth_browser.lock().await).await?);
+        if e.is::<InteractionRequired>() || e.is::<InputRequired>() {
+            exit(InteractionRequired::EXIT_CODE);
+        }
+
+        exit(1);
     }
+
     exit(0);
 }
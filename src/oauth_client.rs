@@ -1,19 +1,61 @@
 use crate::args::Arguments;
-use crate::openidc_discovery::get_endpoints_from_discovery_url;
-use anyhow::{Context, Result};
+use crate::auth_browser::AuthorizationResponse;
+use crate::client_assertion::{self, CLIENT_ASSERTION_TYPE};
+use crate::client_auth_method::ClientAuthMethod;
+use crate::clock_skew;
+use crate::grant::Grant;
+use crate::http_client;
+use crate::jwt_bearer::{self, AssertionKey, JWT_BEARER_GRANT_TYPE};
+use crate::openidc_discovery::{
+    ProviderMetadata, get_provider_metadata, verify_authorization_issuer, verify_discovered_issuer,
+};
+use crate::providers::Provider;
+use crate::providers::adfs;
+use crate::providers::errors::ProviderError;
+use crate::saml_bearer::SAML2_BEARER_GRANT_TYPE;
+use crate::security_params::SecurityParams;
+use crate::signer::Signer;
+use crate::throttling;
+use crate::token_info::TokenResponse;
+use crate::totp;
+use anyhow::{Context, Result, anyhow};
 use oauth2::basic::{
     BasicErrorResponse, BasicRevocationErrorResponse, BasicTokenIntrospectionResponse,
-    BasicTokenResponse,
 };
+use oauth2::http::{HeaderMap, StatusCode};
 use oauth2::{
-    AuthUrl, AuthorizationCode, AuthorizationRequest, Client, ClientId, ClientSecret, CsrfToken,
-    EndpointNotSet, EndpointSet, PkceCodeChallenge, PkceCodeVerifier, RedirectUrl, RefreshToken,
-    ResourceOwnerPassword, ResourceOwnerUsername, Scope, StandardRevocableToken, TokenUrl,
+    AsyncHttpClient, AuthType, AuthUrl, AuthorizationCode, AuthorizationRequest, Client, ClientId,
+    ClientSecret, CsrfToken, EndpointNotSet, EndpointSet, HttpClientError, HttpRequest,
+    HttpResponse, PkceCodeChallenge, PkceCodeVerifier, RedirectUrl, RefreshToken,
+    RequestTokenError, ResourceOwnerPassword, ResourceOwnerUsername, Scope, StandardRevocableToken,
+    TokenUrl,
 };
-use rand::distr::{Alphanumeric, SampleString};
 use reqwest::redirect::Policy;
+use serde::Deserialize;
+use std::cell::RefCell;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::time::SystemTime;
 use url::Url;
 
+const TOKEN_EXCHANGE_GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:token-exchange";
+const ACCESS_TOKEN_TYPE: &str = "urn:ietf:params:oauth:token-type:access_token";
+const DEVICE_CODE_GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:device_code";
+
+/// Response of the device authorization endpoint <https://www.rfc-editor.org/rfc/rfc8628#section-3.2>
+#[derive(Deserialize, Debug)]
+pub struct DeviceAuthorization {
+    pub device_code: String,
+    pub user_code: String,
+    /// Google names it `verification_url`
+    #[serde(alias = "verification_url")]
+    pub verification_uri: String,
+    pub verification_uri_complete: Option<String>,
+    pub expires_in: u64,
+    pub interval: Option<u64>,
+}
+
 type BaseClient<
     HasAuthUrl = EndpointSet,
     HasDeviceAuthUrl = EndpointNotSet,
@@ -22,7 +64,7 @@ type BaseClient<
     HasTokenUrl = EndpointSet,
 > = Client<
     BasicErrorResponse,
-    BasicTokenResponse,
+    TokenResponse,
     BasicTokenIntrospectionResponse,
     StandardRevocableToken,
     BasicRevocationErrorResponse,
@@ -32,16 +74,52 @@ type BaseClient<
     HasRevocationUrl,
     HasTokenUrl,
 >;
+
+/// Status, headers and the local time of the last response of the token endpoint
+type LastResponse = Mutex<Option<(StatusCode, HeaderMap, SystemTime)>>;
+
+/// HTTP client of the oauth2 crate keeping the last response. It tells whether the provider
+/// throttles refreshes and how far off the local clock is
+struct ObservingClient<'a> {
+    http: &'a reqwest::Client,
+    last_response: &'a LastResponse,
+}
+
+impl<'c> AsyncHttpClient<'c> for ObservingClient<'_> {
+    type Error = HttpClientError<reqwest::Error>;
+    type Future =
+        Pin<Box<dyn Future<Output = Result<HttpResponse, Self::Error>> + Send + Sync + 'c>>;
+
+    fn call(&'c self, request: HttpRequest) -> Self::Future {
+        Box::pin(async move {
+            let response = self.http.call(request).await?;
+            self.last_response.lock().unwrap().replace((
+                response.status(),
+                response.headers().to_owned(),
+                SystemTime::now(),
+            ));
+
+            Ok(response)
+        })
+    }
+}
+
 pub struct OAuthClient<'a> {
     args: &'a Arguments,
     inner: BaseClient,
     http: reqwest::Client,
+    auth_method: ClientAuthMethod,
+    metadata: ProviderMetadata,
+    security_params: RefCell<SecurityParams>,
+    last_response: LastResponse,
+    signer: Option<Box<dyn Signer>>,
 }
 impl OAuthClient<'_> {
     fn get_client(
         args: &Arguments,
         token_url: Option<&str>,
         authorization_url: &str,
+        auth_method: &ClientAuthMethod,
     ) -> Result<BaseClient> {
         let token = match token_url {
             Some(url) => Some(TokenUrl::new(url.to_owned()).with_context(|| {
@@ -60,13 +138,20 @@ impl OAuthClient<'_> {
                     authorization_url
                 )
             })?)
-            .set_token_uri(token.unwrap());
+            .set_token_uri(token.context("`--token-url` or `--discovery-url` is required")?);
 
         let client_secret = args.client_secret.to_owned().map(ClientSecret::new);
 
-        if client_secret.is_some() {
-            client = client.set_client_secret(client_secret.unwrap());
-        }
+        // Without a secret the oauth2 crate sends client_id in the request body
+        client = match (auth_method, client_secret) {
+            (ClientAuthMethod::Basic, Some(secret)) => client
+                .set_client_secret(secret)
+                .set_auth_type(AuthType::BasicAuth),
+            (ClientAuthMethod::Post, Some(secret)) => client
+                .set_client_secret(secret)
+                .set_auth_type(AuthType::RequestBody),
+            _ => client.set_auth_type(AuthType::RequestBody),
+        };
 
         if let Some(callback_url) = &args.callback_url {
             client = client.set_redirect_uri(RedirectUrl::new(callback_url.to_owned()).unwrap())
@@ -78,54 +163,235 @@ impl OAuthClient<'_> {
     pub async fn new(args: &Arguments) -> Result<OAuthClient> {
         log::debug!("Creating OAuthClient...");
 
-        let (token_url, authorization_url) =
+        let (token_url, authorization_url, metadata) =
             if let Some(discovery_url) = args.discovery_url.to_owned() {
                 log::debug!(
                     "Using `--discovery-url`={} to get token_url and authorization_url ",
                     discovery_url
                 );
 
-                let (token_url, authorization_url) =
-                    get_endpoints_from_discovery_url(discovery_url).await?;
-
-                (Some(token_url), authorization_url)
+                let discovered = get_provider_metadata(
+                    &http_client::builder(args)?.build()?,
+                    discovery_url.to_owned(),
+                    &args.discovery_map,
+                )
+                .await;
+
+                match (&args.provider, discovered) {
+                    (Provider::Adfs, discovered) => {
+                        let (default_token_url, default_authorization_url) =
+                            adfs::default_endpoints(&discovery_url)?;
+                        let metadata = discovered.unwrap_or_else(|e| {
+                            log::debug!(
+                                "ADFS discovery failed, falling back to default endpoints: {:?}",
+                                e
+                            );
+                            ProviderMetadata::default()
+                        });
+
+                        (
+                            Some(
+                                metadata
+                                    .token_endpoint
+                                    .to_owned()
+                                    .unwrap_or(default_token_url),
+                            ),
+                            metadata
+                                .authorization_endpoint
+                                .to_owned()
+                                .unwrap_or(default_authorization_url),
+                            metadata,
+                        )
+                    }
+                    (Provider::Generic | Provider::Azure, discovered) => {
+                        let metadata = discovered?;
+                        // Entra ID documents of multi-tenant and v1 endpoints name other issuers
+                        if args.provider == Provider::Generic {
+                            verify_discovered_issuer(&discovery_url, &metadata)?;
+                        }
+
+                        (
+                            Some(metadata.token_endpoint.to_owned().context(
+                                "`--discovery-url` document is missing `token_endpoint`",
+                            )?),
+                            metadata.authorization_endpoint.to_owned().context(
+                                "`--discovery-url` document is missing `authorization_endpoint`",
+                            )?,
+                            metadata,
+                        )
+                    }
+                }
             } else {
                 (
                     args.token_url.to_owned(),
-                    args.authorization_url.to_owned().unwrap(),
+                    args.authorization_url
+                        .to_owned()
+                        // Grants without a browser never visit it, but the oauth2 crate needs one
+                        .or_else(|| match args.grant {
+                            Grant::DeviceCode => args.device_authorization_url.to_owned(),
+                            Grant::JwtBearer | Grant::Saml2Bearer => args.token_url.to_owned(),
+                            _ => None,
+                        })
+                        .context("`--authorization-url` or `--discovery-url` is required")?,
+                    ProviderMetadata::default(),
                 )
             };
 
+        let auth_method = match (&args.client_auth_method, &args.client_assertion_key) {
+            (Some(auth_method), _) => auth_method.to_owned(),
+            (None, Some(_)) => ClientAuthMethod::PrivateKeyJwt,
+            (None, None) => ClientAuthMethod::negotiate(
+                &metadata.token_endpoint_auth_methods_supported,
+                args.client_secret.is_some(),
+            ),
+        };
+        log::debug!("Using client authentication method {}", auth_method.name());
+
         log::debug!(
             "Resolved token_url={:?} and authorization_url={}",
             token_url,
             authorization_url
         );
 
-        let client = Self::get_client(args, token_url.as_deref(), &authorization_url)
+        let client = Self::get_client(args, token_url.as_deref(), &authorization_url, &auth_method)
             .context("Failed to create a OAuthClient")?;
 
+        let signer = match auth_method {
+            ClientAuthMethod::PrivateKeyJwt => Some(client_assertion::signer(args)?),
+            _ => None,
+        };
+
         log::debug!("OAuthClient created");
 
-        let http_client = reqwest::Client::builder()
+        let http_client = http_client::builder(args)?
             .redirect(Policy::none())
+            .default_headers(http_client::extra_headers(args)?)
             .build()?;
 
         Ok(OAuthClient {
             args,
             inner: client,
             http: http_client,
+            auth_method,
+            metadata,
+            security_params: Default::default(),
+            last_response: Default::default(),
+            signer,
         })
     }
 
+    /// Generates `state`, `nonce`, PKCE and `jti` with `security_params`, ex. seeded ones in tests
+    pub fn with_security_params(self, security_params: SecurityParams) -> Self {
+        OAuthClient {
+            security_params: RefCell::new(security_params),
+            ..self
+        }
+    }
+
+    pub fn http(&self) -> &reqwest::Client {
+        &self.http
+    }
+
+    fn observing_client(&self) -> ObservingClient<'_> {
+        ObservingClient {
+            http: &self.http,
+            last_response: &self.last_response,
+        }
+    }
+
+    /// Skew of the local clock by the last response of the token endpoint, warned about once it's
+    /// over `--max-clock-skew`. Only `--clock-skew adjust` returns it
+    pub fn clock_skew(&self) -> Option<i64> {
+        let skew = self
+            .last_response
+            .lock()
+            .unwrap()
+            .as_ref()
+            .and_then(|(_, headers, received)| clock_skew::measure(headers, *received))?;
+
+        clock_skew::check(self.args, skew)
+    }
+
+    /// Fresh `client_assertion` of `private_key_jwt`, `None` with other client authentication methods
+    fn client_assertion(&self) -> Result<Option<String>> {
+        let Some(signer) = &self.signer else {
+            return Ok(None);
+        };
+        let jti = self.security_params.borrow_mut().jti();
+
+        client_assertion::client_assertion(
+            signer.as_ref(),
+            &self.args.client_id,
+            self.inner.token_uri().as_str(),
+            jti,
+        )
+        .map(Some)
+    }
+
+    pub fn pkce(&self) -> (PkceCodeChallenge, PkceCodeVerifier) {
+        self.security_params.borrow_mut().pkce()
+    }
+
+    /// Discovery document of the provider. Empty if `--discovery-url` isn't used
+    pub fn metadata(&self) -> &ProviderMetadata {
+        &self.metadata
+    }
+
+    /// Makes sure the code was issued by the provider whose token endpoint it's exchanged at
+    pub fn verify_authorization_response(&self, response: &AuthorizationResponse) -> Result<()> {
+        verify_authorization_issuer(&self.metadata, response.iss.as_deref())
+    }
+
+    fn token_error<RE>(&self, error: RequestTokenError<RE, BasicErrorResponse>) -> anyhow::Error
+    where
+        RE: std::error::Error + Send + Sync + 'static,
+    {
+        let provider_error = match (&self.args.provider, &error) {
+            (_, RequestTokenError::ServerResponse(response)) => ProviderError::from(response),
+            (Provider::Adfs, RequestTokenError::Parse(_, body)) => {
+                let description = adfs::describe_error(body);
+                return anyhow::Error::new(error).context(description);
+            }
+            (_, RequestTokenError::Parse(_, body)) => match ProviderError::from_body(body) {
+                Some(provider_error) => provider_error,
+                None => return anyhow::Error::new(error),
+            },
+            _ => return anyhow::Error::new(error),
+        };
+
+        anyhow::Error::new(self.with_hint(provider_error))
+    }
+
+    fn with_hint(&self, error: ProviderError) -> ProviderError {
+        if error.code() == "invalid_client" {
+            let hint = format!(
+                "`{}` client authentication has been used. Try another `--client-auth-method`",
+                self.auth_method.name()
+            );
+            error.with_hint(hint)
+        } else {
+            error
+        }
+    }
+
     fn authorization_url_builder(&self) -> AuthorizationRequest {
         let mut builder = self
             .inner
-            .authorize_url(CsrfToken::new_random)
+            .authorize_url(|| self.security_params.borrow_mut().csrf_token())
             .add_scope(Scope::new(self.args.scope.to_string()));
 
         if let Some(ref aud) = self.args.audience {
-            builder = builder.add_extra_param("audience", aud);
+            builder = builder.add_extra_param(self.args.provider.audience_param(), aud);
+        }
+
+        for resource in &self.args.resource {
+            builder = builder.add_extra_param("resource", resource);
+        }
+
+        if self.args.tenant_switch {
+            for (name, value) in self.args.provider.tenant_switch_params() {
+                builder = builder.add_extra_param(*name, *value);
+            }
         }
 
         builder
@@ -135,7 +401,7 @@ impl OAuthClient<'_> {
         &self,
         pkce_challenge: Option<PkceCodeChallenge>,
     ) -> (Url, CsrfToken, String) {
-        let nonce = Alphanumeric.sample_string(&mut rand::rng(), 16);
+        let nonce = self.security_params.borrow_mut().nonce();
         let mut builder = self.authorization_url_builder();
 
         builder = builder.add_extra_param("nonce", nonce.to_owned());
@@ -156,7 +422,7 @@ impl OAuthClient<'_> {
             .url()
     }
 
-    pub async fn exchange_client_credentials(&self) -> Result<BasicTokenResponse> {
+    pub async fn exchange_client_credentials(&self) -> Result<TokenResponse> {
         log::debug!("Exchanging credentials for a token...");
 
         // NOTE: offline_mode doesn't make any sense for Client Credentials.
@@ -166,12 +432,23 @@ impl OAuthClient<'_> {
         let mut builder = self.inner.exchange_client_credentials().add_scope(scope);
 
         if let Some(aud) = &self.args.audience {
-            builder = builder.add_extra_param("audience", aud);
+            builder = builder.add_extra_param(self.args.provider.audience_param(), aud);
+        }
+
+        for resource in &self.args.resource {
+            builder = builder.add_extra_param("resource", resource);
+        }
+
+        if let Some(assertion) = self.client_assertion()? {
+            builder = builder
+                .add_extra_param("client_assertion_type", CLIENT_ASSERTION_TYPE)
+                .add_extra_param("client_assertion", assertion);
         }
 
         let token = builder
-            .request_async(&self.http)
+            .request_async(&self.observing_client())
             .await
+            .map_err(|e| self.token_error(e))
             .context("Failed to exchange of client credentials for a token")?;
         log::debug!("Exchange done");
         Ok(token)
@@ -179,25 +456,46 @@ impl OAuthClient<'_> {
 
     pub async fn exchange_resource_owner_password_client_credentials(
         &self,
-    ) -> Result<BasicTokenResponse> {
+    ) -> Result<TokenResponse> {
         log::debug!("Exchanging credentials for a token...");
 
+        let otp = self.args.otp_secret.as_deref().map(totp::now).transpose()?;
+        let password = self.args.password.as_deref().unwrap();
+        let password = match (&otp, &self.args.otp_param) {
+            (Some(otp), None) => format!("{}{}", password, otp),
+            _ => password.to_owned(),
+        };
+
         let username =
             &ResourceOwnerUsername::new(self.args.username.as_deref().unwrap().to_owned());
-        let password =
-            &ResourceOwnerPassword::new(self.args.password.as_deref().unwrap().to_owned());
+        let password = &ResourceOwnerPassword::new(password);
         let mut builder = self
             .inner
             .exchange_password(username, password)
             .add_scope(Scope::new(self.args.scope.to_string()));
 
+        if let (Some(otp), Some(otp_param)) = (&otp, &self.args.otp_param) {
+            builder = builder.add_extra_param(otp_param, otp);
+        }
+
         if let Some(aud) = &self.args.audience {
-            builder = builder.add_extra_param("audience", aud);
+            builder = builder.add_extra_param(self.args.provider.audience_param(), aud);
+        }
+
+        for resource in &self.args.resource {
+            builder = builder.add_extra_param("resource", resource);
+        }
+
+        if let Some(assertion) = self.client_assertion()? {
+            builder = builder
+                .add_extra_param("client_assertion_type", CLIENT_ASSERTION_TYPE)
+                .add_extra_param("client_assertion", assertion);
         }
 
         let token = builder
-            .request_async(&self.http)
+            .request_async(&self.observing_client())
             .await
+            .map_err(|e| self.token_error(e))
             .context("Failed to exchange client credentials for a token")?;
         log::debug!("Exchange done");
         Ok(token)
@@ -207,7 +505,7 @@ impl OAuthClient<'_> {
         &self,
         code: &str,
         code_verifier: Option<PkceCodeVerifier>,
-    ) -> Result<BasicTokenResponse> {
+    ) -> Result<TokenResponse> {
         log::debug!("Exchanging code for a token...");
         let mut builder = self
             .inner
@@ -217,28 +515,284 @@ impl OAuthClient<'_> {
             builder = builder.set_pkce_verifier(verifier);
         }
 
-        let token: BasicTokenResponse = builder
-            .request_async(&self.http)
+        for resource in &self.args.resource {
+            builder = builder.add_extra_param("resource", resource);
+        }
+
+        if let Some(assertion) = self.client_assertion()? {
+            builder = builder
+                .add_extra_param("client_assertion_type", CLIENT_ASSERTION_TYPE)
+                .add_extra_param("client_assertion", assertion);
+        }
+
+        let token: TokenResponse = builder
+            .request_async(&self.observing_client())
             .await
+            .map_err(|e| self.token_error(e))
             .context("Failed to exchange code for a token")?;
         log::debug!("Exchange done");
 
         Ok(token)
     }
 
-    pub async fn refresh_token(&self, refresh_token: String) -> Result<BasicTokenResponse> {
+    /// RFC 8693 exchange. With `requested_subject` Keycloak issues the token as another user (impersonation)
+    pub async fn exchange_token(
+        &self,
+        subject_token: &str,
+        requested_subject: Option<&str>,
+    ) -> Result<TokenResponse> {
+        log::debug!("Exchanging upstream token for a token...");
+
+        let mut params = vec![
+            ("grant_type", TOKEN_EXCHANGE_GRANT_TYPE),
+            ("subject_token", subject_token),
+            ("subject_token_type", ACCESS_TOKEN_TYPE),
+            ("scope", &self.args.scope),
+        ];
+
+        if let Some(requested_subject) = requested_subject {
+            params.push(("requested_subject", requested_subject));
+        }
+
+        if let Some(aud) = &self.args.audience {
+            params.push((self.args.provider.audience_param(), aud));
+        }
+
+        for resource in &self.args.resource {
+            params.push(("resource", resource));
+        }
+
+        let body = self
+            .post_form(self.inner.token_uri().as_str(), params)
+            .await
+            .context("Failed to exchange upstream token for a token")?;
+        let token = serde_json::from_slice::<TokenResponse>(&body)
+            .context("Failed to parse token exchange response")?;
+        log::debug!("Exchange done");
+
+        Ok(token)
+    }
+
+    /// Exchanges an assertion signed with `key` for a token <https://www.rfc-editor.org/rfc/rfc7523#section-2.1>
+    pub async fn exchange_jwt_bearer(&self, key: &AssertionKey) -> Result<TokenResponse> {
+        let jti = self.security_params.borrow_mut().jti();
+        let assertion =
+            jwt_bearer::assertion(key, self.args, self.inner.token_uri().as_str(), jti)?;
+
+        self.exchange_assertion(JWT_BEARER_GRANT_TYPE, &assertion)
+            .await
+    }
+
+    /// Exchanges a base64url encoded SAML assertion for a token <https://www.rfc-editor.org/rfc/rfc7522#section-2.1>
+    pub async fn exchange_saml2_bearer(&self, assertion: &str) -> Result<TokenResponse> {
+        self.exchange_assertion(SAML2_BEARER_GRANT_TYPE, assertion)
+            .await
+    }
+
+    /// Assertion framework request shared by the JWT and SAML bearer grants <https://www.rfc-editor.org/rfc/rfc7521#section-4.1>
+    async fn exchange_assertion(&self, grant_type: &str, assertion: &str) -> Result<TokenResponse> {
+        log::debug!("Exchanging an assertion for a token...");
+
+        // Like in Client Credentials, there's no user to issue a refresh_token for
+        let scope = self.args.scope.replace("offline_access", "");
+
+        let mut params = vec![("grant_type", grant_type), ("assertion", assertion)];
+        if !scope.trim().is_empty() {
+            params.push(("scope", scope.trim()));
+        }
+        if let Some(aud) = &self.args.audience {
+            params.push((self.args.provider.audience_param(), aud));
+        }
+        for resource in &self.args.resource {
+            params.push(("resource", resource));
+        }
+
+        let body = self
+            .post_form(self.inner.token_uri().as_str(), params)
+            .await
+            .context("Failed to exchange the assertion for a token")?;
+        let token = serde_json::from_slice::<TokenResponse>(&body)
+            .context("Failed to parse assertion token response")?;
+        log::debug!("Exchange done");
+
+        Ok(token)
+    }
+
+    /// Starts a device flow <https://www.rfc-editor.org/rfc/rfc8628#section-3.1>
+    pub async fn device_authorization(&self) -> Result<DeviceAuthorization> {
+        log::debug!("Requesting a device code...");
+
+        let endpoint = self
+            .metadata
+            .device_authorization_endpoint
+            .as_deref()
+            .or(self.args.device_authorization_url.as_deref())
+            .context("The provider has no `device_authorization_endpoint`. Pass `--device-authorization-url`")?;
+
+        let mut params = vec![("scope", self.args.scope.as_str())];
+        if let Some(aud) = &self.args.audience {
+            params.push((self.args.provider.audience_param(), aud));
+        }
+        for resource in &self.args.resource {
+            params.push(("resource", resource));
+        }
+
+        let body = self
+            .post_form(endpoint, params)
+            .await
+            .context("Failed to request a device code")?;
+
+        serde_json::from_slice(&body).context("Failed to parse device authorization response")
+    }
+
+    /// Polls for the token of the device flow. Pending authorizations fail with
+    /// `authorization_pending` or `slow_down` [`ProviderError`]s
+    pub async fn exchange_device_code(&self, device_code: &str) -> Result<TokenResponse> {
+        log::debug!("Polling for the device code token...");
+
+        let params = vec![
+            ("grant_type", DEVICE_CODE_GRANT_TYPE),
+            ("device_code", device_code),
+        ];
+        let body = self
+            .post_form(self.inner.token_uri().as_str(), params)
+            .await
+            .context("Failed to exchange device code for a token")?;
+
+        serde_json::from_slice::<TokenResponse>(&body)
+            .context("Failed to parse device code token response")
+    }
+
+    /// Form POST of grants the oauth2 crate doesn't know, authenticating the client the same way
+    async fn post_form(&self, url: &str, mut params: Vec<(&str, &str)>) -> Result<Vec<u8>> {
+        let mut request = self.http.post(url);
+
+        match (&self.auth_method, &self.args.client_secret) {
+            (ClientAuthMethod::Basic, Some(client_secret)) => {
+                let encode = |value: &str| {
+                    form_urlencoded::byte_serialize(value.as_bytes()).collect::<String>()
+                };
+                request =
+                    request.basic_auth(encode(&self.args.client_id), Some(encode(client_secret)));
+            }
+            (ClientAuthMethod::Post, Some(client_secret)) => {
+                params.push(("client_id", &self.args.client_id));
+                params.push(("client_secret", client_secret));
+            }
+            _ => params.push(("client_id", &self.args.client_id)),
+        }
+
+        let assertion = self.client_assertion()?;
+        if let Some(assertion) = &assertion {
+            params.push(("client_assertion_type", CLIENT_ASSERTION_TYPE));
+            params.push(("client_assertion", assertion));
+        }
+
+        let response = request.form(&params).send().await?;
+        let status = response.status();
+        self.last_response.lock().unwrap().replace((
+            status,
+            response.headers().to_owned(),
+            SystemTime::now(),
+        ));
+        let body = response.bytes().await?;
+
+        if !status.is_success() {
+            return Err(match ProviderError::from_body(&body) {
+                Some(error) => anyhow::Error::new(self.with_hint(error)),
+                None => anyhow!("{}: {}", status, String::from_utf8_lossy(&body)),
+            });
+        }
+
+        Ok(body.to_vec())
+    }
+
+    pub async fn refresh_token(&self, refresh_token: String) -> Result<TokenResponse> {
         log::debug!("Refreshing token...");
 
         let refresh_token = RefreshToken::new(refresh_token);
+        let mut builder = self.inner.exchange_refresh_token(&refresh_token);
 
-        let response = self
-            .inner
-            .exchange_refresh_token(&refresh_token)
-            .request_async(&self.http)
+        // The resource has to be named, otherwise the token is issued for the one of the original flow
+        if self.args.provider.has_multi_resource_refresh_tokens() {
+            builder = builder.add_scope(Scope::new(self.args.scope.to_string()));
+
+            if let Some(aud) = &self.args.audience {
+                builder = builder.add_extra_param(self.args.provider.audience_param(), aud);
+            }
+        }
+
+        // RFC 8707 narrows tokens minted from the refresh token down to the resources too
+        for resource in &self.args.resource {
+            builder = builder.add_extra_param("resource", resource);
+        }
+
+        if let Some(assertion) = self.client_assertion()? {
+            builder = builder
+                .add_extra_param("client_assertion_type", CLIENT_ASSERTION_TYPE)
+                .add_extra_param("client_assertion", assertion);
+        }
+
+        let response = builder
+            .request_async(&self.observing_client())
             .await
+            .map_err(|e| {
+                let error = self.token_error(e);
+                let last_response = self.last_response.lock().unwrap().to_owned();
+                // Status and headers of the response tell whether the provider throttles refreshes
+                match last_response.and_then(|(status, headers, _)| {
+                    throttling::classify(status, &headers, error.downcast_ref::<ProviderError>())
+                }) {
+                    Some(throttled) => error.context(throttled),
+                    None => error,
+                }
+            })
             .context("Failed to exchange refresh token to a new token")?;
 
         log::debug!("Refresh done");
         Ok(response)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    #[tokio::test]
+    async fn it_builds_authorization_urls_with_injected_security_params() {
+        let args = Arguments {
+            client_id: "my-client".to_owned(),
+            token_url: Some("https://idp.com/token".to_owned()),
+            authorization_url: Some("https://idp.com/authorize".to_owned()),
+            callback_url: Some("http://localhost:8080/callback".to_owned()),
+            scope: "openid".to_owned(),
+            ..Default::default()
+        };
+        let authorize_url = async || {
+            let oauth_client = OAuthClient::new(&args)
+                .await
+                .unwrap()
+                .with_security_params(SecurityParams::with_rng(Box::new(StdRng::seed_from_u64(7))));
+            let (challenge, verifier) = oauth_client.pkce();
+            let (url, csrf, nonce) = oauth_client.authorize_url(Some(challenge.to_owned()));
+
+            (url, csrf, nonce, challenge, verifier)
+        };
+
+        let (url, csrf, nonce, challenge, verifier) = authorize_url().await;
+        let (same_url, _, _, _, same_verifier) = authorize_url().await;
+        assert_eq!(url, same_url);
+        assert_eq!(verifier.secret(), same_verifier.secret());
+
+        let params: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+        for (name, value) in [
+            ("state", csrf.secret().as_str()),
+            ("nonce", nonce.as_str()),
+            ("code_challenge", challenge.as_str()),
+        ] {
+            assert!(params.contains(&(name.to_owned(), value.to_owned())));
+        }
+    }
+}
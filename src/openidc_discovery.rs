@@ -1,22 +1,511 @@
-use anyhow::{Context, Result};
-use serde::{Deserialize, Serialize};
+use anyhow::{Context, Result, anyhow, bail};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, SystemTime};
 
-#[derive(Deserialize, Serialize, Debug)]
-struct OpenIDProviderMetadata {
-    token_endpoint: String,
+use url::Url;
 
-    authorization_endpoint: String,
+use crate::progress;
+
+/// Fields of the discovery document doken reads, possible keys of `--discovery-map`
+pub const DISCOVERY_FIELDS: [&str; 2] = ["token_endpoint", "authorization_endpoint"];
+
+/// Parses `--discovery-map` values in `<field>=<json path>` form, ex. `token_endpoint=$.endpoints.token`
+pub fn parse_discovery_mapping(value: &str) -> Result<(String, String)> {
+    let (field, path) = value
+        .split_once('=')
+        .context("Expected `<field>=<json path>`, ex. `token_endpoint=$.endpoints.token`")?;
+
+    if !DISCOVERY_FIELDS.contains(&field) {
+        bail!(
+            "Unknown discovery field `{}`. Possible values: {}",
+            field,
+            DISCOVERY_FIELDS.join(", ")
+        );
+    }
+
+    parse_path(path)?;
+
+    Ok((field.to_owned(), path.to_owned()))
+}
+
+#[derive(Debug, PartialEq)]
+enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+/// Supports a subset of JSONPath: `$`, `.key` and `[index]` segments
+fn parse_path(path: &str) -> Result<Vec<Segment>> {
+    let rest = path
+        .strip_prefix('$')
+        .ok_or_else(|| anyhow!("JSON path `{}` has to start with `$`", path))?;
+    let mut segments = vec![];
+
+    for part in rest.split('.').skip(1) {
+        let (key, indexes) = match part.find('[') {
+            Some(position) => part.split_at(position),
+            None => (part, ""),
+        };
+
+        if key.is_empty() && indexes.is_empty() {
+            bail!("JSON path `{}` has an empty segment", path);
+        }
+
+        if !key.is_empty() {
+            segments.push(Segment::Key(key.to_owned()));
+        }
+
+        for index in indexes.split('[').skip(1) {
+            let index = index
+                .strip_suffix(']')
+                .and_then(|index| index.parse::<usize>().ok())
+                .with_context(|| format!("JSON path `{}` has an invalid index", path))?;
+            segments.push(Segment::Index(index));
+        }
+    }
+
+    if !rest.is_empty() && !rest.starts_with('.') {
+        bail!("JSON path `{}` has to continue with `.` after `$`", path);
+    }
+
+    Ok(segments)
 }
 
-pub async fn get_endpoints_from_discovery_url(discovery_url: String) -> Result<(String, String)> {
-    let result = reqwest::get(discovery_url.to_owned())
+fn select<'a>(document: &'a Value, path: &str) -> Result<Option<&'a Value>> {
+    let mut value = document;
+
+    for segment in parse_path(path)? {
+        let next = match segment {
+            Segment::Key(key) => value.get(key),
+            Segment::Index(index) => value.get(index),
+        };
+
+        match next {
+            Some(next) => value = next,
+            None => return Ok(None),
+        }
+    }
+
+    Ok(Some(value))
+}
+
+fn field(document: &Value, name: &str, mapping: &[(String, String)]) -> Result<Option<String>> {
+    let path = mapping
+        .iter()
+        .find(|(field, _)| field == name)
+        .map(|(_, path)| path.to_owned())
+        .unwrap_or_else(|| format!("$.{}", name));
+
+    match select(document, &path)? {
+        Some(Value::String(value)) => Ok(Some(value.to_owned())),
+        Some(other) => bail!("`{}` at `{}` is not a string: {}", name, path, other),
+        None => Ok(None),
+    }
+}
+
+/// Discovery document of a provider with the fields doken uses typed. <https://openid.net/specs/openid-connect-discovery-1_0.html#ProviderMetadata>
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct ProviderMetadata {
+    pub issuer: Option<String>,
+    /// Authorization responses carry `iss` <https://www.rfc-editor.org/rfc/rfc9207>
+    pub authorization_response_iss_parameter_supported: bool,
+    pub token_endpoint: Option<String>,
+    pub authorization_endpoint: Option<String>,
+    pub token_endpoint_auth_methods_supported: Vec<String>,
+    pub scopes_supported: Vec<String>,
+    pub claims_supported: Vec<String>,
+    pub response_types_supported: Vec<String>,
+    pub id_token_signing_alg_values_supported: Vec<String>,
+    pub jwks_uri: Option<String>,
+    pub userinfo_endpoint: Option<String>,
+    pub end_session_endpoint: Option<String>,
+    pub revocation_endpoint: Option<String>,
+    pub introspection_endpoint: Option<String>,
+    pub device_authorization_endpoint: Option<String>,
+    pub pushed_authorization_request_endpoint: Option<String>,
+    pub grant_types_supported: Vec<String>,
+    pub code_challenge_methods_supported: Vec<String>,
+    /// The whole document, including fields without a typed counterpart
+    pub document: Value,
+    pub cache: CacheMetadata,
+}
+
+/// How the discovery document was fetched and for how long it may be reused
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct CacheMetadata {
+    pub discovery_url: Option<String>,
+    pub fetched_at: Option<SystemTime>,
+    /// `max-age` of the `Cache-Control` response header. `no-store` and `no-cache` are zero
+    pub max_age: Option<Duration>,
+    pub etag: Option<String>,
+}
+
+impl CacheMetadata {
+    fn from_response(discovery_url: &str, response: &reqwest::Response) -> CacheMetadata {
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+        };
+
+        CacheMetadata {
+            discovery_url: Some(discovery_url.to_owned()),
+            fetched_at: Some(SystemTime::now()),
+            max_age: header(reqwest::header::CACHE_CONTROL).and_then(max_age),
+            etag: header(reqwest::header::ETAG).map(|etag| etag.to_owned()),
+        }
+    }
+
+    /// Documents without `Cache-Control` are reused for the life of the process
+    pub fn is_fresh(&self) -> bool {
+        match (self.fetched_at, self.max_age) {
+            (Some(fetched_at), Some(max_age)) => {
+                fetched_at.elapsed().is_ok_and(|elapsed| elapsed < max_age)
+            }
+            (Some(_), None) => true,
+            (None, _) => false,
+        }
+    }
+}
+
+pub(crate) fn max_age(cache_control: &str) -> Option<Duration> {
+    cache_control
+        .split(',')
+        .map(|directive| directive.trim())
+        .find_map(|directive| match directive.split_once('=') {
+            Some(("max-age", seconds)) => seconds.trim_matches('"').parse().ok(),
+            None if directive == "no-store" || directive == "no-cache" => Some(0),
+            _ => None,
+        })
+        .map(Duration::from_secs)
+}
+
+static DOCUMENTS: LazyLock<Mutex<HashMap<String, (Value, CacheMetadata)>>> =
+    LazyLock::new(Default::default);
+
+fn strings(document: &Value, name: &str) -> Vec<String> {
+    document
+        .get(name)
+        .and_then(|values| values.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|value| value.as_str().map(|value| value.to_owned()))
+        .collect()
+}
+
+const OPENID_CONFIGURATION: &str = "/.well-known/openid-configuration";
+
+/// Sign-in hosts of Entra ID in the public and national clouds
+const ENTRA_ID_HOSTS: [&str; 4] = [
+    "login.microsoftonline.com",
+    "login.microsoftonline.us",
+    "login.chinacloudapi.cn",
+    "login.partner.microsoftonline.cn",
+];
+
+fn host(url: &str) -> Option<String> {
+    Url::parse(url)
+        .ok()?
+        .host_str()
+        .map(|host| host.to_ascii_lowercase())
+}
+
+/// Entra ID documents of multi-tenant endpoints name a `{tenantid}` template, v1 ones `https://sts.windows.net/<tenant>/`
+fn is_entra_id_issuer(discovery_url: &str, issuer: &str) -> bool {
+    host(discovery_url).is_some_and(|host| ENTRA_ID_HOSTS.contains(&host.as_str()))
+        && (issuer.contains("{tenantid}") || host(issuer).as_deref() == Some("sts.windows.net"))
+}
+
+/// The document has to describe the issuer it's published by, otherwise codes of one issuer could be
+/// exchanged at the token endpoint of another (mix-up attack). Only documents at the standard location are checked
+pub fn verify_discovered_issuer(discovery_url: &str, metadata: &ProviderMetadata) -> Result<()> {
+    let (Some(expected), Some(issuer)) = (
+        discovery_url.strip_suffix(OPENID_CONFIGURATION),
+        &metadata.issuer,
+    ) else {
+        return Ok(());
+    };
+
+    if is_entra_id_issuer(discovery_url, issuer) {
+        log::debug!(
+            "Entra ID document of {} names issuer {}",
+            discovery_url,
+            issuer
+        );
+        return Ok(());
+    }
+
+    if issuer.trim_end_matches('/') != expected.trim_end_matches('/') {
+        bail!(
+            "`--discovery-url` describes issuer `{}` instead of `{}`. Refusing to use its endpoints",
+            issuer,
+            expected
+        );
+    }
+
+    Ok(())
+}
+
+/// `iss` of an authorization response <https://www.rfc-editor.org/rfc/rfc9207#section-2.4>
+pub fn verify_authorization_issuer(metadata: &ProviderMetadata, iss: Option<&str>) -> Result<()> {
+    match (&metadata.issuer, iss) {
+        // Multi-tenant Entra ID documents name a `{tenantid}` template instead
+        (Some(issuer), Some(iss)) if issuer != iss && !issuer.contains("{tenantid}") => bail!(
+            "The authorization response comes from issuer `{}` instead of `{}`, which may be a mix-up attack",
+            iss,
+            issuer
+        ),
+        (Some(_), None) if metadata.authorization_response_iss_parameter_supported => bail!(
+            "The authorization response has no `iss` parameter, although the provider announces it"
+        ),
+        (None, Some(iss)) => {
+            log::debug!("Cannot verify issuer {} without a discovery document", iss);
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+fn string(document: &Value, name: &str) -> Option<String> {
+    document
+        .get(name)
+        .and_then(|value| value.as_str())
+        .map(|value| value.to_owned())
+}
+
+/// Typed metadata of a discovery document, with `--discovery-map` applied
+pub fn parse_provider_metadata(
+    document: Value,
+    mapping: &[(String, String)],
+    cache: CacheMetadata,
+) -> Result<ProviderMetadata> {
+    Ok(ProviderMetadata {
+        issuer: string(&document, "issuer"),
+        authorization_response_iss_parameter_supported: document
+            .get("authorization_response_iss_parameter_supported")
+            .and_then(|supported| supported.as_bool())
+            .unwrap_or_default(),
+        token_endpoint: field(&document, "token_endpoint", mapping)?,
+        authorization_endpoint: field(&document, "authorization_endpoint", mapping)?,
+        token_endpoint_auth_methods_supported: strings(
+            &document,
+            "token_endpoint_auth_methods_supported",
+        ),
+        scopes_supported: strings(&document, "scopes_supported"),
+        claims_supported: strings(&document, "claims_supported"),
+        response_types_supported: strings(&document, "response_types_supported"),
+        id_token_signing_alg_values_supported: strings(
+            &document,
+            "id_token_signing_alg_values_supported",
+        ),
+        grant_types_supported: match strings(&document, "grant_types_supported") {
+            // Default of OpenID Connect Discovery when the field is omitted
+            grant_types if grant_types.is_empty() => {
+                vec!["authorization_code".to_owned(), "implicit".to_owned()]
+            }
+            grant_types => grant_types,
+        },
+        code_challenge_methods_supported: strings(&document, "code_challenge_methods_supported"),
+        jwks_uri: string(&document, "jwks_uri"),
+        userinfo_endpoint: string(&document, "userinfo_endpoint"),
+        end_session_endpoint: string(&document, "end_session_endpoint"),
+        revocation_endpoint: string(&document, "revocation_endpoint"),
+        introspection_endpoint: string(&document, "introspection_endpoint"),
+        device_authorization_endpoint: string(&document, "device_authorization_endpoint"),
+        pushed_authorization_request_endpoint: string(
+            &document,
+            "pushed_authorization_request_endpoint",
+        ),
+        document,
+        cache,
+    })
+}
+
+fn cached_document(discovery_url: &str) -> Option<(Value, CacheMetadata)> {
+    DOCUMENTS
+        .lock()
+        .unwrap()
+        .get(discovery_url)
+        .filter(|(_, cache)| cache.is_fresh())
+        .cloned()
+}
+
+/// Discovery document of `discovery_url`. Documents are cached by url for the life of the
+/// process, unless the provider limits it with `Cache-Control`
+pub async fn get_provider_metadata(
+    http: &reqwest::Client,
+    discovery_url: String,
+    mapping: &[(String, String)],
+) -> Result<ProviderMetadata> {
+    if let Some((document, cache)) = cached_document(&discovery_url) {
+        log::debug!("Using cached discovery document of {}", discovery_url);
+        return parse_provider_metadata(document, mapping, cache);
+    }
+
+    progress::notify(|observer| observer.on_discovery(&discovery_url));
+    let response = http
+        .get(discovery_url.to_owned())
+        .send()
         .await
         .context("Couldn't reach out to provided `--discovery-url`")?
         .error_for_status()
-        .context("Failed during OIDC discovery call")?
-        .json::<OpenIDProviderMetadata>()
+        .context("Failed during OIDC discovery call")?;
+    let cache = CacheMetadata::from_response(&discovery_url, &response);
+    let document = response
+        .json::<Value>()
         .await
         .context("Couldn't process json given by `--discovery-url`")?;
 
-    Ok((result.token_endpoint, result.authorization_endpoint))
+    DOCUMENTS
+        .lock()
+        .unwrap()
+        .insert(discovery_url, (document.clone(), cache.clone()));
+
+    parse_provider_metadata(document, mapping, cache)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn it_reads_fields_via_json_path() {
+        let document = json!({
+            "endpoints": { "token": "https://idp/token", "all": ["https://idp/authorize"] },
+            "token_endpoint": "https://idp/default-token"
+        });
+        let mapping = vec![
+            (
+                "authorization_endpoint".to_owned(),
+                "$.endpoints.all[0]".to_owned(),
+            ),
+            ("token_endpoint".to_owned(), "$.endpoints.token".to_owned()),
+        ];
+
+        assert_eq!(
+            field(&document, "token_endpoint", &mapping).unwrap(),
+            Some("https://idp/token".to_owned())
+        );
+        assert_eq!(
+            field(&document, "authorization_endpoint", &mapping).unwrap(),
+            Some("https://idp/authorize".to_owned())
+        );
+        assert_eq!(
+            field(&document, "token_endpoint", &[]).unwrap(),
+            Some("https://idp/default-token".to_owned())
+        );
+    }
+
+    #[test]
+    fn it_keeps_the_whole_document_and_its_cache_lifetime() {
+        let document = json!({
+            "issuer": "https://idp.com",
+            "claims_supported": ["sub", "email"],
+            "device_authorization_endpoint": "https://idp.com/device",
+            "x_vendor": true
+        });
+        let metadata =
+            parse_provider_metadata(document.clone(), &[], CacheMetadata::default()).unwrap();
+
+        assert_eq!(metadata.claims_supported, vec!["sub", "email"]);
+        assert_eq!(
+            metadata.device_authorization_endpoint.as_deref(),
+            Some("https://idp.com/device")
+        );
+        assert_eq!(metadata.document, document);
+
+        assert_eq!(
+            max_age("public, max-age=3600"),
+            Some(Duration::from_secs(3600))
+        );
+        assert_eq!(max_age("no-store"), Some(Duration::ZERO));
+        assert_eq!(max_age("public"), None);
+    }
+
+    #[test]
+    fn it_rejects_invalid_mappings() {
+        assert!(parse_discovery_mapping("token_endpoint=endpoints.token").is_err());
+        assert!(parse_discovery_mapping("jwks_uri=$.jwks").is_err());
+        assert!(parse_discovery_mapping("token_endpoint=$.a[x]").is_err());
+        assert!(parse_discovery_mapping("token_endpoint=$.a..b").is_err());
+        assert!(parse_discovery_mapping("token_endpoint=$.a.b[1]").is_ok());
+    }
+
+    #[test]
+    fn it_defends_against_mix_up_attacks() {
+        let metadata = ProviderMetadata {
+            issuer: Some("https://idp.com/realms/corp".to_owned()),
+            authorization_response_iss_parameter_supported: true,
+            ..Default::default()
+        };
+
+        assert!(
+            verify_discovered_issuer(
+                "https://idp.com/realms/corp/.well-known/openid-configuration",
+                &metadata
+            )
+            .is_ok()
+        );
+        assert!(
+            verify_discovered_issuer(
+                "https://evil.com/realms/corp/.well-known/openid-configuration",
+                &metadata
+            )
+            .is_err()
+        );
+        assert!(verify_discovered_issuer("https://idp.com/metadata.json", &metadata).is_ok());
+
+        let entra_id = |issuer: &str| {
+            parse_provider_metadata(
+                json!({
+                    "token_endpoint": "https://login.microsoftonline.com/common/oauth2/v2.0/token",
+                    "authorization_endpoint": "https://login.microsoftonline.com/common/oauth2/v2.0/authorize",
+                    "issuer": issuer,
+                    "jwks_uri": "https://login.microsoftonline.com/common/discovery/v2.0/keys",
+                    "response_modes_supported": ["query", "fragment", "form_post"],
+                    "tenant_region_scope": null,
+                    "cloud_instance_name": "microsoftonline.com",
+                }),
+                &[],
+                CacheMetadata::default(),
+            )
+            .unwrap()
+        };
+        assert!(
+            verify_discovered_issuer(
+                "https://login.microsoftonline.com/common/v2.0/.well-known/openid-configuration",
+                &entra_id("https://login.microsoftonline.com/{tenantid}/v2.0")
+            )
+            .is_ok()
+        );
+        assert!(
+            verify_discovered_issuer(
+                "https://login.microsoftonline.com/72f988bf-86f1-41af-91ab-2d7cd011db47/.well-known/openid-configuration",
+                &entra_id("https://sts.windows.net/72f988bf-86f1-41af-91ab-2d7cd011db47/")
+            )
+            .is_ok()
+        );
+        assert!(
+            verify_discovered_issuer(
+                "https://evil.com/common/v2.0/.well-known/openid-configuration",
+                &entra_id("https://login.microsoftonline.com/{tenantid}/v2.0")
+            )
+            .is_err()
+        );
+
+        assert!(
+            verify_authorization_issuer(&metadata, Some("https://idp.com/realms/corp")).is_ok()
+        );
+        assert!(verify_authorization_issuer(&metadata, Some("https://evil.com")).is_err());
+        assert!(verify_authorization_issuer(&metadata, None).is_err());
+        assert!(
+            verify_authorization_issuer(&ProviderMetadata::default(), Some("https://evil.com"))
+                .is_ok()
+        );
+    }
 }
@@ -0,0 +1,196 @@
+use anstyle::{AnsiColor, Style};
+use anyhow::{Result, bail};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::io::IsTerminal;
+use std::time::{Duration, SystemTime};
+
+use crate::args::Arguments;
+use crate::output_template;
+use crate::sdk_hints;
+use crate::token_info::TokenInfo;
+
+#[derive(Serialize, Deserialize, ValueEnum, Clone, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputFormat {
+    /// Prints only the access_token
+    #[default]
+    AccessToken,
+    /// Prints the whole token info including provider specific fields as JSON
+    Json,
+    /// Prints shell exports of environment variables the stack given with `--target` expects
+    SdkHints,
+}
+
+pub fn render(token_info: &TokenInfo, args: &Arguments) -> Result<String> {
+    if let Some(template) = &args.format {
+        return output_template::render(template, token_info);
+    }
+
+    match args.output {
+        OutputFormat::AccessToken => Ok(token_info.access_token.to_owned()),
+        OutputFormat::Json => Ok(serde_json::to_string(token_info)?),
+        OutputFormat::SdkHints => sdk_hints::render(token_info, args),
+    }
+}
+
+const STATUS: Style = Style::new()
+    .bold()
+    .fg_color(Some(anstyle::Color::Ansi(AnsiColor::Green)));
+const WARNING: Style = Style::new()
+    .bold()
+    .fg_color(Some(anstyle::Color::Ansi(AnsiColor::Yellow)));
+const ERROR: Style = Style::new()
+    .bold()
+    .fg_color(Some(anstyle::Color::Ansi(AnsiColor::Red)));
+const KEY: Style = Style::new().bold();
+
+/// Colors are used only in a terminal and never if `NO_COLOR` is set <https://no-color.org>
+fn use_color(is_terminal: bool) -> bool {
+    if env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty()) {
+        return false;
+    }
+
+    if env::var_os("CLICOLOR_FORCE").is_some_and(|value| value != "0") {
+        return true;
+    }
+
+    is_terminal
+}
+
+fn paint(style: Style, text: &str, is_terminal: bool) -> String {
+    if use_color(is_terminal) {
+        format!("{style}{text}{style:#}")
+    } else {
+        text.to_owned()
+    }
+}
+
+/// Output of `--max-stdout-bytes`, refused when it's longer
+pub fn limit(output: String, max_stdout_bytes: Option<usize>) -> Result<String> {
+    match max_stdout_bytes {
+        Some(max) if output.len() > max => bail!(
+            "The output has {} bytes, more than `--max-stdout-bytes` {} allows",
+            output.len(),
+            max
+        ),
+        _ => Ok(output),
+    }
+}
+
+/// Prints a status line to stderr ex. `Refreshed token for my-client`
+pub fn status(label: &str, message: &str) {
+    eprintln!(
+        "{} {}",
+        paint(STATUS, label, std::io::stderr().is_terminal()),
+        message
+    );
+}
+
+pub fn warning(message: &str) {
+    eprintln!(
+        "{} {}",
+        paint(WARNING, "warning:", std::io::stderr().is_terminal()),
+        message
+    );
+}
+
+/// Prints an error with its causes in the same style as clap does
+pub fn error(error: &anyhow::Error) {
+    let is_terminal = std::io::stderr().is_terminal();
+    eprintln!("{} {}", paint(ERROR, "error:", is_terminal), error);
+
+    for cause in error.chain().skip(1) {
+        eprintln!("  {} {}", paint(KEY, "caused by:", is_terminal), cause);
+    }
+}
+
+/// Renders key/value pairs with keys right-aligned to the longest one
+pub fn key_values(pairs: &[(&str, String)]) -> String {
+    let is_terminal = std::io::stdout().is_terminal();
+    let width = pairs.iter().map(|(key, _)| key.len()).max().unwrap_or(0);
+
+    pairs
+        .iter()
+        .map(|(key, value)| {
+            format!(
+                "{}{}  {}",
+                " ".repeat(width - key.len()),
+                paint(KEY, key, is_terminal),
+                value
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Point in time for people, ex. `2024-05-01 14:00:00 +02:00`. With `--utc` RFC 3339 in UTC, ex. `2024-05-01T12:00:00Z`
+pub fn timestamp(time: SystemTime, utc: bool) -> String {
+    if utc {
+        return humantime::format_rfc3339_seconds(time).to_string();
+    }
+
+    chrono::DateTime::<chrono::Local>::from(time)
+        .format("%Y-%m-%d %H:%M:%S %:z")
+        .to_string()
+}
+
+/// Two largest units of a duration, ex. `12m`, `1h 5m` or `3d 2h`
+fn humanize(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    let (days, hours, minutes) = (seconds / 86_400, seconds / 3_600 % 24, seconds / 60 % 60);
+
+    match (days, hours, minutes) {
+        (0, 0, 0) => format!("{}s", seconds),
+        (0, 0, minutes) => format!("{}m", minutes),
+        (0, hours, 0) => format!("{}h", hours),
+        (0, hours, minutes) => format!("{}h {}m", hours, minutes),
+        (days, 0, _) => format!("{}d", days),
+        (days, hours, _) => format!("{}d {}h", days, hours),
+    }
+}
+
+fn relative_to(time: SystemTime, now: SystemTime) -> String {
+    match time.duration_since(now) {
+        Ok(remaining) => format!("in {}", humanize(remaining)),
+        Err(e) => format!("{} ago", humanize(e.duration())),
+    }
+}
+
+/// Distance from now, ex. `in 12m` or `3h 5m ago`
+pub fn relative(time: SystemTime) -> String {
+    relative_to(time, SystemTime::now())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_formats_times_for_people() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+        assert_eq!(
+            relative_to(now + Duration::from_secs(12 * 60 + 30), now),
+            "in 12m"
+        );
+        assert_eq!(
+            relative_to(now - Duration::from_secs(3 * 3_600 + 5 * 60), now),
+            "3h 5m ago"
+        );
+        assert_eq!(
+            relative_to(now + Duration::from_secs(2 * 86_400 + 60), now),
+            "in 2d"
+        );
+        assert_eq!(relative_to(now + Duration::from_secs(42), now), "in 42s");
+        assert_eq!(timestamp(now, true), "2023-11-14T22:13:20Z");
+    }
+
+    #[test]
+    fn it_refuses_output_over_the_limit() {
+        assert_eq!(limit("token".to_owned(), None).unwrap(), "token");
+        assert_eq!(limit("token".to_owned(), Some(5)).unwrap(), "token");
+        assert!(limit("token\n".to_owned(), Some(5)).is_err());
+    }
+}
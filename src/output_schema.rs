@@ -0,0 +1,167 @@
+use serde_json::{Value, json};
+
+/// Version of the machine-readable output contract. Fields are only added within a version,
+/// removing or changing one bumps it
+pub const OUTPUT_SCHEMA_VERSION: u32 = 1;
+
+/// JSON Schema (draft 2020-12) of `--output json`, `doken watch` lines and `--progress-events` lines,
+/// printed with `--output-schema`
+pub fn schema() -> Value {
+    let nullable_string = json!({ "type": ["string", "null"] });
+    let event = |name: &str, properties: Value, required: Value| {
+        let mut properties = properties;
+        properties["event"] = json!({ "const": name });
+
+        json!({
+            "type": "object",
+            "properties": properties,
+            "required": required,
+        })
+    };
+
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "$id": format!(
+            "https://github.com/RiddleMan/o2-get-token/schemas/v{}/output.json",
+            OUTPUT_SCHEMA_VERSION
+        ),
+        "title": "doken output",
+        "version": OUTPUT_SCHEMA_VERSION,
+        "$defs": {
+            "token": {
+                "description": "Printed by `--output json` and as lines by `doken watch`",
+                "type": "object",
+                "properties": {
+                    "access_token": { "type": "string" },
+                    "refresh_token": nullable_string,
+                    "expires": {
+                        "oneOf": [
+                            { "type": "null" },
+                            {
+                                "type": "object",
+                                "properties": {
+                                    "secs_since_epoch": { "type": "integer", "minimum": 0 },
+                                    "nanos_since_epoch": { "type": "integer", "minimum": 0 },
+                                },
+                                "required": ["secs_since_epoch", "nanos_since_epoch"],
+                            },
+                        ],
+                    },
+                    "scope": nullable_string,
+                    "requested_scope": { "type": "string" },
+                    "extra": {
+                        "description": "Provider specific fields of the token response, ex. `id_token`",
+                        "type": "object",
+                    },
+                },
+                "required": ["access_token", "refresh_token", "expires", "scope"],
+            },
+            "progress-event": {
+                "description": "Printed as lines to stderr by `--progress-events`",
+                "oneOf": [
+                    event(
+                        "discovery",
+                        json!({ "discovery_url": { "type": "string" } }),
+                        json!(["event", "discovery_url"]),
+                    ),
+                    event(
+                        "browser-launch",
+                        json!({ "headless": { "type": "boolean" } }),
+                        json!(["event", "headless"]),
+                    ),
+                    event(
+                        "waiting-for-user",
+                        json!({ "authorization_url": { "type": "string" } }),
+                        json!(["event", "authorization_url"]),
+                    ),
+                    event("code-received", json!({}), json!(["event"])),
+                    event(
+                        "device-code-pending",
+                        json!({ "remaining_secs": { "type": "integer", "minimum": 0 } }),
+                        json!(["event", "remaining_secs"]),
+                    ),
+                    event(
+                        "token-received",
+                        json!({
+                            "state_key": { "type": "string" },
+                            "expires": { "type": ["string", "null"], "format": "date-time" },
+                        }),
+                        json!(["event", "state_key", "expires"]),
+                    ),
+                    event(
+                        "cache-write",
+                        json!({ "state_key": { "type": "string" } }),
+                        json!(["event", "state_key"]),
+                    ),
+                ],
+            },
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::progress::ProgressEvent;
+    use crate::token_info::TokenInfo;
+    use std::time::SystemTime;
+
+    /// Every field doken prints has to be described, otherwise the schema went out of date
+    fn assert_described(definition: &Value, output: &Value) {
+        let properties = definition["properties"].as_object().unwrap();
+
+        for field in output.as_object().unwrap().keys() {
+            assert!(
+                properties.contains_key(field),
+                "`{}` isn't described",
+                field
+            );
+        }
+        for field in definition["required"].as_array().unwrap() {
+            assert!(output.get(field.as_str().unwrap()).is_some());
+        }
+    }
+
+    #[test]
+    fn it_describes_every_printed_field() {
+        let schema = schema();
+        let token_info = TokenInfo {
+            access_token: "access".to_owned(),
+            refresh_token: Some("refresh".to_owned()),
+            expires: Some(SystemTime::now()),
+            scope: Some("openid".to_owned()),
+            requested_scope: Some("openid".to_owned()),
+            extra: [("id_token".to_owned(), json!("id"))].into(),
+        };
+        assert_described(
+            &schema["$defs"]["token"],
+            &serde_json::to_value(&token_info).unwrap(),
+        );
+
+        let events = [
+            ProgressEvent::Discovery { discovery_url: "" },
+            ProgressEvent::BrowserLaunch { headless: true },
+            ProgressEvent::WaitingForUser {
+                authorization_url: "",
+            },
+            ProgressEvent::CodeReceived,
+            ProgressEvent::DeviceCodePending { remaining_secs: 0 },
+            ProgressEvent::TokenReceived {
+                state_key: "",
+                expires: None,
+            },
+            ProgressEvent::CacheWrite { state_key: "" },
+        ];
+        let definitions = schema["$defs"]["progress-event"]["oneOf"]
+            .as_array()
+            .unwrap();
+        for event in events {
+            let event = serde_json::to_value(&event).unwrap();
+            let definition = definitions
+                .iter()
+                .find(|definition| definition["properties"]["event"]["const"] == event["event"])
+                .unwrap();
+            assert_described(definition, &event);
+        }
+    }
+}
@@ -0,0 +1,191 @@
+use anyhow::{Context, Result, bail};
+use serde_json::{Map, Value};
+use std::time::SystemTime;
+
+use crate::claims::unverified_claims;
+use crate::token_info::TokenInfo;
+
+/// Fields available to `--format`. Provider specific fields of the response are at the top level
+/// next to the token info's own ones, claims of the tokens under `claims` and `id_token_claims`
+fn fields(token_info: &TokenInfo) -> Value {
+    let mut fields: Map<String, Value> = token_info
+        .extra
+        .iter()
+        .map(|(name, value)| (name.to_owned(), value.to_owned()))
+        .collect();
+
+    fields.insert(
+        "access_token".to_owned(),
+        token_info.access_token.clone().into(),
+    );
+    fields.insert(
+        "refresh_token".to_owned(),
+        token_info.refresh_token.clone().into(),
+    );
+    fields.insert("scope".to_owned(), token_info.scope.clone().into());
+    fields.insert(
+        "requested_scope".to_owned(),
+        token_info.requested_scope.clone().into(),
+    );
+    fields.insert(
+        "expires_at".to_owned(),
+        token_info
+            .expires
+            .map(|expires| humantime::format_rfc3339_seconds(expires).to_string())
+            .into(),
+    );
+    fields.insert(
+        "expires_in".to_owned(),
+        token_info
+            .expires
+            .map(|expires| {
+                expires
+                    .duration_since(SystemTime::now())
+                    .map(|left| left.as_secs())
+                    .unwrap_or_default()
+            })
+            .into(),
+    );
+    fields.insert(
+        "claims".to_owned(),
+        unverified_claims(&token_info.access_token).unwrap_or_default(),
+    );
+    fields.insert(
+        "id_token_claims".to_owned(),
+        token_info
+            .extra
+            .get("id_token")
+            .and_then(|token| token.as_str())
+            .and_then(unverified_claims)
+            .unwrap_or_default(),
+    );
+
+    Value::Object(fields)
+}
+
+/// Strings are written as is, missing values as nothing and everything else as JSON
+fn text(value: &Value) -> String {
+    match value {
+        Value::String(value) => value.to_owned(),
+        Value::Null => String::new(),
+        value => value.to_string(),
+    }
+}
+
+/// Field at a dot separated `path`, ex. `claims.email`
+fn lookup<'a>(fields: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.')
+        .try_fold(fields, |value, name| value.get(name))
+}
+
+/// Single field for `doken token print --field`, ex. `id_token` or `claims.email`. Unlike templates
+/// missing claims are an error, so scripts don't go on with an empty value
+pub fn field(token_info: &TokenInfo, path: &str) -> Result<String> {
+    match lookup(&fields(token_info), path) {
+        Some(Value::Null) | None => bail!("The token has no `{}`", path),
+        Some(value) => Ok(text(value)),
+    }
+}
+
+/// Renders a `--format` template, ex. `{{.access_token}} exp={{.expires_at}}`. Nested fields are
+/// separated with dots, ex. `{{.claims.realm_access.roles}}`
+pub fn render(template: &str, token_info: &TokenInfo) -> Result<String> {
+    let fields = fields(token_info);
+    let mut result = String::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        let end = rest[start..]
+            .find("}}")
+            .with_context(|| format!("`{}` is never closed with `}}}}`", &rest[start..]))?;
+        let placeholder = rest[start + 2..start + end].trim();
+        let Some(path) = placeholder.strip_prefix('.') else {
+            bail!(
+                "`{{{{{}}}}}` has to start with a dot, ex. `{{{{.access_token}}}}`",
+                placeholder
+            );
+        };
+
+        let value = lookup(&fields, path);
+        // Claims differ between tokens, so ones the token doesn't carry are left empty
+        let value = match value {
+            Some(value) => text(value),
+            None if path.starts_with("claims.") || path.starts_with("id_token_claims.") => {
+                String::new()
+            }
+            None => bail!("`{}` is not a field of the token", path),
+        };
+
+        result.push_str(&rest[..start]);
+        result.push_str(&value);
+        rest = &rest[start + end + 2..];
+    }
+
+    result.push_str(rest);
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::Engine;
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    fn token_info() -> TokenInfo {
+        let claims = json!({ "sub": "alice", "realm_access": { "roles": ["admin"] } });
+
+        TokenInfo {
+            access_token: format!(
+                "e30.{}.signature",
+                URL_SAFE_NO_PAD.encode(claims.to_string())
+            ),
+            refresh_token: None,
+            expires: Some(SystemTime::UNIX_EPOCH),
+            scope: Some("openid".to_owned()),
+            requested_scope: None,
+            extra: HashMap::from([("token_type".to_owned(), json!("Bearer"))]),
+        }
+    }
+
+    #[test]
+    fn it_renders_fields_and_claims() {
+        let token_info = token_info();
+
+        assert_eq!(
+            render(
+                "{{ .token_type }} sub={{.claims.sub}} roles={{.claims.realm_access.roles}} exp={{.expires_at}} refresh={{.refresh_token}}{{.claims.tid}}",
+                &token_info
+            )
+            .unwrap(),
+            r#"Bearer sub=alice roles=["admin"] exp=1970-01-01T00:00:00Z refresh="#
+        );
+    }
+
+    #[test]
+    fn it_rejects_unknown_fields_and_malformed_templates() {
+        let token_info = token_info();
+
+        assert!(render("{{.acces_token}}", &token_info).is_err());
+        assert!(render("{{access_token}}", &token_info).is_err());
+        assert!(render("{{.access_token", &token_info).is_err());
+    }
+
+    #[test]
+    fn it_prints_a_single_field_unquoted() {
+        let token_info = token_info();
+
+        assert_eq!(field(&token_info, "claims.sub").unwrap(), "alice");
+        assert_eq!(
+            field(&token_info, "expires_at").unwrap(),
+            "1970-01-01T00:00:00Z"
+        );
+        assert_eq!(
+            field(&token_info, "claims.realm_access.roles").unwrap(),
+            r#"["admin"]"#
+        );
+        assert!(field(&token_info, "refresh_token").is_err());
+        assert!(field(&token_info, "claims.email").is_err());
+    }
+}
@@ -0,0 +1,187 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::args::Arguments;
+use crate::output;
+use crate::permissions;
+
+/// How long a sign-in started by an earlier run can be resumed
+pub const PENDING_FLOW_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// Authorization request a run is waiting to be answered. With `--resume-flows` it's kept on
+/// disk, so a rerun after a crash accepts the callback of the same request instead of starting over
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PendingFlow {
+    pub authorization_url: String,
+    pub csrf_token: String,
+    pub pkce_verifier: Option<String>,
+    pub nonce: String,
+    /// Settings of the request, which has to be made again once any of them changes
+    scope: String,
+    audience: Option<String>,
+    callback_url: Option<String>,
+    created: SystemTime,
+}
+
+fn pending_dir() -> Result<PathBuf> {
+    let mut path = home::home_dir().context("Couldn't access $HOME_DIR")?;
+    path.push(".doken/pending");
+    Ok(path)
+}
+
+/// State keys may contain audiences or scopes, so they're hashed to get a file name
+fn file_name(state_key: &str) -> String {
+    let hash = Sha256::digest(state_key.as_bytes());
+    format!(
+        "{}.json",
+        hash.iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>()
+    )
+}
+
+impl PendingFlow {
+    pub fn new(
+        args: &Arguments,
+        authorization_url: String,
+        csrf_token: String,
+        pkce_verifier: Option<String>,
+        nonce: String,
+    ) -> Self {
+        PendingFlow {
+            authorization_url,
+            csrf_token,
+            pkce_verifier,
+            nonce,
+            scope: args.scope.to_owned(),
+            audience: args.audience.to_owned(),
+            callback_url: args.callback_url.to_owned(),
+            created: SystemTime::now(),
+        }
+    }
+
+    fn is_resumable(&self, args: &Arguments, now: SystemTime) -> bool {
+        let fresh = now
+            .duration_since(self.created)
+            .is_ok_and(|age| age < PENDING_FLOW_TTL);
+
+        fresh
+            && self.scope == args.scope
+            && self.audience == args.audience
+            && self.callback_url == args.callback_url
+    }
+
+    fn read(dir: &Path, args: &Arguments, now: SystemTime) -> Option<PendingFlow> {
+        let path = dir.join(file_name(&args.state_key()));
+        let flow: PendingFlow = serde_json::from_str(&fs::read_to_string(&path).ok()?).ok()?;
+
+        if !flow.is_resumable(args, now) {
+            let _ = fs::remove_file(path);
+            return None;
+        }
+
+        Some(flow)
+    }
+
+    fn write(&self, dir: &Path, state_key: &str) -> Result<()> {
+        fs::create_dir_all(dir)?;
+
+        let path = dir.join(file_name(state_key));
+        permissions::write_private(&path, serde_json::to_string(self)?.as_bytes())
+            .with_context(|| format!("Cannot write {}", path.display()))
+    }
+
+    /// Flow of the same settings started less than `PENDING_FLOW_TTL` ago
+    pub fn resume(args: &Arguments) -> Option<PendingFlow> {
+        PendingFlow::read(&pending_dir().ok()?, args, SystemTime::now())
+    }
+
+    pub fn save(&self, args: &Arguments) -> Result<()> {
+        self.write(&pending_dir()?, &args.state_key())
+    }
+
+    /// Forgets the flow once its code was exchanged, successfully or not
+    pub fn finish(args: &Arguments) {
+        if let Ok(dir) = pending_dir() {
+            let _ = fs::remove_file(dir.join(file_name(&args.state_key())));
+        }
+    }
+
+    pub fn age(&self) -> Duration {
+        SystemTime::now()
+            .duration_since(self.created)
+            .unwrap_or_default()
+    }
+}
+
+/// Flow of an earlier run, if `--resume-flows` is set and there's one, otherwise a new one made by `start`
+pub fn resume_or_start(args: &Arguments, start: impl FnOnce() -> PendingFlow) -> PendingFlow {
+    if !args.resume_flows {
+        return start();
+    }
+
+    if let Some(flow) = PendingFlow::resume(args) {
+        output::status(
+            "Resuming",
+            &format!(
+                "the sign-in started {} ago",
+                humantime::format_duration(Duration::from_secs(flow.age().as_secs()))
+            ),
+        );
+        return flow;
+    }
+
+    let flow = start();
+    if let Err(e) = flow.save(args) {
+        output::warning(&format!("The sign-in cannot be resumed later: {:#}", e));
+    }
+
+    flow
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_resumes_only_fresh_flows_of_the_same_settings() {
+        let dir = tempfile::tempdir().unwrap();
+        let args = Arguments {
+            client_id: "my-client".to_owned(),
+            scope: "openid".to_owned(),
+            ..Default::default()
+        };
+        let flow = PendingFlow::new(
+            &args,
+            "https://idp.com/authorize?state=xyz".to_owned(),
+            "xyz".to_owned(),
+            Some("verifier".to_owned()),
+            "nonce".to_owned(),
+        );
+        flow.write(dir.path(), &args.state_key()).unwrap();
+
+        let other_scope = Arguments {
+            scope: "openid profile".to_owned(),
+            ..args.clone()
+        };
+        assert_eq!(
+            PendingFlow::read(dir.path(), &other_scope, flow.created),
+            None
+        );
+
+        flow.write(dir.path(), &args.state_key()).unwrap();
+        assert_eq!(
+            PendingFlow::read(dir.path(), &args, flow.created + Duration::from_secs(60)),
+            Some(flow.clone())
+        );
+        assert_eq!(
+            PendingFlow::read(dir.path(), &args, flow.created + PENDING_FLOW_TTL),
+            None
+        );
+        assert!(!dir.path().join(file_name(&args.state_key())).exists());
+    }
+}
@@ -0,0 +1,128 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+#[cfg(unix)]
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt, PermissionsExt};
+
+/// Opens a file for reading and writing, creating it readable only by the current user
+pub fn open_private(path: &Path) -> io::Result<File> {
+    open(
+        path,
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false),
+    )
+}
+
+/// Replaces the contents of a file, creating it readable only by the current user
+pub fn write_private(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let mut file = open_private(path)?;
+    file.set_len(0)?;
+    file.write_all(bytes)
+}
+
+/// Opens a file for appending, creating it readable only by the current user. Writes of concurrent
+/// runs don't overwrite each other
+pub fn append_private(path: &Path) -> io::Result<File> {
+    open(path, OpenOptions::new().create(true).append(true))
+}
+
+fn open(path: &Path, options: &mut OpenOptions) -> io::Result<File> {
+    #[cfg(windows)]
+    let existed = path.exists();
+
+    #[cfg(unix)]
+    options.mode(0o600);
+
+    let file = options.open(path)?;
+
+    #[cfg(windows)]
+    if !existed {
+        restrict_acl(path)?;
+    }
+
+    Ok(file)
+}
+
+/// Creates a directory, and its parents, only the current user may enter
+pub fn create_private_dir(dir: &Path) -> io::Result<()> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    builder.mode(0o700);
+    builder.create(dir)?;
+
+    // Created by an older version under the default umask
+    #[cfg(unix)]
+    fs::set_permissions(dir, fs::Permissions::from_mode(0o700))?;
+
+    Ok(())
+}
+
+/// Drops inherited ACL entries and grants access only to the current user
+#[cfg(windows)]
+fn restrict_acl(path: &Path) -> io::Result<()> {
+    let user = std::env::var("USERNAME").map_err(io::Error::other)?;
+    let status = std::process::Command::new("icacls")
+        .arg(path)
+        .args(["/inheritance:r", "/grant:r"])
+        .arg(format!("{}:F", user))
+        .stdout(std::process::Stdio::null())
+        .status()?;
+
+    if !status.success() {
+        return Err(io::Error::other(format!(
+            "icacls failed to restrict access to {}",
+            path.display()
+        )));
+    }
+
+    Ok(())
+}
+
+/// Existing files readable or writable by group or others.
+/// On Windows ACLs are only set when a file is created, so nothing is reported
+pub fn exposed(paths: &[PathBuf]) -> Vec<PathBuf> {
+    paths
+        .iter()
+        .filter(|path| is_exposed(path))
+        .cloned()
+        .collect()
+}
+
+#[cfg(unix)]
+fn is_exposed(path: &Path) -> bool {
+    std::fs::metadata(path)
+        .map(|metadata| metadata.permissions().mode() & 0o077 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_exposed(_path: &Path) -> bool {
+    false
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn it_creates_private_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+
+        open_private(&path).unwrap();
+        append_private(&dir.path().join("history.jsonl")).unwrap();
+
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert!(exposed(&[path.to_owned(), dir.path().join("history.jsonl")]).is_empty());
+
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        assert_eq!(exposed(&[path.to_owned()]), vec![path]);
+    }
+}
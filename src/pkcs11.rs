@@ -0,0 +1,282 @@
+use anyhow::{Context, Result, bail};
+use cryptoki::context::{CInitializeArgs, Pkcs11};
+use cryptoki::mechanism::Mechanism;
+use cryptoki::object::{Attribute, ObjectClass};
+use cryptoki::session::UserType;
+use cryptoki::slot::Slot;
+use cryptoki::types::AuthPin;
+use jsonwebtoken::Algorithm;
+use percent_encoding::percent_decode_str;
+use sha2::{Digest, Sha256, Sha384};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, Mutex, OnceLock};
+use std::{env, fs};
+
+use crate::prompt::{PromptOptions, prompt_secret};
+use crate::signer::Signer;
+
+/// Module of `pkcs11:` URIs without `module-path`, ex. `/usr/lib/softhsm/libsofthsm2.so`
+const MODULE_ENV: &str = "DOKEN_PKCS11_MODULE";
+
+/// Modules are initialized once per process, a second `C_Initialize` fails
+static CONTEXTS: LazyLock<Mutex<HashMap<PathBuf, Pkcs11>>> = LazyLock::new(Default::default);
+
+/// Attributes of a `pkcs11:` URI <https://www.rfc-editor.org/rfc/rfc7512> finding a private key
+#[derive(Debug, Default, PartialEq)]
+pub struct Pkcs11Uri {
+    /// Label of the token
+    pub token: Option<String>,
+    /// Serial number of the token
+    pub serial: Option<String>,
+    /// Label of the key
+    pub object: Option<String>,
+    /// `CKA_ID` of the key
+    pub id: Option<Vec<u8>>,
+    pub module_path: Option<PathBuf>,
+    /// File holding the PIN. It's asked for otherwise
+    pub pin_source: Option<PathBuf>,
+}
+
+impl Pkcs11Uri {
+    pub fn parse(uri: &str) -> Result<Pkcs11Uri> {
+        let rest = uri
+            .strip_prefix("pkcs11:")
+            .context("PKCS#11 URIs start with `pkcs11:`")?;
+        let (path, query) = rest.split_once('?').unwrap_or((rest, ""));
+        let decode = |value: &str| percent_decode_str(value).collect::<Vec<u8>>();
+        let text = |value: &str| -> Result<String> {
+            String::from_utf8(decode(value)).context("The PKCS#11 URI is not valid UTF-8")
+        };
+
+        let mut parsed = Pkcs11Uri::default();
+        for (attributes, separator) in [(path, ';'), (query, '&')] {
+            for attribute in attributes.split(separator).filter(|a| !a.is_empty()) {
+                let (name, value) = attribute
+                    .split_once('=')
+                    .with_context(|| format!("`{}` of the PKCS#11 URI has no value", attribute))?;
+
+                match name {
+                    "token" => parsed.token = Some(text(value)?),
+                    "serial" => parsed.serial = Some(text(value)?),
+                    "object" => parsed.object = Some(text(value)?),
+                    "id" => parsed.id = Some(decode(value)),
+                    "module-path" => parsed.module_path = Some(PathBuf::from(text(value)?)),
+                    "pin-source" => {
+                        let source = text(value)?;
+                        let path = source
+                            .strip_prefix("file://")
+                            .or(source.strip_prefix("file:"))
+                            .unwrap_or(&source);
+                        parsed.pin_source = Some(PathBuf::from(path));
+                    }
+                    "pin-value" => bail!(
+                        "Keep the PIN out of the PKCS#11 URI, it's asked for or read from `pin-source=<file>`"
+                    ),
+                    name => log::debug!("Ignoring `{}` of the PKCS#11 URI", name),
+                }
+            }
+        }
+
+        if parsed.object.is_none() && parsed.id.is_none() {
+            bail!("The PKCS#11 URI has to name the key with `object=<label>` or `id=<id>`");
+        }
+
+        Ok(parsed)
+    }
+}
+
+fn context(module: &Path) -> Result<Pkcs11> {
+    let mut contexts = CONTEXTS.lock().unwrap();
+    if let Some(pkcs11) = contexts.get(module) {
+        return Ok(pkcs11.clone());
+    }
+
+    let pkcs11 = Pkcs11::new(module)
+        .with_context(|| format!("Cannot load the PKCS#11 module {}", module.display()))?;
+    pkcs11
+        .initialize(CInitializeArgs::OsThreads)
+        .with_context(|| format!("Cannot initialize the PKCS#11 module {}", module.display()))?;
+    contexts.insert(module.to_owned(), pkcs11.clone());
+
+    Ok(pkcs11)
+}
+
+/// Mechanism signing for `algorithm` and the data it signs. `CKM_ECDSA` signs a digest and
+/// returns `r || s`, as JWS wants it
+fn mechanism(algorithm: Algorithm, message: &[u8]) -> Result<(Mechanism<'static>, Vec<u8>)> {
+    Ok(match algorithm {
+        Algorithm::RS256 => (Mechanism::Sha256RsaPkcs, message.to_vec()),
+        Algorithm::RS384 => (Mechanism::Sha384RsaPkcs, message.to_vec()),
+        Algorithm::RS512 => (Mechanism::Sha512RsaPkcs, message.to_vec()),
+        Algorithm::ES256 => (Mechanism::Ecdsa, Sha256::digest(message).to_vec()),
+        Algorithm::ES384 => (Mechanism::Ecdsa, Sha384::digest(message).to_vec()),
+        algorithm => bail!(
+            "PKCS#11 keys sign with RS256, RS384, RS512, ES256 or ES384, not {:?}",
+            algorithm
+        ),
+    })
+}
+
+/// Private key of a PKCS#11 token, ex. an HSM or a smart card. The key never leaves the token,
+/// its PIN is asked for once per run
+pub struct Pkcs11Signer {
+    pkcs11: Pkcs11,
+    slot: Slot,
+    key: Vec<Attribute>,
+    algorithm: Algorithm,
+    key_id: Option<String>,
+    pin_source: Option<PathBuf>,
+    pin_label: String,
+    prompt: PromptOptions,
+    pin: OnceLock<String>,
+}
+
+impl Pkcs11Signer {
+    /// Key of a `pkcs11:` URI. Its module comes from `module-path` or `DOKEN_PKCS11_MODULE`
+    pub fn from_uri(
+        uri: &str,
+        algorithm: Algorithm,
+        key_id: Option<String>,
+        prompt: PromptOptions,
+    ) -> Result<Pkcs11Signer> {
+        Pkcs11Signer::new(&Pkcs11Uri::parse(uri)?, algorithm, key_id, prompt)
+    }
+
+    pub fn new(
+        uri: &Pkcs11Uri,
+        algorithm: Algorithm,
+        key_id: Option<String>,
+        prompt: PromptOptions,
+    ) -> Result<Pkcs11Signer> {
+        mechanism(algorithm, &[])?;
+
+        let module = uri
+            .module_path
+            .to_owned()
+            .or_else(|| env::var_os(MODULE_ENV).map(PathBuf::from))
+            .with_context(|| {
+                format!(
+                    "Pass the PKCS#11 module with `module-path=<path>` in the URI or `{}`",
+                    MODULE_ENV
+                )
+            })?;
+        let pkcs11 = context(&module)?;
+
+        let slot = pkcs11
+            .get_slots_with_token()?
+            .into_iter()
+            .find(|slot| {
+                pkcs11.get_token_info(*slot).is_ok_and(|info| {
+                    uri.token
+                        .as_deref()
+                        .is_none_or(|token| info.label() == token)
+                        && uri
+                            .serial
+                            .as_deref()
+                            .is_none_or(|serial| info.serial_number() == serial)
+                })
+            })
+            .with_context(|| format!("No token of {} matches the URI", module.display()))?;
+
+        let mut key = vec![Attribute::Class(ObjectClass::PRIVATE_KEY)];
+        if let Some(object) = &uri.object {
+            key.push(Attribute::Label(object.as_bytes().to_vec()));
+        }
+        if let Some(id) = &uri.id {
+            key.push(Attribute::Id(id.to_owned()));
+        }
+
+        Ok(Pkcs11Signer {
+            pkcs11,
+            slot,
+            key,
+            algorithm,
+            key_id,
+            pin_source: uri.pin_source.to_owned(),
+            pin_label: "PKCS#11 PIN".to_owned(),
+            prompt,
+            pin: OnceLock::new(),
+        })
+    }
+
+    /// What the PIN is called when it's asked for, ex. `YubiKey PIN`
+    pub fn with_pin_label(mut self, pin_label: &str) -> Self {
+        self.pin_label = pin_label.to_owned();
+        self
+    }
+
+    fn pin(&self) -> Result<&str> {
+        if let Some(pin) = self.pin.get() {
+            return Ok(pin);
+        }
+
+        let pin = match &self.pin_source {
+            Some(path) => fs::read_to_string(path)
+                .with_context(|| format!("Cannot read the PIN from {}", path.display()))?
+                .trim_end_matches(['\r', '\n'])
+                .to_owned(),
+            None => prompt_secret(&self.prompt, &self.pin_label)?,
+        };
+        Ok(self.pin.get_or_init(|| pin))
+    }
+}
+
+impl Signer for Pkcs11Signer {
+    fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    fn key_id(&self) -> Option<&str> {
+        self.key_id.as_deref()
+    }
+
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>> {
+        let (mechanism, data) = mechanism(self.algorithm, message)?;
+
+        // Closing the session logs out again
+        let session = self
+            .pkcs11
+            .open_ro_session(self.slot)
+            .context("Cannot open a session with the PKCS#11 token")?;
+        session
+            .login(UserType::User, Some(&AuthPin::new(self.pin()?.into())))
+            .context("The PKCS#11 token didn't accept the PIN")?;
+        let key = session
+            .find_objects(&self.key)?
+            .into_iter()
+            .next()
+            .context("The PKCS#11 token has no private key matching the URI")?;
+
+        session
+            .sign(&mechanism, key, &data)
+            .context("The PKCS#11 token couldn't sign")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_pkcs11_uris() {
+        assert_eq!(
+            Pkcs11Uri::parse(
+                "pkcs11:token=My%20HSM;object=client-key;id=%01%02?module-path=/usr/lib/softhsm/libsofthsm2.so&pin-source=file:/run/secrets/pin"
+            )
+            .unwrap(),
+            Pkcs11Uri {
+                token: Some("My HSM".to_owned()),
+                serial: None,
+                object: Some("client-key".to_owned()),
+                id: Some(vec![1, 2]),
+                module_path: Some(PathBuf::from("/usr/lib/softhsm/libsofthsm2.so")),
+                pin_source: Some(PathBuf::from("/run/secrets/pin")),
+            }
+        );
+        assert!(Pkcs11Uri::parse("pkcs11:token=My%20HSM").is_err());
+        assert!(Pkcs11Uri::parse("pkcs11:object=key?pin-value=1234").is_err());
+        assert!(Pkcs11Uri::parse("yubikey:9a").is_err());
+        assert!(mechanism(Algorithm::EdDSA, b"").is_err());
+    }
+}
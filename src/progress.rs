@@ -0,0 +1,153 @@
+use serde::Serialize;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use url::Url;
+
+use crate::token_info::TokenInfo;
+
+/// Steps of getting a token, ex. for GUI wrappers showing progress to their users.
+/// Every method does nothing by default, so observers implement only the ones they need
+pub trait ProgressObserver: Send + Sync {
+    /// OpenID Connect discovery document is about to be fetched
+    fn on_discovery(&self, _discovery_url: &str) {}
+
+    /// The controlled browser is about to be started
+    fn on_browser_launch(&self, _headless: bool) {}
+
+    /// The authorization url is open and the user has to sign in
+    fn on_waiting_for_user(&self, _authorization_url: &Url) {}
+
+    /// The IdP sent the code to the callback url
+    fn on_code_received(&self) {}
+
+    /// The device code flow polls again, the user has `remaining` time to enter the code
+    fn on_device_code_pending(&self, _remaining: Duration) {}
+
+    /// A token is returned, cached, refreshed or from a new flow
+    fn on_token_received(&self, _state_key: &str, _token_info: &TokenInfo) {}
+
+    /// The token is saved to the state file
+    fn on_cache_write(&self, _state_key: &str) {}
+}
+
+static OBSERVERS: RwLock<Vec<Arc<dyn ProgressObserver>>> = RwLock::new(Vec::new());
+
+/// Registers an observer notified by every following call in the process
+pub fn register(observer: Arc<dyn ProgressObserver>) {
+    OBSERVERS.write().unwrap().push(observer);
+}
+
+pub(crate) fn notify(event: impl Fn(&dyn ProgressObserver)) {
+    for observer in OBSERVERS.read().unwrap().iter() {
+        event(observer.as_ref());
+    }
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub(crate) enum ProgressEvent<'a> {
+    Discovery {
+        discovery_url: &'a str,
+    },
+    BrowserLaunch {
+        headless: bool,
+    },
+    WaitingForUser {
+        authorization_url: &'a str,
+    },
+    CodeReceived,
+    DeviceCodePending {
+        remaining_secs: u64,
+    },
+    TokenReceived {
+        state_key: &'a str,
+        expires: Option<String>,
+    },
+    CacheWrite {
+        state_key: &'a str,
+    },
+}
+
+/// Prints every step as a JSON line to stderr with `--progress-events`. Never prints tokens
+pub struct JsonEventLog;
+
+impl JsonEventLog {
+    fn print(&self, event: ProgressEvent) {
+        if let Ok(line) = serde_json::to_string(&event) {
+            eprintln!("{}", line);
+        }
+    }
+}
+
+fn expires(token_info: &TokenInfo) -> Option<String> {
+    token_info
+        .expires
+        .map(|expires: SystemTime| humantime::format_rfc3339_seconds(expires).to_string())
+}
+
+impl ProgressObserver for JsonEventLog {
+    fn on_discovery(&self, discovery_url: &str) {
+        self.print(ProgressEvent::Discovery { discovery_url });
+    }
+
+    fn on_browser_launch(&self, headless: bool) {
+        self.print(ProgressEvent::BrowserLaunch { headless });
+    }
+
+    fn on_waiting_for_user(&self, authorization_url: &Url) {
+        self.print(ProgressEvent::WaitingForUser {
+            authorization_url: authorization_url.as_str(),
+        });
+    }
+
+    fn on_code_received(&self) {
+        self.print(ProgressEvent::CodeReceived);
+    }
+
+    fn on_device_code_pending(&self, remaining: Duration) {
+        self.print(ProgressEvent::DeviceCodePending {
+            remaining_secs: remaining.as_secs(),
+        });
+    }
+
+    fn on_token_received(&self, state_key: &str, token_info: &TokenInfo) {
+        self.print(ProgressEvent::TokenReceived {
+            state_key,
+            expires: expires(token_info),
+        });
+    }
+
+    fn on_cache_write(&self, state_key: &str) {
+        self.print(ProgressEvent::CacheWrite { state_key });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_logs_events_without_tokens() {
+        let token_info = TokenInfo {
+            access_token: "secret".to_owned(),
+            refresh_token: None,
+            expires: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000)),
+            scope: None,
+            requested_scope: None,
+            extra: Default::default(),
+        };
+
+        assert_eq!(
+            serde_json::to_string(&ProgressEvent::TokenReceived {
+                state_key: "my-client",
+                expires: expires(&token_info),
+            })
+            .unwrap(),
+            r#"{"event":"token-received","state_key":"my-client","expires":"2023-11-14T22:13:20Z"}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&ProgressEvent::CodeReceived).unwrap(),
+            r#"{"event":"code-received"}"#
+        );
+    }
+}
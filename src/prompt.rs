@@ -0,0 +1,158 @@
+use anyhow::{Context, Result, bail};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, Write};
+use std::process::{Command, Output};
+use thiserror::Error;
+
+#[derive(Serialize, Deserialize, ValueEnum, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum PromptBackend {
+    /// Asks in the attached terminal
+    #[default]
+    Terminal,
+    /// Asks via a native dialog (zenity on Linux, osascript on macOS, PowerShell on Windows)
+    Gui,
+}
+
+/// How far doken may interact with the user
+#[derive(Clone, Debug, Default)]
+pub struct PromptOptions {
+    pub backend: PromptBackend,
+    /// Answers yes to every confirmation
+    pub assume_yes: bool,
+    /// Fails with `InputRequired` instead of asking
+    pub non_interactive: bool,
+}
+
+/// Input that `--non-interactive` runs cannot ask for
+#[derive(Error, Debug)]
+#[error("{0} is required, but `--non-interactive` is set")]
+pub struct InputRequired(pub String);
+
+fn terminal_prompt(label: &str) -> Result<String> {
+    rpassword::prompt_password(format!("{}: ", label)).with_context(|| {
+        format!(
+            "Cannot ask for {} without a terminal. Try `--prompt-backend gui`",
+            label
+        )
+    })
+}
+
+fn dialog_command(label: &str) -> Command {
+    if cfg!(target_os = "macos") {
+        let mut command = Command::new("osascript");
+        command.arg("-e").arg(format!(
+            "text returned of (display dialog \"{}:\" default answer \"\" with hidden answer with title \"doken\")",
+            label.replace('"', "\\\"")
+        ));
+        command
+    } else if cfg!(windows) {
+        let mut command = Command::new("powershell");
+        command.args(["-NoProfile", "-Command"]).arg(format!(
+            "$c = Get-Credential -UserName doken -Message '{}'; if ($c) {{ $c.GetNetworkCredential().Password }} else {{ exit 1 }}",
+            label.replace('\'', "''")
+        ));
+        command
+    } else {
+        let mut command = Command::new("zenity");
+        command
+            .args(["--entry", "--hide-text", "--title", "doken", "--text"])
+            .arg(format!("{}:", label));
+        command
+    }
+}
+
+fn gui_prompt(label: &str) -> Result<String> {
+    let mut command = dialog_command(label);
+    log::debug!("Asking for {} via {:?}", label, command.get_program());
+
+    let Output { status, stdout, .. } = command
+        .output()
+        .with_context(|| format!("Cannot open a dialog with {:?}", command.get_program()))?;
+
+    if !status.success() {
+        bail!("{} dialog has been cancelled", label);
+    }
+
+    Ok(String::from_utf8(stdout)
+        .context("Dialog returned a non UTF-8 value")?
+        .trim_end_matches(['\r', '\n'])
+        .to_owned())
+}
+
+pub fn prompt_secret(options: &PromptOptions, label: &str) -> Result<String> {
+    if options.non_interactive {
+        return Err(InputRequired(label.to_owned()).into());
+    }
+
+    match options.backend {
+        PromptBackend::Terminal => terminal_prompt(label),
+        PromptBackend::Gui => gui_prompt(label),
+    }
+}
+
+fn terminal_confirm(question: &str) -> Result<bool> {
+    eprint!("{} [y/N] ", question);
+    std::io::stderr().flush()?;
+
+    let mut answer = String::new();
+    std::io::stdin()
+        .lock()
+        .read_line(&mut answer)
+        .context("Cannot read the answer. Try `--prompt-backend gui`")?;
+
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+fn confirm_dialog_command(question: &str) -> Command {
+    if cfg!(target_os = "macos") {
+        let mut command = Command::new("osascript");
+        command.arg("-e").arg(format!(
+            "display dialog \"{}\" buttons {{\"Cancel\", \"Continue\"}} default button \"Continue\" with title \"doken\"",
+            question.replace('"', "\\\"")
+        ));
+        command
+    } else if cfg!(windows) {
+        let mut command = Command::new("powershell");
+        command.args(["-NoProfile", "-Command"]).arg(format!(
+            "Add-Type -AssemblyName PresentationFramework; if ([System.Windows.MessageBox]::Show('{}', 'doken', 'OKCancel') -ne 'OK') {{ exit 1 }}",
+            question.replace('\'', "''")
+        ));
+        command
+    } else {
+        let mut command = Command::new("zenity");
+        command
+            .args(["--question", "--title", "doken", "--text"])
+            .arg(question);
+        command
+    }
+}
+
+fn gui_confirm(question: &str) -> Result<bool> {
+    let mut command = confirm_dialog_command(question);
+    log::debug!("Asking for confirmation via {:?}", command.get_program());
+
+    let status = command
+        .status()
+        .with_context(|| format!("Cannot open a dialog with {:?}", command.get_program()))?;
+
+    Ok(status.success())
+}
+
+/// Asks a yes/no question. Anything but an explicit yes is a no
+pub fn confirm(options: &PromptOptions, question: &str) -> Result<bool> {
+    if options.assume_yes {
+        log::debug!("{} Assuming yes", question);
+        return Ok(true);
+    }
+
+    if options.non_interactive {
+        return Err(InputRequired(format!("An answer to `{}`", question)).into());
+    }
+
+    match options.backend {
+        PromptBackend::Terminal => terminal_confirm(question),
+        PromptBackend::Gui => gui_confirm(question),
+    }
+}
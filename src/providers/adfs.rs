@@ -0,0 +1,47 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use url::Url;
+
+/// ADFS error payloads use either the OAuth 2.0 shape or the .NET one with `Message`
+#[derive(Deserialize, Debug)]
+struct ErrorPayload {
+    error: Option<String>,
+    error_description: Option<String>,
+    #[serde(rename = "Message")]
+    message: Option<String>,
+}
+
+/// Endpoints every ADFS farm exposes, used when the discovery document is unavailable or incomplete
+pub fn default_endpoints(discovery_url: &str) -> Result<(String, String)> {
+    let url = Url::parse(discovery_url)
+        .with_context(|| format!("`--discovery-url` is not a correct URL: {}", discovery_url))?;
+    let origin = url.origin().ascii_serialization();
+
+    Ok((
+        format!("{}/adfs/oauth2/token", origin),
+        format!("{}/adfs/oauth2/authorize", origin),
+    ))
+}
+
+/// Readable message out of an error body that doesn't follow RFC 6749, ex. `MSIS9607: ...` or an HTML error page
+pub fn describe_error(body: &[u8]) -> String {
+    let text = String::from_utf8_lossy(body);
+
+    if let Ok(payload) = serde_json::from_str::<ErrorPayload>(&text) {
+        let parts: Vec<String> = [payload.error, payload.error_description, payload.message]
+            .into_iter()
+            .flatten()
+            .collect();
+
+        if !parts.is_empty() {
+            return format!("ADFS error: {}", parts.join(": "));
+        }
+    }
+
+    let text = match (text.find("<title>"), text.find("</title>")) {
+        (Some(start), Some(end)) if start < end => text[start + "<title>".len()..end].trim(),
+        _ => text.trim(),
+    };
+
+    format!("ADFS error: {}", text.chars().take(200).collect::<String>())
+}
@@ -0,0 +1,244 @@
+use oauth2::basic::BasicErrorResponse;
+use serde::Deserialize;
+use std::fmt;
+
+/// What the standard error codes usually mean in practice. <https://www.rfc-editor.org/rfc/rfc6749#section-5.2>
+const ERROR_CODES: [(&str, &str); 10] = [
+    (
+        "invalid_request",
+        "The request is missing a parameter or has an unsupported one",
+    ),
+    (
+        "invalid_client",
+        "The provider doesn't recognize the client or its secret",
+    ),
+    (
+        "invalid_grant",
+        "The code, refresh_token or credentials are invalid, expired or already used. Try again with `--force`",
+    ),
+    (
+        "unauthorized_client",
+        "The client isn't allowed to use this grant. Check the grant types enabled for the application",
+    ),
+    (
+        "unsupported_grant_type",
+        "The provider doesn't support this grant",
+    ),
+    (
+        "invalid_scope",
+        "The requested `--scope` is unknown or not allowed for the client",
+    ),
+    (
+        "access_denied",
+        "The user or the provider denied the authorization",
+    ),
+    (
+        "consent_required",
+        "The user has to consent to the requested scopes first",
+    ),
+    ("login_required", "The user has to log in interactively"),
+    (
+        "invalid_target",
+        "The requested `--audience` is unknown or not allowed for the client",
+    ),
+];
+
+/// Known provider specific codes found in `error_description`, ex. Microsoft Entra ID's AADSTS codes
+/// <https://learn.microsoft.com/en-us/entra/identity-platform/reference-error-codes>
+const PROVIDER_CODES: [(&str, &str); 14] = [
+    (
+        "AADSTS50011",
+        "`--callback-url` doesn't match any redirect URI registered for the application",
+    ),
+    (
+        "AADSTS50076",
+        "Multi-factor authentication is required. Use a browser based grant",
+    ),
+    (
+        "AADSTS50079",
+        "The user has to enroll in multi-factor authentication",
+    ),
+    ("AADSTS50126", "Invalid username or password"),
+    (
+        "AADSTS50173",
+        "The grant has been revoked, ex. after a password change. Try again with `--force`",
+    ),
+    (
+        "AADSTS65001",
+        "The user or an administrator hasn't consented to the application",
+    ),
+    (
+        "AADSTS70011",
+        "The requested `--scope` is invalid. Use `<resource>/.default` or scopes of a single resource",
+    ),
+    (
+        "AADSTS700016",
+        "The application wasn't found in the tenant. Check `--client-id` and the tenant in the URLs",
+    ),
+    (
+        "AADSTS700082",
+        "The refresh_token expired due to inactivity. Try again with `--force`",
+    ),
+    (
+        "AADSTS7000215",
+        "Invalid client secret. Check if it hasn't expired and that the secret value, not its id, is used",
+    ),
+    (
+        "AADSTS7000218",
+        "The application requires a client secret or assertion",
+    ),
+    (
+        "AADSTS90014",
+        "A required parameter is missing in the request",
+    ),
+    (
+        "AADSTS90033",
+        "The provider is temporarily unavailable or throttling requests. Try again later",
+    ),
+    (
+        "AADSTS50196",
+        "The client sent too many identical requests in a short time (request loop)",
+    ),
+];
+
+/// Standard and provider specific codes meaning the client should slow down
+const THROTTLING_CODES: [&str; 4] = [
+    "temporarily_unavailable",
+    "slow_down",
+    "AADSTS90033",
+    "AADSTS50196",
+];
+
+/// `error` response of a token or authorization endpoint rendered for humans
+#[derive(Debug, Deserialize)]
+pub struct ProviderError {
+    error: String,
+    error_description: Option<String>,
+    error_uri: Option<String>,
+    #[serde(skip)]
+    hint: Option<String>,
+}
+
+impl ProviderError {
+    pub fn new(
+        error: String,
+        error_description: Option<String>,
+        error_uri: Option<String>,
+    ) -> Self {
+        ProviderError {
+            error,
+            error_description,
+            error_uri,
+            hint: None,
+        }
+    }
+
+    /// Parses an error body that the oauth2 crate couldn't, ex. sent with an unexpected status code
+    pub fn from_body(body: &[u8]) -> Option<Self> {
+        serde_json::from_slice(body).ok()
+    }
+
+    pub fn code(&self) -> &str {
+        &self.error
+    }
+
+    /// Whether the code is one of the standard ones, which are safe to report without leaking provider details
+    pub fn is_standard(&self) -> bool {
+        ERROR_CODES.iter().any(|(code, _)| *code == self.error)
+    }
+
+    pub fn is_throttling(&self) -> bool {
+        let description = self.error_description.as_deref().unwrap_or_default();
+
+        THROTTLING_CODES
+            .iter()
+            .any(|code| *code == self.error || description.contains(code))
+    }
+
+    pub fn with_hint(self, hint: String) -> Self {
+        ProviderError {
+            hint: Some(hint),
+            ..self
+        }
+    }
+
+    fn explanations(&self) -> Vec<&'static str> {
+        let description = self.error_description.as_deref().unwrap_or_default();
+        let provider = PROVIDER_CODES
+            .iter()
+            .filter(|(code, _)| description.contains(code))
+            .map(|(_, explanation)| *explanation);
+        let standard = ERROR_CODES
+            .iter()
+            .filter(|(code, _)| *code == self.error)
+            .map(|(_, explanation)| *explanation);
+
+        // The provider specific explanation is more precise than the standard one
+        provider.chain(standard).take(1).collect()
+    }
+}
+
+impl From<&BasicErrorResponse> for ProviderError {
+    fn from(response: &BasicErrorResponse) -> Self {
+        ProviderError::new(
+            response.error().to_string(),
+            response.error_description().cloned(),
+            response.error_uri().cloned(),
+        )
+    }
+}
+
+impl fmt::Display for ProviderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "The provider responded with `{}`", self.error)?;
+
+        if let Some(description) = &self.error_description {
+            write!(f, ": {}", description.trim())?;
+        }
+
+        for explanation in self.explanations() {
+            write!(f, "\n  {}", explanation)?;
+        }
+
+        if let Some(hint) = &self.hint {
+            write!(f, "\n  {}", hint)?;
+        }
+
+        if let Some(uri) = &self.error_uri {
+            write!(f, "\n  More: {}", uri)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for ProviderError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_explains_known_provider_codes() {
+        let error = ProviderError::new(
+            "invalid_grant".to_owned(),
+            Some("AADSTS700082: The refresh token has expired due to inactivity.".to_owned()),
+            Some("https://login.microsoftonline.com/error?code=700082".to_owned()),
+        );
+
+        assert_eq!(
+            error.to_string(),
+            "The provider responded with `invalid_grant`: AADSTS700082: The refresh token has expired due to inactivity.\n  \
+             The refresh_token expired due to inactivity. Try again with `--force`\n  \
+             More: https://login.microsoftonline.com/error?code=700082"
+        );
+
+        let error = ProviderError::new("invalid_scope".to_owned(), None, None);
+
+        assert_eq!(
+            error.to_string(),
+            "The provider responded with `invalid_scope`\n  \
+             The requested `--scope` is unknown or not allowed for the client"
+        );
+    }
+}
@@ -0,0 +1,43 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+pub mod adfs;
+pub mod errors;
+pub mod okta;
+
+#[derive(Serialize, Deserialize, ValueEnum, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Provider {
+    /// Any standard compliant OAuth 2.0 / OpenID Connect provider
+    #[default]
+    Generic,
+    /// Active Directory Federation Services 2016/2019. Sends `--audience` as `resource`, tolerates incomplete discovery documents and non-standard errors
+    Adfs,
+    /// Microsoft Entra ID (Azure AD). Sends `--audience` as `resource` for v1 endpoints. One refresh token mints tokens for every resource (audience or scope) the client may access
+    Azure,
+}
+
+impl Provider {
+    /// Name of the parameter carrying `--audience` in authorization and token requests
+    pub fn audience_param(&self) -> &'static str {
+        match self {
+            Provider::Generic => "audience",
+            Provider::Adfs | Provider::Azure => "resource",
+        }
+    }
+
+    /// Refresh tokens redeemable for other resources, so tokens are cached per resource and a new
+    /// resource is requested with an existing refresh token instead of a new interactive flow
+    pub fn has_multi_resource_refresh_tokens(&self) -> bool {
+        matches!(self, Provider::Adfs | Provider::Azure)
+    }
+
+    /// Authorization parameters of `--tenant-switch` making the provider ask for the account or
+    /// tenant instead of reusing its session. ADFS knows only `prompt=login`
+    pub fn tenant_switch_params(&self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Provider::Adfs => &[("prompt", "login")],
+            Provider::Generic | Provider::Azure => &[("prompt", "select_account")],
+        }
+    }
+}
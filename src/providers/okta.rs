@@ -0,0 +1,144 @@
+use anyhow::{Context, Result, anyhow, bail};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use url::Url;
+
+const TOTP_FACTOR_TYPE: &str = "token:software:totp";
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct AuthnResponse {
+    status: String,
+    session_token: Option<String>,
+    state_token: Option<String>,
+    #[serde(rename = "_embedded")]
+    embedded: Option<AuthnEmbedded>,
+}
+
+#[derive(Deserialize, Debug)]
+struct AuthnEmbedded {
+    factors: Option<Vec<Factor>>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Factor {
+    factor_type: String,
+    #[serde(rename = "_links")]
+    links: FactorLinks,
+}
+
+#[derive(Deserialize, Debug)]
+struct FactorLinks {
+    verify: Link,
+}
+
+#[derive(Deserialize, Debug)]
+struct Link {
+    href: String,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct VerifyRequest<'a> {
+    state_token: &'a str,
+    pass_code: &'a str,
+}
+
+/// Okta Classic Authentication API <https://developer.okta.com/docs/reference/api/authn/>
+pub struct OktaAuthn<'a> {
+    http: &'a reqwest::Client,
+    org_url: Url,
+}
+
+impl OktaAuthn<'_> {
+    /// Okta org is the origin of the authorization server ex. `https://my-org.okta.com`
+    pub fn new<'b>(http: &'b reqwest::Client, authorization_url: &Url) -> OktaAuthn<'b> {
+        OktaAuthn {
+            http,
+            org_url: Url::parse(&authorization_url.origin().ascii_serialization()).unwrap(),
+        }
+    }
+
+    async fn post<T: Serialize>(&self, url: &str, body: &T) -> Result<AuthnResponse> {
+        let response = self
+            .http
+            .post(url)
+            .json(body)
+            .send()
+            .await
+            .context("Couldn't reach out to Okta Authentication API")?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            bail!(
+                "Okta Authentication API responded with {}: {}",
+                status,
+                body
+            );
+        }
+
+        response
+            .json::<AuthnResponse>()
+            .await
+            .context("Couldn't process Okta Authentication API response")
+    }
+
+    async fn verify_totp(&self, response: AuthnResponse, pass_code: &str) -> Result<AuthnResponse> {
+        let state_token = response
+            .state_token
+            .context("Okta didn't return a stateToken for MFA verification")?;
+        let factor = response
+            .embedded
+            .and_then(|embedded| embedded.factors)
+            .unwrap_or_default()
+            .into_iter()
+            .find(|factor| factor.factor_type == TOTP_FACTOR_TYPE)
+            .context("User has no TOTP factor enrolled. Only TOTP MFA is supported")?;
+
+        log::debug!("Verifying TOTP factor");
+        self.post(
+            &factor.links.verify.href,
+            &VerifyRequest {
+                state_token: &state_token,
+                pass_code,
+            },
+        )
+        .await
+    }
+
+    /// Exchanges user credentials for a one-time sessionToken. `mfa_code` is asked only if
+    /// Okta requires MFA for the user
+    pub async fn session_token<F>(
+        &self,
+        username: &str,
+        password: &str,
+        mfa_code: F,
+    ) -> Result<String>
+    where
+        F: Fn() -> Result<String>,
+    {
+        log::debug!("Requesting Okta sessionToken from {}", self.org_url);
+        let authn_url = self.org_url.join("/api/v1/authn")?;
+
+        let mut response = self
+            .post(
+                authn_url.as_str(),
+                &json!({ "username": username, "password": password }),
+            )
+            .await?;
+
+        if response.status == "MFA_REQUIRED" {
+            response = self.verify_totp(response, &mfa_code()?).await?;
+        }
+
+        match (response.status.as_str(), response.session_token) {
+            ("SUCCESS", Some(session_token)) => Ok(session_token),
+            (status, _) => Err(anyhow!(
+                "Okta authentication ended with unsupported status `{}`",
+                status
+            )),
+        }
+    }
+}
@@ -1,16 +1,18 @@
 use crate::args::Arguments;
-use crate::auth_browser::page::Page;
+use crate::auth_browser::CodeSource;
 use crate::oauth_client::OAuthClient;
+use crate::pending_flow::{self, PendingFlow};
 use crate::token_info::TokenInfo;
 use anyhow::Result;
 use async_trait::async_trait;
+use oauth2::CsrfToken;
 use url::Url;
 
 use super::token_retriever::TokenRetriever;
 
 pub struct AuthorizationCodeRetriever<'a> {
     oauth_client: &'a OAuthClient<'a>,
-    auth_page: Page,
+    code_source: Box<dyn CodeSource>,
     args: &'a Arguments,
 }
 
@@ -18,11 +20,11 @@ impl AuthorizationCodeRetriever<'_> {
     pub fn new<'b>(
         args: &'b Arguments,
         oauth_client: &'b OAuthClient<'b>,
-        auth_page: Page,
+        code_source: Box<dyn CodeSource>,
     ) -> AuthorizationCodeRetriever<'b> {
         AuthorizationCodeRetriever {
             oauth_client,
-            auth_page,
+            code_source,
             args,
         }
     }
@@ -31,20 +33,35 @@ impl AuthorizationCodeRetriever<'_> {
 #[async_trait(?Send)]
 impl TokenRetriever for AuthorizationCodeRetriever<'_> {
     async fn retrieve(&mut self) -> Result<TokenInfo> {
-        let (url, csrf, _nonce) = self.oauth_client.authorize_url(None);
+        let flow = pending_flow::resume_or_start(self.args, || {
+            let (url, csrf, nonce) = self.oauth_client.authorize_url(None);
 
-        let code = self
-            .auth_page
+            PendingFlow::new(
+                self.args,
+                url.to_string(),
+                csrf.secret().to_owned(),
+                None,
+                nonce,
+            )
+        });
+
+        let response = self
+            .code_source
             .get_code(
                 self.args.timeout,
-                url,
+                Url::parse(&flow.authorization_url)?,
                 Url::parse(self.args.callback_url.as_deref().unwrap())?,
-                csrf,
+                CsrfToken::new(flow.csrf_token),
             )
             .await?;
 
-        let token = self.oauth_client.exchange_code(&code, None).await?;
+        let token = match self.oauth_client.verify_authorization_response(&response) {
+            Ok(()) => self.oauth_client.exchange_code(&response.code, None).await,
+            Err(e) => Err(e),
+        };
+        PendingFlow::finish(self.args);
+        let token = token?;
 
-        Ok(TokenInfo::from_token_response(token))
+        Ok(TokenInfo::from_token_response(token).with_session(response.session_state))
     }
 }
@@ -1,17 +1,18 @@
 use crate::args::Arguments;
-use crate::auth_browser::page::Page;
+use crate::auth_browser::CodeSource;
 use crate::oauth_client::OAuthClient;
+use crate::pending_flow::{self, PendingFlow};
 use crate::token_info::TokenInfo;
 use anyhow::Result;
 use async_trait::async_trait;
-use oauth2::PkceCodeChallenge;
+use oauth2::{CsrfToken, PkceCodeVerifier};
 use url::Url;
 
 use super::token_retriever::TokenRetriever;
 
 pub struct AuthorizationCodeWithPKCERetriever<'a> {
     oauth_client: &'a OAuthClient<'a>,
-    auth_page: Page,
+    code_source: Box<dyn CodeSource>,
     args: &'a Arguments,
 }
 
@@ -19,11 +20,11 @@ impl AuthorizationCodeWithPKCERetriever<'_> {
     pub fn new<'b>(
         args: &'b Arguments,
         oauth_client: &'b OAuthClient<'b>,
-        auth_page: Page,
+        code_source: Box<dyn CodeSource>,
     ) -> AuthorizationCodeWithPKCERetriever<'b> {
         AuthorizationCodeWithPKCERetriever {
             oauth_client,
-            auth_page,
+            code_source,
             args,
         }
     }
@@ -32,25 +33,43 @@ impl AuthorizationCodeWithPKCERetriever<'_> {
 #[async_trait(?Send)]
 impl TokenRetriever for AuthorizationCodeWithPKCERetriever<'_> {
     async fn retrieve(&mut self) -> Result<TokenInfo> {
-        let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+        let flow = pending_flow::resume_or_start(self.args, || {
+            let (pkce_challenge, pkce_verifier) = self.oauth_client.pkce();
+            let (url, csrf, nonce) = self.oauth_client.authorize_url(Some(pkce_challenge));
 
-        let (url, csrf, _nonce) = self.oauth_client.authorize_url(Some(pkce_challenge));
+            PendingFlow::new(
+                self.args,
+                url.to_string(),
+                csrf.secret().to_owned(),
+                Some(pkce_verifier.secret().to_owned()),
+                nonce,
+            )
+        });
 
-        let code = self
-            .auth_page
+        let response = self
+            .code_source
             .get_code(
                 self.args.timeout,
-                url,
+                Url::parse(&flow.authorization_url)?,
                 Url::parse(self.args.callback_url.as_deref().unwrap())?,
-                csrf,
+                CsrfToken::new(flow.csrf_token),
             )
             .await?;
 
-        let token = self
-            .oauth_client
-            .exchange_code(&code, Some(pkce_verifier))
-            .await?;
+        let token = match self.oauth_client.verify_authorization_response(&response) {
+            Ok(()) => {
+                self.oauth_client
+                    .exchange_code(
+                        &response.code,
+                        flow.pkce_verifier.map(PkceCodeVerifier::new),
+                    )
+                    .await
+            }
+            Err(e) => Err(e),
+        };
+        PendingFlow::finish(self.args);
+        let token = token?;
 
-        return Ok(TokenInfo::from_token_response(token));
+        return Ok(TokenInfo::from_token_response(token).with_session(response.session_state));
     }
 }
@@ -0,0 +1,108 @@
+use crate::OAuthClient;
+use crate::output;
+use crate::progress;
+use crate::providers::errors::ProviderError;
+use crate::token_info::TokenInfo;
+use anyhow::{Result, bail};
+use async_trait::async_trait;
+use std::time::{Duration, Instant};
+
+use super::token_retriever::TokenRetriever;
+
+/// Polling interval when the provider doesn't give one <https://www.rfc-editor.org/rfc/rfc8628#section-3.2>
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// `slow_down` asks to poll this much less often
+const SLOW_DOWN_INCREMENT: Duration = Duration::from_secs(5);
+
+pub struct DeviceCodeRetriever<'a> {
+    oauth_client: &'a OAuthClient<'a>,
+}
+
+impl DeviceCodeRetriever<'_> {
+    pub fn new<'b>(oauth_client: &'b OAuthClient<'b>) -> DeviceCodeRetriever<'b> {
+        DeviceCodeRetriever { oauth_client }
+    }
+}
+
+/// Interval of the next poll while the user hasn't finished yet, `None` for any other error
+fn next_interval(error: &anyhow::Error, interval: Duration) -> Option<Duration> {
+    match error.downcast_ref::<ProviderError>()?.code() {
+        "authorization_pending" => Some(interval),
+        "slow_down" => Some(interval + SLOW_DOWN_INCREMENT),
+        _ => None,
+    }
+}
+
+#[async_trait(?Send)]
+impl TokenRetriever for DeviceCodeRetriever<'_> {
+    async fn retrieve(&mut self) -> Result<TokenInfo> {
+        let authorization = self.oauth_client.device_authorization().await?;
+        let expires_at = Instant::now() + Duration::from_secs(authorization.expires_in);
+        let mut interval = authorization
+            .interval
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_INTERVAL);
+
+        output::status(
+            "Visit",
+            &format!(
+                "{} on any device and enter the code {}",
+                authorization.verification_uri, authorization.user_code
+            ),
+        );
+        if let Some(complete) = &authorization.verification_uri_complete {
+            output::status("Or open", complete);
+        }
+
+        loop {
+            let remaining = expires_at.saturating_duration_since(Instant::now());
+            progress::notify(|observer| observer.on_device_code_pending(remaining));
+            tokio::time::sleep(interval).await;
+
+            match self
+                .oauth_client
+                .exchange_device_code(&authorization.device_code)
+                .await
+            {
+                Ok(response) => return Ok(TokenInfo::from_token_response(response)),
+                Err(e) => match next_interval(&e, interval) {
+                    Some(next) => {
+                        log::debug!("Device authorization is pending");
+                        interval = next;
+                    }
+                    None => return Err(e),
+                },
+            }
+
+            if Instant::now() + interval >= expires_at {
+                bail!("The device code expired before the authorization was completed");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_keeps_polling_while_the_authorization_is_pending() {
+        let error = |code: &str| {
+            anyhow::Error::new(ProviderError::new(code.to_owned(), None, None))
+                .context("Failed to exchange device code for a token")
+        };
+        let interval = Duration::from_secs(5);
+
+        assert_eq!(
+            next_interval(&error("authorization_pending"), interval),
+            Some(interval)
+        );
+        assert_eq!(
+            next_interval(&error("slow_down"), interval),
+            Some(Duration::from_secs(10))
+        );
+        assert_eq!(next_interval(&error("access_denied"), interval), None);
+        assert_eq!(next_interval(&error("expired_token"), interval), None);
+    }
+}
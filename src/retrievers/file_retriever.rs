@@ -1,6 +1,10 @@
 use crate::FileState;
 use crate::args::Arguments;
+use crate::claims;
+use crate::explain::Explanation;
+use crate::jwt;
 use crate::oauth_client::OAuthClient;
+use crate::output;
 use crate::token_info::TokenInfo;
 use anyhow::Result;
 use async_trait::async_trait;
@@ -19,6 +23,7 @@ pub struct FileRetriever<'a> {
     oauth_client: &'a OAuthClient<'a>,
     file_state: &'a mut FileState,
     args: &'a Arguments,
+    explanation: &'a mut Explanation,
 }
 
 impl FileRetriever<'_> {
@@ -26,15 +31,21 @@ impl FileRetriever<'_> {
         args: &'b Arguments,
         oauth_client: &'b OAuthClient<'b>,
         file_state: &'b mut FileState,
+        explanation: &'b mut Explanation,
     ) -> FileRetriever<'b> {
         FileRetriever {
             oauth_client,
             file_state,
             args,
+            explanation,
         }
     }
 
-    async fn refresh_token(&mut self, refresh_token: &str) -> Result<TokenInfo> {
+    async fn refresh_token(
+        &mut self,
+        cached: &TokenInfo,
+        refresh_token: &str,
+    ) -> Result<TokenInfo> {
         let result = self
             .oauth_client
             .refresh_token(refresh_token.to_owned())
@@ -42,51 +53,165 @@ impl FileRetriever<'_> {
 
         match result {
             Ok(token_response) => {
-                let token_info = TokenInfo::from_token_response(token_response);
+                let mut token_info = TokenInfo::from_token_response(token_response)
+                    .with_session_of(cached)
+                    .with_clock_skew(self.oauth_client.clock_skew());
+                token_info.requested_scope = cached.requested_scope.clone();
+                jwt::verify_token_info(&token_info, self.args, self.oauth_client).await?;
 
                 self.file_state
-                    .upsert_token_info(self.args.client_id.to_owned(), token_info.to_owned())?;
+                    .upsert_token_info(self.args.state_key(), token_info.to_owned())?;
+                self.explanation.step("Refreshed the token");
 
                 Ok(token_info)
             }
-            Err(_) => {
-                self.file_state
-                    .clear_token_info(self.args.client_id.to_owned())?;
+            Err(e) => {
+                self.explanation.step(format!(
+                    "The provider rejected the refresh_token ({:#}), so the cached token was removed",
+                    e
+                ));
+                self.file_state.clear_token_info(self.args.state_key())?;
 
                 Err(FileRetrieverError::TokenInfoNotFound.into())
             }
         }
     }
+
+    /// Requests a new resource with a refresh token cached for another one, if the provider allows it
+    async fn redeem_related_refresh_token(&mut self) -> Result<TokenInfo> {
+        if !self.args.provider.has_multi_resource_refresh_tokens() {
+            return Err(FileRetrieverError::TokenInfoNotFound.into());
+        }
+
+        // Refresh tokens of other resources may belong to another tenant
+        if self.args.tenant.is_some() {
+            return Err(FileRetrieverError::TokenInfoNotFound.into());
+        }
+
+        let Some(related) = self
+            .file_state
+            .read_related_refresh_token_info(&self.args.client_id)
+        else {
+            self.explanation
+                .step("No refresh_token of another resource of the client is cached either");
+            return Err(FileRetrieverError::TokenInfoNotFound.into());
+        };
+        self.explanation.step(
+            "Redeeming the refresh_token of another resource, as the provider issues multi-resource refresh tokens",
+        );
+
+        log::debug!(
+            "Redeeming a refresh token of another resource for {}",
+            self.args.state_key()
+        );
+        let token_response = self
+            .oauth_client
+            .refresh_token(related.refresh_token.unwrap_or_default())
+            .await
+            .map_err(|e| {
+                self.explanation.step(format!(
+                    "The provider rejected the refresh_token of another resource ({:#})",
+                    e
+                ));
+                FileRetrieverError::TokenInfoNotFound
+            })?;
+
+        let token_info = TokenInfo::from_token_response(token_response)
+            .with_requested_scope(&self.args.scope)
+            .with_clock_skew(self.oauth_client.clock_skew());
+        jwt::verify_token_info(&token_info, self.args, self.oauth_client).await?;
+
+        self.file_state
+            .upsert_token_info(self.args.state_key(), token_info.to_owned())?;
+
+        Ok(token_info)
+    }
 }
 
 #[async_trait(?Send)]
 impl TokenRetriever for FileRetriever<'_> {
     async fn retrieve(&mut self) -> Result<TokenInfo> {
-        let token_info = self.file_state.read_token_info(&self.args.client_id);
+        let token_info = self.file_state.read_token_info(&self.args.state_key());
 
         if token_info.is_none() {
-            return Err(FileRetrieverError::TokenInfoNotFound.into());
+            self.explanation.step(format!(
+                "No token is cached under `{}`",
+                self.args.state_key()
+            ));
+            return self.redeem_related_refresh_token().await;
         }
 
         let token_info = token_info.unwrap();
 
+        let (added, removed) = token_info.scope_changes(&self.args.scope);
+        if !added.is_empty() || !removed.is_empty() {
+            self.explanation.step(format!(
+                "The cached token was requested with other scopes (added: [{}], removed: [{}])",
+                added.join(" "),
+                removed.join(" ")
+            ));
+            output::status(
+                "Re-consenting",
+                &format!(
+                    "because scopes changed since the token was requested (added: [{}], removed: [{}])",
+                    added.join(" "),
+                    removed.join(" ")
+                ),
+            );
+
+            return Err(FileRetrieverError::TokenInfoNotFound.into());
+        }
+
+        let unmet = claims::unmet(&token_info, &self.args.require_claim);
+        if !unmet.is_empty() {
+            self.explanation.step(format!(
+                "The cached token doesn't carry {}",
+                claims::describe(&unmet)
+            ));
+            output::status(
+                "Re-authenticating",
+                &format!(
+                    "because the cached token doesn't carry {}",
+                    claims::describe(&unmet)
+                ),
+            );
+
+            return Err(FileRetrieverError::TokenInfoNotFound.into());
+        }
+
         let expires = token_info.expires.unwrap_or_else(SystemTime::now);
 
         let is_token_expired = expires < SystemTime::now();
 
         if !is_token_expired {
+            self.explanation.step(match token_info.expires {
+                Some(expires) => {
+                    format!(
+                        "The cached token is valid until {}",
+                        output::timestamp(expires, self.args.utc)
+                    )
+                }
+                None => "The cached token has no expiry".to_owned(),
+            });
             return Ok(token_info);
         }
 
-        match token_info.refresh_token {
+        match &token_info.refresh_token {
             Some(token) => {
-                let token_info = self.refresh_token(&token).await?;
+                self.explanation.step(format!(
+                    "The cached token expired at {}, refreshing it with the cached refresh_token",
+                    output::timestamp(expires, self.args.utc)
+                ));
+                let token_info = self.refresh_token(&token_info, token).await?;
 
                 Ok(token_info)
             }
             None => {
-                self.file_state
-                    .clear_token_info(self.args.client_id.to_owned())?;
+                self.explanation.step(format!(
+                    "The cached token expired at {} and there's no refresh_token",
+                    output::timestamp(expires, self.args.utc)
+                ));
+                self.file_state.clear_token_info(self.args.state_key())?;
 
                 Err(FileRetrieverError::TokenInfoNotFound.into())
             }
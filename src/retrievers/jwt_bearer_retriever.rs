@@ -0,0 +1,32 @@
+use crate::args::Arguments;
+use crate::jwt_bearer::AssertionKey;
+use crate::{OAuthClient, token_info::TokenInfo};
+use anyhow::Result;
+use async_trait::async_trait;
+
+use super::token_retriever::TokenRetriever;
+
+pub struct JwtBearerRetriever<'a> {
+    args: &'a Arguments,
+    oauth_client: &'a OAuthClient<'a>,
+}
+
+impl JwtBearerRetriever<'_> {
+    pub fn new<'b>(
+        args: &'b Arguments,
+        oauth_client: &'b OAuthClient<'b>,
+    ) -> JwtBearerRetriever<'b> {
+        JwtBearerRetriever { args, oauth_client }
+    }
+}
+
+#[async_trait(?Send)]
+impl TokenRetriever for JwtBearerRetriever<'_> {
+    async fn retrieve(&mut self) -> Result<TokenInfo> {
+        let key = AssertionKey::read(self.args)?;
+
+        Ok(TokenInfo::from_token_response(
+            self.oauth_client.exchange_jwt_bearer(&key).await?,
+        ))
+    }
+}
@@ -1,7 +1,13 @@
 pub mod authorization_code_retriever;
 pub mod authorization_code_with_pkce_retriever;
 pub mod client_credentials_retriever;
+pub mod device_code_retriever;
 pub mod file_retriever;
 pub mod implicit_retriever;
+pub mod jwt_bearer_retriever;
+pub mod okta_session_token_retriever;
+pub mod refresh_token_retriever;
 pub mod resource_owner_password_client_credentials_retriever;
+pub mod saml2_bearer_retriever;
+pub mod token_exchange_retriever;
 pub mod token_retriever;
@@ -0,0 +1,91 @@
+use crate::OAuthClient;
+use crate::args::Arguments;
+use crate::prompt::prompt_secret;
+use crate::providers::errors::ProviderError;
+use crate::providers::okta::OktaAuthn;
+use crate::token_info::TokenInfo;
+use anyhow::{Context, Result, anyhow};
+use async_trait::async_trait;
+use reqwest::header::LOCATION;
+use url::Url;
+
+use super::token_retriever::TokenRetriever;
+
+pub struct OktaSessionTokenRetriever<'a> {
+    args: &'a Arguments,
+    oauth_client: &'a OAuthClient<'a>,
+}
+
+impl OktaSessionTokenRetriever<'_> {
+    pub fn new<'b>(
+        args: &'b Arguments,
+        oauth_client: &'b OAuthClient<'b>,
+    ) -> OktaSessionTokenRetriever<'b> {
+        OktaSessionTokenRetriever { args, oauth_client }
+    }
+}
+
+#[async_trait(?Send)]
+impl TokenRetriever for OktaSessionTokenRetriever<'_> {
+    async fn retrieve(&mut self) -> Result<TokenInfo> {
+        let (pkce_challenge, pkce_verifier) = self.oauth_client.pkce();
+        let (mut url, csrf, _nonce) = self.oauth_client.authorize_url(Some(pkce_challenge));
+
+        let session_token = OktaAuthn::new(self.oauth_client.http(), &url)
+            .session_token(
+                self.args.username.as_deref().unwrap(),
+                self.args.password.as_deref().unwrap(),
+                || prompt_secret(&self.args.prompt_options(), "MFA code"),
+            )
+            .await?;
+
+        url.query_pairs_mut()
+            .append_pair("sessionToken", &session_token);
+
+        log::debug!("Completing authorization with sessionToken");
+        let response = self
+            .oauth_client
+            .http()
+            .get(url)
+            .send()
+            .await
+            .context("Failed to complete authorization with Okta sessionToken")?;
+
+        let location = response
+            .headers()
+            .get(LOCATION)
+            .context("Okta didn't redirect to `--callback-url` after authorization")?
+            .to_str()?;
+        let location = Url::parse(location)?;
+        let param = |name: &str| {
+            location
+                .query_pairs()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.to_string())
+        };
+
+        if let Some(error) = param("error") {
+            return Err(anyhow!(ProviderError::new(
+                error,
+                param("error_description"),
+                param("error_uri"),
+            ))
+            .context("Authorization failed"));
+        }
+
+        if param("state").as_deref() != Some(csrf.secret()) {
+            return Err(anyhow!(
+                "Incorrect CSRF token in Okta authorization response"
+            ));
+        }
+
+        let code = param("code").context("Okta authorization response has no code")?;
+
+        let token = self
+            .oauth_client
+            .exchange_code(&code, Some(pkce_verifier))
+            .await?;
+
+        Ok(TokenInfo::from_token_response(token))
+    }
+}
@@ -0,0 +1,69 @@
+use crate::args::Arguments;
+use crate::prompt::prompt_secret;
+use crate::{OAuthClient, token_info::TokenInfo};
+use anyhow::{Context, Result, bail};
+use async_trait::async_trait;
+use std::io::{IsTerminal, Read};
+
+use super::token_retriever::TokenRetriever;
+
+/// Piped refresh tokens are read as they are, a terminal is asked like for other secrets
+pub fn read_refresh_token(args: &Arguments) -> Result<String> {
+    if std::io::stdin().is_terminal() {
+        return prompt_secret(&args.prompt_options(), "Refresh token");
+    }
+
+    let mut refresh_token = String::new();
+    std::io::stdin()
+        .read_to_string(&mut refresh_token)
+        .context("Cannot read the refresh token from standard input")?;
+
+    match refresh_token.trim() {
+        "" => bail!("The standard input has no refresh token"),
+        refresh_token => Ok(refresh_token.to_owned()),
+    }
+}
+
+/// Refresh token of `--refresh-token` or `--refresh-token-stdin`, if given
+pub fn external_refresh_token(args: &Arguments) -> Result<Option<String>> {
+    match &args.refresh_token {
+        _ if args.refresh_token_stdin => read_refresh_token(args).map(Some),
+        refresh_token => Ok(refresh_token.to_owned()),
+    }
+}
+
+/// Redeems a refresh token kept outside of doken. Neither reads nor writes the state file
+pub struct RefreshTokenRetriever<'a> {
+    oauth_client: &'a OAuthClient<'a>,
+    refresh_token: String,
+}
+
+impl RefreshTokenRetriever<'_> {
+    pub fn new<'b>(
+        oauth_client: &'b OAuthClient<'b>,
+        refresh_token: String,
+    ) -> RefreshTokenRetriever<'b> {
+        RefreshTokenRetriever {
+            oauth_client,
+            refresh_token,
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl TokenRetriever for RefreshTokenRetriever<'_> {
+    async fn retrieve(&mut self) -> Result<TokenInfo> {
+        let mut token_info = TokenInfo::from_token_response(
+            self.oauth_client
+                .refresh_token(self.refresh_token.clone())
+                .await?,
+        );
+
+        // Providers that don't rotate refresh tokens return only a new access_token
+        if token_info.refresh_token.is_none() {
+            token_info.refresh_token = Some(self.refresh_token.clone());
+        }
+
+        Ok(token_info)
+    }
+}
@@ -0,0 +1,32 @@
+use crate::args::Arguments;
+use crate::saml_bearer;
+use crate::{OAuthClient, token_info::TokenInfo};
+use anyhow::Result;
+use async_trait::async_trait;
+
+use super::token_retriever::TokenRetriever;
+
+pub struct Saml2BearerRetriever<'a> {
+    args: &'a Arguments,
+    oauth_client: &'a OAuthClient<'a>,
+}
+
+impl Saml2BearerRetriever<'_> {
+    pub fn new<'b>(
+        args: &'b Arguments,
+        oauth_client: &'b OAuthClient<'b>,
+    ) -> Saml2BearerRetriever<'b> {
+        Saml2BearerRetriever { args, oauth_client }
+    }
+}
+
+#[async_trait(?Send)]
+impl TokenRetriever for Saml2BearerRetriever<'_> {
+    async fn retrieve(&mut self) -> Result<TokenInfo> {
+        let assertion = saml_bearer::read_assertion(self.args)?;
+
+        Ok(TokenInfo::from_token_response(
+            self.oauth_client.exchange_saml2_bearer(&assertion).await?,
+        ))
+    }
+}
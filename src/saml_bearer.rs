@@ -0,0 +1,72 @@
+use anyhow::{Context, Result, bail};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use std::fs;
+use std::io::Read;
+
+use crate::args::Arguments;
+
+/// `grant_type` of the SAML 2.0 Bearer grant <https://www.rfc-editor.org/rfc/rfc7522#section-2.1>
+pub const SAML2_BEARER_GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:saml2-bearer";
+
+/// The grant wants the assertion base64url encoded without padding. IdPs hand out raw XML or
+/// standard base64, both are converted
+fn encode(content: &str) -> Result<String> {
+    let content = content.trim();
+    if content.starts_with('<') {
+        return Ok(URL_SAFE_NO_PAD.encode(content));
+    }
+
+    let normalized: String = content
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '=')
+        .map(|c| match c {
+            '+' => '-',
+            '/' => '_',
+            c => c,
+        })
+        .collect();
+    let xml = URL_SAFE_NO_PAD
+        .decode(&normalized)
+        .context("The SAML assertion is neither XML nor base64")?;
+    if !String::from_utf8_lossy(&xml).trim_start().starts_with('<') {
+        bail!("The decoded SAML assertion is not XML");
+    }
+
+    Ok(normalized)
+}
+
+/// Assertion of `--saml-assertion-file`, or of the standard input with `--saml-assertion-stdin`
+pub fn read_assertion(args: &Arguments) -> Result<String> {
+    let content = match &args.saml_assertion_file {
+        Some(path) if !args.saml_assertion_stdin => fs::read_to_string(path)
+            .with_context(|| format!("Cannot read the SAML assertion {}", path))?,
+        _ => {
+            let mut content = String::new();
+            std::io::stdin()
+                .read_to_string(&mut content)
+                .context("Cannot read the SAML assertion from standard input")?;
+            content
+        }
+    };
+
+    encode(&content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::engine::general_purpose::STANDARD;
+
+    #[test]
+    fn it_encodes_assertions_as_base64url() {
+        let xml = "<saml:Assertion ID=\"_1\">??></saml:Assertion>";
+        let expected = URL_SAFE_NO_PAD.encode(xml);
+
+        assert_eq!(encode(&format!("{}\n", xml)).unwrap(), expected);
+        assert_eq!(encode(&STANDARD.encode(xml)).unwrap(), expected);
+        assert_eq!(encode(&expected).unwrap(), expected);
+        assert!(encode(&STANDARD.encode("not xml")).is_err());
+        assert!(encode("not base64!").is_err());
+    }
+}
@@ -0,0 +1,142 @@
+use anyhow::{Context, Result, bail};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::env;
+use url::Url;
+
+use crate::args::Arguments;
+use crate::token_info::TokenInfo;
+
+const DISCOVERY_SUFFIX: &str = "/.well-known/openid-configuration";
+
+#[derive(Serialize, Deserialize, ValueEnum, Clone, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum SdkTarget {
+    /// OpenTelemetry OTLP exporters: `OTEL_EXPORTER_OTLP_HEADERS`
+    Otel,
+    /// Spring Boot OAuth 2.0 client and resource server: `SPRING_SECURITY_OAUTH2_*`
+    Spring,
+    /// npm registries referencing `${NPM_TOKEN}` in .npmrc
+    NpmRegistry,
+    /// pip with the token put into `PIP_INDEX_URL`, which has to be set to the index without credentials
+    PipIndex,
+}
+
+/// Issuer is the discovery url without the well-known suffix <https://openid.net/specs/openid-connect-discovery-1_0.html#ProviderConfig>
+fn issuer(args: &Arguments) -> Option<String> {
+    args.discovery_url
+        .as_deref()?
+        .strip_suffix(DISCOVERY_SUFFIX)
+        .map(|issuer| issuer.to_owned())
+}
+
+fn hints(
+    token_info: &TokenInfo,
+    args: &Arguments,
+    target: &SdkTarget,
+) -> Result<Vec<(String, String)>> {
+    let access_token = &token_info.access_token;
+
+    Ok(match target {
+        // Values are W3C Baggage encoded, so the space has to be escaped
+        SdkTarget::Otel => vec![(
+            "OTEL_EXPORTER_OTLP_HEADERS".to_owned(),
+            format!("Authorization=Bearer%20{}", access_token),
+        )],
+        SdkTarget::Spring => {
+            let mut hints = vec![(
+                "SPRING_SECURITY_OAUTH2_CLIENT_REGISTRATION_DOKEN_CLIENT_ID".to_owned(),
+                args.client_id.to_owned(),
+            )];
+
+            if let Some(issuer) = issuer(args) {
+                hints.push((
+                    "SPRING_SECURITY_OAUTH2_CLIENT_PROVIDER_DOKEN_ISSUER_URI".to_owned(),
+                    issuer.to_owned(),
+                ));
+                hints.push((
+                    "SPRING_SECURITY_OAUTH2_RESOURCESERVER_JWT_ISSUER_URI".to_owned(),
+                    issuer,
+                ));
+            } else if let Some(token_url) = &args.token_url {
+                hints.push((
+                    "SPRING_SECURITY_OAUTH2_CLIENT_PROVIDER_DOKEN_TOKEN_URI".to_owned(),
+                    token_url.to_owned(),
+                ));
+            }
+
+            hints
+        }
+        SdkTarget::NpmRegistry => vec![("NPM_TOKEN".to_owned(), access_token.to_owned())],
+        SdkTarget::PipIndex => {
+            let index_url = env::var("PIP_INDEX_URL")
+                .context("Set `PIP_INDEX_URL` to the index url without credentials")?;
+            let mut index_url = Url::parse(&index_url).context("`PIP_INDEX_URL` is not a url")?;
+
+            if index_url.set_username("__token__").is_err()
+                || index_url.set_password(Some(access_token)).is_err()
+            {
+                bail!("`PIP_INDEX_URL` cannot carry credentials");
+            }
+
+            vec![("PIP_INDEX_URL".to_owned(), index_url.to_string())]
+        }
+    })
+}
+
+/// Single quotes keep the value literal in POSIX shells
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// Shell exports for the stack given with `--target`
+pub fn render(token_info: &TokenInfo, args: &Arguments) -> Result<String> {
+    let target = args
+        .target
+        .as_ref()
+        .context("`--target` is required with `--output sdk-hints`")?;
+
+    Ok(hints(token_info, args, target)?
+        .iter()
+        .map(|(name, value)| format!("export {}={}", name, quote(value)))
+        .collect::<Vec<String>>()
+        .join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_renders_quoted_exports() {
+        let token_info = TokenInfo {
+            access_token: "it's".to_owned(),
+            refresh_token: None,
+            expires: None,
+            scope: None,
+            requested_scope: None,
+            extra: Default::default(),
+        };
+        let args = Arguments {
+            client_id: "my-client".to_owned(),
+            discovery_url: Some(
+                "https://idp.com/realms/dev/.well-known/openid-configuration".to_owned(),
+            ),
+            target: Some(SdkTarget::Otel),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            render(&token_info, &args).unwrap(),
+            r"export OTEL_EXPORTER_OTLP_HEADERS='Authorization=Bearer%20it'\''s'"
+        );
+
+        let args = Arguments {
+            target: Some(SdkTarget::Spring),
+            ..args
+        };
+        assert!(render(&token_info, &args).unwrap().contains(
+            "SPRING_SECURITY_OAUTH2_RESOURCESERVER_JWT_ISSUER_URI='https://idp.com/realms/dev'"
+        ));
+    }
+}
@@ -0,0 +1,185 @@
+use anyhow::{Context, Result, anyhow, bail};
+use serde::Deserialize;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::process::Command;
+
+/// How long a password manager CLI may take, ex. waiting for a biometric unlock
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Secret kept in a password manager, read with its CLI when needed
+#[derive(Debug, PartialEq)]
+pub enum SecretSource {
+    /// `op://<vault>/<item>/<field>` read with the 1Password CLI
+    OnePassword(String),
+    /// `bw://<item>[/<field>]` read with the Bitwarden CLI. The password if no field is given
+    Bitwarden { item: String, field: String },
+}
+
+/// Fields `bw get` reads directly. Others are custom fields of the item
+const BITWARDEN_FIELDS: [&str; 4] = ["password", "username", "totp", "notes"];
+
+#[derive(Deserialize)]
+struct BitwardenItem {
+    #[serde(default)]
+    fields: Vec<BitwardenField>,
+}
+
+#[derive(Deserialize)]
+struct BitwardenField {
+    name: String,
+    value: Option<String>,
+}
+
+impl SecretSource {
+    pub fn parse(reference: &str) -> Result<Self> {
+        if reference.starts_with("op://") {
+            if reference.trim_start_matches("op://").split('/').count() < 3 {
+                bail!("1Password references look like `op://<vault>/<item>/<field>`");
+            }
+
+            return Ok(SecretSource::OnePassword(reference.to_owned()));
+        }
+
+        if let Some(path) = reference.strip_prefix("bw://") {
+            let (item, field) = path.split_once('/').unwrap_or((path, "password"));
+            if item.is_empty() || field.is_empty() {
+                bail!("Bitwarden references look like `bw://<item>` or `bw://<item>/<field>`");
+            }
+
+            return Ok(SecretSource::Bitwarden {
+                item: item.to_owned(),
+                field: field.to_owned(),
+            });
+        }
+
+        bail!(
+            "Unsupported secret reference. Use `op://<vault>/<item>/<field>` or `bw://<item>/<field>`"
+        )
+    }
+
+    pub async fn read(&self) -> Result<String> {
+        match self {
+            SecretSource::OnePassword(reference) => {
+                run("op", &["read", "--no-newline", reference]).await
+            }
+            SecretSource::Bitwarden { item, field }
+                if BITWARDEN_FIELDS.contains(&field.as_str()) =>
+            {
+                run("bw", &["get", field, item]).await
+            }
+            SecretSource::Bitwarden { item, field } => {
+                let item: BitwardenItem =
+                    serde_json::from_str(&run("bw", &["get", "item", item]).await?)
+                        .context("Unexpected `bw` output")?;
+
+                custom_field(item, field)
+            }
+        }
+    }
+}
+
+fn custom_field(item: BitwardenItem, field: &str) -> Result<String> {
+    item.fields
+        .into_iter()
+        .find(|custom| custom.name == field)
+        .and_then(|custom| custom.value)
+        .with_context(|| format!("The Bitwarden item has no `{}` field", field))
+}
+
+/// Output of a password manager CLI, which is killed after `TIMEOUT`
+async fn run(program: &str, args: &[&str]) -> Result<String> {
+    let child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| format!("Cannot run `{}`. Is it installed and in PATH?", program))?;
+
+    // Pipes are read while waiting, so a long output cannot fill them up and stall the CLI
+    let output = tokio::time::timeout(TIMEOUT, child.wait_with_output())
+        .await
+        .map_err(|_| {
+            anyhow!(
+                "`{}` didn't answer within {}s. Is the vault unlocked?",
+                program,
+                TIMEOUT.as_secs()
+            )
+        })??;
+
+    if !output.status.success() {
+        return Err(anyhow!(
+            "{}",
+            String::from_utf8_lossy(&output.stderr).trim().to_owned()
+        ))
+        .with_context(|| format!("`{}` failed to read the secret", program));
+    }
+
+    Ok(String::from_utf8(output.stdout)
+        .with_context(|| format!("`{}` printed a secret which isn't UTF-8", program))?
+        .trim_end_matches(['\r', '\n'])
+        .to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_secret_references() {
+        assert_eq!(
+            SecretSource::parse("op://Private/my-client/credential").unwrap(),
+            SecretSource::OnePassword("op://Private/my-client/credential".to_owned())
+        );
+        assert!(SecretSource::parse("op://Private/my-client").is_err());
+        assert_eq!(
+            SecretSource::parse("bw://my-client").unwrap(),
+            SecretSource::Bitwarden {
+                item: "my-client".to_owned(),
+                field: "password".to_owned(),
+            }
+        );
+        assert_eq!(
+            SecretSource::parse("bw://my-client/client_secret").unwrap(),
+            SecretSource::Bitwarden {
+                item: "my-client".to_owned(),
+                field: "client_secret".to_owned(),
+            }
+        );
+        assert!(SecretSource::parse("vault://secret/my-client").is_err());
+    }
+
+    #[test]
+    fn it_reads_custom_bitwarden_fields() {
+        let item: BitwardenItem = serde_json::from_str(
+            r#"{"name":"my-client","fields":[{"name":"client_secret","value":"secret","type":1}]}"#,
+        )
+        .unwrap();
+
+        assert_eq!(custom_field(item, "client_secret").unwrap(), "secret");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn it_reports_failures_of_the_cli() {
+        let error = run("sh", &["-c", "echo 'vault is locked' >&2; exit 1"])
+            .await
+            .unwrap_err();
+
+        assert_eq!(
+            format!("{:#}", error),
+            "`sh` failed to read the secret: vault is locked"
+        );
+        assert_eq!(run("sh", &["-c", "echo secret"]).await.unwrap(), "secret");
+        // More than a pipe buffer holds
+        assert_eq!(
+            run("sh", &["-c", "head -c 200000 /dev/zero | tr '\\0' x"])
+                .await
+                .unwrap()
+                .len(),
+            200000
+        );
+    }
+}
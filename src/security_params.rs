@@ -0,0 +1,117 @@
+//! Generation of the parameters protecting authorization flows: `state` (CSRF token), `nonce` and PKCE.
+
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use hmac::{Hmac, Mac};
+use oauth2::{CsrfToken, PkceCodeChallenge, PkceCodeVerifier};
+use rand::RngCore;
+use rand::distr::{Alphanumeric, SampleString};
+use sha2::Sha256;
+
+/// Source of the random values. Uses the thread-local CSPRNG unless another RNG is injected, ex. a seeded one in tests
+pub struct SecurityParams {
+    rng: Box<dyn RngCore>,
+}
+
+impl Default for SecurityParams {
+    fn default() -> Self {
+        SecurityParams::with_rng(Box::new(rand::rng()))
+    }
+}
+
+impl SecurityParams {
+    pub fn with_rng(rng: Box<dyn RngCore>) -> Self {
+        SecurityParams { rng }
+    }
+
+    fn random_base64(&mut self, len: usize) -> String {
+        let mut bytes = vec![0; len];
+        self.rng.fill_bytes(&mut bytes);
+        URL_SAFE_NO_PAD.encode(bytes)
+    }
+
+    /// `state` parameter <https://www.rfc-editor.org/rfc/rfc6749#section-10.12>
+    pub fn csrf_token(&mut self) -> CsrfToken {
+        CsrfToken::new(self.random_base64(16))
+    }
+
+    /// OpenID Connect `nonce` <https://openid.net/specs/openid-connect-core-1_0.html#NonceNotes>
+    pub fn nonce(&mut self) -> String {
+        Alphanumeric.sample_string(&mut self.rng, 16)
+    }
+
+    /// `jti` of client assertions, unique so they can't be replayed <https://www.rfc-editor.org/rfc/rfc7523#section-3>
+    pub fn jti(&mut self) -> String {
+        self.random_base64(16)
+    }
+
+    /// S256 code challenge and its verifier <https://www.rfc-editor.org/rfc/rfc7636#section-4.1>
+    pub fn pkce(&mut self) -> (PkceCodeChallenge, PkceCodeVerifier) {
+        let verifier = PkceCodeVerifier::new(self.random_base64(32));
+
+        (
+            PkceCodeChallenge::from_code_verifier_sha256(&verifier),
+            verifier,
+        )
+    }
+}
+
+fn mac(key: &[u8], payload: &str) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(payload.as_bytes());
+    mac
+}
+
+/// Embeds a payload in `state` as `<payload>.<HMAC-SHA256>`, so it can be trusted when it comes back to the callback
+pub fn sign_state(key: &[u8], payload: &str) -> String {
+    let signature = URL_SAFE_NO_PAD.encode(mac(key, payload).finalize().into_bytes());
+
+    format!("{}.{}", payload, signature)
+}
+
+/// Payload of a `state` created by [`sign_state`], `None` if it has been tampered with
+pub fn verify_state(key: &[u8], state: &str) -> Option<String> {
+    let (payload, signature) = state.rsplit_once('.')?;
+    let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+
+    mac(key, payload)
+        .verify_slice(&signature)
+        .ok()
+        .map(|_| payload.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    fn seeded() -> SecurityParams {
+        SecurityParams::with_rng(Box::new(StdRng::seed_from_u64(7)))
+    }
+
+    #[test]
+    fn it_generates_deterministic_params_with_seeded_rng() {
+        let (mut first, mut second) = (seeded(), seeded());
+
+        assert_eq!(first.csrf_token().secret(), second.csrf_token().secret());
+        assert_eq!(first.nonce(), second.nonce());
+        assert_eq!(
+            first.pkce().1.secret().to_owned(),
+            second.pkce().1.secret().to_owned()
+        );
+        assert_ne!(first.nonce(), first.nonce());
+    }
+
+    #[test]
+    fn it_verifies_signed_state() {
+        let state = sign_state(b"key", "payload");
+
+        assert_eq!(verify_state(b"key", &state), Some("payload".to_owned()));
+        assert_eq!(verify_state(b"other-key", &state), None);
+        assert_eq!(
+            verify_state(b"key", &state.replace("payload", "tampered")),
+            None
+        );
+    }
+}
@@ -0,0 +1,36 @@
+//! Opt-in settings kept as JSON in ~/.doken, ex. of telemetry or history
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::permissions::write_private;
+
+/// `file` in ~/.doken
+pub fn doken_path(file: &str) -> Result<PathBuf> {
+    let mut path = home::home_dir().context("Couldn't access $HOME_DIR")?;
+    path.push(".doken");
+    path.push(file);
+    Ok(path)
+}
+
+/// Settings of `file`, the default when it doesn't exist or cannot be parsed
+pub fn read<T: DeserializeOwned + Default>(file: &str) -> T {
+    doken_path(file)
+        .and_then(|path| Ok(fs::read_to_string(path)?))
+        .and_then(|text| Ok(serde_json::from_str(&text)?))
+        .unwrap_or_default()
+}
+
+/// Writes the settings readable only by the current user, they may hold endpoints with credentials
+pub fn write<T: Serialize>(file: &str, settings: &T) -> Result<()> {
+    let path = doken_path(file)?;
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    write_private(&path, serde_json::to_string_pretty(settings)?.as_bytes())
+        .with_context(|| format!("Cannot write {}", path.to_string_lossy()))
+}
@@ -0,0 +1,298 @@
+use anyhow::{Context, Result, bail};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use serde::Serialize;
+use serde_json::Value;
+use std::fs;
+
+use crate::jwt::parse_algorithm;
+
+/// Signs JWTs made by doken, ex. client assertions or request objects. The key stays with the
+/// implementation, so keys of an HSM ([`crate::pkcs11::Pkcs11Signer`]) or a cloud KMS never have to leave it
+pub trait Signer {
+    /// `alg` of the JWT header
+    fn algorithm(&self) -> Algorithm;
+
+    /// `kid` of the JWT header, telling the provider which of the client's keys to verify with
+    fn key_id(&self) -> Option<&str>;
+
+    /// Raw signature of `message`, the `<header>.<claims>` part of the JWT
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>>;
+}
+
+/// Compact JWT of `claims` signed by `signer`
+pub fn sign_jwt<S: Signer + ?Sized>(signer: &S, claims: &impl Serialize) -> Result<String> {
+    let header = Header {
+        kid: signer.key_id().map(|kid| kid.to_owned()),
+        ..Header::new(signer.algorithm())
+    };
+    let message = format!(
+        "{}.{}",
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(&header)?),
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(claims)?)
+    );
+    let signature = signer.sign(message.as_bytes())?;
+
+    Ok(format!("{}.{}", message, URL_SAFE_NO_PAD.encode(signature)))
+}
+
+/// Private key read from a PEM file or a JWK into memory
+pub struct PemSigner {
+    key: EncodingKey,
+    algorithm: Algorithm,
+    key_id: Option<String>,
+}
+
+/// DER element of `tag`
+fn der(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut der = vec![tag];
+    match content.len() {
+        len if len < 0x80 => der.push(len as u8),
+        len => {
+            let len: Vec<u8> = len
+                .to_be_bytes()
+                .into_iter()
+                .skip_while(|byte| *byte == 0)
+                .collect();
+            der.push(0x80 | len.len() as u8);
+            der.extend(len);
+        }
+    }
+    der.extend_from_slice(content);
+
+    der
+}
+
+/// DER INTEGER of big-endian unsigned bytes
+fn der_integer(bytes: &[u8]) -> Vec<u8> {
+    let start = bytes
+        .iter()
+        .position(|byte| *byte != 0)
+        .unwrap_or(bytes.len());
+    let mut integer = bytes[start..].to_vec();
+    if integer.first().is_none_or(|byte| byte & 0x80 != 0) {
+        integer.insert(0, 0);
+    }
+
+    der(0x02, &integer)
+}
+
+/// PKCS#8 `PrivateKeyInfo` of the algorithm identifier and the encoded key
+fn pkcs8(algorithm: &[u8], key: &[u8]) -> Vec<u8> {
+    der(
+        0x30,
+        &[der_integer(&[0]), der(0x30, algorithm), der(0x04, key)].concat(),
+    )
+}
+
+const OID_EC_PUBLIC_KEY: [u8; 9] = [0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01];
+const OID_P256: [u8; 10] = [0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+const OID_P384: [u8; 7] = [0x06, 0x05, 0x2b, 0x81, 0x04, 0x00, 0x22];
+const OID_ED25519: [u8; 5] = [0x06, 0x03, 0x2b, 0x65, 0x70];
+
+/// Key of a private JWK <https://www.rfc-editor.org/rfc/rfc7518#section-6> and the algorithm it's
+/// meant for, if its type tells
+fn jwk_key(jwk: &Value) -> Result<(EncodingKey, Option<Algorithm>)> {
+    let param = |name: &str| -> Result<Vec<u8>> {
+        let value = jwk[name]
+            .as_str()
+            .with_context(|| format!("The JWK has no `{}`. Is it a private key?", name))?;
+
+        Ok(URL_SAFE_NO_PAD.decode(value)?)
+    };
+
+    match (jwk["kty"].as_str(), jwk["crv"].as_str()) {
+        (Some("RSA"), _) => {
+            // PKCS#1 RSAPrivateKey
+            let mut integers = der_integer(&[0]);
+            for name in ["n", "e", "d", "p", "q", "dp", "dq", "qi"] {
+                integers.extend(der_integer(&param(name)?));
+            }
+
+            Ok((EncodingKey::from_rsa_der(&der(0x30, &integers)), None))
+        }
+        (Some("EC"), Some(curve)) => {
+            let (oid, algorithm) = match curve {
+                "P-256" => (&OID_P256[..], Algorithm::ES256),
+                "P-384" => (&OID_P384[..], Algorithm::ES384),
+                curve => bail!("JWKs of curve {} aren't supported", curve),
+            };
+            let public_key = [&[0x00, 0x04][..], &param("x")?, &param("y")?].concat();
+            // SEC1 ECPrivateKey
+            let key = der(
+                0x30,
+                &[
+                    der_integer(&[1]),
+                    der(0x04, &param("d")?),
+                    der(0xa1, &der(0x03, &public_key)),
+                ]
+                .concat(),
+            );
+
+            Ok((
+                EncodingKey::from_ec_der(&pkcs8(&[&OID_EC_PUBLIC_KEY[..], oid].concat(), &key)),
+                Some(algorithm),
+            ))
+        }
+        (Some("OKP"), Some("Ed25519")) => Ok((
+            EncodingKey::from_ed_der(&pkcs8(&OID_ED25519, &der(0x04, &param("d")?))),
+            Some(Algorithm::EdDSA),
+        )),
+        (kty, crv) => bail!(
+            "JWKs of type {} {} aren't supported",
+            kty.unwrap_or("?"),
+            crv.unwrap_or_default()
+        ),
+    }
+}
+
+impl PemSigner {
+    /// Reads a PKCS#8 (or PKCS#1 RSA, SEC1 EC) key for an asymmetric `algorithm`
+    pub fn from_file(
+        path: &str,
+        algorithm: Algorithm,
+        key_id: Option<String>,
+    ) -> Result<PemSigner> {
+        let pem =
+            fs::read(path).with_context(|| format!("Cannot read the private key {}", path))?;
+
+        PemSigner::from_pem(&pem, algorithm, key_id)
+            .with_context(|| format!("{} is not a valid {:?} private key", path, algorithm))
+    }
+
+    pub fn from_pem(pem: &[u8], algorithm: Algorithm, key_id: Option<String>) -> Result<PemSigner> {
+        let key = match algorithm {
+            Algorithm::RS256
+            | Algorithm::RS384
+            | Algorithm::RS512
+            | Algorithm::PS256
+            | Algorithm::PS384
+            | Algorithm::PS512 => EncodingKey::from_rsa_pem(pem)?,
+            Algorithm::ES256 | Algorithm::ES384 => EncodingKey::from_ec_pem(pem)?,
+            Algorithm::EdDSA => EncodingKey::from_ed_pem(pem)?,
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 => {
+                bail!("{:?} is not signed with a private key", algorithm)
+            }
+        };
+
+        Ok(PemSigner {
+            key,
+            algorithm,
+            key_id,
+        })
+    }
+
+    /// Reads a private JWK. Its `alg` and `kid`, or the algorithm of its curve, win over the given ones
+    pub fn from_jwk(
+        jwk: &Value,
+        algorithm: Algorithm,
+        key_id: Option<String>,
+    ) -> Result<PemSigner> {
+        let (key, curve_algorithm) = jwk_key(jwk)?;
+        let algorithm = match jwk["alg"].as_str() {
+            Some(alg) => parse_algorithm(alg)?,
+            None => curve_algorithm.unwrap_or(algorithm),
+        };
+
+        Ok(PemSigner {
+            key,
+            algorithm,
+            key_id: jwk["kid"].as_str().map(str::to_owned).or(key_id),
+        })
+    }
+}
+
+impl Signer for PemSigner {
+    fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    fn key_id(&self) -> Option<&str> {
+        self.key_id.as_deref()
+    }
+
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>> {
+        let signature = jsonwebtoken::crypto::sign(message, &self.key, self.algorithm)?;
+
+        Ok(URL_SAFE_NO_PAD.decode(signature)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{DecodingKey, Validation};
+    use serde_json::json;
+
+    /// Stands in for a key kept elsewhere, ex. in an HSM
+    struct SecretSigner;
+
+    impl Signer for SecretSigner {
+        fn algorithm(&self) -> Algorithm {
+            Algorithm::HS256
+        }
+
+        fn key_id(&self) -> Option<&str> {
+            Some("hsm-key-1")
+        }
+
+        fn sign(&self, message: &[u8]) -> Result<Vec<u8>> {
+            let key = EncodingKey::from_secret(b"secret");
+            let signature = jsonwebtoken::crypto::sign(message, &key, Algorithm::HS256)?;
+
+            Ok(URL_SAFE_NO_PAD.decode(signature)?)
+        }
+    }
+
+    #[test]
+    fn it_signs_jwts_with_any_signer() {
+        let token = sign_jwt(&SecretSigner, &json!({ "sub": "my-client" })).unwrap();
+
+        let mut validation = Validation::new(Algorithm::HS256);
+        validation.required_spec_claims.clear();
+        let decoded = jsonwebtoken::decode::<Value>(
+            &token,
+            &DecodingKey::from_secret(b"secret"),
+            &validation,
+        )
+        .unwrap();
+
+        assert_eq!(decoded.header.kid.as_deref(), Some("hsm-key-1"));
+        assert_eq!(decoded.claims["sub"], "my-client");
+        assert!(PemSigner::from_file("Cargo.toml", Algorithm::HS256, None).is_err());
+    }
+
+    #[test]
+    fn it_signs_with_private_jwks() {
+        // Key of RFC 7515 Appendix A.3
+        let x = "f83OJ3D2xF1Bg8vub9tLe1gHMzV76e8Tus9uPHvRVEU";
+        let y = "x_FEzRu9m36HLN_tue659LNpXW6pCyStikYjKIWI5a0";
+        let jwk = json!({
+            "kty": "EC",
+            "crv": "P-256",
+            "kid": "jwk-1",
+            "x": x,
+            "y": y,
+            "d": "jpsQnnGQmL-YBIffH1136cLJQh6PMVeS3aPUa2RHfqE",
+        });
+
+        let signer = PemSigner::from_jwk(&jwk, Algorithm::RS256, None).unwrap();
+        assert_eq!(signer.algorithm(), Algorithm::ES256);
+        assert_eq!(signer.key_id(), Some("jwk-1"));
+
+        let token = sign_jwt(&signer, &json!({ "sub": "my-client" })).unwrap();
+        let mut validation = Validation::new(Algorithm::ES256);
+        validation.required_spec_claims.clear();
+        let decoded = jsonwebtoken::decode::<Value>(
+            &token,
+            &DecodingKey::from_ec_components(x, y).unwrap(),
+            &validation,
+        )
+        .unwrap();
+        assert_eq!(decoded.claims["sub"], "my-client");
+
+        let public_only = json!({ "kty": "EC", "crv": "P-256", "x": x, "y": y });
+        assert!(PemSigner::from_jwk(&public_only, Algorithm::ES256, None).is_err());
+    }
+}
@@ -0,0 +1,52 @@
+use std::collections::HashMap;
+use std::sync::{Arc, LazyLock, Mutex};
+use tokio::sync::OwnedMutexGuard;
+
+type Registry = Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>;
+
+// The state file lock doesn't help here, because POSIX record locks are owned by the whole process
+static IN_FLIGHT: LazyLock<Registry> = LazyLock::new(Default::default);
+
+/// Waits until no other task of this process retrieves or refreshes the token of `state_key`.
+/// The next task then finds the shared result in the state file instead of using an already rotated refresh_token
+pub async fn lock(state_key: &str) -> OwnedMutexGuard<()> {
+    let mutex = IN_FLIGHT
+        .lock()
+        .unwrap()
+        .entry(state_key.to_owned())
+        .or_default()
+        .clone();
+
+    mutex.lock_owned().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn it_serializes_flights_of_the_same_key() {
+        let running = Arc::new(AtomicUsize::new(0));
+
+        let flights = (0..4).map(|_| {
+            let running = running.clone();
+            tokio::spawn(async move {
+                let _flight = lock("client").await;
+                assert_eq!(running.fetch_add(1, Ordering::SeqCst), 0);
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                running.fetch_sub(1, Ordering::SeqCst);
+            })
+        });
+
+        for flight in flights.collect::<Vec<_>>() {
+            flight.await.unwrap();
+        }
+
+        let _first = lock("first").await;
+        let _second = tokio::time::timeout(Duration::from_millis(100), lock("second"))
+            .await
+            .expect("Different keys shouldn't wait for each other");
+    }
+}
@@ -0,0 +1,126 @@
+use anyhow::{Context, Result};
+use std::env;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::Path;
+use tokio::net::TcpListener;
+
+/// Secrets read from systemd credentials (`LoadCredential=`, `SetCredentialEncrypted=`, ...) and their variables
+const CREDENTIALS: [(&str, &str); 4] = [
+    ("client_secret", "DOKEN_CLIENT_SECRET"),
+    ("password", "DOKEN_PASSWORD"),
+    ("otp_secret", "DOKEN_OTP_SECRET"),
+    ("webhook_secret", "DOKEN_WEBHOOK_SECRET"),
+];
+
+/// File descriptor of the first socket passed by systemd <https://www.freedesktop.org/software/systemd/man/sd_listen_fds.html>
+#[cfg(unix)]
+const SD_LISTEN_FDS_START: std::os::fd::RawFd = 3;
+
+fn read_credential(dir: &Path, profile: Option<&str>, key: &str) -> Option<String> {
+    let names = profile
+        .map(|profile| format!("{}.{}", profile, key))
+        .into_iter()
+        .chain([key.to_owned()]);
+
+    names
+        .filter_map(|name| fs::read_to_string(dir.join(name)).ok())
+        .map(|value| value.trim_end_matches(['\r', '\n']).to_owned())
+        .next()
+}
+
+/// Credential named `<profile>.<key>` or `<key>` in `$CREDENTIALS_DIRECTORY`, if doken runs as a systemd service
+pub fn credential(profile: Option<&str>, key: &str) -> Option<String> {
+    let dir = env::var_os("CREDENTIALS_DIRECTORY")?;
+    let value = read_credential(Path::new(&dir), profile, key)?;
+    log::debug!("Using `{}` from systemd credentials", key);
+
+    Some(value)
+}
+
+/// Exposes systemd credentials as `DOKEN_*` variables, unless the environment or the profile sets them already
+pub fn apply_credentials(profile: Option<&str>) {
+    for (key, var) in CREDENTIALS {
+        if env::var_os(var).is_some() {
+            continue;
+        }
+
+        if let Some(value) = credential(profile, key) {
+            unsafe {
+                env::set_var(var, value);
+            }
+        }
+    }
+}
+
+/// Socket passed with systemd socket activation (`LISTEN_PID`/`LISTEN_FDS`). It's taken once, as the variables are removed
+#[cfg(unix)]
+fn activated_listener() -> Result<Option<std::net::TcpListener>> {
+    use std::os::fd::FromRawFd;
+
+    let for_this_process = env::var("LISTEN_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+        .is_some_and(|pid| pid == std::process::id());
+    let fds = env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|fds| fds.parse::<u32>().ok())
+        .unwrap_or_default();
+
+    if !for_this_process || fds == 0 {
+        return Ok(None);
+    }
+
+    unsafe {
+        env::remove_var("LISTEN_PID");
+        env::remove_var("LISTEN_FDS");
+        env::remove_var("LISTEN_FDNAMES");
+    }
+
+    // systemd owns the descriptor until now and hands it over to this process only
+    let listener = unsafe { std::net::TcpListener::from_raw_fd(SD_LISTEN_FDS_START) };
+    listener
+        .set_nonblocking(true)
+        .context("The socket passed by systemd is not a TCP socket")?;
+
+    Ok(Some(listener))
+}
+
+#[cfg(not(unix))]
+fn activated_listener() -> Result<Option<std::net::TcpListener>> {
+    Ok(None)
+}
+
+/// Listens on the socket passed by systemd socket activation or binds to `listen` otherwise
+pub async fn bind(listen: SocketAddr) -> Result<TcpListener> {
+    if let Some(listener) = activated_listener()? {
+        log::debug!("Using the socket passed by systemd instead of {}", listen);
+        return Ok(TcpListener::from_std(listener)?);
+    }
+
+    TcpListener::bind(listen)
+        .await
+        .with_context(|| format!("Cannot listen on {}", listen))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_prefers_profile_specific_credentials() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("client_secret"), "shared-secret\n").unwrap();
+        fs::write(dir.path().join("corp.client_secret"), "corp-secret").unwrap();
+
+        assert_eq!(
+            read_credential(dir.path(), Some("corp"), "client_secret").as_deref(),
+            Some("corp-secret")
+        );
+        assert_eq!(
+            read_credential(dir.path(), Some("other"), "client_secret").as_deref(),
+            Some("shared-secret")
+        );
+        assert_eq!(read_credential(dir.path(), None, "password"), None);
+    }
+}
@@ -0,0 +1,178 @@
+use anyhow::{Result, bail};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_variant::to_variant_name;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::InteractionRequired;
+use crate::grant::Grant;
+use crate::providers::errors::ProviderError;
+use crate::settings;
+
+/// Opt-in settings. Telemetry is off when the file doesn't exist
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct TelemetrySettings {
+    pub enabled: bool,
+    /// `https://` endpoint receiving JSON events or `file://` path events are appended to
+    pub endpoint: Option<String>,
+}
+
+/// Everything that's ever reported. No identifiers, urls, client ids nor tokens
+#[derive(Serialize, Debug, PartialEq)]
+pub struct UsageEvent {
+    pub version: &'static str,
+    pub os: &'static str,
+    pub grant: String,
+    /// `ok` or an error category, ex. `invalid_grant`, `interaction_required`, `network`
+    pub outcome: String,
+}
+
+impl UsageEvent {
+    pub fn new(grant: &Grant, error: Option<&anyhow::Error>) -> Self {
+        UsageEvent {
+            version: env!("CARGO_PKG_VERSION"),
+            os: std::env::consts::OS,
+            grant: to_variant_name(grant).unwrap_or_default().to_owned(),
+            outcome: error.map(category).unwrap_or_else(|| "ok".to_owned()),
+        }
+    }
+}
+
+fn category(error: &anyhow::Error) -> String {
+    if error.is::<InteractionRequired>() {
+        return "interaction_required".to_owned();
+    }
+
+    for cause in error.chain() {
+        if let Some(provider_error) = cause.downcast_ref::<ProviderError>() {
+            return match provider_error.is_standard() {
+                true => provider_error.code().to_owned(),
+                false => "provider_error".to_owned(),
+            };
+        }
+
+        if cause.is::<reqwest::Error>() {
+            return "network".to_owned();
+        }
+    }
+
+    "other".to_owned()
+}
+
+/// Destination of usage events. Enterprises can point doken at their own collector
+#[async_trait(?Send)]
+pub trait TelemetrySink {
+    async fn send(&self, event: &UsageEvent) -> Result<()>;
+}
+
+pub struct HttpSink {
+    endpoint: String,
+}
+
+#[async_trait(?Send)]
+impl TelemetrySink for HttpSink {
+    async fn send(&self, event: &UsageEvent) -> Result<()> {
+        reqwest::Client::builder()
+            .user_agent(concat!("doken/", env!("CARGO_PKG_VERSION")))
+            .timeout(Duration::from_secs(2))
+            .build()?
+            .post(&self.endpoint)
+            .json(event)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+}
+
+/// Appends events as JSON lines, ex. to be shipped by an existing log collector
+pub struct FileSink {
+    path: PathBuf,
+}
+
+#[async_trait(?Send)]
+impl TelemetrySink for FileSink {
+    async fn send(&self, event: &UsageEvent) -> Result<()> {
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{}", serde_json::to_string(event)?)?;
+
+        Ok(())
+    }
+}
+
+pub fn sink(endpoint: &str) -> Result<Box<dyn TelemetrySink>> {
+    if let Some(path) = endpoint.strip_prefix("file://") {
+        return Ok(Box::new(FileSink {
+            path: PathBuf::from(path),
+        }));
+    }
+
+    if !endpoint.starts_with("https://") && !endpoint.starts_with("http://") {
+        bail!("Telemetry endpoint has to be an `https://` url or a `file://` path");
+    }
+
+    Ok(Box::new(HttpSink {
+        endpoint: endpoint.to_owned(),
+    }))
+}
+
+pub fn read_settings() -> TelemetrySettings {
+    settings::read("telemetry.json")
+}
+
+pub fn write_settings(settings: &TelemetrySettings) -> Result<()> {
+    settings::write("telemetry.json", settings)
+}
+
+/// Sends a usage event if telemetry is enabled. Never fails the run
+pub async fn report(grant: &Grant, error: Option<&anyhow::Error>) {
+    let settings = read_settings();
+    let Some(endpoint) = settings.endpoint.filter(|_| settings.enabled) else {
+        return;
+    };
+
+    let result = match sink(&endpoint) {
+        Ok(sink) => sink.send(&UsageEvent::new(grant, error)).await,
+        Err(e) => Err(e),
+    };
+
+    if let Err(e) = result {
+        log::debug!("Telemetry event wasn't sent: {:?}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    #[test]
+    fn it_reports_only_error_categories() {
+        let provider_error = anyhow!(ProviderError::new(
+            "invalid_grant".to_owned(),
+            Some("Token of user@example.com is expired".to_owned()),
+            None,
+        ))
+        .context("Refresh failed");
+        let custom_error = anyhow!(ProviderError::new(
+            "tenant_12345_blocked".to_owned(),
+            None,
+            None,
+        ));
+
+        assert_eq!(category(&provider_error), "invalid_grant");
+        assert_eq!(category(&custom_error), "provider_error");
+        assert_eq!(
+            category(&anyhow!(InteractionRequired)),
+            "interaction_required"
+        );
+        assert_eq!(category(&anyhow!("https://idp.com failed")), "other");
+    }
+}
@@ -0,0 +1,162 @@
+use oauth2::http::{HeaderMap, StatusCode};
+use std::fmt;
+use std::time::{Duration, SystemTime};
+
+use crate::http_client::parse_http_date;
+use crate::providers::errors::ProviderError;
+
+/// First wait when the provider throttles without saying for how long. Doubled with every further attempt
+const INITIAL_BACKOFF: Duration = Duration::from_secs(30);
+const MAX_BACKOFF: Duration = Duration::from_secs(30 * 60);
+
+/// The provider refused a request because of rate limits. Attached as context to its error
+#[derive(Debug, Clone, PartialEq)]
+pub struct Throttled {
+    /// How long the provider asks to wait, if it says so
+    pub retry_after: Option<Duration>,
+}
+
+impl fmt::Display for Throttled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.retry_after {
+            Some(retry_after) => write!(
+                f,
+                "The provider is throttling requests and asks to retry in {}",
+                humantime::format_duration(Duration::from_secs(retry_after.as_secs()))
+            ),
+            None => write!(f, "The provider is throttling requests"),
+        }
+    }
+}
+
+/// `Retry-After` as seconds or a date <https://www.rfc-editor.org/rfc/rfc9110#section-10.2.3>,
+/// otherwise Okta's `X-Rate-Limit-Reset` epoch seconds
+fn retry_after(headers: &HeaderMap, now: SystemTime) -> Option<Duration> {
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+
+    if let Some(value) = header("retry-after") {
+        return match value.trim().parse::<u64>() {
+            Ok(seconds) => Some(Duration::from_secs(seconds)),
+            Err(_) => Some(
+                parse_http_date(value.trim())?
+                    .duration_since(now)
+                    .unwrap_or_default(),
+            ),
+        };
+    }
+
+    let reset = header("x-rate-limit-reset")?.trim().parse::<u64>().ok()?;
+    Some(
+        (SystemTime::UNIX_EPOCH + Duration::from_secs(reset))
+            .duration_since(now)
+            .unwrap_or_default(),
+    )
+}
+
+/// Whether the response means "slow down", by its status or the provider's error code
+pub fn classify(
+    status: StatusCode,
+    headers: &HeaderMap,
+    provider_error: Option<&ProviderError>,
+) -> Option<Throttled> {
+    let throttled = status == StatusCode::TOO_MANY_REQUESTS
+        || (status == StatusCode::SERVICE_UNAVAILABLE && headers.contains_key("retry-after"))
+        || provider_error.is_some_and(|error| error.is_throttling());
+
+    throttled.then(|| Throttled {
+        retry_after: retry_after(headers, SystemTime::now()),
+    })
+}
+
+/// Throttling the error was caused by, if any
+pub fn throttled(error: &anyhow::Error) -> Option<&Throttled> {
+    error.downcast_ref::<Throttled>()
+}
+
+/// Waits between attempts of a throttled refresh. Provider hints win, otherwise waits grow exponentially
+#[derive(Debug, Default)]
+pub struct Backoff {
+    attempts: u32,
+}
+
+impl Backoff {
+    pub fn delay(&mut self, throttled: &Throttled) -> Duration {
+        let fallback = INITIAL_BACKOFF
+            .saturating_mul(2u32.saturating_pow(self.attempts))
+            .min(MAX_BACKOFF);
+        self.attempts += 1;
+
+        throttled.retry_after.unwrap_or(fallback)
+    }
+
+    pub fn reset(&mut self) {
+        self.attempts = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use oauth2::http::HeaderValue;
+
+    #[test]
+    fn it_reads_provider_hints() {
+        let now = humantime::parse_rfc3339("2015-10-21T07:27:00Z").unwrap();
+        let headers = |name: &'static str, value: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(name, HeaderValue::from_static(value));
+            headers
+        };
+
+        assert_eq!(
+            retry_after(&headers("retry-after", "120"), now),
+            Some(Duration::from_secs(120))
+        );
+        assert_eq!(
+            retry_after(
+                &headers("retry-after", "Wed, 21 Oct 2015 07:28:00 GMT"),
+                now
+            ),
+            Some(Duration::from_secs(60))
+        );
+        assert_eq!(
+            retry_after(&headers("x-rate-limit-reset", "1445412450"), now),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(retry_after(&HeaderMap::new(), now), None);
+
+        assert!(classify(StatusCode::TOO_MANY_REQUESTS, &HeaderMap::new(), None).is_some());
+        assert!(classify(StatusCode::SERVICE_UNAVAILABLE, &HeaderMap::new(), None).is_none());
+        let aad = ProviderError::new(
+            "temporarily_unavailable".to_owned(),
+            Some("AADSTS90033: A transient error has occurred. Please try again.".to_owned()),
+            None,
+        );
+        assert_eq!(
+            classify(StatusCode::BAD_REQUEST, &HeaderMap::new(), Some(&aad)),
+            Some(Throttled { retry_after: None })
+        );
+    }
+
+    #[test]
+    fn it_backs_off_exponentially_without_hints() {
+        let mut backoff = Backoff::default();
+        let unknown = Throttled { retry_after: None };
+
+        assert_eq!(backoff.delay(&unknown), Duration::from_secs(30));
+        assert_eq!(backoff.delay(&unknown), Duration::from_secs(60));
+        assert_eq!(
+            backoff.delay(&Throttled {
+                retry_after: Some(Duration::from_secs(5))
+            }),
+            Duration::from_secs(5)
+        );
+        for _ in 0..10 {
+            backoff.delay(&unknown);
+        }
+        assert_eq!(backoff.delay(&unknown), MAX_BACKOFF);
+
+        backoff.reset();
+        assert_eq!(backoff.delay(&unknown), Duration::from_secs(30));
+    }
+}
@@ -1,22 +1,58 @@
-use oauth2::TokenResponse;
-use oauth2::basic::BasicTokenResponse;
+use oauth2::basic::{BasicTokenResponse, BasicTokenType};
+use oauth2::{ExtraTokenFields, StandardTokenResponse, TokenResponse as _};
+use reqwest::header::{HeaderValue, InvalidHeaderValue};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
 use std::ops::Add;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
+use crate::claims;
+use crate::clock_skew;
+
+/// Provider specific fields of a token response that aren't covered by RFC 6749
+/// ex. Slack's `authed_user`, GitHub's `refresh_token_expires_in` or Azure's `ext_expires_in`
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct ProviderFields {
+    #[serde(flatten)]
+    pub fields: HashMap<String, Value>,
+}
+
+impl ExtraTokenFields for ProviderFields {}
+
+/// Fields of `extra` identifying the IdP session the token belongs to
+const SESSION_FIELDS: [&str; 2] = ["session_state", "sid"];
+
+pub type TokenResponse = StandardTokenResponse<ProviderFields, BasicTokenType>;
+
+/// Token as printed by `--output json` and kept in the state file. Its serialized form is part of
+/// the output contract of [`OUTPUT_SCHEMA_VERSION`](crate::output_schema::OUTPUT_SCHEMA_VERSION):
+/// within a version fields are only added, so consumers should ignore unknown ones
 #[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct TokenInfo {
     pub access_token: String,
 
     pub refresh_token: Option<String>,
 
+    /// Serialized as `{"secs_since_epoch": .., "nanos_since_epoch": ..}`
     pub expires: Option<SystemTime>,
 
+    /// Scope granted by the provider, if it differs from the requested one
     pub scope: Option<String>,
+
+    /// `--scope` the token was requested with. Tells whether the configured scopes changed since
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub requested_scope: Option<String>,
+
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub extra: HashMap<String, Value>,
 }
 
 impl TokenInfo {
-    pub fn from_token_response(response: BasicTokenResponse) -> TokenInfo {
+    fn from_standard_response<EF: ExtraTokenFields>(
+        response: &StandardTokenResponse<EF, BasicTokenType>,
+        extra: HashMap<String, Value>,
+    ) -> TokenInfo {
         TokenInfo {
             access_token: response.access_token().secret().to_owned(),
             refresh_token: response
@@ -28,6 +64,183 @@ impl TokenInfo {
             scope: response
                 .scopes()
                 .map(|v| v.iter().map(|scope| scope.to_string()).collect()),
+            requested_scope: None,
+            extra,
+        }
+    }
+
+    pub fn from_token_response(response: TokenResponse) -> TokenInfo {
+        let extra = response.extra_fields().fields.to_owned();
+
+        Self::from_standard_response(&response, extra)
+    }
+
+    /// Value of the `Authorization` header, ex. `Bearer eyJ...`
+    pub fn authorization_header(&self) -> String {
+        format!("Bearer {}", self.access_token)
+    }
+
+    pub fn with_requested_scope(self, scope: &str) -> TokenInfo {
+        TokenInfo {
+            requested_scope: Some(scope.to_owned()),
+            ..self
+        }
+    }
+
+    /// Keeps `session_state` of the authorization response and `sid` of the id_token in `extra`,
+    /// so the token can be matched with logout notifications of the IdP session after refreshes
+    pub fn with_session(mut self, session_state: Option<String>) -> TokenInfo {
+        let sid = self
+            .extra
+            .get("id_token")
+            .and_then(|token| token.as_str())
+            .and_then(claims::unverified_claims)
+            .and_then(|claims| claims.get("sid").cloned());
+
+        if let Some(session_state) = session_state {
+            self.extra
+                .entry("session_state".to_owned())
+                .or_insert(Value::String(session_state));
+        }
+        if let Some(sid) = sid {
+            self.extra.entry("sid".to_owned()).or_insert(sid);
+        }
+
+        self
+    }
+
+    /// Expiry by the `exp` claim of the access token, converted to the local clock, when the provider
+    /// omits `expires_in`. Takes the skew measured with `--clock-skew adjust`
+    pub fn with_clock_skew(mut self, skew: Option<i64>) -> TokenInfo {
+        let exp = claims::unverified_claims(&self.access_token)
+            .and_then(|claims| claims.get("exp").and_then(Value::as_u64));
+
+        if let (None, Some(skew), Some(exp)) = (self.expires, skew, exp) {
+            self.expires = Some(clock_skew::to_local(
+                SystemTime::UNIX_EPOCH + Duration::from_secs(exp),
+                skew,
+            ));
+        }
+
+        self
+    }
+
+    /// Session fields of the cached token, as refresh responses usually carry neither
+    pub fn with_session_of(mut self, cached: &TokenInfo) -> TokenInfo {
+        for field in SESSION_FIELDS {
+            if let Some(value) = cached.extra.get(field) {
+                self.extra
+                    .entry(field.to_owned())
+                    .or_insert_with(|| value.to_owned());
+            }
         }
+
+        self
+    }
+
+    /// Scopes added to or removed from `scope` since the token was requested. Tokens cached
+    /// before the requested scope was stored are never reported as changed
+    pub fn scope_changes(&self, scope: &str) -> (Vec<String>, Vec<String>) {
+        let Some(requested_scope) = &self.requested_scope else {
+            return (vec![], vec![]);
+        };
+        let requested: HashSet<&str> = requested_scope.split_whitespace().collect();
+        let configured: HashSet<&str> = scope.split_whitespace().collect();
+
+        let mut added: Vec<String> = configured
+            .difference(&requested)
+            .map(|scope| scope.to_string())
+            .collect();
+        let mut removed: Vec<String> = requested
+            .difference(&configured)
+            .map(|scope| scope.to_string())
+            .collect();
+        added.sort();
+        removed.sort();
+
+        (added, removed)
+    }
+}
+
+impl From<TokenResponse> for TokenInfo {
+    fn from(response: TokenResponse) -> Self {
+        TokenInfo::from_token_response(response)
+    }
+}
+
+impl From<BasicTokenResponse> for TokenInfo {
+    fn from(response: BasicTokenResponse) -> Self {
+        TokenInfo::from_standard_response(&response, HashMap::new())
+    }
+}
+
+/// `Authorization` header marked as sensitive, so HTTP clients don't log it
+impl TryFrom<&TokenInfo> for HeaderValue {
+    type Error = InvalidHeaderValue;
+
+    fn try_from(token_info: &TokenInfo) -> Result<Self, Self::Error> {
+        let mut header = HeaderValue::from_str(&token_info.authorization_header())?;
+        header.set_sensitive(true);
+
+        Ok(header)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_reports_scope_changes_since_request() {
+        let token_info = TokenInfo {
+            access_token: "token".to_owned(),
+            refresh_token: None,
+            expires: None,
+            scope: None,
+            requested_scope: None,
+            extra: HashMap::new(),
+        };
+
+        assert_eq!(token_info.scope_changes("openid email"), (vec![], vec![]));
+
+        let token_info = token_info.with_requested_scope("openid profile");
+        assert_eq!(
+            token_info.scope_changes("email  openid"),
+            (vec!["email".to_owned()], vec!["profile".to_owned()])
+        );
+        assert_eq!(token_info.scope_changes("profile openid"), (vec![], vec![]));
+    }
+
+    // The serialized form is a public contract, changing it requires a new `OUTPUT_SCHEMA_VERSION`
+    #[test]
+    fn it_keeps_the_serialized_form() {
+        let json = r#"{"access_token":"token","refresh_token":"refresh","expires":{"secs_since_epoch":1700000000,"nanos_since_epoch":0},"scope":"openid","requested_scope":"openid email","extra":{"id_token":"id"}}"#;
+
+        let token_info: TokenInfo = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            token_info.expires,
+            Some(SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000))
+        );
+        assert_eq!(serde_json::to_string(&token_info).unwrap(), json);
+
+        let token_info: TokenInfo = serde_json::from_str(
+            r#"{"access_token":"token","refresh_token":null,"expires":null,"scope":null}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            serde_json::to_string(&token_info).unwrap(),
+            r#"{"access_token":"token","refresh_token":null,"expires":null,"scope":null}"#
+        );
+    }
+
+    #[test]
+    fn it_converts_to_an_authorization_header() {
+        let response: BasicTokenResponse =
+            serde_json::from_str(r#"{"access_token":"token","token_type":"Bearer"}"#).unwrap();
+        let token_info = TokenInfo::from(response);
+
+        let header = HeaderValue::try_from(&token_info).unwrap();
+        assert_eq!(header, "Bearer token");
+        assert!(header.is_sensitive());
     }
 }
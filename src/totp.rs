@@ -0,0 +1,79 @@
+//! Time-based one-time passwords <https://www.rfc-editor.org/rfc/rfc6238> for providers accepting them with the password
+
+use anyhow::{Result, bail};
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const STEP_SECS: u64 = 30;
+const DIGITS: u32 = 6;
+const BASE32_ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// Decodes the base32 secret shown by authenticator enrollments. Spaces, dashes and padding are ignored
+fn decode_base32(secret: &str) -> Result<Vec<u8>> {
+    let mut bytes = vec![];
+    let mut buffer = 0u64;
+    let mut bits = 0;
+
+    for c in secret
+        .chars()
+        .filter(|c| !matches!(c, ' ' | '-' | '='))
+        .map(|c| c.to_ascii_uppercase())
+    {
+        let Some(value) = BASE32_ALPHABET.iter().position(|a| *a as char == c) else {
+            bail!("The OTP secret is not a valid base32 string");
+        };
+
+        buffer = (buffer << 5) | value as u64;
+        bits += 5;
+
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+
+    Ok(bytes)
+}
+
+fn totp_at(secret: &[u8], time: SystemTime) -> Result<String> {
+    let counter = time.duration_since(UNIX_EPOCH)?.as_secs() / STEP_SECS;
+
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret)?;
+    mac.update(&counter.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    // Dynamic truncation <https://www.rfc-editor.org/rfc/rfc4226#section-5.3>
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let code = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]) % 10u32.pow(DIGITS);
+
+    Ok(format!("{:0width$}", code, width = DIGITS as usize))
+}
+
+/// Current 6 digit code of a base32 secret
+pub fn now(secret: &str) -> Result<String> {
+    totp_at(&decode_base32(secret)?, SystemTime::now())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn it_generates_rfc6238_codes() {
+        let secret = decode_base32("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ").unwrap();
+        assert_eq!(secret, b"12345678901234567890");
+
+        let at = |secs| UNIX_EPOCH + Duration::from_secs(secs);
+        assert_eq!(totp_at(&secret, at(59)).unwrap(), "287082");
+        assert_eq!(totp_at(&secret, at(1111111109)).unwrap(), "081804");
+        assert_eq!(totp_at(&secret, at(2000000000)).unwrap(), "279037");
+    }
+}
@@ -0,0 +1,126 @@
+use anyhow::{Context, Result};
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use std::time::SystemTime;
+
+use crate::args::Arguments;
+use crate::http_client;
+use crate::output;
+use crate::token_info::TokenInfo;
+
+/// Header with the HMAC-SHA256 of the body, ex. `sha256=5d7f...`, set when `--webhook-secret` is given
+pub const SIGNATURE_HEADER: &str = "X-Doken-Signature-256";
+
+/// Event POSTed to `--webhook-url` by `doken daemon` and `doken watch`. Never carries tokens
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum WebhookEvent {
+    TokenRefreshed {
+        profile: Option<String>,
+        client_id: String,
+        expires: Option<String>,
+        time: String,
+    },
+    RefreshFailed {
+        profile: Option<String>,
+        client_id: String,
+        error: String,
+        time: String,
+    },
+}
+
+impl WebhookEvent {
+    pub fn refreshed(args: &Arguments, token_info: &TokenInfo) -> Self {
+        WebhookEvent::TokenRefreshed {
+            profile: args.profile.to_owned(),
+            client_id: args.client_id.to_owned(),
+            expires: token_info
+                .expires
+                .map(|expires| humantime::format_rfc3339_seconds(expires).to_string()),
+            time: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+        }
+    }
+
+    pub fn failed(args: &Arguments, error: &anyhow::Error) -> Self {
+        WebhookEvent::RefreshFailed {
+            profile: args.profile.to_owned(),
+            client_id: args.client_id.to_owned(),
+            error: format!("{:#}", error),
+            time: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+        }
+    }
+}
+
+fn signature(secret: &str, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(body.as_bytes());
+
+    format!(
+        "sha256={}",
+        mac.finalize()
+            .into_bytes()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>()
+    )
+}
+
+async fn post(url: &str, args: &Arguments, event: &WebhookEvent) -> Result<()> {
+    let body = serde_json::to_string(event)?;
+    let mut request = http_client::builder(args)?
+        .build()?
+        .post(url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json");
+
+    if let Some(secret) = &args.webhook_secret {
+        request = request.header(SIGNATURE_HEADER, signature(secret, &body));
+    }
+
+    request
+        .body(body)
+        .send()
+        .await?
+        .error_for_status()
+        .context("The webhook answered with an error")?;
+
+    Ok(())
+}
+
+/// POSTs the event to `--webhook-url`, if set. Never fails the run
+pub async fn notify(args: &Arguments, event: WebhookEvent) {
+    let Some(url) = &args.webhook_url else {
+        return;
+    };
+
+    if let Err(e) = post(url, args, &event).await {
+        output::warning(&format!("Cannot notify the webhook: {:#}", e));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_signs_events_without_tokens() {
+        let event = WebhookEvent::RefreshFailed {
+            profile: Some("ci".to_owned()),
+            client_id: "my-client".to_owned(),
+            error: "invalid_grant".to_owned(),
+            time: "2023-11-14T22:13:20Z".to_owned(),
+        };
+        let body = serde_json::to_string(&event).unwrap();
+
+        assert_eq!(
+            body,
+            r#"{"event":"refresh-failed","profile":"ci","client_id":"my-client","error":"invalid_grant","time":"2023-11-14T22:13:20Z"}"#
+        );
+        // Well-known HMAC-SHA256 test vector
+        assert_eq!(
+            signature("key", "The quick brown fox jumps over the lazy dog"),
+            "sha256=f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+        );
+    }
+}
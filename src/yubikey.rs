@@ -0,0 +1,122 @@
+use anyhow::{Context, Result, bail};
+use jsonwebtoken::Algorithm;
+use std::env;
+use std::path::{Path, PathBuf};
+
+use crate::pkcs11::{Pkcs11Signer, Pkcs11Uri};
+use crate::prompt::PromptOptions;
+use crate::signer::Signer;
+
+/// `libykcs11` of another place than where `yubico-piv-tool` installs it
+const MODULE_ENV: &str = "DOKEN_YKCS11_MODULE";
+
+const MODULES: &[&str] = &[
+    "/usr/lib/x86_64-linux-gnu/libykcs11.so",
+    "/usr/lib/aarch64-linux-gnu/libykcs11.so",
+    "/usr/lib64/libykcs11.so",
+    "/usr/lib/libykcs11.so",
+    "/usr/local/lib/libykcs11.so",
+    "/usr/local/lib/libykcs11.dylib",
+    "/opt/homebrew/lib/libykcs11.dylib",
+    r"C:\Program Files\Yubico\Yubico PIV Tool\bin\libykcs11.dll",
+];
+
+/// Key in a PIV slot of a YubiKey, used through Yubico's PKCS#11 module `libykcs11`. The key never
+/// leaves the YubiKey, its PIN is asked for once per run and handed to the module, never to a process
+pub struct YubiKeySigner(Pkcs11Signer);
+
+/// `CKA_ID` of the key in `slot` as `libykcs11` numbers them: `9a`, `9c`, `9d`, `9e` and the
+/// retired key management slots `82`-`95`
+fn key_id(slot: &str) -> Option<u8> {
+    match slot {
+        "9a" => Some(1),
+        "9c" => Some(2),
+        "9d" => Some(3),
+        "9e" => Some(4),
+        slot => u8::from_str_radix(slot, 16)
+            .ok()
+            .filter(|slot| (0x82..=0x95).contains(slot))
+            .map(|slot| slot - 0x82 + 5),
+    }
+}
+
+fn module() -> Result<PathBuf> {
+    if let Some(module) = env::var_os(MODULE_ENV) {
+        return Ok(PathBuf::from(module));
+    }
+
+    MODULES
+        .iter()
+        .map(Path::new)
+        .find(|module| module.exists())
+        .map(Path::to_owned)
+        .with_context(|| {
+            format!(
+                "Cannot find `libykcs11`, which signs with YubiKeys. Install `yubico-piv-tool` or pass its path in `{}`",
+                MODULE_ENV
+            )
+        })
+}
+
+impl YubiKeySigner {
+    pub fn new(
+        slot: &str,
+        algorithm: Algorithm,
+        key_id: Option<String>,
+        prompt: PromptOptions,
+    ) -> Result<YubiKeySigner> {
+        let slot = slot.to_lowercase();
+        let Some(id) = self::key_id(&slot) else {
+            bail!("`{}` is not a PIV slot, ex. `yubikey:9a`", slot);
+        };
+        if !matches!(
+            algorithm,
+            Algorithm::RS256 | Algorithm::ES256 | Algorithm::ES384
+        ) {
+            bail!(
+                "YubiKeys sign with RS256, ES256 or ES384, not {:?}",
+                algorithm
+            );
+        }
+
+        let uri = Pkcs11Uri {
+            id: Some(vec![id]),
+            module_path: Some(module()?),
+            ..Default::default()
+        };
+        let signer = Pkcs11Signer::new(&uri, algorithm, key_id, prompt)
+            .context("Cannot use the YubiKey. Is it plugged in?")?
+            .with_pin_label("YubiKey PIN");
+
+        Ok(YubiKeySigner(signer))
+    }
+}
+
+impl Signer for YubiKeySigner {
+    fn algorithm(&self) -> Algorithm {
+        self.0.algorithm()
+    }
+
+    fn key_id(&self) -> Option<&str> {
+        self.0.key_id()
+    }
+
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>> {
+        self.0.sign(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_finds_keys_of_piv_slots() {
+        assert_eq!(key_id("9a"), Some(1));
+        assert_eq!(key_id("9e"), Some(4));
+        assert_eq!(key_id("82"), Some(5));
+        assert_eq!(key_id("95"), Some(24));
+        assert_eq!(key_id("9b"), None);
+        assert_eq!(key_id("96"), None);
+    }
+}